@@ -0,0 +1,58 @@
+use std::hint::black_box;
+use std::io::Cursor;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use tokio::runtime::Runtime;
+
+use codecrafters_http_server::HttpResponse;
+
+/// The response to `GET /echo/hello` with the headers a typical handler sets.
+fn echo() -> HttpResponse {
+    HttpResponse::builder()
+        .header("Content-Type", "text/plain")
+        .header("Date", "Wed, 14 Oct 2026 12:00:00 GMT")
+        .header("Cache-Control", "no-cache")
+        .body("hello")
+        .build()
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let file = vec![b'x'; 64 * 1024];
+    let mut group = c.benchmark_group("encode");
+
+    group.bench_function("echo/head", |b| b.iter(|| black_box(echo()).encode_head()));
+    group.bench_function("echo/write", |b| {
+        b.iter(|| {
+            let mut out = Vec::with_capacity(256);
+            runtime
+                .block_on(black_box(echo()).write_to(&mut out))
+                .unwrap();
+            out
+        })
+    });
+
+    group.throughput(Throughput::Bytes(file.len() as u64));
+    group.bench_function("full_64k/write", |b| {
+        b.iter(|| {
+            let resp = HttpResponse::builder().body(file.clone()).build();
+            let mut out = Vec::with_capacity(file.len() + 256);
+            runtime.block_on(resp.write_to(&mut out)).unwrap();
+            out
+        })
+    });
+    group.bench_function("chunked_64k/write", |b| {
+        b.iter(|| {
+            let resp = HttpResponse::builder()
+                .stream(Cursor::new(file.clone()))
+                .build();
+            let mut out = Vec::with_capacity(file.len() + 256);
+            runtime.block_on(resp.write_to(&mut out)).unwrap();
+            out
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+use bytes::BytesMut;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use codecrafters_http_server::HttpRequest;
+use codecrafters_http_server::request::{Parsed, RequestParser};
+
+/// A browser-like GET with the header fields most clients send.
+const GET: &[u8] = b"GET /echo/hello?upper=true HTTP/1.1\r\n\
+Host: localhost:4221\r\n\
+User-Agent: Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0\r\n\
+Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\n\
+Accept-Language: en-US,en;q=0.5\r\n\
+Accept-Encoding: gzip, deflate, br, zstd\r\n\
+Connection: keep-alive\r\n\
+Cookie: session=0123456789abcdef; theme=dark\r\n\
+Upgrade-Insecure-Requests: 1\r\n\
+Sec-Fetch-Dest: document\r\n\
+Sec-Fetch-Mode: navigate\r\n\
+Sec-Fetch-Site: none\r\n\
+\r\n";
+
+fn upload() -> Vec<u8> {
+    let body = vec![b'x'; 64 * 1024];
+    let mut request = format!(
+        "POST /files/upload.bin HTTP/1.1\r\nHost: localhost:4221\r\n\
+         Content-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&body);
+    request
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, request) in [("get", GET.to_vec()), ("upload_64k", upload())] {
+        group.throughput(Throughput::Bytes(request.len() as u64));
+        group.bench_function(format!("{name}/from_bytes"), |b| {
+            b.iter(|| HttpRequest::from_bytes(BytesMut::from(black_box(&request[..]))).unwrap())
+        });
+        group.bench_function(format!("{name}/feed"), |b| {
+            b.iter(
+                || match RequestParser::new().feed(black_box(&request)).unwrap() {
+                    Parsed::Complete(request, _) => request,
+                    Parsed::NeedMoreData => unreachable!(),
+                },
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
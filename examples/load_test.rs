@@ -0,0 +1,135 @@
+//! Loads a running server with keep-alive requests and reports throughput and latencies, to
+//! compare builds before and after a change:
+//!
+//! ```sh
+//! cargo run --release -- --directory /tmp &
+//! cargo run --release --example load_test -- --connections 64 --duration 10
+//! ```
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::{Buf, BytesMut};
+use clap::Parser;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+#[derive(Parser)]
+struct Args {
+    /// The server to load.
+    #[arg(long, default_value = "127.0.0.1:4221")]
+    addr: String,
+    /// The path requested over and over, answered with a 2xx and a Content-Length.
+    #[arg(long, default_value = "/echo/hello")]
+    path: String,
+    /// Concurrent connections, each sending its next request once the previous one is answered.
+    #[arg(long, default_value_t = 50)]
+    connections: usize,
+    /// How long to keep sending requests, in seconds.
+    #[arg(long, default_value_t = 10)]
+    duration: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", args.path, args.addr);
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(args.duration);
+
+    let mut connections = JoinSet::new();
+    for _ in 0..args.connections {
+        connections.spawn(connection(
+            args.addr.clone(),
+            request.clone().into_bytes(),
+            deadline,
+        ));
+    }
+    let mut latencies = vec![];
+    let mut failed = 0;
+    while let Some(result) = connections.join_next().await {
+        match result? {
+            Ok(mut samples) => latencies.append(&mut samples),
+            Err(e) => {
+                failed += 1;
+                eprintln!("connection failed: {e:#}");
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+    latencies.sort();
+
+    println!(
+        "{} requests in {:.2?} over {} connections ({failed} failed)",
+        latencies.len(),
+        elapsed,
+        args.connections
+    );
+    if latencies.is_empty() {
+        anyhow::bail!("no request was answered");
+    }
+    println!(
+        "{:.0} requests/s",
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+    println!(
+        "latency p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        percentile(100)
+    );
+    Ok(())
+}
+
+/// Sends requests one after another until `deadline`, returning how long each one took.
+async fn connection(addr: String, request: Vec<u8>, deadline: Instant) -> Result<Vec<Duration>> {
+    let mut stream = TcpStream::connect(&addr)
+        .await
+        .with_context(|| format!("unable to connect to {addr}"))?;
+    stream.set_nodelay(true)?;
+    let mut buf = BytesMut::with_capacity(8192);
+    let mut latencies = vec![];
+    while Instant::now() < deadline {
+        let sent = Instant::now();
+        stream.write_all(&request).await?;
+        read_response(&mut stream, &mut buf).await?;
+        latencies.push(sent.elapsed());
+    }
+    Ok(latencies)
+}
+
+/// Reads one response off `stream`, leaving any bytes after it in `buf`.
+async fn read_response(stream: &mut TcpStream, buf: &mut BytesMut) -> Result<()> {
+    loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            let head = std::str::from_utf8(&buf[..end]).context("response head is not UTF-8")?;
+            let status_line = head.lines().next().unwrap_or_default();
+            if !status_line.starts_with("HTTP/1.1 2") {
+                anyhow::bail!("unexpected response {status_line:?}");
+            }
+            let length: usize = head
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("Content-Length")
+                        .then(|| value.trim().parse().ok())?
+                })
+                .context("response without a Content-Length")?;
+            let total = end + 4 + length;
+            while buf.len() < total {
+                if stream.read_buf(buf).await? == 0 {
+                    anyhow::bail!("connection closed in the middle of a response");
+                }
+            }
+            buf.advance(total);
+            return Ok(());
+        }
+        if stream.read_buf(buf).await? == 0 {
+            anyhow::bail!("connection closed by the server");
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+use anyhow::Result;
+
+use crate::date;
+use crate::error::HttpError;
+use crate::middleware::{Middleware, Next};
+use crate::request::{HttpRequest, Version};
+use crate::response::{Body, CLIENT_CLOSED_REQUEST, Delivery, HttpResponse};
+use crate::router::BoxFuture;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogFormat {
+    /// One Common Log Format line per request.
+    #[default]
+    Common,
+    /// Structured fields, meant for a JSON tracing subscriber.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "common" => Ok(LogFormat::Common),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "unknown log format {format:?}, expected common or json"
+            )),
+        }
+    }
+}
+
+/// Emits one access log event per request through `tracing` on the `access_log` target, once
+/// the response has been written. Responses the client disconnected from are logged with status
+/// 499, handler errors with the status they are answered with.
+pub struct AccessLog {
+    format: LogFormat,
+}
+
+impl AccessLog {
+    pub fn new(format: LogFormat) -> Self {
+        AccessLog { format }
+    }
+}
+
+impl<S: Send + Sync + 'static> Middleware<S> for AccessLog {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        state: Arc<S>,
+        next: Next<'a, S>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let started = Instant::now();
+            let method = request.method.clone();
+            let target = request.raw_path.clone();
+            let version = request.version;
+            let peer = request
+                .remote_addr()
+                .map_or("-".to_string(), |client| client.to_string());
+            let user_agent = request.headers.get("User-Agent").unwrap_or("-").to_string();
+
+            let mut response = next.run(request, state).await;
+            let entry = Entry {
+                format: self.format,
+                started,
+                method,
+                target,
+                version,
+                peer,
+                user_agent,
+            };
+            match &mut response {
+                // logged once the response has been written, to tell whether the client got it
+                Ok(resp) => {
+                    let status = resp.status_code.as_u16();
+                    let bytes = body_length(resp);
+                    resp.on_complete(move |delivery| match delivery {
+                        Delivery::Aborted => entry.log(CLIENT_CLOSED_REQUEST, None),
+                        _ => entry.log(status, bytes),
+                    });
+                }
+                Err(error) => {
+                    let status = error
+                        .downcast_ref::<HttpError>()
+                        .map_or(500, |error| error.status().as_u16());
+                    entry.log(status, None)
+                }
+            }
+            response
+        })
+    }
+}
+
+/// What is logged about a request, collected before it is handled.
+struct Entry {
+    format: LogFormat,
+    started: Instant,
+    method: String,
+    target: String,
+    version: Version,
+    peer: String,
+    user_agent: String,
+}
+
+impl Entry {
+    fn log(self, status: u16, bytes: Option<u64>) {
+        let duration_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+        match self.format {
+            LogFormat::Common => tracing::info!(
+                target: "access_log",
+                "{} - - {} \"{} {} {}\" {} {}",
+                self.peer,
+                date::common_log(SystemTime::now()),
+                self.method,
+                self.target,
+                self.version,
+                status,
+                bytes.map_or("-".to_string(), |bytes| bytes.to_string())
+            ),
+            LogFormat::Json => tracing::info!(
+                target: "access_log",
+                method = self.method,
+                path = self.target,
+                version = self.version.as_str(),
+                status,
+                duration_ms,
+                bytes,
+                peer = self.peer,
+                user_agent = self.user_agent,
+            ),
+        }
+    }
+}
+
+fn body_length(response: &HttpResponse) -> Option<u64> {
+    match &response.body {
+        Body::Full(body) => Some(body.len() as u64),
+        Body::Stream(_) => response.headers.get("Content-Length")?.parse().ok(),
+    }
+}
+
+#[tokio::test]
+async fn tests_access_log_handler_errors() {
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    use crate::router::Router;
+    use crate::status::StatusCode;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let router: Router<()> = Router::new()
+        .get("/rejected", |_, _| async {
+            Err(HttpError::new(StatusCode::Forbidden, "no access").into())
+        })
+        .get("/broken", |_, _| async {
+            Err(anyhow::anyhow!("disk on fire"))
+        })
+        .layer(AccessLog::new(LogFormat::Common));
+    for path in ["/rejected", "/broken"] {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            raw_path: path.to_string(),
+            ..Default::default()
+        };
+        assert!(router.handle(request, Arc::new(())).await.is_err());
+    }
+
+    let log = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    assert!(log.contains("\"GET /rejected HTTP/1.1\" 403 -"), "{log}");
+    assert!(log.contains("\"GET /broken HTTP/1.1\" 500 -"), "{log}");
+}
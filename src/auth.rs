@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sha1::{Digest, Sha1};
+
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::{BoxFuture, has_path_prefix};
+use crate::status::StatusCode;
+
+/// A password hash from an htpasswd file.
+#[derive(Debug, Clone)]
+enum PasswordHash {
+    /// `$2y$...` and the other bcrypt variants, as written by `htpasswd -B`.
+    Bcrypt(String),
+    /// `{SHA}` followed by the base64 SHA-1 digest, as written by `htpasswd -s`.
+    Sha1(Vec<u8>),
+}
+
+impl PasswordHash {
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            PasswordHash::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            PasswordHash::Sha1(digest) => {
+                constant_time_eq(&Sha1::digest(password.as_bytes()), digest)
+            }
+        }
+    }
+}
+
+/// Who a request authenticated as, added to its extensions by [`Auth`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Identity {
+    /// A user of the htpasswd file, authenticated with Basic credentials.
+    User(String),
+    /// One of the bearer tokens, which aren't told apart.
+    Bearer,
+}
+
+/// Requires Basic or Bearer credentials for requests below the protected path prefixes, or for
+/// all requests if no prefix was given. Unauthenticated requests get a 401 with a
+/// WWW-Authenticate challenge for each configured scheme.
+pub struct Auth {
+    realm: String,
+    users: HashMap<String, PasswordHash>,
+    tokens: Vec<String>,
+    paths: Vec<String>,
+}
+
+impl Auth {
+    pub fn new(realm: impl Into<String>) -> Self {
+        Auth {
+            realm: realm.into(),
+            users: HashMap::new(),
+            tokens: vec![],
+            paths: vec![],
+        }
+    }
+
+    /// Accepts Basic credentials for the users in an htpasswd file. Only bcrypt and `{SHA}`
+    /// hashes are supported.
+    pub fn htpasswd_file(self, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read htpasswd file {}", path.display()))?;
+        self.htpasswd(&contents)
+            .with_context(|| format!("Invalid htpasswd file {}", path.display()))
+    }
+
+    /// Accepts Basic credentials for the users in htpasswd-formatted `contents`.
+    pub fn htpasswd(mut self, contents: &str) -> Result<Self> {
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = line
+                .split_once(':')
+                .with_context(|| format!("line {}: expected user:hash", number + 1))?;
+            let hash = if let Some(digest) = hash.strip_prefix("{SHA}") {
+                PasswordHash::Sha1(
+                    BASE64
+                        .decode(digest)
+                        .with_context(|| format!("line {}: invalid SHA digest", number + 1))?,
+                )
+            } else if ["$2a$", "$2b$", "$2x$", "$2y$"]
+                .iter()
+                .any(|prefix| hash.starts_with(prefix))
+            {
+                PasswordHash::Bcrypt(hash.to_string())
+            } else {
+                anyhow::bail!(
+                    "line {}: unsupported hash for {user:?}, use bcrypt or {{SHA}}",
+                    number + 1
+                );
+            };
+            self.users.insert(user.to_string(), hash);
+        }
+        Ok(self)
+    }
+
+    /// Accepts `Authorization: Bearer <token>` for any of `tokens`.
+    pub fn bearer_tokens(mut self, tokens: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.tokens.extend(tokens.into_iter().map(Into::into));
+        self
+    }
+
+    /// Protects requests whose path is `prefix` or below it.
+    pub fn protect(mut self, prefix: impl Into<String>) -> Self {
+        self.paths.push(prefix.into());
+        self
+    }
+
+    fn is_protected(&self, path: &str) -> bool {
+        self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|prefix| has_path_prefix(path, prefix))
+    }
+
+    async fn authenticate(&self, authorization: Option<&str>) -> Credentials {
+        let Some((scheme, credentials)) = authorization.and_then(|value| value.split_once(' '))
+        else {
+            return Credentials::Missing;
+        };
+        let credentials = credentials.trim();
+
+        if scheme.eq_ignore_ascii_case("Basic") && !self.users.is_empty() {
+            let Some((user, password)) = BASE64
+                .decode(credentials)
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .and_then(|decoded| {
+                    let (user, password) = decoded.split_once(':')?;
+                    Some((user.to_string(), password.to_string()))
+                })
+            else {
+                return Credentials::Invalid;
+            };
+            let Some(hash) = self.users.get(&user).cloned() else {
+                return Credentials::Invalid;
+            };
+            // bcrypt is deliberately slow, so keep it off the async workers
+            let verified = tokio::task::spawn_blocking(move || hash.verify(&password))
+                .await
+                .unwrap_or(false);
+            return if verified {
+                Credentials::Valid(Identity::User(user))
+            } else {
+                Credentials::Invalid
+            };
+        }
+
+        if scheme.eq_ignore_ascii_case("Bearer") && !self.tokens.is_empty() {
+            let valid = self
+                .tokens
+                .iter()
+                .any(|token| constant_time_eq(token.as_bytes(), credentials.as_bytes()));
+            return if valid {
+                Credentials::Valid(Identity::Bearer)
+            } else {
+                Credentials::InvalidToken
+            };
+        }
+        Credentials::Missing
+    }
+
+    fn unauthorized(&self, credentials: Credentials) -> HttpResponse {
+        let mut resp = HttpResponse::new(StatusCode::Unauthorized);
+        let realm = self.realm.replace(['"', '\\'], "");
+        if !self.users.is_empty() {
+            resp.headers.append(
+                "WWW-Authenticate".to_string(),
+                format!("Basic realm=\"{realm}\", charset=\"UTF-8\""),
+            );
+        }
+        if !self.tokens.is_empty() {
+            let error = match credentials {
+                Credentials::InvalidToken => ", error=\"invalid_token\"",
+                _ => "",
+            };
+            resp.headers.append(
+                "WWW-Authenticate".to_string(),
+                format!("Bearer realm=\"{realm}\"{error}"),
+            );
+        }
+        resp
+    }
+}
+
+enum Credentials {
+    Valid(Identity),
+    Missing,
+    Invalid,
+    InvalidToken,
+}
+
+impl<S: Send + Sync + 'static> Middleware<S> for Auth {
+    fn handle<'a>(
+        &'a self,
+        mut request: HttpRequest,
+        state: Arc<S>,
+        next: Next<'a, S>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            if !self.is_protected(&request.path) {
+                return next.run(request, state).await;
+            }
+            match self
+                .authenticate(request.headers.get("Authorization"))
+                .await
+            {
+                Credentials::Valid(identity) => {
+                    request.extensions.insert(identity);
+                    next.run(request, state).await
+                }
+                credentials => Ok(self.unauthorized(credentials)),
+            }
+        })
+    }
+}
+
+/// Compares without bailing out at the first difference, so timing doesn't reveal secrets.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[tokio::test]
+async fn tests_auth() {
+    use crate::router::Router;
+
+    // "secret" hashed like `htpasswd -s` and `htpasswd -B` would
+    let htpasswd = format!(
+        "# users\nalice:{{SHA}}5en6G6MezRroT3XKqkdPOmY/BfQ=\nbob:{}\n",
+        bcrypt::hash("secret", 4).unwrap()
+    );
+    let router: Router<()> = Router::new()
+        .get("/private/:name", |request, _| async move {
+            let identity = request.extensions.get::<Identity>();
+            Ok(HttpResponse::builder()
+                .body(format!("{identity:?}"))
+                .build())
+        })
+        .get("/public", |_, _| async { Ok(HttpResponse::ok()) })
+        .layer(
+            Auth::new("files")
+                .htpasswd(&htpasswd)
+                .unwrap()
+                .bearer_tokens(["token-1"])
+                .protect("/private"),
+        );
+    let handle = async |path: &str, authorization: Option<String>| {
+        let mut request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        if let Some(authorization) = authorization {
+            request
+                .headers
+                .insert("Authorization".to_string(), authorization);
+        }
+        router.handle(request, Arc::new(())).await.unwrap()
+    };
+    let basic = |credentials: &str| Some(format!("Basic {}", BASE64.encode(credentials)));
+
+    assert_eq!(200, handle("/public", None).await.status_code);
+
+    let resp = handle("/private/a", None).await;
+    assert_eq!(401, resp.status_code);
+    assert_eq!(
+        vec![
+            "Basic realm=\"files\", charset=\"UTF-8\"",
+            "Bearer realm=\"files\""
+        ],
+        resp.headers.get_all("WWW-Authenticate").collect::<Vec<_>>()
+    );
+
+    assert_eq!(
+        200,
+        handle("/private/a", basic("alice:secret"))
+            .await
+            .status_code
+    );
+    let resp = handle("/private/a", basic("bob:secret")).await;
+    assert_eq!(Some(&b"Some(User(\"bob\"))"[..]), resp.body.as_bytes());
+    assert_eq!(
+        401,
+        handle("/private/a", basic("alice:wrong")).await.status_code
+    );
+    assert_eq!(
+        401,
+        handle("/private/a", basic("carol:secret"))
+            .await
+            .status_code
+    );
+    let resp = handle("/private/a", Some("Bearer token-1".to_string())).await;
+    assert_eq!(Some(&b"Some(Bearer)"[..]), resp.body.as_bytes());
+    let resp = handle("/private/a", Some("Bearer token-2".to_string())).await;
+    assert_eq!(401, resp.status_code);
+    assert!(
+        resp.headers
+            .get_all("WWW-Authenticate")
+            .any(|challenge| challenge.contains("error=\"invalid_token\""))
+    );
+
+    assert!(Auth::new("x").htpasswd("carol:$apr1$abc$def").is_err());
+}
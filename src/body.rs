@@ -0,0 +1,416 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use anyhow::Result;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::headers::HeaderMap;
+use crate::request::split_field;
+use crate::response::HttpResponse;
+use crate::server::is_disconnect;
+
+/// How many received pieces of a body may wait for the handler before reading from the socket
+/// pauses.
+const BUFFERED_PIECES: usize = 8;
+
+/// Longest accepted chunk size line, extensions included.
+pub(crate) const MAX_CHUNK_LINE: usize = 1024;
+
+/// What the connection passes on to a [`BodyStream`].
+#[derive(Debug)]
+pub(crate) enum Piece {
+    Data(Bytes),
+    /// The trailer fields of a chunked body, sent after its last chunk.
+    Trailers(HeaderMap),
+}
+
+pub(crate) type BodySender = mpsc::Sender<io::Result<Piece>>;
+
+/// A request body read from the connection while the handler runs, for routes registered with
+/// [`Router::route_streaming`](crate::Router::route_streaming). It is read either as bytes
+/// through [`AsyncRead`] or chunk by chunk as they arrive, with [`BodyStream::chunk`] or as a
+/// [`Stream`]. Reading fails if the client disconnects or breaks a limit before the body is
+/// complete, so reaching the end means the whole body arrived.
+#[derive(Debug)]
+pub struct BodyStream {
+    rx: mpsc::Receiver<io::Result<Piece>>,
+    pending: Bytes,
+    trailers: HeaderMap,
+}
+
+impl BodyStream {
+    pub(crate) fn channel() -> (BodySender, BodyStream) {
+        let (tx, rx) = mpsc::channel(BUFFERED_PIECES);
+        (
+            tx,
+            BodyStream {
+                rx,
+                pending: Bytes::new(),
+                trailers: HeaderMap::new(),
+            },
+        )
+    }
+
+    /// A body that was received in full before the handler runs.
+    pub(crate) fn buffered(body: Bytes, trailers: HeaderMap) -> BodyStream {
+        let (_, rx) = mpsc::channel(1);
+        BodyStream {
+            rx,
+            pending: body,
+            trailers,
+        }
+    }
+
+    /// The trailer fields sent after a chunked body, complete once reading reached its end.
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    /// The next piece of the body as it arrived, `None` once the body is complete.
+    pub async fn chunk(&mut self) -> Option<io::Result<Bytes>> {
+        std::future::poll_fn(|cx| self.poll_chunk(cx)).await
+    }
+
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        if !self.pending.is_empty() {
+            return Poll::Ready(Some(Ok(std::mem::take(&mut self.pending))));
+        }
+        loop {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(Ok(Piece::Data(bytes)))) if bytes.is_empty() => {}
+                Poll::Ready(Some(Ok(Piece::Data(bytes)))) => return Poll::Ready(Some(Ok(bytes))),
+                Poll::Ready(Some(Ok(Piece::Trailers(trailers)))) => self.trailers = trailers,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                // the server only drops the sender once the body is complete
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Stream for BodyStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_chunk(cx)
+    }
+}
+
+impl AsyncRead for BodyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.pending.is_empty() {
+            match self.poll_chunk(cx) {
+                Poll::Ready(Some(Ok(bytes))) => self.pending = bytes,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let len = buf.remaining().min(self.pending.len());
+        buf.put_slice(&self.pending.split_to(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// How the end of a request body is found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Framing {
+    Length(usize),
+    Chunked,
+}
+
+pub(crate) enum Forwarded {
+    /// The whole body was passed on, the connection can serve another request.
+    Complete,
+    /// The handler stopped reading, so the rest of the body was never received.
+    Abandoned,
+    /// The body breaks a limit or its framing, answered with this response instead.
+    Rejected(HttpResponse),
+    /// The client disconnected abruptly, nothing can be answered.
+    Disconnected,
+}
+
+/// Passes the body of a streamed request, starting with the already `buffered` bytes, from
+/// `stream` to the handler's [`BodyStream`] until it is complete or `deadline` passes. Bytes
+/// read past the end of the body are left in `buffered`.
+pub(crate) async fn forward<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    buffered: &mut BytesMut,
+    framing: Framing,
+    tx: BodySender,
+    deadline: Instant,
+    max_size: usize,
+) -> Result<Forwarded> {
+    let mut reader = Reader {
+        stream,
+        buf: buffered,
+        tx: &tx,
+        deadline,
+    };
+    let result = finish(match framing {
+        Framing::Length(length) => reader.copy(length).await,
+        Framing::Chunked => reader.copy_chunked(max_size).await,
+    });
+    // tell the handler the body is incomplete, dropping the sender alone would look like its end
+    if !matches!(result, Ok(Forwarded::Complete | Forwarded::Abandoned)) {
+        let _ = tx
+            .send(Err(io::Error::other("request body incomplete")))
+            .await;
+    }
+    result
+}
+
+struct Reader<'a, S> {
+    stream: &'a mut S,
+    buf: &'a mut BytesMut,
+    tx: &'a BodySender,
+    deadline: Instant,
+}
+
+/// Why a [`Reader`] stopped before the body was complete.
+enum Stop {
+    Forwarded(Forwarded),
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Stop {
+    fn from(e: anyhow::Error) -> Self {
+        Stop::Failed(e)
+    }
+}
+
+fn finish(result: Result<(), Stop>) -> Result<Forwarded> {
+    match result {
+        Ok(()) => Ok(Forwarded::Complete),
+        Err(Stop::Forwarded(forwarded)) => Ok(forwarded),
+        Err(Stop::Failed(e)) => Err(e),
+    }
+}
+
+impl<S: AsyncRead + Unpin> Reader<'_, S> {
+    /// Reads more bytes into the buffer.
+    async fn fill(&mut self) -> Result<(), Stop> {
+        let read = tokio::select! {
+            read = tokio::time::timeout_at(self.deadline, self.stream.read_buf(&mut *self.buf)) => read,
+            _ = self.tx.closed() => return Err(Stop::Forwarded(Forwarded::Abandoned)),
+        };
+        match read {
+            // the client shut down its side, it may still read why the request failed
+            Ok(Ok(0)) => Err(Stop::Forwarded(Forwarded::Rejected(
+                HttpResponse::bad_request(),
+            ))),
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) if is_disconnect(&e) => Err(Stop::Forwarded(Forwarded::Disconnected)),
+            Ok(Err(e)) => Err(anyhow::Error::new(e).context("Failed to read").into()),
+            Err(_) => Err(Stop::Forwarded(Forwarded::Rejected(
+                HttpResponse::request_timeout(),
+            ))),
+        }
+    }
+
+    /// Sends up to `len` buffered bytes to the handler, returning how many were sent.
+    async fn send(&mut self, len: usize) -> Result<usize, Stop> {
+        let piece = self.buf.split_to(len.min(self.buf.len())).freeze();
+        let sent = piece.len();
+        if sent > 0 && self.tx.send(Ok(Piece::Data(piece))).await.is_err() {
+            return Err(Stop::Forwarded(Forwarded::Abandoned));
+        }
+        Ok(sent)
+    }
+
+    async fn copy(&mut self, mut remaining: usize) -> Result<(), Stop> {
+        while remaining > 0 {
+            if self.buf.is_empty() {
+                self.fill().await?;
+            }
+            remaining -= self.send(remaining).await?;
+        }
+        Ok(())
+    }
+
+    async fn line(&mut self) -> Result<Bytes, Stop> {
+        loop {
+            if let Some(end) = self.buf.windows(2).position(|word| word == b"\r\n") {
+                let line = self.buf.split_to(end).freeze();
+                self.buf.advance(2);
+                return Ok(line);
+            }
+            if self.buf.len() > MAX_CHUNK_LINE {
+                return Err(malformed());
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn copy_chunked(&mut self, max_size: usize) -> Result<(), Stop> {
+        let mut total: usize = 0;
+        loop {
+            let line = self.line().await?;
+            let size = parse_chunk_size(&line).ok_or_else(malformed)?;
+            if size == 0 {
+                let mut trailers = HeaderMap::new();
+                loop {
+                    let line = self.line().await?;
+                    if line.is_empty() {
+                        break;
+                    }
+                    // otherwise trailers could go on forever
+                    total = total.saturating_add(line.len());
+                    if total > max_size {
+                        return Err(too_large());
+                    }
+                    let (name, value) = parse_trailer(&line).ok_or_else(malformed)?;
+                    trailers.append(name, value);
+                }
+                if !trailers.is_empty()
+                    && self.tx.send(Ok(Piece::Trailers(trailers))).await.is_err()
+                {
+                    return Err(Stop::Forwarded(Forwarded::Abandoned));
+                }
+                return Ok(());
+            }
+            // refused before any of the chunk is read
+            total = total.saturating_add(size);
+            if total > max_size {
+                return Err(too_large());
+            }
+            self.copy(size).await?;
+            if !self.line().await?.is_empty() {
+                return Err(malformed());
+            }
+        }
+    }
+}
+
+/// Parses the size of a chunk size line, hex digits optionally followed by extensions. Anything
+/// looser, like a sign or whitespace before the size, is refused since other parsers might read
+/// the line differently.
+pub(crate) fn parse_chunk_size(line: &[u8]) -> Option<usize> {
+    let digits = line
+        .iter()
+        .take_while(|byte| byte.is_ascii_hexdigit())
+        .count();
+    let rest = &line[digits..];
+    let extensions = &rest[rest
+        .iter()
+        .take_while(|&&byte| matches!(byte, b' ' | b'\t'))
+        .count()..];
+    if digits == 0 || !(extensions.is_empty() || extensions.starts_with(b";")) {
+        return None;
+    }
+    usize::from_str_radix(std::str::from_utf8(&line[..digits]).ok()?, 16).ok()
+}
+
+/// Parses a `name: value` trailer field line.
+pub(crate) fn parse_trailer(line: &[u8]) -> Option<(String, String)> {
+    let (name, value) = split_field(std::str::from_utf8(line).ok()?)?;
+    Some((name.to_string(), value.to_string()))
+}
+
+fn malformed() -> Stop {
+    Stop::Forwarded(Forwarded::Rejected(HttpResponse::bad_request()))
+}
+
+fn too_large() -> Stop {
+    Stop::Forwarded(Forwarded::Rejected(HttpResponse::payload_too_large()))
+}
+
+#[tokio::test]
+async fn tests_forward() {
+    use std::time::Duration;
+
+    let forward_all = async |buffered: &[u8], rest: &[u8], framing| {
+        let (tx, mut body) = BodyStream::channel();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut rest = rest;
+        let mut buffered = BytesMut::from(buffered);
+        let (forwarded, received) = tokio::join!(
+            forward(&mut rest, &mut buffered, framing, tx, deadline, 64),
+            async {
+                let mut received = vec![];
+                let read = body.read_to_end(&mut received).await;
+                read.map(|_| (received, body.trailers().clone()))
+            }
+        );
+        (forwarded.unwrap(), received)
+    };
+
+    let (forwarded, received) = forward_all(b"hel", b"lo world", Framing::Length(5)).await;
+    assert!(matches!(forwarded, Forwarded::Complete));
+    assert_eq!(b"hello", &received.unwrap().0[..]);
+
+    let (forwarded, received) = forward_all(
+        b"4\r\nWiki\r\n5\r",
+        b"\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nX-Trailer: 1\r\n\r\n",
+        Framing::Chunked,
+    )
+    .await;
+    assert!(matches!(forwarded, Forwarded::Complete));
+    let (received, trailers) = received.unwrap();
+    assert_eq!(b"Wikipedia in\r\n\r\nchunks.", &received[..]);
+    assert_eq!(Some("1"), trailers.get("X-Trailer"));
+
+    let (forwarded, received) = forward_all(b"41\r\n", &[b'a'; 70], Framing::Chunked).await;
+    assert!(matches!(forwarded, Forwarded::Rejected(resp) if resp.status_code == 413));
+    assert!(received.is_err());
+
+    let (forwarded, received) = forward_all(b"zz\r\n", b"", Framing::Chunked).await;
+    assert!(matches!(forwarded, Forwarded::Rejected(resp) if resp.status_code == 400));
+    assert!(received.is_err());
+
+    let (tx, body) = BodyStream::channel();
+    drop(body);
+    let forwarded = forward(
+        &mut &b"abc"[..],
+        &mut BytesMut::new(),
+        Framing::Length(10),
+        tx,
+        Instant::now() + Duration::from_secs(5),
+        64,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(forwarded, Forwarded::Abandoned));
+}
+
+#[tokio::test]
+async fn tests_chunks() {
+    let (tx, mut body) = BodyStream::channel();
+    tx.send(Ok(Piece::Data(Bytes::from_static(b"abc"))))
+        .await
+        .unwrap();
+    tx.send(Ok(Piece::Data(Bytes::new()))).await.unwrap();
+    tx.send(Ok(Piece::Data(Bytes::from_static(b"de"))))
+        .await
+        .unwrap();
+    let mut trailers = HeaderMap::new();
+    trailers.insert("X-Trailer".to_string(), "1".to_string());
+    tx.send(Ok(Piece::Trailers(trailers))).await.unwrap();
+    drop(tx);
+
+    assert_eq!(b"abc", &body.chunk().await.unwrap().unwrap()[..]);
+    let next = std::future::poll_fn(|cx| Pin::new(&mut body).poll_next(cx)).await;
+    assert_eq!(b"de", &next.unwrap().unwrap()[..]);
+    assert!(body.chunk().await.is_none());
+    assert_eq!(Some("1"), body.trailers().get("X-Trailer"));
+
+    let (tx, mut body) = BodyStream::channel();
+    tx.send(Err(io::ErrorKind::UnexpectedEof.into()))
+        .await
+        .unwrap();
+    assert!(body.chunk().await.unwrap().is_err());
+
+    let mut body = BodyStream::buffered(Bytes::from_static(b"whole"), HeaderMap::new());
+    assert_eq!(b"whole", &body.chunk().await.unwrap().unwrap()[..]);
+    assert!(body.chunk().await.is_none());
+}
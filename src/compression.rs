@@ -0,0 +1,366 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_compression::Level;
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::{Body, HttpResponse};
+use crate::router::BoxFuture;
+
+/// Content types compressed by default, a trailing `*` matches any subtype.
+const COMPRESSIBLE_TYPES: [&str; 8] = [
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/xhtml+xml",
+    "application/manifest+json",
+    "application/wasm",
+    "image/svg+xml",
+];
+
+/// The content codings the server can produce.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// Preferred first when the client accepts several equally.
+    pub(crate) const ALL: [Encoding; 3] = [Encoding::Zstd, Encoding::Brotli, Encoding::Gzip];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The extension of a file precompressed with the encoding, e.g. "gz" for app.js.gz.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zst",
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    fn encode(
+        self,
+        reader: impl AsyncRead + Send + Unpin + 'static,
+    ) -> Box<dyn AsyncRead + Send + Unpin> {
+        let reader = BufReader::new(reader);
+        match self {
+            Encoding::Zstd => Box::new(ZstdEncoder::new(reader)),
+            // brotli's default quality is meant for offline use and far too slow per request
+            Encoding::Brotli => Box::new(BrotliEncoder::with_quality(reader, Level::Precise(4))),
+            Encoding::Gzip => Box::new(GzipEncoder::new(reader)),
+        }
+    }
+}
+
+/// Picks the encoding the client prefers from an Accept-Encoding header, honouring q-values and
+/// `*`. Returns `None` if no supported encoding is acceptable or identity is preferred.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    negotiate_among(accept_encoding, &Encoding::ALL)
+}
+
+/// Like [`negotiate`], but picks only among `available`, e.g. the precompressed variants of a
+/// file.
+pub fn negotiate_among(accept_encoding: &str, available: &[Encoding]) -> Option<Encoding> {
+    let mut preferences: Vec<(&str, f32)> = vec![];
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default();
+        if coding.is_empty() {
+            continue;
+        }
+        let quality = parts
+            .find_map(|param| {
+                param
+                    .strip_prefix("q=")
+                    .or_else(|| param.strip_prefix("Q="))
+            })
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+            .unwrap_or(0.0);
+        preferences.push((coding, quality.clamp(0.0, 1.0)));
+    }
+    let quality_of = |coding: &str| {
+        preferences
+            .iter()
+            .find(|(accepted, _)| accepted.eq_ignore_ascii_case(coding))
+            .or_else(|| preferences.iter().find(|(accepted, _)| *accepted == "*"))
+            .map(|(_, quality)| *quality)
+    };
+
+    let (encoding, quality) = Encoding::ALL
+        .into_iter()
+        .filter(|encoding| available.contains(encoding))
+        .filter_map(|encoding| Some((encoding, quality_of(encoding.as_str())?)))
+        .filter(|(_, quality)| *quality > 0.0)
+        // max_by keeps the last of equal elements, so iterate in reverse preference order
+        .rev()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    // identity is only weighed in when the client ranks it explicitly
+    let identity = preferences
+        .iter()
+        .find(|(accepted, _)| accepted.eq_ignore_ascii_case("identity"))
+        .map(|(_, quality)| *quality);
+    if identity.is_some_and(|identity| identity > quality) {
+        return None;
+    }
+    Some(encoding)
+}
+
+/// Middleware compressing responses with zstd, brotli or gzip, whichever the request's
+/// Accept-Encoding prefers. Only bodies of compressible content types and at least `min_size`
+/// bytes are compressed.
+pub struct Compression {
+    min_size: u64,
+    content_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: 256,
+            content_types: COMPRESSIBLE_TYPES.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression::default()
+    }
+
+    /// Leaves bodies smaller than `bytes` uncompressed, where the encoding overhead outweighs
+    /// the savings. Streams of unknown length are always compressed.
+    pub fn min_size(mut self, bytes: u64) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Replaces the content types that are compressed, e.g. `["text/*", "application/json"]`.
+    pub fn content_types(mut self, types: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.content_types = types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    fn is_compressible(&self, content_type: &str) -> bool {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        self.content_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix('*') {
+                Some(prefix) => essence
+                    .get(..prefix.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(prefix)),
+                None => essence.eq_ignore_ascii_case(allowed),
+            })
+    }
+
+    /// Compresses the response body, buffered or streamed, with the encoding negotiated from
+    /// `accept_encoding`. Responses of compressible types vary by Accept-Encoding even when they
+    /// go out uncompressed, so caches don't hand the identity body to clients accepting gzip.
+    pub async fn compress(
+        &self,
+        accept_encoding: Option<&str>,
+        response: &mut HttpResponse,
+    ) -> Result<()> {
+        // Content-Range offsets refer to the identity encoding, so partial bodies stay
+        // uncompressed, and the encoder would hold back server-sent events until its buffer fills
+        if response.headers.contains_key("Content-Encoding")
+            || response.headers.contains_key("Content-Range")
+        {
+            return Ok(());
+        }
+        match response.headers.get("Content-Type") {
+            Some(content_type)
+                if self.is_compressible(content_type)
+                    && !content_type.starts_with("text/event-stream") => {}
+            _ => return Ok(()),
+        }
+        vary_by_accept_encoding(response);
+        let Some(encoding) = accept_encoding.and_then(negotiate) else {
+            return Ok(());
+        };
+        let length = match &response.body {
+            Body::Full(body) => Some(body.len() as u64),
+            Body::Stream(_) => response
+                .headers
+                .get("Content-Length")
+                .and_then(|length| length.parse().ok()),
+        };
+        if length.is_some_and(|length| length == 0 || length < self.min_size) {
+            return Ok(());
+        }
+
+        let body = std::mem::replace(&mut response.body, Body::Full(vec![]));
+        match body {
+            Body::Full(body) => {
+                let mut compressed = vec![];
+                encoding
+                    .encode(std::io::Cursor::new(body))
+                    .read_to_end(&mut compressed)
+                    .await
+                    .context("Unable to compress body")?;
+                if response.trailers.is_none() {
+                    let length = compressed.len().to_string();
+                    response.set_header("Content-Length".to_string(), length);
+                }
+                response.set_body(compressed);
+            }
+            Body::Stream(reader) => {
+                // the compressed length is unknown up front, so the body is sent chunked
+                response.headers.remove("Content-Length");
+                response.set_stream(encoding.encode(reader));
+            }
+        }
+        response.set_header(
+            "Content-Encoding".to_string(),
+            encoding.as_str().to_string(),
+        );
+        // the digest of the file no longer matches what is sent, and the encoded bytes differ
+        // from those a strong ETag vouches for
+        response.headers.remove("Digest");
+        if let Some(etag) = response.headers.get("ETag")
+            && !etag.starts_with("W/")
+        {
+            let weak = format!("W/{etag}");
+            response.set_header("ETag".to_string(), weak);
+        }
+        Ok(())
+    }
+}
+
+/// Adds Accept-Encoding to the response's Vary header. Other Vary values set by the handler
+/// still apply, like that of a file with precompressed variants.
+fn vary_by_accept_encoding(response: &mut HttpResponse) {
+    let varies = response.headers.get_all("Vary").any(|vary| {
+        vary.split(',')
+            .any(|field| field.trim().eq_ignore_ascii_case("Accept-Encoding"))
+    });
+    if !varies {
+        response
+            .headers
+            .append("Vary".to_string(), "Accept-Encoding".to_string());
+    }
+}
+
+impl<S: Send + Sync + 'static> Middleware<S> for Compression {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        state: Arc<S>,
+        next: Next<'a, S>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let accept_encoding = request.headers.get("Accept-Encoding").map(str::to_string);
+            let mut response = next.run(request, state).await?;
+            self.compress(accept_encoding.as_deref(), &mut response)
+                .await?;
+            Ok(response)
+        })
+    }
+}
+
+#[test]
+fn tests_negotiate() {
+    assert_eq!(Some(Encoding::Gzip), negotiate("invalid-encoding-1, gzip"));
+    assert_eq!(Some(Encoding::Zstd), negotiate("gzip, deflate, br, zstd"));
+    assert_eq!(
+        Some(Encoding::Brotli),
+        negotiate("gzip;q=0.8, br, zstd;q=0.5")
+    );
+    assert_eq!(Some(Encoding::Gzip), negotiate("br;q=0, zstd;q=0, *;q=0.1"));
+    assert_eq!(Some(Encoding::Zstd), negotiate("*"));
+    assert_eq!(None, negotiate("gzip;q=0"));
+    assert_eq!(None, negotiate("gzip;q=0.5, identity"));
+    assert_eq!(None, negotiate("deflate"));
+    assert_eq!(
+        Some(Encoding::Gzip),
+        negotiate_among("zstd, gzip;q=0.5", &[Encoding::Brotli, Encoding::Gzip])
+    );
+    assert_eq!(None, negotiate_among("zstd, br", &[Encoding::Gzip]));
+}
+
+#[tokio::test]
+async fn tests_compress() {
+    use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder};
+
+    let text = "abc".repeat(100);
+    let response = || {
+        let mut response = HttpResponse::ok();
+        response.set_header("Content-Type".to_string(), "text/plain".to_string());
+        response.set_body(text.clone().into_bytes());
+        response
+    };
+    let compression = Compression::new();
+
+    let mut gzipped = response();
+    gzipped.set_header("ETag".to_string(), "\"abc\"".to_string());
+    compression
+        .compress(Some("invalid-encoding-1, gzip"), &mut gzipped)
+        .await
+        .unwrap();
+    assert_eq!(Some("gzip"), gzipped.headers.get("Content-Encoding"));
+    assert_eq!(Some("W/\"abc\""), gzipped.headers.get("ETag"));
+    let mut decoded = String::new();
+    GzipDecoder::new(gzipped.body.as_bytes().unwrap())
+        .read_to_string(&mut decoded)
+        .await
+        .unwrap();
+    assert_eq!(text, decoded);
+
+    let mut brotli = response();
+    compression
+        .compress(Some("gzip;q=0.5, br"), &mut brotli)
+        .await
+        .unwrap();
+    assert_eq!(Some("br"), brotli.headers.get("Content-Encoding"));
+    let mut decoded = String::new();
+    BrotliDecoder::new(brotli.body.as_bytes().unwrap())
+        .read_to_string(&mut decoded)
+        .await
+        .unwrap();
+    assert_eq!(text, decoded);
+
+    let mut unsupported = response();
+    compression
+        .compress(Some("invalid-encoding-1"), &mut unsupported)
+        .await
+        .unwrap();
+    assert_eq!(None, unsupported.headers.get("Content-Encoding"));
+    assert_eq!(Some(text.as_bytes()), unsupported.body.as_bytes());
+
+    let mut small = response();
+    Compression::new()
+        .min_size(1024)
+        .compress(Some("gzip"), &mut small)
+        .await
+        .unwrap();
+    assert_eq!(None, small.headers.get("Content-Encoding"));
+    // the compressed variant exists all the same
+    assert_eq!(Some("Accept-Encoding"), small.headers.get("Vary"));
+    let mut identity = response();
+    compression.compress(None, &mut identity).await.unwrap();
+    assert_eq!(None, identity.headers.get("Content-Encoding"));
+    assert_eq!(Some("Accept-Encoding"), identity.headers.get("Vary"));
+
+    let mut image = response();
+    image.set_header("Content-Type".to_string(), "image/png".to_string());
+    compression
+        .compress(Some("gzip"), &mut image)
+        .await
+        .unwrap();
+    assert_eq!(None, image.headers.get("Content-Encoding"));
+    assert_eq!(None, image.headers.get("Vary"));
+    assert!(compression.is_compressible("text/html; charset=utf-8"));
+    assert!(compression.is_compressible("application/JSON"));
+}
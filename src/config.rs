@@ -0,0 +1,541 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+use tokio::sync::watch;
+use tracing::level_filters::LevelFilter;
+
+use crate::access_log::LogFormat;
+use crate::error_pages::ErrorPage;
+use crate::forwarded::Network;
+use crate::proxy::ProxyConfig;
+use crate::rewrite::{RedirectRule, RewriteRule};
+use crate::static_files::{CacheControlRule, SymlinkPolicy};
+use crate::timeout::TimeoutRule;
+use crate::vhost::VirtualHostConfig;
+
+/// What happens to new connections once `max_connections` are open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overload {
+    /// Leave them in the listen backlog until a connection closes.
+    #[default]
+    Queue,
+    /// Answer them with 503 Service Unavailable and close them.
+    Reject,
+}
+
+impl FromStr for Overload {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "queue" => Ok(Overload::Queue),
+            "reject" => Ok(Overload::Reject),
+            _ => Err(format!(
+                "unknown overload policy {policy:?}, expected queue or reject"
+            )),
+        }
+    }
+}
+
+/// Everything the server and its built-in middleware can be configured with, loadable from a TOML
+/// file with [`ServerConfig::load`]. Durations are given in seconds there, and every key is
+/// optional.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Addresses listened on, each a host listened on at `port` or a "host:port" pair like
+    /// "[::]:8080". A single address may be given as a string.
+    #[serde(alias = "address", deserialize_with = "one_or_many")]
+    pub addresses: Vec<String>,
+    pub port: u16,
+    /// A Unix domain socket path listened on instead of `address` and `port`.
+    pub unix_socket: Option<String>,
+    #[serde(alias = "directory")]
+    pub static_directory: Option<String>,
+    /// Whether directories below `static_directory` are answered with an index of their contents.
+    pub dir_listing: bool,
+    /// File extensions (without the dot, in any case) the /files routes serve and accept
+    /// uploads for, any if empty.
+    pub allowed_extensions: Vec<String>,
+    /// File extensions the /files routes refuse, even if allowed.
+    pub denied_extensions: Vec<String>,
+    /// Whether files and directories whose name starts with a dot, like .env or .git, are
+    /// served and written rather than refused.
+    pub allow_dotfiles: bool,
+    /// Which symlinks inside `static_directory` are followed: "never", "same-dir" (those
+    /// pointing inside it, the default) or "always".
+    #[serde(deserialize_with = "from_str")]
+    pub follow_symlinks: SymlinkPolicy,
+    /// Whether file responses carry a Digest header with the SHA-256 of the file, which takes
+    /// reading the whole file for every response.
+    pub file_digests: bool,
+    /// Whether a file's precompressed sibling, like app.js.br or app.js.gz next to app.js, is
+    /// served in its place to clients accepting that encoding.
+    pub precompressed: bool,
+    /// Bytes of small, recently requested static files kept in memory, 0 disables the cache.
+    pub file_cache_size: usize,
+    /// Cache-Control headers for static files, by path or extension.
+    pub cache_control: Vec<CacheControlRule>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// Whether clients may speak HTTP/2, negotiated through ALPN, announced with the connection
+    /// preface or by upgrading to h2c.
+    pub http2: bool,
+    /// How long in-flight connections may keep running after a shutdown signal.
+    #[serde(deserialize_with = "seconds")]
+    pub grace_period: Duration,
+    /// How long a client may take to send a request's header block, starting at connection
+    /// accept or the first byte of a keep-alive request.
+    #[serde(deserialize_with = "seconds")]
+    pub header_timeout: Duration,
+    /// How long a client may take to send the body once the header block has arrived.
+    #[serde(deserialize_with = "seconds")]
+    pub body_timeout: Duration,
+    /// How long a keep-alive connection may sit idle between requests.
+    #[serde(deserialize_with = "seconds")]
+    pub keep_alive_timeout: Duration,
+    /// Requests served on one connection before it is closed, unlimited if `None`.
+    pub max_keep_alive_requests: Option<usize>,
+    /// How long handlers may take to produce a response before the
+    /// [`Timeouts`](crate::timeout::Timeouts) layer answers 503 instead, unlimited if `None`.
+    #[serde(deserialize_with = "optional_seconds")]
+    pub handler_timeout: Option<Duration>,
+    /// Handler timeouts for path prefixes, overriding `handler_timeout`.
+    pub handler_timeouts: Vec<TimeoutRule>,
+    /// Largest accepted request line and header block in bytes, larger ones get a 431.
+    pub max_header_size: usize,
+    /// Whether header values folded onto lines starting with whitespace are unfolded rather
+    /// than rejected with 400.
+    pub allow_obs_fold: bool,
+    /// Largest accepted request body in bytes, larger ones get a 413.
+    pub max_body_size: usize,
+    /// Upper bound on concurrently served connections, unlimited if `None`.
+    pub max_connections: Option<usize>,
+    #[serde(deserialize_with = "from_str")]
+    pub overload: Overload,
+    /// Content types for file extensions (lowercase, without the dot), overriding the built-in
+    /// table.
+    pub mime_types: HashMap<String, String>,
+    /// The most verbose level logged, e.g. "info" or "debug".
+    #[serde(deserialize_with = "from_str")]
+    pub log_level: LevelFilter,
+    #[serde(deserialize_with = "from_str")]
+    pub log_format: LogFormat,
+    /// Whether Prometheus metrics are served at /metrics.
+    pub metrics: bool,
+    /// Whether TRACE requests are echoed back by the [`Trace`](crate::method::Trace) layer.
+    pub trace: bool,
+    /// Addresses or CIDR blocks of proxies whose Forwarded and X-Forwarded-For headers are
+    /// believed, see [`HttpRequest::remote_addr`](crate::HttpRequest::remote_addr).
+    pub trusted_proxies: Vec<Network>,
+    /// Requests per second allowed per client IP, unlimited if `None`.
+    pub rate_limit: Option<f64>,
+    /// Requests a client may burst above `rate_limit`.
+    pub rate_burst: u32,
+    /// An htpasswd file whose users may authenticate with Basic auth.
+    pub htpasswd: Option<String>,
+    /// Tokens accepted as Bearer credentials.
+    pub bearer_tokens: Vec<String>,
+    /// Path prefixes that require authentication, all paths if empty. Authentication is only
+    /// enabled when `htpasswd` or `bearer_tokens` is set.
+    pub auth_paths: Vec<String>,
+    /// Per-host settings, applied by the [`VirtualHosts`](crate::vhost::VirtualHosts) layer.
+    pub vhosts: Vec<VirtualHostConfig>,
+    /// Path prefixes forwarded to upstream servers by [`Proxy`](crate::proxy::Proxy) layers.
+    pub proxies: Vec<ProxyConfig>,
+    /// `host:port` patterns the [`Tunnel`](crate::tunnel::Tunnel) layer lets CONNECT reach,
+    /// CONNECT is not served if empty.
+    pub connect_targets: Vec<String>,
+    /// Whether absolute-form requests are forwarded to the origin server they name by the
+    /// [`ForwardProxy`](crate::proxy::ForwardProxy) layer.
+    pub proxy_mode: bool,
+    /// Redirects answered before routing by the [`Rewrites`](crate::rewrite::Rewrites) layer.
+    pub redirects: Vec<RedirectRule>,
+    /// Internal path rewrites applied before routing by the same layer.
+    pub rewrites: Vec<RewriteRule>,
+    /// Bodies for error responses, filled in by the [`ErrorPages`](crate::error_pages::ErrorPages)
+    /// layer.
+    pub error_pages: Vec<ErrorPage>,
+}
+
+impl fmt::Debug for ServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the config is logged on startup, so secrets are left out
+        f.debug_struct("ServerConfig")
+            .field("addresses", &self.addresses)
+            .field("port", &self.port)
+            .field("unix_socket", &self.unix_socket)
+            .field("static_directory", &self.static_directory)
+            .field("dir_listing", &self.dir_listing)
+            .field("allowed_extensions", &self.allowed_extensions)
+            .field("denied_extensions", &self.denied_extensions)
+            .field("allow_dotfiles", &self.allow_dotfiles)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("file_digests", &self.file_digests)
+            .field("precompressed", &self.precompressed)
+            .field("file_cache_size", &self.file_cache_size)
+            .field("cache_control", &self.cache_control)
+            .field("tls_cert", &self.tls_cert)
+            .field("tls_key", &self.tls_key)
+            .field("http2", &self.http2)
+            .field("grace_period", &self.grace_period)
+            .field("header_timeout", &self.header_timeout)
+            .field("body_timeout", &self.body_timeout)
+            .field("keep_alive_timeout", &self.keep_alive_timeout)
+            .field("max_keep_alive_requests", &self.max_keep_alive_requests)
+            .field("handler_timeout", &self.handler_timeout)
+            .field("handler_timeouts", &self.handler_timeouts)
+            .field("max_header_size", &self.max_header_size)
+            .field("allow_obs_fold", &self.allow_obs_fold)
+            .field("max_body_size", &self.max_body_size)
+            .field("max_connections", &self.max_connections)
+            .field("overload", &self.overload)
+            .field("mime_types", &self.mime_types)
+            .field("log_level", &self.log_level)
+            .field("log_format", &self.log_format)
+            .field("metrics", &self.metrics)
+            .field("trace", &self.trace)
+            .field("trusted_proxies", &self.trusted_proxies)
+            .field("rate_limit", &self.rate_limit)
+            .field("rate_burst", &self.rate_burst)
+            .field("htpasswd", &self.htpasswd)
+            .field(
+                "bearer_tokens",
+                &format_args!("<{} redacted>", self.bearer_tokens.len()),
+            )
+            .field("auth_paths", &self.auth_paths)
+            .field("vhosts", &self.vhosts)
+            .field("proxies", &self.proxies)
+            .field("connect_targets", &self.connect_targets)
+            .field("proxy_mode", &self.proxy_mode)
+            .field("redirects", &self.redirects)
+            .field("rewrites", &self.rewrites)
+            .field("error_pages", &self.error_pages)
+            .finish()
+    }
+}
+
+impl ServerConfig {
+    /// Reads a TOML config file, keys missing from it keep their defaults.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read config file {}", path.display()))?;
+        ServerConfig::from_toml(&contents)
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        let mut config: ServerConfig = toml::from_str(contents)?;
+        config.mime_types = config
+            .mime_types
+            .into_iter()
+            .map(|(extension, content_type)| {
+                (
+                    extension.trim_start_matches('.').to_ascii_lowercase(),
+                    content_type,
+                )
+            })
+            .collect();
+        Ok(config)
+    }
+}
+
+/// The configuration of a running server. Connections pick up a reloaded config with their next
+/// request.
+#[derive(Clone)]
+pub struct ConfigHandle {
+    tx: Arc<watch::Sender<Arc<ServerConfig>>>,
+}
+
+impl ConfigHandle {
+    pub fn new(config: ServerConfig) -> Self {
+        ConfigHandle {
+            tx: Arc::new(watch::Sender::new(Arc::new(config))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.tx.borrow().clone()
+    }
+
+    /// Swaps in `config`, except for the settings that only take effect on a restart, which keep
+    /// their current values. Returns the names of those that differed.
+    pub fn reload(&self, mut config: ServerConfig) -> Vec<&'static str> {
+        let current = self.current();
+        let mut ignored = vec![];
+        macro_rules! keep {
+            ($($field:ident),*) => {
+                $(if config.$field != current.$field {
+                    ignored.push(stringify!($field));
+                    config.$field = current.$field.clone();
+                })*
+            };
+        }
+        // the listener, TLS acceptor and middleware are set up once at startup
+        keep!(
+            addresses,
+            port,
+            unix_socket,
+            tls_cert,
+            tls_key,
+            http2,
+            max_connections,
+            overload,
+            log_format,
+            metrics,
+            rate_limit,
+            rate_burst,
+            htpasswd,
+            bearer_tokens,
+            auth_paths,
+            proxies
+        );
+        self.tx.send_replace(Arc::new(config));
+        ignored
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(address) => vec![address],
+        OneOrMany::Many(addresses) => addresses,
+    })
+}
+
+pub(crate) fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+fn optional_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    seconds(deserializer).map(Some)
+}
+
+/// Deserializes a string through the type's `FromStr`, so the config file accepts the same values
+/// as the command line.
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            addresses: vec!["127.0.0.1".to_string()],
+            port: 4221,
+            unix_socket: None,
+            static_directory: None,
+            dir_listing: false,
+            allowed_extensions: vec![],
+            denied_extensions: vec![],
+            allow_dotfiles: false,
+            follow_symlinks: SymlinkPolicy::SameDir,
+            file_digests: false,
+            precompressed: false,
+            file_cache_size: 0,
+            cache_control: vec![],
+            tls_cert: None,
+            tls_key: None,
+            http2: true,
+            grace_period: Duration::from_secs(30),
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(30),
+            keep_alive_timeout: Duration::from_secs(5),
+            max_keep_alive_requests: None,
+            handler_timeout: None,
+            handler_timeouts: vec![],
+            max_header_size: 8 * 1024,
+            allow_obs_fold: false,
+            max_body_size: 16 * 1024 * 1024,
+            max_connections: None,
+            overload: Overload::Queue,
+            mime_types: HashMap::new(),
+            log_level: LevelFilter::INFO,
+            log_format: LogFormat::Common,
+            metrics: false,
+            trace: false,
+            trusted_proxies: vec![],
+            rate_limit: None,
+            rate_burst: 10,
+            htpasswd: None,
+            bearer_tokens: vec![],
+            auth_paths: vec![],
+            vhosts: vec![],
+            proxies: vec![],
+            connect_targets: vec![],
+            proxy_mode: false,
+            redirects: vec![],
+            rewrites: vec![],
+            error_pages: vec![],
+        }
+    }
+}
+
+#[test]
+fn tests_from_toml() {
+    let config = ServerConfig::from_toml(
+        r#"
+        address = "0.0.0.0"
+        port = 8080
+        unix_socket = "/run/http.sock"
+        directory = "/srv/files"
+        file_cache_size = 65536
+        allowed_extensions = ["html", "css"]
+        denied_extensions = ["bak"]
+        allow_dotfiles = true
+        follow_symlinks = "never"
+        file_digests = true
+        precompressed = true
+        keep_alive_timeout = 15
+        max_keep_alive_requests = 1000
+        handler_timeout = 30
+        allow_obs_fold = true
+        max_connections = 100
+        overload = "reject"
+        log_level = "debug"
+        log_format = "json"
+        metrics = true
+        trace = true
+        connect_targets = ["*.example.com:443"]
+        proxy_mode = true
+        trusted_proxies = ["10.0.0.0/8", "::1"]
+        http2 = false
+
+        [mime_types]
+        ".MD" = "text/markdown"
+
+        [[cache_control]]
+        extensions = ["html"]
+        value = "no-cache"
+
+        [[vhosts]]
+        hosts = ["blog.example", "*.blog.example"]
+        directory = "/srv/blog"
+
+        [[proxies]]
+        path = "/api"
+        upstream = "http://127.0.0.1:3000"
+
+        [[redirects]]
+        from = "/old/*"
+        to = "/new/*"
+
+        [[rewrites]]
+        from = "/"
+        to = "/files/index.html"
+
+        [[error_pages]]
+        status = 404
+        file = "404.html"
+
+        [[handler_timeouts]]
+        path = "/upload"
+        timeout = 300
+        "#,
+    )
+    .unwrap();
+    assert_eq!(vec!["0.0.0.0"], config.addresses);
+    assert_eq!(8080, config.port);
+    assert_eq!(Some("/run/http.sock".to_string()), config.unix_socket);
+    assert_eq!(Some("/srv/files".to_string()), config.static_directory);
+    assert_eq!(65536, config.file_cache_size);
+    assert_eq!(vec!["html", "css"], config.allowed_extensions);
+    assert_eq!(vec!["bak"], config.denied_extensions);
+    assert!(config.allow_dotfiles);
+    assert_eq!(SymlinkPolicy::Never, config.follow_symlinks);
+    assert!(config.file_digests);
+    assert!(config.precompressed);
+    assert_eq!(vec!["html"], config.cache_control[0].extensions);
+    assert_eq!("no-cache", config.cache_control[0].value);
+    assert_eq!(Duration::from_secs(15), config.keep_alive_timeout);
+    assert_eq!(Some(1000), config.max_keep_alive_requests);
+    assert_eq!(Duration::from_secs(10), config.header_timeout);
+    assert_eq!(Some(Duration::from_secs(30)), config.handler_timeout);
+    assert_eq!(Duration::from_secs(300), config.handler_timeouts[0].timeout);
+    assert!(config.allow_obs_fold);
+    assert_eq!(Some(100), config.max_connections);
+    assert_eq!(Overload::Reject, config.overload);
+    assert_eq!(LevelFilter::DEBUG, config.log_level);
+    assert_eq!(LogFormat::Json, config.log_format);
+    assert!(config.metrics);
+    assert!(config.trace);
+    assert_eq!(vec!["*.example.com:443"], config.connect_targets);
+    assert!(config.proxy_mode);
+    assert_eq!(
+        vec!["10.0.0.0/8", "::1/128"],
+        config
+            .trusted_proxies
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+    );
+    assert!(!config.http2);
+    assert_eq!(
+        Some("text/markdown"),
+        config.mime_types.get("md").map(String::as_str)
+    );
+    assert_eq!(
+        vec![VirtualHostConfig {
+            hosts: vec!["blog.example".to_string(), "*.blog.example".to_string()],
+            static_directory: Some("/srv/blog".to_string()),
+            dir_listing: None,
+        }],
+        config.vhosts
+    );
+    assert_eq!("http://127.0.0.1:3000", config.proxies[0].upstream);
+    assert!(!config.proxies[0].strip_prefix);
+    assert_eq!(
+        crate::StatusCode::MovedPermanently,
+        config.redirects[0].status
+    );
+    assert_eq!("/files/index.html", config.rewrites[0].to);
+    assert_eq!(Some("404.html".to_string()), config.error_pages[0].file);
+
+    let config = ServerConfig::from_toml(r#"addresses = ["0.0.0.0:80", "[::]:8080"]"#).unwrap();
+    assert_eq!(vec!["0.0.0.0:80", "[::]:8080"], config.addresses);
+
+    let error = ServerConfig::from_toml("overload = \"drop\"").unwrap_err();
+    assert!(format!("{error:#}").contains("unknown overload policy"));
+    assert!(ServerConfig::from_toml("prot = 80").is_err());
+    let error = ServerConfig::from_toml("[[redirects]]\nstatus = 303").unwrap_err();
+    assert!(format!("{error:#}").contains("invalid redirect status 303"));
+}
+
+#[test]
+fn tests_reload() {
+    let handle = ConfigHandle::new(ServerConfig::default());
+    let ignored = handle.reload(ServerConfig {
+        port: 8080,
+        static_directory: Some("/srv".to_string()),
+        max_body_size: 1024,
+        log_level: LevelFilter::DEBUG,
+        ..Default::default()
+    });
+    assert_eq!(vec!["port"], ignored);
+
+    let current = handle.current();
+    assert_eq!(4221, current.port);
+    assert_eq!(Some("/srv".to_string()), current.static_directory);
+    assert_eq!(1024, current.max_body_size);
+    assert_eq!(LevelFilter::DEBUG, current.log_level);
+}
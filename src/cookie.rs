@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie to send in a Set-Cookie header, see [`HttpResponse::add_cookie`].
+///
+/// [`HttpResponse::add_cookie`]: crate::HttpResponse::add_cookie
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// A session cookie; `value` is sent as-is, so it must not contain `;`, `,`, whitespace or
+    /// quotes.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that makes the client delete a previously set cookie called `name`.
+    pub fn removal(name: impl Into<String>) -> Self {
+        Cookie::new(name, "").max_age(Duration::ZERO)
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/// Formats the cookie as a Set-Cookie header value.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        // browsers only accept SameSite=None on secure cookies
+        if self.secure || self.same_site == Some(SameSite::None) {
+            f.write_str("; Secure")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => f.write_str("; SameSite=Strict")?,
+            Some(SameSite::Lax) => f.write_str("; SameSite=Lax")?,
+            Some(SameSite::None) => f.write_str("; SameSite=None")?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Parses a Cookie request header like `a=1; b="2"`. The first of several cookies with the same
+/// name wins, as clients send the most specific one first.
+pub fn parse(header: &str) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    for pair in header.split(';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        cookies
+            .entry(name.to_string())
+            .or_insert_with(|| value.to_string());
+    }
+    cookies
+}
+
+#[test]
+fn tests_set_cookie() {
+    assert_eq!("id=abc", Cookie::new("id", "abc").to_string());
+    assert_eq!(
+        "id=abc; Path=/; Domain=example.com; Max-Age=3600; HttpOnly; Secure; SameSite=Lax",
+        Cookie::new("id", "abc")
+            .path("/")
+            .domain("example.com")
+            .max_age(Duration::from_secs(3600))
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Lax)
+            .to_string()
+    );
+    assert_eq!(
+        "id=; Max-Age=0; Secure; SameSite=None",
+        Cookie::removal("id").same_site(SameSite::None).to_string()
+    );
+}
+
+#[test]
+fn tests_parse_cookies() {
+    let cookies = parse("id=abc; theme=\"dark\";empty=; id=shadowed; broken; =x");
+    assert_eq!(Some("abc"), cookies.get("id").map(String::as_str));
+    assert_eq!(Some("dark"), cookies.get("theme").map(String::as_str));
+    assert_eq!(Some(""), cookies.get("empty").map(String::as_str));
+    assert_eq!(3, cookies.len());
+}
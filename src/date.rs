@@ -0,0 +1,132 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+struct Civil {
+    weekday: usize,
+    year: i64,
+    month: usize,
+    day: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+}
+
+fn civil(time: SystemTime) -> Civil {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // days-to-civil conversion from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u64;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as usize;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    Civil {
+        // 1970-01-01 was a Thursday
+        weekday: ((days + 4) % 7) as usize,
+        year,
+        month,
+        day,
+        hour: rem / 3600,
+        minute: rem % 3600 / 60,
+        second: rem % 60,
+    }
+}
+
+/// Formats `time` like `[10/Oct/2000:13:55:36 +0000]` for Common Log Format lines.
+pub fn common_log(time: SystemTime) -> String {
+    let c = civil(time);
+    format!(
+        "[{:02}/{}/{}:{:02}:{:02}:{:02} +0000]",
+        c.day,
+        MONTHS[c.month - 1],
+        c.year,
+        c.hour,
+        c.minute,
+        c.second
+    )
+}
+
+/// Formats `time` as an HTTP-date like `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    let c = civil(time);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[c.weekday],
+        c.day,
+        MONTHS[c.month - 1],
+        c.year,
+        c.hour,
+        c.minute,
+        c.second
+    )
+}
+
+/// Parses an HTTP-date in the preferred IMF-fixdate format. The obsolete RFC 850 and asctime
+/// formats are not supported and yield `None`.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let (_weekday, rest) = value.trim().split_once(", ")?;
+    let parts: Vec<&str> = rest.split(' ').collect();
+    let [day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year: i64 = year.parse().ok()?;
+    let mut hms = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+    if hms.next().is_some() || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // civil-to-days conversion, the inverse of `civil`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146097 + doe - 719468).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second))
+}
+
+#[test]
+fn tests_common_log() {
+    assert_eq!(
+        "[10/Oct/2000:13:55:36 +0000]",
+        common_log(UNIX_EPOCH + Duration::from_secs(971_186_136))
+    );
+    assert_eq!(
+        "[29/Feb/2024:00:00:00 +0000]",
+        common_log(UNIX_EPOCH + Duration::from_secs(1_709_164_800))
+    );
+}
+
+#[test]
+fn tests_http_date() {
+    let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+    assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", http_date(time));
+    assert_eq!(Some(time), parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"));
+
+    let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_164_800);
+    assert_eq!("Thu, 29 Feb 2024 00:00:00 GMT", http_date(leap_day));
+    assert_eq!(Some(leap_day), parse_http_date(&http_date(leap_day)));
+
+    assert_eq!(None, parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"));
+    assert_eq!(None, parse_http_date("Sun Nov  6 08:49:37 1994"));
+    assert_eq!(None, parse_http_date("Sun, 06 Nov 1994 25:49:37 GMT"));
+}
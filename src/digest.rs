@@ -0,0 +1,272 @@
+//! Digests of file contents: the RFC 3230 Digest header of file responses, and the Digest or
+//! Content-MD5 header an upload is checked against.
+
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ring::digest;
+use tokio::io::AsyncReadExt;
+
+use crate::headers::HeaderMap;
+
+/// The Digest header value of `data`, its SHA-256.
+pub(crate) fn sha256(data: &[u8]) -> String {
+    let digest = digest::digest(&digest::SHA256, data);
+    format!("sha-256={}", BASE64.encode(digest.as_ref()))
+}
+
+/// The Digest header value of the file at `path`, read in pieces rather than all at once.
+pub(crate) async fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buf[..read]);
+    }
+    Ok(format!(
+        "sha-256={}",
+        BASE64.encode(context.finish().as_ref())
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Algorithm {
+    Sha256,
+    Md5,
+}
+
+/// Checks a body against the digests its request claims, hashing it piece by piece as it is
+/// written.
+pub(crate) struct Verifier {
+    expected: Vec<(Algorithm, Vec<u8>)>,
+    sha256: Option<digest::Context>,
+    md5: Option<Md5>,
+}
+
+impl Verifier {
+    /// The verifier for the Digest and Content-MD5 headers, `None` if there are neither.
+    /// Digest algorithms other than SHA-256 and MD5 are ignored.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Result<Option<Verifier>, String> {
+        let mut expected = vec![];
+        let mut add = |algorithm, value: &str, header: &str| {
+            let len = match algorithm {
+                Algorithm::Sha256 => 32,
+                Algorithm::Md5 => 16,
+            };
+            match BASE64.decode(value.trim()) {
+                Ok(digest) if digest.len() == len => {
+                    expected.push((algorithm, digest));
+                    Ok(())
+                }
+                _ => Err(format!("invalid {header} {value:?}")),
+            }
+        };
+        for value in headers.get_all("Content-MD5") {
+            add(Algorithm::Md5, value, "Content-MD5")?;
+        }
+        for element in headers.get_all("Digest").flat_map(|value| value.split(',')) {
+            let Some((name, value)) = element.split_once('=') else {
+                return Err(format!("invalid Digest {element:?}"));
+            };
+            let algorithm = match name.trim().to_ascii_lowercase().as_str() {
+                "sha-256" => Algorithm::Sha256,
+                "md5" => Algorithm::Md5,
+                _ => continue,
+            };
+            add(algorithm, value, "Digest")?;
+        }
+        if expected.is_empty() {
+            return Ok(None);
+        }
+        let uses = |wanted| expected.iter().any(|(algorithm, _)| *algorithm == wanted);
+        Ok(Some(Verifier {
+            sha256: uses(Algorithm::Sha256).then(|| digest::Context::new(&digest::SHA256)),
+            md5: uses(Algorithm::Md5).then(Md5::new),
+            expected,
+        }))
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        if let Some(sha256) = &mut self.sha256 {
+            sha256.update(data);
+        }
+        if let Some(md5) = &mut self.md5 {
+            md5.update(data);
+        }
+    }
+
+    /// Whether the body matched every digest.
+    pub(crate) fn matches(self) -> bool {
+        let sha256 = self
+            .sha256
+            .map(|context| context.finish().as_ref().to_vec());
+        let md5 = self.md5.map(|md5| md5.finish().to_vec());
+        self.expected
+            .iter()
+            .all(|(algorithm, expected)| match algorithm {
+                Algorithm::Sha256 => sha256.as_ref() == Some(expected),
+                Algorithm::Md5 => md5.as_ref() == Some(expected),
+            })
+    }
+}
+
+const SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// The integer parts of `abs(sin(i + 1)) * 2^32`.
+const CONSTANTS: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+/// MD5 (RFC 1321), which ring leaves out as broken. It is only used to detect corrupted
+/// uploads, where it still does the job.
+struct Md5 {
+    state: [u32; 4],
+    /// The bytes of an incomplete block.
+    block: Vec<u8>,
+    length: u64,
+}
+
+impl Md5 {
+    fn new() -> Self {
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            block: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let take = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.block.len() == 64 {
+                let block: [u8; 64] = self.block[..].try_into().expect("the block is full");
+                self.compress(&block);
+                self.block.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 16] {
+        let bits = self.length.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block.len() != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_le_bytes());
+        let mut digest = [0; 16];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let words: Vec<u32> = block
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(CONSTANTS[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[i]);
+            (a, b, c, d) = (d, b.wrapping_add(rotated), b, c);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[test]
+fn tests_md5() {
+    let md5 = |data: &[u8]| {
+        let mut md5 = Md5::new();
+        md5.update(data);
+        BASE64.encode(md5.finish())
+    };
+    assert_eq!("1B2M2Y8AsgTpgAmY7PhCfg==", md5(b""));
+    assert_eq!("kAFQmDzST7DWlj99KOF/cg==", md5(b"abc"));
+    let fox = b"The quick brown fox jumps over the lazy dog".repeat(3);
+    assert_eq!("TmfbSnpAawz9rdiHzeeIjg==", md5(&fox));
+
+    // the same digest whatever pieces the data arrives in
+    let mut pieces = Md5::new();
+    for piece in fox.chunks(7) {
+        pieces.update(piece);
+    }
+    assert_eq!("TmfbSnpAawz9rdiHzeeIjg==", BASE64.encode(pieces.finish()));
+}
+
+#[test]
+fn tests_verifier() {
+    let verify = |name: &str, value: &str, body: &[u8]| {
+        let mut headers = HeaderMap::new();
+        headers.insert(name.to_string(), value.to_string());
+        let mut verifier = Verifier::from_headers(&headers)?.expect("a digest was given");
+        verifier.update(body);
+        Ok::<_, String>(verifier.matches())
+    };
+
+    assert_eq!(
+        Ok(true),
+        verify("Content-MD5", "kAFQmDzST7DWlj99KOF/cg==", b"abc")
+    );
+    assert_eq!(
+        Ok(false),
+        verify("Content-MD5", "kAFQmDzST7DWlj99KOF/cg==", b"abd")
+    );
+    assert_eq!(
+        Ok(true),
+        verify(
+            "Digest",
+            "SHA-256=ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=, unixsum=30637",
+            b"abc"
+        )
+    );
+    assert_eq!(
+        Ok(false),
+        verify(
+            "Digest",
+            "sha-256=ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=,md5=1B2M2Y8AsgTpgAmY7PhCfg==",
+            b"abc"
+        )
+    );
+    assert!(verify("Content-MD5", "abc", b"abc").is_err());
+    assert!(verify("Digest", "sha-256", b"abc").is_err());
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Digest".to_string(), "unixsum=30637".to_string());
+    assert!(Verifier::from_headers(&headers).unwrap().is_none());
+    assert_eq!(
+        "sha-256=47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+        sha256(b"")
+    );
+}
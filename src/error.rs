@@ -0,0 +1,92 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::request::ParseError;
+use crate::response::HttpResponse;
+use crate::status::StatusCode;
+
+/// Why a request could not be served. Handlers may return one to choose the status they fail
+/// with, anything else they return is answered with a 500.
+#[derive(Debug, Error)]
+pub enum HttpError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    /// The client took too long to send the request.
+    #[error("request timed out")]
+    Timeout,
+    #[error("request body too large")]
+    PayloadTooLarge,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A failure answered with `status`, e.g. a handler rejecting a body it can't use.
+    #[error("{message}")]
+    Handler { status: StatusCode, message: String },
+}
+
+impl HttpError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        HttpError::Handler {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// The status the error is answered with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            HttpError::Parse(ParseError::Malformed(_)) => StatusCode::BadRequest,
+            HttpError::Parse(ParseError::HeadTooLarge) => StatusCode::RequestHeaderFieldsTooLarge,
+            HttpError::Parse(ParseError::BodyTooLarge) => StatusCode::ContentTooLarge,
+            HttpError::Parse(ParseError::UnsupportedVersion(_)) => {
+                StatusCode::HttpVersionNotSupported
+            }
+            HttpError::Timeout => StatusCode::RequestTimeout,
+            HttpError::PayloadTooLarge => StatusCode::ContentTooLarge,
+            HttpError::Io(_) => StatusCode::InternalServerError,
+            HttpError::Handler { status, .. } => *status,
+        }
+    }
+}
+
+/// Answers with the error's status. Client errors explain themselves in a plain text body,
+/// server errors don't give their cause away.
+impl From<HttpError> for HttpResponse {
+    fn from(error: HttpError) -> Self {
+        let status = error.status();
+        let mut resp = HttpResponse::new(status);
+        let explained = matches!(
+            error,
+            HttpError::Parse(ParseError::Malformed(_)) | HttpError::Handler { .. }
+        );
+        if explained && status.is_client_error() {
+            resp.set_header("Content-Type".to_string(), "text/plain".to_string());
+            resp.set_body(format!("{}: {error}\n", status.reason()).into_bytes());
+        }
+        resp
+    }
+}
+
+#[test]
+fn tests_http_error() {
+    let error = HttpError::from(ParseError::Malformed("invalid chunk size".to_string()));
+    assert_eq!(StatusCode::BadRequest, error.status());
+    let resp = HttpResponse::from(error);
+    assert_eq!(400, resp.status_code);
+    assert_eq!(
+        Some(&b"Bad Request: invalid chunk size\n"[..]),
+        resp.body.as_bytes()
+    );
+
+    let resp = HttpResponse::from(HttpError::Timeout);
+    assert_eq!(408, resp.status_code);
+    assert_eq!(Some(&b""[..]), resp.body.as_bytes());
+
+    let error = HttpError::new(StatusCode::Conflict, "already exists");
+    assert_eq!(StatusCode::Conflict, error.status());
+    assert_eq!("already exists", error.to_string());
+
+    let resp = HttpResponse::from(HttpError::from(io::Error::other("disk full")));
+    assert_eq!(500, resp.status_code);
+    assert_eq!(Some(&b""[..]), resp.body.as_bytes());
+}
@@ -0,0 +1,209 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::config::ServerConfig;
+use crate::middleware::{Middleware, Next};
+use crate::mime;
+use crate::request::HttpRequest;
+use crate::response::{Body, HttpResponse};
+use crate::router::BoxFuture;
+use crate::template::Template;
+
+/// An `[[error_pages]]` table of the config file. `file` and `text` are
+/// [templates](crate::template) in which `{{status}}`, `{{reason}}` and `{{path}}` are the
+/// response's status code, its reason phrase and the request path. Values are only HTML-escaped
+/// in HTML pages.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorPage {
+    /// The 4xx or 5xx status the page is shown for.
+    pub status: u16,
+    /// A file inside the static directory, e.g. "404.html", its type picked by its extension.
+    pub file: Option<String>,
+    /// A plain text body, used if `file` is not set.
+    pub text: Option<String>,
+}
+
+/// Fills in the bodies of error responses that have none from the config's `error_pages`.
+/// Responses whose handler already set a body are left alone, while errors a handler returned
+/// get the page in place of their plain text explanation.
+#[derive(Default)]
+pub struct ErrorPages;
+
+impl ErrorPages {
+    pub fn new() -> Self {
+        ErrorPages
+    }
+}
+
+/// Reads the page's template and content type, `None` if it can't be shown.
+async fn load(page: &ErrorPage, config: &ServerConfig) -> Option<(String, String)> {
+    let Some(file) = &page.file else {
+        let text = page.text.clone()?;
+        return Some((text, "text/plain; charset=utf-8".to_string()));
+    };
+    let root = config.static_directory.as_deref()?;
+    let path = crate::static_files::resolve(Path::new(root), file, config.follow_symlinks).await?;
+    match tokio::fs::read_to_string(&path).await {
+        Ok(template) => Some((template, mime::content_type(&path, &config.mime_types))),
+        Err(e) => {
+            tracing::warn!("Unable to read error page {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+impl Middleware<ServerConfig> for ErrorPages {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        config: Arc<ServerConfig>,
+        next: Next<'a, ServerConfig>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let path = request.path.clone();
+            let (mut resp, failed) = match next.run(request, config.clone()).await {
+                Ok(resp) => (resp, false),
+                Err(error) => (crate::server::error_response(error), true),
+            };
+            let status = resp.status_code;
+            if !(status.is_client_error() || status.is_server_error())
+                || !(failed || matches!(&resp.body, Body::Full(body) if body.is_empty()))
+            {
+                return Ok(resp);
+            }
+            let Some(page) = config
+                .error_pages
+                .iter()
+                .find(|page| page.status == status.as_u16())
+            else {
+                return Ok(resp);
+            };
+            let Some((template, content_type)) = load(page, &config).await else {
+                return Ok(resp);
+            };
+            let template = match Template::parse(&template) {
+                Ok(template) if content_type.starts_with("text/html") => template,
+                Ok(template) => template.without_escaping(),
+                Err(e) => {
+                    tracing::warn!("Invalid error page for {status}: {e}");
+                    return Ok(resp);
+                }
+            };
+            let context = serde_json::json!({
+                "status": status.as_u16(),
+                "reason": status.reason(),
+                "path": path,
+            });
+            let body = template.render(&context)?;
+            resp.headers.remove("Content-Length");
+            resp.set_header("Content-Type".to_string(), content_type);
+            resp.set_body(body.into_bytes());
+            Ok(resp)
+        })
+    }
+}
+
+#[tokio::test]
+async fn tests_error_pages() {
+    use crate::router::Router;
+
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-error-pages-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(
+        root.join("404.html"),
+        "<h1>{{status}} {{reason}}: {{path}}</h1>",
+    )
+    .unwrap();
+
+    let router: Router<ServerConfig> = Router::new()
+        .get("/fail", |_, _| async {
+            Ok(HttpResponse::internal_server_error())
+        })
+        .get("/invalid", |_, _| async {
+            Ok(HttpResponse::builder()
+                .status(crate::StatusCode::BadRequest)
+                .body("handler body")
+                .build())
+        })
+        .get("/rejected", |_, _| async {
+            Err(crate::HttpError::new(crate::StatusCode::Forbidden, "no access").into())
+        })
+        .get("/broken", |_, _| async {
+            Err(anyhow::anyhow!("disk on fire"))
+        })
+        .layer(ErrorPages::new());
+    let config = Arc::new(ServerConfig {
+        static_directory: Some(root.to_str().unwrap().to_string()),
+        error_pages: vec![
+            ErrorPage {
+                status: 404,
+                file: Some("404.html".to_string()),
+                text: None,
+            },
+            ErrorPage {
+                status: 500,
+                file: None,
+                text: Some("{{status}} {{reason}} <{{path}}>".to_string()),
+            },
+            ErrorPage {
+                status: 403,
+                file: None,
+                text: Some("{{status}} {{reason}}".to_string()),
+            },
+            ErrorPage {
+                status: 400,
+                file: None,
+                text: Some("unused".to_string()),
+            },
+        ],
+        ..Default::default()
+    });
+    let get = async |path: &str| {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        router.handle(request, config.clone()).await.unwrap()
+    };
+
+    let resp = get("/<missing>").await;
+    assert_eq!(404, resp.status_code);
+    assert_eq!(
+        Some("text/html; charset=utf-8"),
+        resp.headers.get("Content-Type")
+    );
+    assert_eq!(
+        Some(&b"<h1>404 Not Found: /&lt;missing&gt;</h1>"[..]),
+        resp.body.as_bytes()
+    );
+
+    let resp = get("/fail").await;
+    assert_eq!(
+        Some(&b"500 Internal Server Error </fail>"[..]),
+        resp.body.as_bytes()
+    );
+
+    let resp = get("/invalid").await;
+    assert_eq!(Some(&b"handler body"[..]), resp.body.as_bytes());
+
+    let resp = get("/rejected").await;
+    assert_eq!(403, resp.status_code);
+    assert_eq!(Some(&b"403 Forbidden"[..]), resp.body.as_bytes());
+
+    let resp = get("/broken").await;
+    assert_eq!(500, resp.status_code);
+    assert_eq!(
+        Some(&b"500 Internal Server Error </broken>"[..]),
+        resp.body.as_bytes()
+    );
+
+    std::fs::remove_dir_all(root).unwrap();
+}
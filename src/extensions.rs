@@ -0,0 +1,77 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Values attached to a request by the layers it passed through, one per type, e.g. the
+/// [`Session`](crate::session::Session) or the authenticated [`Identity`](crate::auth::Identity).
+/// Layers define their own types for what they attach, so they can't overwrite each other's.
+#[derive(Default)]
+pub struct Extensions {
+    values: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Extensions::default()
+    }
+
+    /// Attaches `value`, returning the value of the same type it replaces.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        let previous = self.values.insert(TypeId::of::<T>(), Box::new(value))?;
+        previous.downcast().ok().map(|previous| *previous)
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        let value = self.values.remove(&TypeId::of::<T>())?;
+        value.downcast().ok().map(|value| *value)
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.values.len())
+            .finish()
+    }
+}
+
+#[test]
+fn tests_extensions() {
+    #[derive(Debug, PartialEq)]
+    struct RequestId(u64);
+
+    let mut extensions = Extensions::new();
+    assert!(extensions.is_empty());
+    assert_eq!(None, extensions.insert(RequestId(1)));
+    assert_eq!(None, extensions.insert("user".to_string()));
+    assert_eq!(Some(&RequestId(1)), extensions.get::<RequestId>());
+    assert_eq!(Some(RequestId(1)), extensions.insert(RequestId(2)));
+    extensions.get_mut::<RequestId>().unwrap().0 += 1;
+    assert_eq!(Some(&RequestId(3)), extensions.get());
+    assert_eq!(2, extensions.len());
+
+    assert!(!extensions.contains::<u64>());
+    assert_eq!(Some("user".to_string()), extensions.remove::<String>());
+    assert_eq!(None, extensions.get::<String>());
+    assert_eq!(1, extensions.len());
+}
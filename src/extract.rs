@@ -0,0 +1,414 @@
+//! Handlers taking typed arguments pulled out of the request, answering with a 400 (or the
+//! extractor's status) when one can't be extracted:
+//!
+//! ```
+//! # use codecrafters_http_server::{HttpResponse, Router};
+//! use codecrafters_http_server::extract::{self, Json, Path};
+//!
+//! #[derive(serde::Deserialize)]
+//! struct Rename {
+//!     name: String,
+//! }
+//!
+//! async fn rename(Path(id): Path<u32>, Json(body): Json<Rename>) -> HttpResponse {
+//!     HttpResponse::builder().body(format!("{id} is now {}", body.name)).build()
+//! }
+//!
+//! let router: Router<()> = Router::new().handler("PUT", "/users/:id", extract::handler(rename));
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::de::value::{Error as ValueError, MapDeserializer};
+use serde::de::{self, DeserializeOwned, Deserializer, IntoDeserializer, Visitor};
+
+use crate::error::HttpError;
+use crate::headers::HeaderMap;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::{BoxFuture, Handler};
+use crate::status::StatusCode;
+
+/// A handler argument built from the request and the router's state.
+pub trait FromRequest<S>: Sized {
+    fn from_request(request: &HttpRequest, state: &Arc<S>) -> Result<Self, HttpError>;
+}
+
+/// The route's path parameters, a struct with a field per parameter or a single value for
+/// routes with one parameter, e.g. `Path<u32>` for "/users/:id".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path<T>(pub T);
+
+/// The query string's parameters, deserialized like [`Path`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query<T>(pub T);
+
+/// An `application/x-www-form-urlencoded` body, deserialized like [`Path`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Form<T>(pub T);
+
+/// A JSON body, see [`HttpRequest::json`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+/// The request headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Headers(pub HeaderMap);
+
+/// The router's state.
+#[derive(Debug)]
+pub struct State<S>(pub Arc<S>);
+
+impl<S, T: DeserializeOwned> FromRequest<S> for Path<T> {
+    fn from_request(request: &HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        T::deserialize(Params(&request.params))
+            .map(Path)
+            .map_err(|e| HttpError::new(StatusCode::BadRequest, format!("invalid path: {e}")))
+    }
+}
+
+impl<S, T: DeserializeOwned> FromRequest<S> for Query<T> {
+    fn from_request(request: &HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        T::deserialize(Params(&request.query))
+            .map(Query)
+            .map_err(|e| HttpError::new(StatusCode::BadRequest, format!("invalid query: {e}")))
+    }
+}
+
+impl<S, T: DeserializeOwned> FromRequest<S> for Form<T> {
+    fn from_request(request: &HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        T::deserialize(Params(&request.form()?))
+            .map(Form)
+            .map_err(|e| HttpError::new(StatusCode::BadRequest, format!("invalid form: {e}")))
+    }
+}
+
+impl<S, T: DeserializeOwned> FromRequest<S> for Json<T> {
+    fn from_request(request: &HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        request.json().map(Json)
+    }
+}
+
+impl<S> FromRequest<S> for Headers {
+    fn from_request(request: &HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        Ok(Headers(request.headers.clone()))
+    }
+}
+
+impl<S> FromRequest<S> for State<S> {
+    fn from_request(_: &HttpRequest, state: &Arc<S>) -> Result<Self, HttpError> {
+        Ok(State(state.clone()))
+    }
+}
+
+/// What an extractor handler may return.
+pub trait IntoResponse {
+    fn into_response(self) -> Result<HttpResponse>;
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self) -> Result<HttpResponse> {
+        Ok(self)
+    }
+}
+
+impl<E: Into<anyhow::Error>> IntoResponse for Result<HttpResponse, E> {
+    fn into_response(self) -> Result<HttpResponse> {
+        self.map_err(Into::into)
+    }
+}
+
+/// A function taking extractors, see [`handler`].
+pub struct Extracted<F, Args> {
+    f: F,
+    args: PhantomData<fn() -> Args>,
+}
+
+/// Turns an async function whose arguments all implement [`FromRequest`] into a [`Handler`].
+pub fn handler<F, Args>(f: F) -> Extracted<F, Args> {
+    Extracted {
+        f,
+        args: PhantomData,
+    }
+}
+
+macro_rules! extracted_handler {
+    ($($arg:ident),*) => {
+        impl<S, F, Fut, R, $($arg,)*> Handler<S> for Extracted<F, ($($arg,)*)>
+        where
+            S: Send + Sync + 'static,
+            F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            R: IntoResponse,
+            $($arg: FromRequest<S> + 'static,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn call(
+                &self,
+                request: HttpRequest,
+                state: Arc<S>,
+            ) -> BoxFuture<'_, Result<HttpResponse>> {
+                $(
+                    let $arg = match $arg::from_request(&request, &state) {
+                        Ok(value) => value,
+                        Err(error) => return Box::pin(async move { Err(error.into()) }),
+                    };
+                )*
+                let response = (self.f)($($arg),*);
+                Box::pin(async move { response.await.into_response() })
+            }
+        }
+    };
+}
+
+extracted_handler!();
+extracted_handler!(A);
+extracted_handler!(A, B);
+extracted_handler!(A, B, C);
+extracted_handler!(A, B, C, D);
+extracted_handler!(A, B, C, D, E);
+
+/// Deserializes a struct from a string map, or a single value from a map with one entry.
+struct Params<'a>(&'a HashMap<String, String>);
+
+impl Params<'_> {
+    fn single(&self) -> Result<Value<'_>, ValueError> {
+        match self.0.values().next() {
+            Some(value) if self.0.len() == 1 => Ok(Value(value)),
+            _ => Err(de::Error::custom(format!(
+                "expected 1 parameter, found {}",
+                self.0.len()
+            ))),
+        }
+    }
+}
+
+macro_rules! single_value {
+    ($($method:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Params<'_> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        let mut map = MapDeserializer::new(
+            self.0
+                .iter()
+                .map(|(name, value)| (name.as_str(), Value(value))),
+        );
+        let value = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.single()?.deserialize_option(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        self.single()?.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    single_value!(
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string
+    );
+
+    serde::forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Deserializes one parameter, parsing it for numbers and booleans.
+struct Value<'a>(&'a str);
+
+impl<'de> IntoDeserializer<'de, ValueError> for Value<'_> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Value<'_> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        self.0
+            .into_deserializer()
+            .deserialize_enum(name, variants, visitor)
+    }
+
+    parse_value!(
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char
+    );
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+#[tokio::test]
+async fn tests_extract() {
+    use crate::router::Router;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Page {
+        page: u32,
+        sort: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct Rename {
+        name: String,
+    }
+    #[derive(Deserialize)]
+    struct Member {
+        team: String,
+        id: u32,
+    }
+    async fn list(Path(team): Path<String>, Query(page): Query<Page>) -> HttpResponse {
+        let sort = page.sort.unwrap_or_default();
+        let body = format!("{team} page {} {sort}", page.page);
+        HttpResponse::builder().body(body).build()
+    }
+    async fn rename(
+        Path(member): Path<Member>,
+        Json(body): Json<Rename>,
+        State(prefix): State<String>,
+    ) -> Result<HttpResponse> {
+        let body = format!("{prefix}{}/{} is {}", member.team, member.id, body.name);
+        Ok(HttpResponse::builder().body(body).build())
+    }
+    async fn agent(Headers(headers): Headers) -> HttpResponse {
+        let agent = headers.get("User-Agent").unwrap_or_default().to_string();
+        HttpResponse::builder().body(agent).build()
+    }
+    let router = Router::new()
+        .handler("GET", "/teams/:team", handler(list))
+        .handler("PUT", "/teams/:team/:id", handler(rename))
+        .handler("GET", "/agent", handler(agent));
+    let send = async |method: &str, target: &str, json: Option<&str>| {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut request = HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: crate::request::parse_query(query).unwrap(),
+            ..Default::default()
+        };
+        request
+            .headers
+            .insert("User-Agent".to_string(), "test".to_string());
+        if let Some(json) = json {
+            request
+                .headers
+                .insert("Content-Type".to_string(), "application/json".to_string());
+            request.body = bytes::Bytes::copy_from_slice(json.as_bytes());
+        }
+        match router.handle(request, Arc::new("> ".to_string())).await {
+            Ok(resp) => resp,
+            Err(error) => HttpResponse::from(error.downcast::<HttpError>().unwrap()),
+        }
+    };
+    let text = |resp: HttpResponse| String::from_utf8(resp.body.as_bytes().unwrap().to_vec());
+
+    let resp = send("GET", "/teams/red?page=2&sort=name", None).await;
+    assert_eq!("red page 2 name", text(resp).unwrap());
+    let resp = send("GET", "/teams/red?page=2", None).await;
+    assert_eq!("red page 2 ", text(resp).unwrap());
+    let resp = send("GET", "/teams/red?page=two", None).await;
+    assert_eq!(400, resp.status_code);
+    assert!(text(resp).unwrap().contains("invalid query"));
+    assert_eq!(400, send("GET", "/teams/red", None).await.status_code);
+
+    let resp = send("PUT", "/teams/red/7", Some(r#"{"name": "ann"}"#)).await;
+    assert_eq!("> red/7 is ann", text(resp).unwrap());
+    let resp = send("PUT", "/teams/red/x", Some(r#"{"name": "ann"}"#)).await;
+    assert_eq!(400, resp.status_code);
+    assert_eq!(415, send("PUT", "/teams/red/7", None).await.status_code);
+    let resp = send("PUT", "/teams/red/7", Some("{")).await;
+    assert_eq!(400, resp.status_code);
+
+    assert_eq!("test", text(send("GET", "/agent", None).await).unwrap());
+}
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+
+use bytes::Bytes;
+
+/// Files larger than this are always read from disk.
+pub const MAX_CACHED_FILE: u64 = 1024 * 1024;
+
+/// Shared by all servers of the process, like the files it caches.
+static CACHE: LazyLock<Mutex<FileCache>> = LazyLock::new(|| Mutex::new(FileCache::new(0)));
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+struct Entry {
+    modified: SystemTime,
+    body: Bytes,
+    last_used: u64,
+}
+
+/// File contents kept in memory up to a total size, evicting the least recently used files
+/// first. Entries are only served while the file's modification time and length still match.
+pub struct FileCache {
+    capacity: usize,
+    size: usize,
+    uses: u64,
+    entries: HashMap<PathBuf, Entry>,
+    /// Cached paths by when they were last used, oldest first.
+    recency: BTreeMap<u64, PathBuf>,
+}
+
+impl FileCache {
+    pub fn new(capacity: usize) -> Self {
+        FileCache {
+            capacity,
+            size: 0,
+            uses: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    /// The contents of `path` if they were cached when it had this modification time and length.
+    /// Stale contents are dropped.
+    pub fn get(&mut self, path: &Path, modified: SystemTime, length: u64) -> Option<Bytes> {
+        let entry = self.entries.get(path)?;
+        if entry.modified != modified || entry.body.len() as u64 != length {
+            self.remove(path);
+            return None;
+        }
+        self.uses += 1;
+        let entry = self.entries.get_mut(path)?;
+        let path = self.recency.remove(&entry.last_used)?;
+        entry.last_used = self.uses;
+        self.recency.insert(self.uses, path);
+        Some(entry.body.clone())
+    }
+
+    /// Caches `body` as the contents of `path` at modification time `modified`, unless it is
+    /// larger than the whole cache.
+    pub fn insert(&mut self, path: PathBuf, modified: SystemTime, body: Bytes) {
+        self.remove(&path);
+        if body.len() > self.capacity {
+            return;
+        }
+        self.uses += 1;
+        self.size += body.len();
+        self.recency.insert(self.uses, path.clone());
+        let entry = Entry {
+            modified,
+            body,
+            last_used: self.uses,
+        };
+        self.entries.insert(path, entry);
+        self.evict();
+    }
+
+    /// Changes the total size, evicting files that no longer fit.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    /// The total size of the cached files.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.recency.remove(&entry.last_used);
+            self.size -= entry.body.len();
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            let Some((_, path)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&path) {
+                self.size -= entry.body.len();
+            }
+        }
+    }
+}
+
+/// Reads the file at `path` through the process-wide cache, sized to `capacity` bytes first.
+/// Returns `None` for files that aren't cached, because the cache is disabled, they are too
+/// large or changed while being read, for the caller to read from disk.
+pub(crate) async fn read(
+    path: &Path,
+    modified: Option<SystemTime>,
+    length: u64,
+    capacity: usize,
+) -> io::Result<Option<Bytes>> {
+    let cacheable = capacity > 0 && length <= MAX_CACHED_FILE.min(capacity as u64);
+    let Some(modified) = modified.filter(|_| cacheable) else {
+        return Ok(None);
+    };
+    {
+        let mut cache = CACHE.lock().unwrap();
+        cache.set_capacity(capacity);
+        if let Some(body) = cache.get(path, modified, length) {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(body));
+        }
+    }
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    let body = Bytes::from(tokio::fs::read(path).await?);
+    if body.len() as u64 != length {
+        return Ok(None);
+    }
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), modified, body.clone());
+    Ok(Some(body))
+}
+
+/// How many cached reads were served from memory and how many went to disk.
+pub(crate) fn stats() -> (u64, u64) {
+    (HITS.load(Ordering::Relaxed), MISSES.load(Ordering::Relaxed))
+}
+
+#[test]
+fn tests_file_cache() {
+    let modified = SystemTime::UNIX_EPOCH;
+    let body = |len| Bytes::from(vec![b'x'; len]);
+    let mut cache = FileCache::new(10);
+    cache.insert("a".into(), modified, body(4));
+    cache.insert("b".into(), modified, body(4));
+    assert!(cache.get(Path::new("a"), modified, 4).is_some());
+    // "b" is now the least recently used
+    cache.insert("c".into(), modified, body(4));
+    assert!(cache.get(Path::new("b"), modified, 4).is_none());
+    assert!(cache.get(Path::new("a"), modified, 4).is_some());
+    assert_eq!(8, cache.size());
+
+    let later = modified + std::time::Duration::from_secs(1);
+    assert!(cache.get(Path::new("c"), later, 4).is_none());
+    assert!(cache.get(Path::new("c"), modified, 4).is_none());
+    assert!(cache.get(Path::new("a"), modified, 5).is_none());
+    assert_eq!(0, cache.size());
+
+    cache.insert("big".into(), modified, body(11));
+    assert_eq!(0, cache.size());
+    cache.insert("a".into(), modified, body(4));
+    cache.insert("a".into(), modified, body(6));
+    assert_eq!(6, cache.size());
+    cache.set_capacity(5);
+    assert_eq!(0, cache.size());
+}
+
+#[tokio::test]
+async fn tests_read() {
+    let path = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-cache-{}.txt",
+        std::process::id()
+    ));
+    std::fs::write(&path, b"cached").unwrap();
+    let modified = std::fs::metadata(&path).unwrap().modified().ok();
+
+    assert_eq!(None, read(&path, modified, 6, 0).await.unwrap());
+    let (hits, misses) = stats();
+    assert_eq!(
+        b"cached",
+        &read(&path, modified, 6, 1024).await.unwrap().unwrap()[..]
+    );
+    std::fs::write(&path, b"on disk").unwrap();
+    // served from memory while the modification time and length match
+    assert_eq!(
+        b"cached",
+        &read(&path, modified, 6, 1024).await.unwrap().unwrap()[..]
+    );
+    let reread = read(&path, modified, 7, 1024).await.unwrap();
+    assert_eq!(b"on disk", &reread.unwrap()[..]);
+    let (new_hits, new_misses) = stats();
+    assert!(new_hits > hits && new_misses >= misses + 2);
+
+    std::fs::remove_file(path).unwrap();
+}
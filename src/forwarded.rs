@@ -0,0 +1,188 @@
+//! Finding the client behind the proxies a request came through, from the addresses they
+//! recorded in Forwarded or X-Forwarded-For. Those headers can be sent by anyone, so they are
+//! only believed as far as the hops that appended to them are trusted.
+
+use std::fmt::{self, Display};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::headers::HeaderMap;
+
+/// An address or a CIDR block like "10.0.0.0/8" or "fd00::/8".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Network {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Network {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let (network, addr, bits) = match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                (network.to_bits() as u128, addr.to_bits() as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => (network.to_bits(), addr.to_bits(), 128),
+            _ => return false,
+        };
+        let shift = bits - u32::from(self.prefix_len);
+        network.checked_shr(shift).unwrap_or(0) == addr.checked_shr(shift).unwrap_or(0)
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid network {value:?}, expected an address or CIDR block");
+        let (addr, prefix_len) = match value.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().ok().filter(|len| *len <= max),
+            None => Some(max),
+        };
+        Ok(Network {
+            addr,
+            prefix_len: prefix_len.ok_or_else(invalid)?,
+        })
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// The address of one hop in a Forwarded `for=` parameter or an X-Forwarded-For entry, with or
+/// without a port. Obfuscated and "unknown" identifiers give `None`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    if let Ok(addr) = hop.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = hop.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    // an IPv6 address in brackets without a port
+    hop.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+/// The `for=` hops of the Forwarded headers, `None` for hops that are not addresses.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("Forwarded")
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_hop(value))
+            })
+        })
+        .collect()
+}
+
+/// The client address of a request received from `peer`: the peer itself unless it is one of
+/// the `trusted` proxies, otherwise the last hop recorded in Forwarded, or else X-Forwarded-For,
+/// that isn't a trusted proxy. An unreadable hop ends the walk at the proxy that recorded it.
+pub fn client_addr(peer: IpAddr, headers: &HeaderMap, trusted: &[Network]) -> IpAddr {
+    let is_trusted = |addr: IpAddr| trusted.iter().any(|network| network.contains(addr));
+    if !is_trusted(peer) {
+        return peer;
+    }
+    let mut hops = forwarded_for(headers);
+    if hops.is_empty() {
+        hops = headers
+            .get_all("X-Forwarded-For")
+            .flat_map(|value| value.split(','))
+            .map(parse_hop)
+            .collect();
+    }
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        let Some(addr) = hop else {
+            break;
+        };
+        client = addr;
+        if !is_trusted(addr) {
+            break;
+        }
+    }
+    client
+}
+
+#[test]
+fn tests_network() {
+    let network: Network = "10.0.0.0/8".parse().unwrap();
+    assert!(network.contains("10.1.2.3".parse().unwrap()));
+    assert!(!network.contains("11.0.0.1".parse().unwrap()));
+    // IPv4 peers of dual-stack sockets
+    assert!(network.contains("::ffff:10.0.0.1".parse().unwrap()));
+
+    let network: Network = "fd00::/8".parse().unwrap();
+    assert!(network.contains("fd12::1".parse().unwrap()));
+    assert!(!network.contains("10.0.0.1".parse().unwrap()));
+    assert!(
+        "0.0.0.0/0"
+            .parse::<Network>()
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap())
+    );
+    assert_eq!(
+        "127.0.0.1/32",
+        "127.0.0.1".parse::<Network>().unwrap().to_string()
+    );
+
+    for invalid in ["10.0.0.0/33", "localhost", "10.0.0.0/", "::1/129"] {
+        assert!(invalid.parse::<Network>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn tests_client_addr() {
+    let trusted: Vec<Network> = vec!["10.0.0.0/8".parse().unwrap()];
+    let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+    let headers = |name: &str, value: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(name.to_string(), value.to_string());
+        headers
+    };
+    let client_addr = |peer, headers: &HeaderMap| client_addr(peer, headers, &trusted).to_string();
+
+    let spoofed = headers("X-Forwarded-For", "192.0.2.1");
+    assert_eq!(
+        "198.51.100.7",
+        client_addr("198.51.100.7".parse().unwrap(), &spoofed)
+    );
+    assert_eq!("192.0.2.1", client_addr(proxy, &spoofed));
+    // the client may have sent its own header, only the part appended by trusted proxies counts
+    let chain = headers("X-Forwarded-For", "1.1.1.1, 192.0.2.1:4711, 10.0.0.3");
+    assert_eq!("192.0.2.1", client_addr(proxy, &chain));
+    assert_eq!("10.0.0.2", client_addr(proxy, &HeaderMap::new()));
+    let garbage = headers("X-Forwarded-For", "192.0.2.1, not-an-ip");
+    assert_eq!("10.0.0.2", client_addr(proxy, &garbage));
+
+    let forwarded = headers(
+        "Forwarded",
+        "for=192.0.2.60;proto=http, For=\"[2001:db8:cafe::17]:4711\"",
+    );
+    assert_eq!("2001:db8:cafe::17", client_addr(proxy, &forwarded));
+    let hidden = headers("Forwarded", "for=192.0.2.60, for=_hidden");
+    assert_eq!("10.0.0.2", client_addr(proxy, &hidden));
+}
@@ -0,0 +1,90 @@
+//! Conditions a route places on requests beyond their method and path, see
+//! [`Router::guard`](crate::Router::guard). Requests a route's guards turn away fall through to
+//! the next route matching them, e.g. to version an API by its Accept header:
+//!
+//! ```
+//! # use codecrafters_http_server::{HttpResponse, Router, guard};
+//! let router: Router<()> = Router::new()
+//!     .get("/items", |_, _| async { Ok(HttpResponse::builder().body("v2").build()) })
+//!     .guard(guard::accept("application/vnd.items.v2+json"))
+//!     .get("/items", |_, _| async { Ok(HttpResponse::builder().body("v1").build()) });
+//! ```
+
+use crate::request::HttpRequest;
+
+/// Decides whether a route handles a request its method and path match. Closures taking
+/// `&HttpRequest` are guards.
+pub trait Guard: Send + Sync + 'static {
+    fn check(&self, request: &HttpRequest) -> bool;
+}
+
+impl<F> Guard for F
+where
+    F: Fn(&HttpRequest) -> bool + Send + Sync + 'static,
+{
+    fn check(&self, request: &HttpRequest) -> bool {
+        self(request)
+    }
+}
+
+/// Passes requests carrying the header `name`, whatever its value.
+pub fn header_present(name: &str) -> impl Guard {
+    let name = name.to_string();
+    move |request: &HttpRequest| request.headers.contains_key(&name)
+}
+
+/// Passes requests with a header `name` whose value is `value`, ignoring case.
+pub fn header(name: &str, value: &str) -> impl Guard {
+    let (name, value) = (name.to_string(), value.to_string());
+    move |request: &HttpRequest| {
+        request
+            .headers
+            .get_all(&name)
+            .any(|actual| actual.trim().eq_ignore_ascii_case(&value))
+    }
+}
+
+/// Passes requests whose Content-Type is `media_type`, e.g. "application/json", with any
+/// parameters like a charset.
+pub fn content_type(media_type: &str) -> impl Guard {
+    let media_type = media_type.to_string();
+    move |request: &HttpRequest| {
+        request.headers.get("Content-Type").is_some_and(|actual| {
+            let essence = actual.split(';').next().unwrap_or_default().trim();
+            essence.eq_ignore_ascii_case(&media_type)
+        })
+    }
+}
+
+/// Passes requests whose Accept header accepts `media_type`, and those without one. Wildcards
+/// like `*/*` accept any type, so the route guarded for the most specific type goes first.
+pub fn accept(media_type: &str) -> impl Guard {
+    let media_type = media_type.to_string();
+    move |request: &HttpRequest| request.negotiate(&[media_type.as_str()]).is_ok()
+}
+
+#[test]
+fn tests_guards() {
+    let mut request = HttpRequest::default();
+    request.headers.insert(
+        "Content-Type".to_string(),
+        "Application/JSON; charset=utf-8".to_string(),
+    );
+    request
+        .headers
+        .insert("X-Version".to_string(), "2".to_string());
+    request.headers.insert(
+        "Accept".to_string(),
+        "application/vnd.items.v2+json".to_string(),
+    );
+
+    assert!(header_present("x-version").check(&request));
+    assert!(!header_present("X-Missing").check(&request));
+    assert!(header("X-Version", "2").check(&request));
+    assert!(!header("X-Version", "1").check(&request));
+    assert!(content_type("application/json").check(&request));
+    assert!(!content_type("text/plain").check(&request));
+    assert!(accept("application/vnd.items.v2+json").check(&request));
+    assert!(!accept("application/vnd.items.v1+json").check(&request));
+    assert!(accept("text/html").check(&HttpRequest::default()));
+}
@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::compression::Compression;
+use crate::config::ServerConfig;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::Router;
+use crate::static_files;
+use crate::websocket::{self, Message};
+
+/// The built-in routes: `/`, `/echo/:msg`, `/user-agent`, the `/files/*name` routes for any
+/// path below the static directory and a `/ws` WebSocket echo endpoint.
+pub fn default_router() -> Router<ServerConfig> {
+    Router::new()
+        .get("/", |_, _| async { Ok(HttpResponse::ok()) })
+        .get("/echo/:msg", echo)
+        .get("/user-agent", user_agent)
+        .get("/files", static_files::get_file)
+        .get("/files/*name", static_files::get_file)
+        .route_streaming("POST", "/files/*name", static_files::post_file)
+        .route_streaming("PUT", "/files/*name", static_files::put_file)
+        .delete("/files/*name", static_files::delete_file)
+        .get("/ws", ws_echo)
+        // even short bodies like those of /echo are compressed
+        .layer(Compression::new().min_size(0))
+}
+
+pub async fn echo(request: HttpRequest, _config: Arc<ServerConfig>) -> Result<HttpResponse> {
+    let message = request.param("msg").unwrap_or_default();
+
+    Ok(HttpResponse::builder()
+        .header("Content-Type", "text/plain")
+        .body(message)
+        .build())
+}
+
+pub async fn user_agent(request: HttpRequest, _config: Arc<ServerConfig>) -> Result<HttpResponse> {
+    let Some(user_agent) = request.headers.get("User-Agent") else {
+        return Ok(HttpResponse::internal_server_error());
+    };
+
+    Ok(HttpResponse::builder()
+        .header("Content-Type", "text/plain")
+        .body(user_agent)
+        .build())
+}
+
+/// Echoes text and binary messages back to the WebSocket client.
+pub async fn ws_echo(request: HttpRequest, _config: Arc<ServerConfig>) -> Result<HttpResponse> {
+    Ok(websocket::upgrade(&request, |mut socket| async move {
+        while let Some(message) = socket.recv().await? {
+            if let Message::Text(_) | Message::Binary(_) = message {
+                socket.send(message).await?;
+            }
+        }
+        Ok(())
+    }))
+}
+
+#[cfg(test)]
+async fn handle(
+    router: &Router<ServerConfig>,
+    request: HttpRequest,
+    config: &Arc<ServerConfig>,
+) -> HttpResponse {
+    router.handle(request, config.clone()).await.unwrap()
+}
+
+#[tokio::test]
+async fn tests_handle_request() {
+    let config = Arc::new(ServerConfig::default());
+    let router = default_router();
+
+    for (path, expected) in [
+        ("/", 200),
+        ("", 200),
+        ("/something", 404),
+        ("/something/something", 404),
+        ("/echo/something", 200),
+    ] {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            expected,
+            handle(&router, request, &config).await.status_code,
+            "{path}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn tests_handle_request_compressed() {
+    let config = Arc::new(ServerConfig::default());
+    let router = default_router();
+
+    let mut request = HttpRequest {
+        method: "GET".to_string(),
+        path: "/echo/abc".to_string(),
+        ..Default::default()
+    };
+    request
+        .headers
+        .insert("Accept-Encoding".to_string(), "gzip".to_string());
+    let actual = handle(&router, request, &config).await;
+    assert_eq!(Some("gzip"), actual.headers.get("Content-Encoding"));
+    assert_eq!(Some("Accept-Encoding"), actual.headers.get("Vary"));
+}
+
+#[tokio::test]
+async fn tests_handle_request_files() {
+    let root_dir = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-files-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root_dir).unwrap();
+    let config = Arc::new(ServerConfig {
+        static_directory: Some(root_dir.to_string_lossy().to_string()),
+        ..Default::default()
+    });
+    let router = default_router();
+
+    let request = HttpRequest {
+        method: "POST".to_string(),
+        path: "/files/upload.txt".to_string(),
+        body: bytes::Bytes::from_static(b"uploaded"),
+        ..Default::default()
+    };
+    assert_eq!(201, handle(&router, request, &config).await.status_code);
+    assert_eq!(
+        b"uploaded".to_vec(),
+        std::fs::read(root_dir.join("upload.txt")).unwrap()
+    );
+
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        path: "/files/upload.txt".to_string(),
+        ..Default::default()
+    };
+    let actual = handle(&router, request, &config).await;
+    assert_eq!(200, actual.status_code);
+    assert_eq!(Some("8"), actual.headers.get("Content-Length"));
+    assert!(matches!(actual.body, crate::response::Body::Stream(_)));
+
+    let delete = || HttpRequest {
+        method: "DELETE".to_string(),
+        path: "/files/upload.txt".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(204, handle(&router, delete(), &config).await.status_code);
+    assert!(!root_dir.join("upload.txt").exists());
+    assert_eq!(404, handle(&router, delete(), &config).await.status_code);
+
+    let request = HttpRequest {
+        method: "PATCH".to_string(),
+        path: "/files/upload.txt".to_string(),
+        ..Default::default()
+    };
+    let actual = handle(&router, request, &config).await;
+    assert_eq!(405, actual.status_code);
+    assert_eq!(
+        Some("GET, HEAD, POST, PUT, DELETE, OPTIONS"),
+        actual.headers.get("Allow")
+    );
+
+    let _ = std::fs::remove_dir_all(root_dir.join("a"));
+    let request = HttpRequest {
+        method: "PUT".to_string(),
+        path: "/files/a/b/c.txt".to_string(),
+        body: bytes::Bytes::from_static(b"nested"),
+        ..Default::default()
+    };
+    assert_eq!(201, handle(&router, request, &config).await.status_code);
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        path: "/files/a/b/c.txt".to_string(),
+        ..Default::default()
+    };
+    let actual = handle(&router, request, &config).await;
+    assert_eq!(200, actual.status_code);
+    assert_eq!(Some("6"), actual.headers.get("Content-Length"));
+}
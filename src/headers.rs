@@ -0,0 +1,184 @@
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Header fields with case-insensitive name lookup, kept in insertion order. A name may occur
+/// several times, e.g. for Set-Cookie.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HeaderMap {
+    entries: Vec<(Text, Text)>,
+}
+
+/// A header name or value, either owned or a range of a received header block that all fields
+/// of a request share.
+#[derive(Clone)]
+enum Text {
+    Owned(String),
+    Shared(Arc<str>, Range<usize>),
+}
+
+impl Text {
+    fn as_str(&self) -> &str {
+        match self {
+            Text::Owned(text) => text,
+            Text::Shared(head, range) => &head[range.clone()],
+        }
+    }
+
+    fn into_string(self) -> String {
+        match self {
+            Text::Owned(text) => text,
+            shared => shared.as_str().to_string(),
+        }
+    }
+}
+
+impl PartialEq for Text {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl fmt::Debug for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap { entries: vec![] }
+    }
+
+    /// Returns the first value for `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).next()
+    }
+
+    /// Returns every value for `name` in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(move |(header, _)| header.as_str().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets a header, replacing all existing values regardless of the name's case. The header
+    /// keeps the position of its first occurrence.
+    pub fn insert(&mut self, name: String, value: String) {
+        match self
+            .entries
+            .iter()
+            .position(|(header, _)| header.as_str().eq_ignore_ascii_case(&name))
+        {
+            Some(index) => {
+                self.entries[index].1 = Text::Owned(value);
+                let mut seen = 0;
+                self.entries.retain(|(header, _)| {
+                    let matches = header.as_str().eq_ignore_ascii_case(&name);
+                    seen += usize::from(matches);
+                    !matches || seen == 1
+                });
+            }
+            None => self.entries.push((Text::Owned(name), Text::Owned(value))),
+        }
+    }
+
+    /// Adds a header after the existing ones, keeping earlier values for the same name.
+    pub fn append(&mut self, name: String, value: String) {
+        self.entries.push((Text::Owned(name), Text::Owned(value)));
+    }
+
+    /// Appends a field whose name and value are ranges of `head`, without copying them.
+    pub(crate) fn append_shared(
+        &mut self,
+        head: &Arc<str>,
+        name: Range<usize>,
+        value: Range<usize>,
+    ) {
+        self.entries.push((
+            Text::Shared(head.clone(), name),
+            Text::Shared(head.clone(), value),
+        ));
+    }
+
+    /// Appends `more` to the value of the last field, separated by a space. Returns false if there
+    /// is no field.
+    pub(crate) fn extend_last(&mut self, more: &str) -> bool {
+        let Some((_, value)) = self.entries.last_mut() else {
+            return false;
+        };
+        if !more.is_empty() {
+            *value = Text::Owned(format!("{} {more}", value.as_str()));
+        }
+        true
+    }
+
+    /// Removes all values for `name`, returning the first one.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let index = self
+            .entries
+            .iter()
+            .position(|(header, _)| header.as_str().eq_ignore_ascii_case(name))?;
+        let first = self.entries.remove(index).1;
+        self.entries
+            .retain(|(header, _)| !header.as_str().eq_ignore_ascii_case(name));
+        Some(first.into_string())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(header, value)| (header.as_str(), value.as_str()))
+    }
+}
+
+#[test]
+fn tests_header_map() {
+    let mut headers = HeaderMap::new();
+    headers.insert("User-Agent".to_string(), "curl".to_string());
+    assert_eq!(Some("curl"), headers.get("user-agent"));
+    assert_eq!(Some("curl"), headers.get("USER-AGENT"));
+
+    headers.insert("user-agent".to_string(), "wget".to_string());
+    assert_eq!(1, headers.iter().count());
+    assert_eq!(Some("wget"), headers.get("User-Agent"));
+
+    assert_eq!(Some("wget".to_string()), headers.remove("User-agent"));
+    assert_eq!(0, headers.iter().count());
+
+    headers.append("Set-Cookie".to_string(), "a=1".to_string());
+    headers.append("Vary".to_string(), "Accept".to_string());
+    headers.append("set-cookie".to_string(), "b=2".to_string());
+    assert_eq!(Some("a=1"), headers.get("Set-Cookie"));
+    assert_eq!(
+        vec!["a=1", "b=2"],
+        headers.get_all("SET-COOKIE").collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec![
+            ("Set-Cookie", "a=1"),
+            ("Vary", "Accept"),
+            ("set-cookie", "b=2")
+        ],
+        headers.iter().collect::<Vec<_>>()
+    );
+
+    headers.insert("Set-Cookie".to_string(), "c=3".to_string());
+    assert_eq!(
+        vec![("Set-Cookie", "c=3"), ("Vary", "Accept")],
+        headers.iter().collect::<Vec<_>>()
+    );
+
+    headers.append("Set-Cookie".to_string(), "d=4".to_string());
+    assert_eq!(Some("c=3".to_string()), headers.remove("set-cookie"));
+    assert_eq!(None, headers.get("Set-Cookie"));
+}
@@ -0,0 +1,594 @@
+//! HTTP/2 connections, negotiated through ALPN on TLS connections, started with the connection
+//! preface by clients that know the server speaks it, or upgraded to from an HTTP/1.1 request
+//! with `Upgrade: h2c`. The requests of all streams run through the same router as HTTP/1
+//! requests.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use bytes::{Bytes, BytesMut};
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+use crate::body::{BodySender, BodyStream, Piece};
+use crate::config::{ConfigHandle, ServerConfig};
+use crate::error::HttpError;
+use crate::headers::HeaderMap;
+use crate::metrics::Metrics;
+use crate::request::{HttpRequest, ParseError, Version, parse_query, percent_decode};
+use crate::response::{Body, CLIENT_CLOSED_REQUEST, Delivery, HttpResponse};
+use crate::router::Router;
+use crate::server::{error_response, is_disconnect, request_span};
+use crate::status::StatusCode;
+use crate::upgrade::Upgraded;
+
+/// What a client speaking HTTP/2 sends first.
+pub(crate) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Streams a client may have open at once.
+const MAX_CONCURRENT_STREAMS: u32 = 128;
+
+/// The largest frame payload a peer may send before the settings say otherwise.
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+
+/// Header fields that only apply to an HTTP/1.1 connection and must not appear in HTTP/2.
+const CONNECTION_SPECIFIC: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// What the streams of a connection are served with.
+#[derive(Clone)]
+struct Shared {
+    router: Arc<Router<ServerConfig>>,
+    config: ConfigHandle,
+    metrics: Arc<Metrics>,
+    peer: Option<SocketAddr>,
+}
+
+/// Serves an HTTP/2 connection whose preface is the next thing read from `io`, until the client
+/// closes it, it sits idle for the keep-alive timeout, or the server shuts down.
+pub(crate) async fn serve<S>(
+    io: S,
+    peer: Option<SocketAddr>,
+    router: Arc<Router<ServerConfig>>,
+    config: ConfigHandle,
+    metrics: Arc<Metrics>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let current = config.current();
+    let handshake = h2::server::Builder::new()
+        .max_concurrent_streams(MAX_CONCURRENT_STREAMS)
+        .max_header_list_size(u32::try_from(current.max_header_size).unwrap_or(u32::MAX))
+        .handshake::<_, Bytes>(io);
+    let mut connection = match tokio::time::timeout(current.header_timeout, handshake).await {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(e)) if e.get_io().is_some_and(is_disconnect) => return Ok(()),
+        Ok(Err(e)) => return Err(anyhow::Error::new(e).context("HTTP/2 handshake failed")),
+        // a client that never sends its preface gets no answer
+        Err(_) => return Ok(()),
+    };
+    let shared = Shared {
+        router,
+        config,
+        metrics,
+        peer,
+    };
+    let mut streams = JoinSet::new();
+    let mut closing = false;
+    loop {
+        let idle = tokio::time::sleep(shared.config.current().keep_alive_timeout);
+        // accepting also drives the connection, writing what the streams send
+        let accepted = tokio::select! {
+            accepted = connection.accept() => accepted,
+            Some(_) = streams.join_next(), if !streams.is_empty() => continue,
+            // open streams finish, new ones are refused
+            Ok(_) = shutdown.wait_for(|shutting_down| *shutting_down), if !closing => {
+                closing = true;
+                connection.graceful_shutdown();
+                continue;
+            }
+            _ = idle, if streams.is_empty() && !closing => {
+                closing = true;
+                connection.graceful_shutdown();
+                continue;
+            }
+        };
+        match accepted {
+            Some(Ok((request, respond))) => {
+                let span = request_span(request.method().as_str(), request.uri().path());
+                streams.spawn(handle_stream(request, respond, shared.clone()).instrument(span));
+            }
+            Some(Err(e)) if e.get_io().is_some_and(is_disconnect) => break,
+            Some(Err(e)) if e.is_go_away() && e.reason() == Some(h2::Reason::NO_ERROR) => break,
+            Some(Err(e)) => return Err(anyhow::Error::new(e).context("HTTP/2 connection failed")),
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+async fn handle_stream(
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    shared: Shared,
+) {
+    let config = shared.config.current();
+    let started = Instant::now();
+    let (parts, body) = request.into_parts();
+    let mut request = match into_request(parts, shared.peer) {
+        Ok(mut request) => {
+            request.resolve_remote_addr(&config.trusted_proxies);
+            request
+        }
+        Err(e) => {
+            // the stream's framing is intact, so only this request fails
+            let _ =
+                send_response(HttpResponse::from(HttpError::from(e)), &mut respond, false).await;
+            return;
+        }
+    };
+    let method = request.method.clone();
+    let head = method == "HEAD";
+    let deadline = Instant::now() + config.body_timeout;
+
+    // h2 holds the client to the length it announces, so a larger one needn't be read at all
+    let announced = request
+        .headers
+        .get("content-length")
+        .and_then(|length| length.parse::<usize>().ok());
+    let mut resp = if announced.is_some_and(|length| length > config.max_body_size) {
+        HttpResponse::from(HttpError::PayloadTooLarge)
+    } else if shared.router.streams_body(&request, &config) {
+        // the handler reads the body while it arrives
+        let (tx, stream) = BodyStream::channel();
+        request.body_stream = Some(stream);
+        let (handled, forwarded) = tokio::join!(
+            shared.router.handle(request, config.clone()),
+            forward(body, tx, deadline, config.max_body_size)
+        );
+        match forwarded {
+            Ok(()) => handled.unwrap_or_else(error_response),
+            Err(e) => HttpResponse::from(e),
+        }
+    } else {
+        match read_body(body, deadline, config.max_body_size).await {
+            Ok((body, trailers)) => {
+                request.body = body;
+                request.trailers = trailers;
+                shared
+                    .router
+                    .handle(request, config.clone())
+                    .await
+                    .unwrap_or_else(error_response)
+            }
+            Err(e) => HttpResponse::from(e),
+        }
+    };
+    resp.version = Version::Http2;
+    let route = resp.route.take();
+    let on_complete = resp.on_complete.take();
+    let mut status = resp.status_code.as_u16();
+    let sent = send_response(resp, &mut respond, head).await;
+    let delivery = match &sent {
+        Ok(_) => Delivery::Complete,
+        Err(e) if is_client_abort(e) => Delivery::Aborted,
+        Err(_) => Delivery::Failed,
+    };
+    if delivery == Delivery::Aborted {
+        status = CLIENT_CLOSED_REQUEST;
+    }
+    shared
+        .metrics
+        .record_request(&method, route.as_deref(), status, started.elapsed());
+    tracing::Span::current().record("status", status);
+    HttpResponse::completed(on_complete, delivery);
+    match sent {
+        Ok(bytes) => shared.metrics.record_bytes_sent(bytes),
+        // the client reset the stream or went away, nobody is left to tell
+        Err(e) => respond.send_reset(reset_reason(&e)),
+    }
+}
+
+/// Whether sending a response failed because the client reset the stream or went away.
+fn is_client_abort(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<h2::Error>()
+        .is_some_and(|e| e.is_remote() || e.is_go_away() || e.get_io().is_some_and(is_disconnect))
+        || error
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(is_disconnect)
+}
+
+/// Builds the request the router sees from the stream's header block.
+fn into_request(
+    parts: http::request::Parts,
+    peer: Option<SocketAddr>,
+) -> Result<HttpRequest, ParseError> {
+    let raw_path = parts.uri.path().to_string();
+    let raw_query = parts.uri.query().unwrap_or_default().to_string();
+    let mut request = HttpRequest {
+        method: parts.method.as_str().to_string(),
+        path: percent_decode(&raw_path, false)?,
+        query: parse_query(&raw_query)?,
+        raw_path,
+        raw_query,
+        version: Version::Http2,
+        peer_addr: peer,
+        ..Default::default()
+    };
+    let authority = parts.uri.authority();
+    if let Some(authority) = authority {
+        request
+            .headers
+            .insert("Host".to_string(), authority.to_string());
+    }
+    let mut cookies = vec![];
+    for (name, value) in &parts.headers {
+        let value = value
+            .to_str()
+            .map_err(|_| ParseError::Malformed(format!("invalid value of header {name}")))?;
+        match name.as_str() {
+            // :authority takes the place of Host
+            "host" if authority.is_some() => {}
+            // clients may split the cookies across fields, HTTP/1 sends them in one
+            "cookie" => cookies.push(value),
+            name => request.headers.append(name.to_string(), value.to_string()),
+        }
+    }
+    if !cookies.is_empty() {
+        request
+            .headers
+            .insert("Cookie".to_string(), cookies.join("; "));
+    }
+    Ok(request)
+}
+
+/// Reads the whole request body and its trailers.
+async fn read_body(
+    mut body: RecvStream,
+    deadline: Instant,
+    max_body_size: usize,
+) -> Result<(Bytes, HeaderMap), HttpError> {
+    let mut buf = BytesMut::new();
+    while let Some(data) = next_data(&mut body, deadline).await? {
+        if buf.len() + data.len() > max_body_size {
+            return Err(HttpError::PayloadTooLarge);
+        }
+        buf.extend_from_slice(&data);
+    }
+    Ok((buf.freeze(), trailers(&mut body, deadline).await?))
+}
+
+/// Passes the request body on to the handler's [`BodyStream`] as it arrives.
+async fn forward(
+    mut body: RecvStream,
+    tx: BodySender,
+    deadline: Instant,
+    max_body_size: usize,
+) -> Result<(), HttpError> {
+    let mut received = 0;
+    loop {
+        let piece = match next_data(&mut body, deadline).await {
+            Ok(Some(data)) => {
+                received += data.len();
+                if received > max_body_size {
+                    Err(HttpError::PayloadTooLarge)
+                } else {
+                    Ok(Piece::Data(data))
+                }
+            }
+            Ok(None) => trailers(&mut body, deadline).await.map(Piece::Trailers),
+            Err(e) => Err(e),
+        };
+        let last = matches!(piece, Ok(Piece::Trailers(_)));
+        match piece {
+            // the handler may have stopped reading, which is up to it
+            Ok(piece) => {
+                if tx.send(Ok(piece)).await.is_err() || last {
+                    return Ok(());
+                }
+            }
+            Err(e) => {
+                let _ = tx.send(Err(io::Error::other(e.to_string()))).await;
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// The next piece of the body, giving its size back to the client's flow control window.
+async fn next_data(body: &mut RecvStream, deadline: Instant) -> Result<Option<Bytes>, HttpError> {
+    match tokio::time::timeout_at(deadline, body.data()).await {
+        Err(_) => Err(HttpError::Timeout),
+        Ok(None) => Ok(None),
+        Ok(Some(Err(e))) => Err(stream_error(e)),
+        Ok(Some(Ok(data))) => {
+            let _ = body.flow_control().release_capacity(data.len());
+            Ok(Some(data))
+        }
+    }
+}
+
+async fn trailers(body: &mut RecvStream, deadline: Instant) -> Result<HeaderMap, HttpError> {
+    let trailers = match tokio::time::timeout_at(deadline, body.trailers()).await {
+        Err(_) => return Err(HttpError::Timeout),
+        Ok(trailers) => trailers.map_err(stream_error)?,
+    };
+    let mut map = HeaderMap::new();
+    for (name, value) in trailers.iter().flatten() {
+        let value = value
+            .to_str()
+            .map_err(|_| ParseError::Malformed(format!("invalid value of trailer {name}")))?;
+        map.append(name.to_string(), value.to_string());
+    }
+    Ok(map)
+}
+
+fn stream_error(error: h2::Error) -> HttpError {
+    match error.into_io() {
+        Some(error) => HttpError::Io(error),
+        None => HttpError::Io(io::Error::other("HTTP/2 stream failed")),
+    }
+}
+
+fn reset_reason(error: &anyhow::Error) -> h2::Reason {
+    error
+        .downcast_ref::<h2::Error>()
+        .and_then(h2::Error::reason)
+        .unwrap_or(h2::Reason::INTERNAL_ERROR)
+}
+
+/// Sends the response, leaving out the body for HEAD requests, and returns the body bytes sent.
+async fn send_response(
+    mut resp: HttpResponse,
+    respond: &mut SendResponse<Bytes>,
+    head: bool,
+) -> Result<u64> {
+    let mut builder = http::Response::builder().status(resp.status_code.as_u16());
+    for (name, value) in resp.headers.iter() {
+        if !CONNECTION_SPECIFIC
+            .iter()
+            .any(|specific| name.eq_ignore_ascii_case(specific))
+        {
+            builder = builder.header(name, value);
+        }
+    }
+    if let Body::Full(body) = &resp.body
+        && !resp.headers.contains_key("Content-Length")
+        && resp.status_code != StatusCode::NoContent
+        && resp.status_code != StatusCode::NotModified
+    {
+        builder = builder.header("content-length", body.len());
+    }
+    let trailers = resp.trailers.take();
+    let empty = matches!(&resp.body, Body::Full(body) if body.is_empty()) && trailers.is_none();
+    let response = builder
+        .body(())
+        .context("Invalid response header for HTTP/2")?;
+    let mut send = respond.send_response(response, head || empty)?;
+    if head || empty {
+        return Ok(0);
+    }
+
+    let sent = match resp.body {
+        Body::Full(body) => {
+            let len = body.len() as u64;
+            send_data(&mut send, Bytes::from(body)).await?;
+            len
+        }
+        Body::Stream(mut reader) => {
+            let mut sent = 0;
+            let mut buf = BytesMut::with_capacity(DEFAULT_MAX_FRAME_SIZE);
+            loop {
+                buf.reserve(DEFAULT_MAX_FRAME_SIZE);
+                if reader.read_buf(&mut buf).await? == 0 {
+                    break;
+                }
+                sent += buf.len() as u64;
+                send_data(&mut send, buf.split().freeze()).await?;
+            }
+            sent
+        }
+    };
+    match trailers {
+        Some(trailers) => {
+            let mut map = http::HeaderMap::new();
+            for (name, value) in trailers().iter() {
+                map.append(
+                    http::HeaderName::from_bytes(name.as_bytes())?,
+                    http::HeaderValue::from_str(value)?,
+                );
+            }
+            send.send_trailers(map)?;
+        }
+        None => send.send_data(Bytes::new(), true)?,
+    }
+    Ok(sent)
+}
+
+/// Sends `data` as the client's flow control window lets it through.
+async fn send_data(send: &mut SendStream<Bytes>, mut data: Bytes) -> Result<()> {
+    while !data.is_empty() {
+        send.reserve_capacity(data.len());
+        let capacity = std::future::poll_fn(|cx| send.poll_capacity(cx))
+            .await
+            .context("HTTP/2 stream closed")??;
+        if capacity > 0 {
+            send.send_data(data.split_to(capacity.min(data.len())), false)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `request` asks to continue the connection in HTTP/2 and can be answered there. The
+/// body of an upgraded request would have to be resent in HTTP/2, so only requests without one
+/// and with a header that fits a frame are upgraded; others are answered in HTTP/1.1, as the
+/// upgrade is optional for the server.
+pub(crate) fn wants_upgrade(request: &HttpRequest) -> bool {
+    let has_token = |name: &str, token: &str| {
+        request.headers.get_all(name).any(|value| {
+            value
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    request.version == Version::Http11
+        && has_token("Upgrade", "h2c")
+        && has_token("Connection", "upgrade")
+        && has_token("Connection", "http2-settings")
+        && request.headers.get_all("HTTP2-Settings").count() == 1
+        && request.body.is_empty()
+        && request.framing().is_none()
+        && headers_frame(request).is_some()
+}
+
+/// Reads the preface the client sends once it got the 101 response to `request`, and returns
+/// the connection for [`serve`], with `request` standing in as the client's first stream.
+pub(crate) async fn upgrade<S: AsyncRead + Unpin>(
+    mut stream: S,
+    mut input: BytesMut,
+    request: &HttpRequest,
+    deadline: Instant,
+) -> Result<Upgraded<S>> {
+    let frame = headers_frame(request).context("the upgraded request's header is too large")?;
+    // the client's settings frame has to come before the first stream's headers
+    let preface_len = loop {
+        if input.len() >= PREFACE.len() + 9 {
+            anyhow::ensure!(
+                input.starts_with(PREFACE) && input[PREFACE.len() + 3] == 0x4,
+                "expected the HTTP/2 connection preface after upgrading"
+            );
+            let header = &input[PREFACE.len()..];
+            let length =
+                usize::from(header[0]) << 16 | usize::from(header[1]) << 8 | usize::from(header[2]);
+            if input.len() >= PREFACE.len() + 9 + length {
+                break PREFACE.len() + 9 + length;
+            }
+        }
+        let read = tokio::time::timeout_at(deadline, stream.read_buf(&mut input))
+            .await
+            .context("timed out waiting for the HTTP/2 connection preface")?
+            .context("Failed to read")?;
+        anyhow::ensure!(
+            read > 0,
+            "connection closed before the HTTP/2 connection preface"
+        );
+    };
+    let rest = input.split_off(preface_len);
+    input.extend_from_slice(&frame);
+    input.extend_from_slice(&rest);
+    Ok(Upgraded::new(input.freeze(), stream))
+}
+
+/// Encodes `request` as a HEADERS frame opening stream 1, as RFC 9113 treats the request that
+/// asked for the upgrade. Fields are sent as literals that don't touch the HPACK dynamic table,
+/// so the decoder's state still matches the client's encoder.
+fn headers_frame(request: &HttpRequest) -> Option<Vec<u8>> {
+    let mut block = vec![];
+    let path = match request.raw_query.as_str() {
+        "" => request.raw_path.clone(),
+        query => format!("{}?{query}", request.raw_path),
+    };
+    literal(&mut block, ":method", &request.method);
+    literal(&mut block, ":scheme", "http");
+    literal(&mut block, ":path", &path);
+    if let Some(host) = request.headers.get("Host") {
+        literal(&mut block, ":authority", host);
+    }
+    for (name, value) in request.headers.iter() {
+        let name = name.to_ascii_lowercase();
+        let skipped = CONNECTION_SPECIFIC.contains(&name.as_str())
+            || name == "host"
+            || name == "http2-settings"
+            || name == "te" && !value.eq_ignore_ascii_case("trailers");
+        if !skipped {
+            literal(&mut block, &name, value);
+        }
+    }
+    if block.len() > DEFAULT_MAX_FRAME_SIZE {
+        return None;
+    }
+    let mut frame = (block.len() as u32).to_be_bytes()[1..].to_vec();
+    // HEADERS with END_STREAM and END_HEADERS, on stream 1
+    frame.extend([0x1, 0x1 | 0x4, 0, 0, 0, 1]);
+    frame.extend(block);
+    Some(frame)
+}
+
+/// A literal header field without indexing, with a new name.
+fn literal(block: &mut Vec<u8>, name: &str, value: &str) {
+    block.push(0);
+    for string in [name, value] {
+        integer(block, string.len(), 7);
+        block.extend(string.as_bytes());
+    }
+}
+
+/// An HPACK integer with an `prefix_bits` bit prefix, RFC 7541 section 5.1.
+fn integer(block: &mut Vec<u8>, mut value: usize, prefix_bits: u32) {
+    let max = (1 << prefix_bits) - 1;
+    if value < max {
+        block.push(value as u8);
+        return;
+    }
+    block.push(max as u8);
+    value -= max;
+    while value >= 128 {
+        block.push((value % 128 + 128) as u8);
+        value /= 128;
+    }
+    block.push(value as u8);
+}
+
+#[test]
+fn tests_hpack_literals() {
+    // RFC 7541 C.1
+    let mut block = vec![];
+    integer(&mut block, 10, 5);
+    integer(&mut block, 1337, 5);
+    integer(&mut block, 42, 8);
+    assert_eq!(vec![0x0a, 0x1f, 0x9a, 0x0a, 0x2a], block);
+
+    // RFC 7541 C.2.2, which reuses a static table name where this encodes a new one
+    let mut block = vec![];
+    literal(&mut block, ":path", "/sample/path");
+    assert_eq!(b"\x00\x05:path\x0c/sample/path".to_vec(), block);
+
+    let mut request = HttpRequest {
+        method: "GET".to_string(),
+        raw_path: "/a".to_string(),
+        raw_query: "b=1".to_string(),
+        ..Default::default()
+    };
+    request.headers.insert("Host".to_string(), "x".to_string());
+    request.headers.insert(
+        "Connection".to_string(),
+        "Upgrade, HTTP2-Settings".to_string(),
+    );
+    request
+        .headers
+        .insert("User-Agent".to_string(), "t".to_string());
+    let frame = headers_frame(&request).unwrap();
+    assert_eq!([0, 0, frame.len() as u8 - 9, 1, 5, 0, 0, 0, 1], frame[..9]);
+    let mut expected = vec![];
+    literal(&mut expected, ":method", "GET");
+    literal(&mut expected, ":scheme", "http");
+    literal(&mut expected, ":path", "/a?b=1");
+    literal(&mut expected, ":authority", "x");
+    literal(&mut expected, "user-agent", "t");
+    assert_eq!(expected, frame[9..]);
+}
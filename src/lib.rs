@@ -0,0 +1,71 @@
+//! A small HTTP/1.1 server that can be embedded with custom routes.
+//!
+//! ```no_run
+//! use codecrafters_http_server::{HttpResponse, Server, ServerConfig};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! Server::builder()
+//!     .config(ServerConfig::default())
+//!     .route("GET", "/hello", |_, _| async {
+//!         let mut resp = HttpResponse::ok();
+//!         resp.set_body(b"hello".to_vec());
+//!         Ok(resp)
+//!     })
+//!     .build()
+//!     .run()
+//!     .await
+//! # }
+//! ```
+
+pub mod access_log;
+pub mod auth;
+pub mod body;
+pub mod compression;
+pub mod config;
+pub mod cookie;
+mod date;
+mod digest;
+pub mod error;
+pub mod error_pages;
+pub mod extensions;
+pub mod extract;
+pub mod file_cache;
+pub mod forwarded;
+pub mod guard;
+pub mod handlers;
+pub mod headers;
+mod http2;
+pub mod listener;
+pub mod method;
+pub mod metrics;
+pub mod middleware;
+pub mod mime;
+pub mod negotiate;
+pub mod proxy;
+pub mod rate_limit;
+pub mod request;
+pub mod response;
+pub mod rewrite;
+pub mod router;
+pub mod server;
+pub mod session;
+pub mod sse;
+pub mod static_files;
+pub mod status;
+pub mod template;
+pub mod timeout;
+mod tls;
+pub mod tunnel;
+pub mod upgrade;
+pub mod vhost;
+pub mod websocket;
+
+pub use config::ServerConfig;
+pub use error::HttpError;
+pub use guard::Guard;
+pub use middleware::{Middleware, Next};
+pub use request::{HttpRequest, Version};
+pub use response::{HttpResponse, ResponseBuilder};
+pub use router::{Handler, Router};
+pub use server::{Server, ServerBuilder};
+pub use status::StatusCode;
@@ -1,259 +1,441 @@
-use std::env;
+use std::fmt;
+use std::io::IsTerminal;
+use std::time::Duration;
 
-use crate::request::HttpRequest;
-use crate::response::HttpResponse;
 use anyhow::{Context, Result};
-use bytes::BytesMut;
-use flate2::Compression;
-use flate2::write::GzEncoder;
-use std::io::Write;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-
-mod request;
-mod response;
-
-#[derive(Debug, Clone)]
-struct ServerConfig {
-    static_directory: Option<String>,
+use clap::Parser;
+use codecrafters_http_server::access_log::{AccessLog, LogFormat};
+use codecrafters_http_server::auth::Auth;
+use codecrafters_http_server::config::{ConfigHandle, Overload};
+use codecrafters_http_server::error_pages::ErrorPages;
+use codecrafters_http_server::forwarded::Network;
+use codecrafters_http_server::method::Trace;
+use codecrafters_http_server::proxy::{ForwardProxy, Proxy, ProxyConfig};
+use codecrafters_http_server::rate_limit::RateLimit;
+use codecrafters_http_server::rewrite::Rewrites;
+use codecrafters_http_server::static_files::SymlinkPolicy;
+use codecrafters_http_server::timeout::Timeouts;
+use codecrafters_http_server::tunnel::Tunnel;
+use codecrafters_http_server::vhost::VirtualHosts;
+use codecrafters_http_server::{Server, ServerConfig};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::fmt::format::{FormatEvent, FormatFields, Writer};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Registry, reload};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "A small HTTP/1.1 server")]
+struct Cli {
+    /// TOML config file; the flags below override its settings
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Address to bind to, optionally with a port like `[::]:8080`; may be repeated
+    /// [default: 127.0.0.1]
+    #[arg(long = "address", value_name = "ADDRESS")]
+    addresses: Vec<String>,
+
+    /// Port to listen on [default: 4221]
+    #[arg(long, env = "PORT")]
+    port: Option<u16>,
+
+    /// Listen on a Unix domain socket at this path instead of --address and --port
+    #[arg(long)]
+    unix_socket: Option<String>,
+
+    /// Directory served and written by the /files routes
+    #[arg(long)]
+    directory: Option<String>,
+
+    /// List directory contents under --directory instead of answering 404
+    #[arg(long)]
+    enable_dir_listing: bool,
+
+    /// File extension the /files routes serve and accept, any if omitted; may be repeated or
+    /// comma-separated
+    #[arg(long, value_name = "EXT", value_delimiter = ',')]
+    allowed_extensions: Vec<String>,
+
+    /// File extension the /files routes refuse; may be repeated or comma-separated
+    #[arg(long, value_name = "EXT", value_delimiter = ',')]
+    denied_extensions: Vec<String>,
+
+    /// Serve and write files whose name starts with a dot, like .env, instead of refusing them
+    #[arg(long)]
+    allow_dotfiles: bool,
+
+    /// Which symlinks under --directory are followed: never, same-dir (those pointing inside
+    /// it) or always [default: same-dir]
+    #[arg(long, value_name = "POLICY")]
+    follow_symlinks: Option<SymlinkPolicy>,
+
+    /// Send a Digest header with the SHA-256 of every file served
+    #[arg(long)]
+    enable_file_digests: bool,
+
+    /// Serve a file's precompressed .zst, .br or .gz sibling to clients accepting that encoding
+    #[arg(long)]
+    enable_precompressed: bool,
+
+    /// PEM certificate chain, enables HTTPS together with --key
+    #[arg(long, requires = "key")]
+    cert: Option<String>,
+
+    /// PEM private key for --cert
+    #[arg(long, requires = "cert")]
+    key: Option<String>,
+
+    /// Only speak HTTP/1.1, refusing HTTP/2 through ALPN, prior knowledge and h2c upgrades
+    #[arg(long)]
+    disable_http2: bool,
+
+    /// Seconds to wait for in-flight connections on shutdown [default: 30]
+    #[arg(long)]
+    grace_period: Option<u64>,
+
+    /// Seconds a client may take to send the request headers [default: 10]
+    #[arg(long)]
+    header_timeout: Option<u64>,
+
+    /// Seconds a client may take to send the request body [default: 30]
+    #[arg(long)]
+    body_timeout: Option<u64>,
+
+    /// Seconds an idle keep-alive connection stays open [default: 5]
+    #[arg(long)]
+    keep_alive_timeout: Option<u64>,
+
+    /// Requests served on one connection before it is closed, unlimited if omitted
+    #[arg(long)]
+    max_keep_alive_requests: Option<usize>,
+
+    /// Seconds a handler may take to respond before the request gets a 503, unlimited if omitted
+    #[arg(long)]
+    handler_timeout: Option<u64>,
+
+    /// Largest accepted request header block in bytes [default: 8192]
+    #[arg(long)]
+    max_header_size: Option<usize>,
+
+    /// Largest accepted request body in bytes [default: 16777216]
+    #[arg(long)]
+    max_body_size: Option<usize>,
+
+    /// Bytes of small static files cached in memory, 0 disables the cache [default: 0]
+    #[arg(long)]
+    file_cache_size: Option<usize>,
+
+    /// Maximum number of connections served at once
+    #[arg(long)]
+    max_connections: Option<usize>,
+
+    /// What to do with connections beyond --max-connections: queue or reject [default: queue]
+    #[arg(long)]
+    on_overload: Option<Overload>,
+
+    /// htpasswd file (bcrypt or SHA) whose users may authenticate with Basic auth
+    #[arg(long)]
+    htpasswd: Option<String>,
+
+    /// Token accepted as Bearer credentials; may be repeated
+    #[arg(long = "bearer-token", value_name = "TOKEN")]
+    bearer_tokens: Vec<String>,
+
+    /// host:port CONNECT may tunnel to, `*.` and `*` match any subdomain or port; may be repeated
+    #[arg(long = "connect-target", value_name = "HOST:PORT")]
+    connect_targets: Vec<String>,
+
+    /// Forward requests for absolute URLs to their origin servers, as an HTTP forward proxy
+    #[arg(long)]
+    proxy_mode: bool,
+
+    /// Path prefix that requires authentication, all paths if omitted; may be repeated
+    #[arg(long = "auth-path", value_name = "PREFIX")]
+    auth_paths: Vec<String>,
+
+    /// Proxy address or CIDR block whose X-Forwarded-For and Forwarded headers name the client;
+    /// may be repeated or comma-separated
+    #[arg(long, value_name = "NETWORK", value_delimiter = ',')]
+    trusted_proxies: Vec<Network>,
+
+    /// Requests per second allowed per client IP, unlimited if omitted
+    #[arg(long)]
+    rate_limit: Option<f64>,
+
+    /// Requests a client may burst above --rate-limit [default: 10]
+    #[arg(long)]
+    rate_burst: Option<u32>,
+
+    /// Serve Prometheus metrics at /metrics
+    #[arg(long)]
+    enable_metrics: bool,
+
+    /// Echo TRACE requests back to the client
+    #[arg(long)]
+    enable_trace: bool,
+
+    /// Most verbose level logged: error, warn, info, debug, trace or off [default: info]
+    #[arg(long)]
+    log_level: Option<LevelFilter>,
+
+    /// Log format: common, with diagnostics on stderr, or json [default: common]
+    #[arg(long)]
+    log_format: Option<LogFormat>,
+
+    /// Forward requests below a path prefix to an upstream, e.g. `/api=http://127.0.0.1:3000`;
+    /// may be repeated
+    #[arg(long = "proxy", value_name = "PREFIX=URL", value_parser = parse_proxy)]
+    proxies: Vec<ProxyConfig>,
+
+    /// Content type for a file extension, e.g. `md=text/markdown`; may be repeated
+    #[arg(long = "mime-type", value_name = "EXT=TYPE", value_parser = parse_mime_type)]
+    mime_types: Vec<(String, String)>,
 }
-impl ServerConfig {}
-#[tokio::main]
-async fn main() -> Result<()> {
-    let mut args = env::args();
-    let mut config = ServerConfig {
-        static_directory: None,
-    };
-    println!("Arguments: {:?}", args);
-    if args.len() > 2 {
-        let directory_flag = args.nth(1).context("unable to parse directory flag")?;
-        if directory_flag == "--directory" {
-            let abs_directory = args.next().context("unable to parse absolute directory")?;
-            config.static_directory = Some(abs_directory);
-        }
-    }
 
-    let listener = TcpListener::bind("127.0.0.1:4221")
-        .await
-        .context("Unable to bind port")?;
-
-    println!("Service ready with config: {:?}", config);
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let config = config.clone();
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, config).await {
-                eprintln!("Connection error: {e:?}");
-            }
-        });
+impl Cli {
+    /// Reads the config file, if any, and applies the flags that were given on top of it.
+    fn load_config(&self) -> Result<ServerConfig> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::load(path)?,
+            None => ServerConfig::default(),
+        };
+        let secs = Duration::from_secs;
+        macro_rules! set {
+            ($($field:ident = $value:expr),* $(,)?) => {
+                $(if let Some(value) = $value {
+                    config.$field = value;
+                })*
+            };
+        }
+        set!(
+            port = self.port,
+            grace_period = self.grace_period.map(secs),
+            header_timeout = self.header_timeout.map(secs),
+            body_timeout = self.body_timeout.map(secs),
+            keep_alive_timeout = self.keep_alive_timeout.map(secs),
+            max_header_size = self.max_header_size,
+            max_body_size = self.max_body_size,
+            file_cache_size = self.file_cache_size,
+            follow_symlinks = self.follow_symlinks,
+            overload = self.on_overload,
+            rate_burst = self.rate_burst,
+            log_level = self.log_level,
+            log_format = self.log_format,
+        );
+        if !self.addresses.is_empty() {
+            config.addresses = self.addresses.clone();
+        }
+        if self.unix_socket.is_some() {
+            config.unix_socket = self.unix_socket.clone();
+        }
+        if self.directory.is_some() {
+            config.static_directory = self.directory.clone();
+        }
+        if self.cert.is_some() {
+            config.tls_cert = self.cert.clone();
+            config.tls_key = self.key.clone();
+        }
+        if self.max_connections.is_some() {
+            config.max_connections = self.max_connections;
+        }
+        if self.max_keep_alive_requests.is_some() {
+            config.max_keep_alive_requests = self.max_keep_alive_requests;
+        }
+        if self.htpasswd.is_some() {
+            config.htpasswd = self.htpasswd.clone();
+        }
+        if self.rate_limit.is_some() {
+            config.rate_limit = self.rate_limit;
+        }
+        if let Some(timeout) = self.handler_timeout {
+            config.handler_timeout = Some(secs(timeout));
+        }
+        config.dir_listing |= self.enable_dir_listing;
+        config.allow_dotfiles |= self.allow_dotfiles;
+        config.file_digests |= self.enable_file_digests;
+        config.precompressed |= self.enable_precompressed;
+        config.http2 &= !self.disable_http2;
+        config.metrics |= self.enable_metrics;
+        config.trace |= self.enable_trace;
+        config.proxy_mode |= self.proxy_mode;
+        config
+            .bearer_tokens
+            .extend(self.bearer_tokens.iter().cloned());
+        config
+            .connect_targets
+            .extend(self.connect_targets.iter().cloned());
+        config
+            .allowed_extensions
+            .extend(self.allowed_extensions.iter().cloned());
+        config
+            .denied_extensions
+            .extend(self.denied_extensions.iter().cloned());
+        config.auth_paths.extend(self.auth_paths.iter().cloned());
+        config
+            .trusted_proxies
+            .extend(self.trusted_proxies.iter().copied());
+        config.mime_types.extend(self.mime_types.iter().cloned());
+        config.proxies.extend(self.proxies.iter().cloned());
+        Ok(config)
     }
 }
 
-async fn handle_connection(mut stream: TcpStream, config: ServerConfig) -> Result<()> {
-    loop {
-        let mut input = BytesMut::with_capacity(1024);
+/// Formats events as their message alone, for access log lines in Common Log Format.
+struct MessageOnly;
 
-        let _ = stream
-            .read_buf(&mut input)
-            .await
-            .context("Failed to read")?;
+impl<S, N> FormatEvent<S, N> for MessageOnly
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        ctx.format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
 
-        let request = HttpRequest::from_bytes(input)?;
+fn parse_mime_type(value: &str) -> Result<(String, String), String> {
+    let (extension, content_type) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected EXT=TYPE, got {value:?}"))?;
+    let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+    Ok((extension, content_type.to_string()))
+}
 
-        let response = handle_request(&request, &config);
+fn parse_proxy(value: &str) -> Result<ProxyConfig, String> {
+    let (path, upstream) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected PREFIX=URL, got {value:?}"))?;
+    Ok(ProxyConfig {
+        path: path.to_string(),
+        upstream: upstream.to_string(),
+        strip_prefix: false,
+    })
+}
 
-        let result = match response {
-            Ok(mut resp) => {
-                if let Some(accept_encoding) = &request.headers.get("Accept-Encoding") {
-                    println!("Accept-Encoding: {:?}", accept_encoding);
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let config = cli.load_config()?;
 
-                    if accept_encoding.contains("gzip") {
-                        let mut e = GzEncoder::new(Vec::new(), Compression::default());
-                        e.write_all(&resp.body).unwrap();
-                        resp.body = e.finish().unwrap();
-                        resp.set_header("Content-Encoding".to_string(), "gzip".to_string());
-                        resp.set_header("Content-Length".to_string(), resp.body.len().to_string());
-                    }
-                }
-                if let Some(connection) = request.headers.get("Connection")
-                    && connection == "close"
-                {
-                    resp.set_header("Connection".to_string(), "close".to_string());
-                }
+    let (level, log_level) = reload::Layer::new(config.log_level);
+    let registry = tracing_subscriber::registry().with(level);
+    let is_access_log = |meta: &Metadata| meta.target() == "access_log";
+    match config.log_format {
+        // access log lines alone on stdout, diagnostics with their level and spans on stderr
+        LogFormat::Common => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .event_format(MessageOnly)
+                    .with_filter(filter_fn(is_access_log)),
+            )
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(std::io::stderr)
+                    .with_ansi(std::io::stderr().is_terminal())
+                    .with_filter(filter_fn(move |meta| !is_access_log(meta))),
+            )
+            .init(),
+        LogFormat::Json => registry
+            .with(tracing_subscriber::fmt::layer().json())
+            .init(),
+    }
 
-                resp
-            }
-            Err(_) => HttpResponse::internal_server_error(),
-        };
+    // innermost, so the other layers apply to every host, and TRACE to proxied paths is forwarded
+    let mut builder = Server::builder()
+        .layer(VirtualHosts::new())
+        .layer(Trace::new())
+        .layer(Tunnel::new())
+        .layer(ForwardProxy::new());
+    for proxy in &config.proxies {
+        let mut layer = Proxy::new(proxy.path.clone(), &proxy.upstream)?;
+        if proxy.strip_prefix {
+            layer = layer.strip_prefix();
+        }
+        builder = builder.layer(layer);
+    }
+    if config.htpasswd.is_some() || !config.bearer_tokens.is_empty() {
+        let mut auth =
+            Auth::new("codecrafters-http-server").bearer_tokens(config.bearer_tokens.clone());
+        if let Some(htpasswd) = &config.htpasswd {
+            auth = auth.htpasswd_file(htpasswd)?;
+        }
+        for prefix in &config.auth_paths {
+            auth = auth.protect(prefix.clone());
+        }
+        builder = builder.layer(auth);
+    }
 
-        let _res = stream
-            .write(result.encode().as_slice())
-            .await
-            .context("Unable to write")?;
+    if let Some(rate) = config.rate_limit {
+        builder = builder.layer(RateLimit::new(rate, config.rate_burst));
+    }
 
-        if let Some(connection) = request.headers.get("Connection")
-            && connection == "close"
-        {
-            break;
-        }
+    if config.metrics {
+        builder = builder.metrics_endpoint("/metrics");
     }
-    Ok(())
+
+    // outside the others, so they already see the rewritten path
+    let server = builder
+        .layer(Timeouts::new())
+        .layer(Rewrites::new())
+        .layer(ErrorPages::new())
+        .layer(AccessLog::new(config.log_format))
+        .config(config)
+        .build();
+    reload_on_hangup(cli, server.config_handle(), log_level)?;
+    server.run().await
 }
 
-fn handle_request(request: &HttpRequest, config: &ServerConfig) -> Result<HttpResponse> {
-    let segments = request
-        .path
-        .split("/")
-        .filter(|segment| !segment.is_empty())
-        .collect::<Vec<&str>>();
-
-    if let Some(first_segment) = segments.first() {
-        let resp = match *first_segment {
-            "files" => {
-                let Some(file_path) = segments.get(1) else {
-                    return Ok(HttpResponse::not_found());
-                };
-
-                let Some(root_dir) = &config.static_directory else {
-                    return Ok(HttpResponse::not_found());
-                };
-
-                let file_path = format!("{}{}", root_dir, file_path);
-
-                match request.method.as_str() {
-                    "POST" => {
-                        if let Err(_err) = std::fs::write(&file_path, request.body.clone()) {
-                            eprintln!("Error writing file: {:?}", _err);
-                            return Ok(HttpResponse::internal_server_error());
-                        }
-                        HttpResponse::created()
-                    }
-
-                    "GET" => {
-                        if let Ok(metadata) = std::fs::metadata(&file_path) {
-                            if metadata.is_file() {
-                                let mut resp = HttpResponse::ok();
-                                resp.set_header(
-                                    "Content-Type".to_string(),
-                                    "application/octet-stream".to_string(),
-                                );
-                                resp.set_header(
-                                    "Content-Length".to_string(),
-                                    metadata.len().to_string(),
-                                );
-                                let body_content =
-                                    std::fs::read(file_path).context("Failed to read file")?;
-                                resp.set_body(body_content);
-                                resp
-                            } else {
-                                HttpResponse::not_found()
-                            }
-                        } else {
-                            HttpResponse::not_found()
-                        }
-                    }
-                    _ => {
-                        eprintln!("Unsupported method");
-                        HttpResponse::internal_server_error()
-                    }
+/// Re-reads the configuration on SIGHUP and applies what can change without a restart.
+#[cfg(unix)]
+fn reload_on_hangup(
+    cli: Cli,
+    config: ConfigHandle,
+    log_level: reload::Handle<LevelFilter, Registry>,
+) -> Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = signal(SignalKind::hangup()).context("Unable to listen for SIGHUP")?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            let reloaded = match cli.load_config() {
+                Ok(reloaded) => reloaded,
+                Err(e) => {
+                    tracing::error!("Keeping the current config, reload failed: {e:#}");
+                    continue;
                 }
+            };
+            if let Err(e) = log_level.reload(reloaded.log_level) {
+                tracing::warn!("Unable to change the log level: {e}");
             }
-            "echo" => {
-                let message = *segments.get(1).unwrap_or(&"");
-
-                let mut resp = HttpResponse::ok();
-                resp.set_header("Content-Type".to_string(), "text/plain".to_string());
-                resp.set_header("Content-Length".to_string(), message.len().to_string());
-                resp.set_body(message.as_bytes().into());
-                resp
-            }
-            "user-agent" => {
-                if let Some(user_agent) = request.headers.get("User-Agent") {
-                    let mut resp = HttpResponse::ok();
-                    resp.set_header("Content-Type".to_string(), "text/plain".to_string());
-                    resp.set_header("Content-Length".to_string(), user_agent.len().to_string());
-                    resp.set_body(user_agent.as_bytes().into());
-                    resp
-                } else {
-                    HttpResponse::internal_server_error()
-                }
+            let ignored = config.reload(reloaded);
+            if ignored.is_empty() {
+                tracing::info!("Reloaded config");
+            } else {
+                tracing::warn!(
+                    "Reloaded config, changes to {} need a restart",
+                    ignored.join(", ")
+                );
             }
-            _ => HttpResponse::not_found(),
-        };
-        Ok(resp)
-    } else {
-        Ok(HttpResponse::ok())
-    }
+        }
+    });
+    Ok(())
 }
 
-#[test]
-fn tests_handle_request() {
-    let config = ServerConfig {
-        static_directory: None,
-    };
-
-    let actual = handle_request(
-        &HttpRequest {
-            body: vec![],
-            path: "/".to_string(),
-            method: "GET".to_string(),
-            headers: std::collections::HashMap::new(),
-        },
-        &config,
-    )
-    .unwrap()
-    .status_code;
-    assert_eq!(200, actual);
-
-    let actual = handle_request(
-        &HttpRequest {
-            method: "GET".to_string(),
-            path: "".to_string(),
-            headers: std::collections::HashMap::new(),
-            body: vec![],
-        },
-        &config,
-    )
-    .unwrap()
-    .status_code;
-    assert_eq!(200, actual);
-
-    let actual = handle_request(
-        &HttpRequest {
-            method: "GET".to_string(),
-            path: "/something".to_string(),
-            headers: std::collections::HashMap::new(),
-            body: vec![],
-        },
-        &config,
-    )
-    .unwrap()
-    .status_code;
-    assert_eq!(404, actual);
-
-    let actual = handle_request(
-        &HttpRequest {
-            method: "GET".to_string(),
-            path: "/something/something".to_string(),
-            headers: std::collections::HashMap::new(),
-            body: vec![],
-        },
-        &config,
-    )
-    .unwrap()
-    .status_code;
-    assert_eq!(404, actual);
-
-    let actual = handle_request(
-        &HttpRequest {
-            method: "GET".to_string(),
-            path: "/echo/something".to_string(),
-            headers: std::collections::HashMap::new(),
-            body: vec![],
-        },
-        &config,
-    )
-    .unwrap()
-    .status_code;
-    assert_eq!(200, actual);
+#[cfg(not(unix))]
+fn reload_on_hangup(
+    _cli: Cli,
+    _config: ConfigHandle,
+    _log_level: reload::Handle<LevelFilter, Registry>,
+) -> Result<()> {
+    Ok(())
 }
@@ -0,0 +1,155 @@
+//! What the methods mean to the server: which ones it knows, which ones a route answers besides
+//! its own, and the responses it synthesizes for OPTIONS and TRACE.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::config::ServerConfig;
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::BoxFuture;
+
+/// The methods of RFC 9110 and PATCH. Others are answered with 501 unless a route or layer
+/// handles them.
+pub const KNOWN: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Request headers left out of a TRACE echo, since they carry credentials.
+const HIDDEN_FROM_TRACE: [&str; 3] = ["Authorization", "Proxy-Authorization", "Cookie"];
+
+/// The methods a route registered for `method` answers, GET routes also serve HEAD.
+pub(crate) fn implied(method: &str) -> Vec<&str> {
+    match method {
+        "GET" => vec!["GET", "HEAD"],
+        method => vec![method],
+    }
+}
+
+/// The Allow header value for a resource whose routes answer `methods`, which are also
+/// answered with OPTIONS.
+pub(crate) fn allow(methods: &[&str]) -> String {
+    let mut allowed = methods.to_vec();
+    if !allowed.contains(&"OPTIONS") {
+        allowed.push("OPTIONS");
+    }
+    allowed.join(", ")
+}
+
+/// A 204 answer to an OPTIONS request for a resource whose routes answer `methods`.
+pub(crate) fn options(methods: &[&str]) -> HttpResponse {
+    let mut resp = HttpResponse::no_content();
+    resp.set_header("Allow".to_string(), allow(methods));
+    resp
+}
+
+/// The answer to a TRACE request: the request line and header fields as received, without
+/// credentials, as a `message/http` body.
+pub fn trace(request: &HttpRequest) -> HttpResponse {
+    let mut target = request.raw_path.clone();
+    if !request.raw_query.is_empty() {
+        target = format!("{target}?{}", request.raw_query);
+    }
+    let mut message = format!("{} {target} {}\r\n", request.method, request.version);
+    for (name, value) in request.headers.iter() {
+        if !HIDDEN_FROM_TRACE
+            .iter()
+            .any(|hidden| hidden.eq_ignore_ascii_case(name))
+        {
+            message.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    message.push_str("\r\n");
+    HttpResponse::builder()
+        .header("Content-Type", "message/http")
+        .body(message)
+        .build()
+}
+
+/// Answers TRACE requests with [`trace`] when the config's `trace` is set, and adds TRACE to
+/// the Allow headers of the responses it passes on. With `trace` unset, TRACE is routed like
+/// any other method and usually answered with 405.
+#[derive(Default)]
+pub struct Trace;
+
+impl Trace {
+    pub fn new() -> Self {
+        Trace
+    }
+}
+
+impl Middleware<ServerConfig> for Trace {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        config: Arc<ServerConfig>,
+        next: Next<'a, ServerConfig>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            if !config.trace {
+                return next.run(request, config).await;
+            }
+            if request.method == "TRACE" {
+                return Ok(trace(&request));
+            }
+            let mut resp = next.run(request, config).await?;
+            if let Some(allowed) = resp.headers.get("Allow")
+                && !allowed.split(',').any(|method| method.trim() == "TRACE")
+            {
+                let allowed = format!("{allowed}, TRACE");
+                resp.set_header("Allow".to_string(), allowed);
+            }
+            Ok(resp)
+        })
+    }
+
+    fn accepts(&self, request: &HttpRequest, config: &ServerConfig) -> bool {
+        config.trace && request.method == "TRACE"
+    }
+}
+
+#[test]
+fn tests_allow() {
+    assert_eq!(vec!["GET", "HEAD"], implied("GET"));
+    assert_eq!(vec!["PUT"], implied("PUT"));
+    assert_eq!("GET, HEAD, OPTIONS", allow(&["GET", "HEAD"]));
+    assert_eq!("OPTIONS, GET", allow(&["OPTIONS", "GET"]));
+}
+
+#[tokio::test]
+async fn tests_trace() {
+    use crate::Router;
+
+    let input = "TRACE /echo/a?x=1 HTTP/1.1\r\nHost: localhost\r\nCookie: id=secret\r\n\
+                 X-Custom: kept\r\nauthorization: Basic c2VjcmV0\r\n\r\n";
+    let request = || HttpRequest::from_bytes(input.as_bytes().into()).unwrap();
+    let resp = trace(&request());
+    assert_eq!(Some("message/http"), resp.headers.get("Content-Type"));
+    assert_eq!(
+        Some(&b"TRACE /echo/a?x=1 HTTP/1.1\r\nHost: localhost\r\nX-Custom: kept\r\n\r\n"[..]),
+        resp.body.as_bytes()
+    );
+
+    let router = Router::new()
+        .get("/echo/:msg", |_, _| async { Ok(HttpResponse::ok()) })
+        .layer(Trace::new());
+    let mut config = ServerConfig::default();
+    let resp = router
+        .handle(request(), Arc::new(config.clone()))
+        .await
+        .unwrap();
+    assert_eq!(405, resp.status_code);
+    assert_eq!(Some("GET, HEAD, OPTIONS"), resp.headers.get("Allow"));
+
+    config.trace = true;
+    let config = Arc::new(config);
+    assert!(router.accepts(&request(), &config));
+    let resp = router.handle(request(), config.clone()).await.unwrap();
+    assert_eq!(200, resp.status_code);
+    let mut options = request();
+    options.method = "OPTIONS".to_string();
+    let resp = router.handle(options, config).await.unwrap();
+    assert_eq!(Some("GET, HEAD, OPTIONS, TRACE"), resp.headers.get("Allow"));
+}
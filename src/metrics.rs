@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::AsyncWrite;
+
+use crate::file_cache;
+use crate::response::Delivery;
+use crate::server::is_disconnect;
+
+/// Upper bounds of the latency histogram buckets in seconds, the Prometheus client defaults.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The route label of requests no route handled, e.g. 404s or responses from middleware.
+const UNMATCHED: &str = "unmatched";
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative; the last slot counts those above every bound.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Requests {
+    counts: BTreeMap<(String, String, u16), u64>,
+    latencies: BTreeMap<String, Histogram>,
+}
+
+/// Request, connection and traffic counters shared by all connections of a server, rendered in
+/// the Prometheus text format by [`Metrics::render`].
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<Requests>,
+    connections: AtomicI64,
+    bytes_sent: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Counts a connection as open until the returned guard is dropped.
+    pub(crate) fn connection(&self) -> ConnectionGuard<'_> {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self)
+    }
+
+    pub(crate) fn record_request(
+        &self,
+        method: &str,
+        route: Option<&str>,
+        status: u16,
+        duration: Duration,
+    ) {
+        let route = route.unwrap_or(UNMATCHED);
+        let mut requests = self.requests.lock().unwrap();
+        *requests
+            .counts
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+        requests
+            .latencies
+            .entry(route.to_string())
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    pub(crate) fn record_bytes_sent(&self, bytes: u64) {
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let requests = self.requests.lock().unwrap();
+
+        out += "# HELP http_requests_total Requests handled, by method, route and status.\n";
+        out += "# TYPE http_requests_total counter\n";
+        for ((method, route, status), count) in &requests.counts {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{status}\"}} {count}",
+                escape(method),
+                escape(route)
+            );
+        }
+
+        out += "# HELP http_request_duration_seconds Time from the parsed request until the \
+                response was written, by route.\n";
+        out += "# TYPE http_request_duration_seconds histogram\n";
+        for (route, histogram) in &requests.latencies {
+            let route = escape(route);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{route=\"{route}\",le=\"{bound}\"}} \
+                     {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{route=\"{route}\",le=\"+Inf\"}} {}\n\
+                 http_request_duration_seconds_sum{{route=\"{route}\"}} {}\n\
+                 http_request_duration_seconds_count{{route=\"{route}\"}} {}",
+                histogram.count, histogram.sum, histogram.count
+            );
+        }
+        drop(requests);
+
+        out += "# HELP http_connections_in_flight Connections currently open.\n";
+        out += "# TYPE http_connections_in_flight gauge\n";
+        let _ = writeln!(
+            out,
+            "http_connections_in_flight {}",
+            self.connections.load(Ordering::Relaxed)
+        );
+
+        out += "# HELP http_response_bytes_total Bytes written to clients, headers included.\n";
+        out += "# TYPE http_response_bytes_total counter\n";
+        let _ = writeln!(
+            out,
+            "http_response_bytes_total {}",
+            self.bytes_sent.load(Ordering::Relaxed)
+        );
+
+        let (hits, misses) = file_cache::stats();
+        out += "# HELP http_file_cache_hits_total Static files served from the in-memory cache.\n";
+        out += "# TYPE http_file_cache_hits_total counter\n";
+        let _ = writeln!(out, "http_file_cache_hits_total {hits}");
+        out += "# HELP http_file_cache_misses_total Cacheable static files read from disk.\n";
+        out += "# TYPE http_file_cache_misses_total counter\n";
+        let _ = writeln!(out, "http_file_cache_misses_total {misses}");
+        out
+    }
+}
+
+pub(crate) struct ConnectionGuard<'a>(&'a Metrics);
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Label values are quoted, so backslashes, quotes and line breaks need escaping.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Counts the bytes written through it.
+pub(crate) struct CountingWriter<'a, W> {
+    inner: &'a mut W,
+    pub(crate) written: u64,
+    /// Whether a write failed because the client went away, rather than the body failing.
+    pub(crate) disconnected: bool,
+}
+
+impl<'a, W> CountingWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> Self {
+        CountingWriter {
+            inner,
+            written: 0,
+            disconnected: false,
+        }
+    }
+
+    /// How writing a response through this ended, given the result of writing it.
+    pub(crate) fn delivery<T, E>(&self, written: &Result<T, E>) -> Delivery {
+        match written {
+            Ok(_) => Delivery::Complete,
+            Err(_) if self.disconnected => Delivery::Aborted,
+            Err(_) => Delivery::Failed,
+        }
+    }
+
+    fn record<T>(&mut self, poll: &Poll<io::Result<T>>) {
+        if let Poll::Ready(Err(e)) = poll {
+            self.disconnected |= is_disconnect(e);
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.written += written as u64;
+        }
+        self.record(&poll);
+        poll
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.written += written as u64;
+        }
+        self.record(&poll);
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut *self.inner).poll_flush(cx);
+        self.record(&poll);
+        poll
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut *self.inner).poll_shutdown(cx);
+        self.record(&poll);
+        poll
+    }
+}
+
+#[test]
+fn tests_render() {
+    let metrics = Metrics::new();
+    let connection = metrics.connection();
+    metrics.record_request("GET", Some("/echo/:msg"), 200, Duration::from_millis(3));
+    metrics.record_request("GET", Some("/echo/:msg"), 200, Duration::from_millis(30));
+    metrics.record_request("GET", None, 404, Duration::from_secs(20));
+    metrics.record_bytes_sent(120);
+
+    let rendered = metrics.render();
+    for line in [
+        "http_requests_total{method=\"GET\",route=\"/echo/:msg\",status=\"200\"} 2",
+        "http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1",
+        "http_request_duration_seconds_bucket{route=\"/echo/:msg\",le=\"0.005\"} 1",
+        "http_request_duration_seconds_bucket{route=\"/echo/:msg\",le=\"0.025\"} 1",
+        "http_request_duration_seconds_bucket{route=\"/echo/:msg\",le=\"0.05\"} 2",
+        "http_request_duration_seconds_bucket{route=\"/echo/:msg\",le=\"+Inf\"} 2",
+        "http_request_duration_seconds_count{route=\"/echo/:msg\"} 2",
+        "http_request_duration_seconds_bucket{route=\"unmatched\",le=\"10\"} 0",
+        "http_request_duration_seconds_bucket{route=\"unmatched\",le=\"+Inf\"} 1",
+        "http_connections_in_flight 1",
+        "http_response_bytes_total 120",
+    ] {
+        assert!(rendered.lines().any(|actual| actual == line), "{line}");
+    }
+
+    drop(connection);
+    let rendered = metrics.render();
+    assert!(rendered.contains("http_connections_in_flight 0\n"));
+    assert!(rendered.contains("\nhttp_file_cache_hits_total "));
+    assert!(rendered.contains("\nhttp_file_cache_misses_total "));
+    assert_eq!("a\\\"b\\\\c\\n", escape("a\"b\\c\n"));
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::{BoxFuture, Router};
+
+/// A layer that runs around the router's handlers and may inspect or replace requests and
+/// responses.
+pub trait Middleware<S>: Send + Sync + 'static {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        state: Arc<S>,
+        next: Next<'a, S>,
+    ) -> BoxFuture<'a, Result<HttpResponse>>;
+
+    /// Whether the layer answers `request` itself even if no route matches it. The server asks
+    /// before telling a client that sent `Expect: 100-continue` to go ahead with the body.
+    fn accepts(&self, _request: &HttpRequest, _state: &S) -> bool {
+        false
+    }
+
+    /// Whether the layer handles `request` itself and reads its body from the request's
+    /// [`BodyStream`](crate::BodyStream) while it arrives, rather than once it is buffered.
+    fn streams_body(&self, _request: &HttpRequest, _state: &S) -> bool {
+        false
+    }
+}
+
+/// Middleware written as a closure, see [`from_fn`].
+pub struct FromFn<F>(F);
+
+/// Turns a closure into middleware:
+///
+/// ```
+/// # use codecrafters_http_server::{Router, middleware};
+/// let router: Router<()> = Router::new().layer(middleware::from_fn(|request, state, next| {
+///     Box::pin(async move {
+///         let mut resp = next.run(request, state).await?;
+///         resp.set_header("X-Served-By".to_string(), "codecrafters".to_string());
+///         Ok(resp)
+///     })
+/// }));
+/// ```
+pub fn from_fn<S, F>(f: F) -> FromFn<F>
+where
+    F: for<'a> Fn(HttpRequest, Arc<S>, Next<'a, S>) -> BoxFuture<'a, Result<HttpResponse>>,
+{
+    FromFn(f)
+}
+
+impl<S, F> Middleware<S> for FromFn<F>
+where
+    F: for<'a> Fn(HttpRequest, Arc<S>, Next<'a, S>) -> BoxFuture<'a, Result<HttpResponse>>
+        + Send
+        + Sync
+        + 'static,
+{
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        state: Arc<S>,
+        next: Next<'a, S>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        (self.0)(request, state, next)
+    }
+}
+
+/// The rest of the middleware stack, ending in the router's handler.
+pub struct Next<'a, S> {
+    pub(crate) router: &'a Router<S>,
+    pub(crate) middleware: &'a [Arc<dyn Middleware<S>>],
+}
+
+impl<'a, S: 'static> Next<'a, S> {
+    pub async fn run(self, request: HttpRequest, state: Arc<S>) -> Result<HttpResponse> {
+        // the most recently added layer wraps everything added before it
+        match self.middleware.split_last() {
+            Some((layer, rest)) => {
+                let next = Next {
+                    router: self.router,
+                    middleware: rest,
+                };
+                layer.handle(request, state, next).await
+            }
+            None => self.router.dispatch(request, state).await,
+        }
+    }
+}
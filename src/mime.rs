@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+const DEFAULT: &str = "application/octet-stream";
+
+/// Picks the Content-Type for `path` by its extension. `overrides` maps lowercase extensions to
+/// content types and takes precedence over the built-in table.
+pub fn content_type(path: &Path, overrides: &HashMap<String, String>) -> String {
+    let Some(extension) = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+    else {
+        return DEFAULT.to_string();
+    };
+    if let Some(content_type) = overrides.get(&extension) {
+        return content_type.clone();
+    }
+    builtin(&extension).unwrap_or(DEFAULT).to_string()
+}
+
+fn builtin(extension: &str) -> Option<&'static str> {
+    let content_type = match extension {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+#[test]
+fn tests_content_type() {
+    let overrides = HashMap::from([("md".to_string(), "text/x-markdown".to_string())]);
+    let content_type = |path: &str| content_type(Path::new(path), &overrides);
+    assert_eq!("text/html; charset=utf-8", content_type("index.html"));
+    assert_eq!("image/png", content_type("logo.PNG"));
+    assert_eq!("application/json", content_type("dir/data.json"));
+    assert_eq!("text/x-markdown", content_type("README.md"));
+    assert_eq!("application/octet-stream", content_type("archive.unknown"));
+    assert_eq!("application/octet-stream", content_type("Makefile"));
+}
@@ -0,0 +1,174 @@
+//! Content negotiation on the Accept header:
+//!
+//! ```
+//! # use codecrafters_http_server::{HttpRequest, HttpResponse};
+//! fn list(request: &HttpRequest) -> Result<HttpResponse, codecrafters_http_server::HttpError> {
+//!     let body = match request.negotiate(&["application/json", "text/html"])? {
+//!         "application/json" => "[]",
+//!         _ => "<ul></ul>",
+//!     };
+//!     Ok(HttpResponse::builder().header("Vary", "Accept").body(body).build())
+//! }
+//! ```
+
+/// One entry of an Accept header, e.g. `text/*;q=0.5`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    /// The lowercased type, `*` for any.
+    pub kind: String,
+    /// The lowercased subtype, `*` for any.
+    pub subtype: String,
+    /// The q-value, from 0 (not acceptable) to 1.
+    pub quality: f32,
+}
+
+impl MediaRange {
+    fn matches(&self, kind: &str, subtype: &str) -> bool {
+        (self.kind == "*" || self.kind.eq_ignore_ascii_case(kind))
+            && (self.subtype == "*" || self.subtype.eq_ignore_ascii_case(subtype))
+    }
+
+    /// Ranges naming a type outrank wildcards matching it.
+    fn specificity(&self) -> u8 {
+        match (self.kind.as_str(), self.subtype.as_str()) {
+            ("*", _) => 0,
+            (_, "*") => 1,
+            _ => 2,
+        }
+    }
+}
+
+/// Parses an Accept header into its media ranges, most preferred first: by q-value, then
+/// specific types before wildcards, then in header order. Media type parameters other than `q`
+/// are ignored, and malformed entries are skipped.
+pub fn parse_accept(accept: &str) -> Vec<MediaRange> {
+    let mut ranges = vec![];
+    for item in accept.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let Some((kind, subtype)) = parts.next().and_then(|range| range.split_once('/')) else {
+            continue;
+        };
+        if kind.is_empty() || subtype.is_empty() || kind == "*" && subtype != "*" {
+            continue;
+        }
+        let quality = parts
+            .find_map(|param| {
+                param
+                    .strip_prefix("q=")
+                    .or_else(|| param.strip_prefix("Q="))
+            })
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+            .unwrap_or(0.0);
+        ranges.push(MediaRange {
+            kind: kind.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            quality: quality.clamp(0.0, 1.0),
+        });
+    }
+    // a stable sort keeps header order among equals
+    ranges.sort_by(|a, b| {
+        b.quality
+            .total_cmp(&a.quality)
+            .then(b.specificity().cmp(&a.specificity()))
+    });
+    ranges
+}
+
+/// The q-value the client gives `media_type`, taken from the most specific range matching it.
+fn quality(ranges: &[MediaRange], media_type: &str) -> f32 {
+    let essence = media_type.split(';').next().unwrap_or_default().trim();
+    let Some((kind, subtype)) = essence.split_once('/') else {
+        return 0.0;
+    };
+    ranges
+        .iter()
+        .filter(|range| range.matches(kind, subtype))
+        .max_by_key(|range| range.specificity())
+        .map_or(0.0, |range| range.quality)
+}
+
+/// Picks the type in `available` the client prefers according to `accept`, the earliest of
+/// equally preferred ones. Without an Accept header anything is acceptable. Returns `None` if
+/// nothing in `available` is, which is answered with 406.
+pub fn negotiate<'a>(accept: Option<&str>, available: &[&'a str]) -> Option<&'a str> {
+    let Some(accept) = accept else {
+        return available.first().copied();
+    };
+    let ranges = parse_accept(accept);
+    let (media_type, quality) = available
+        .iter()
+        .map(|media_type| (*media_type, quality(&ranges, media_type)))
+        // max_by keeps the last of equal elements, so iterate in reverse preference order
+        .rev()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    (quality > 0.0).then_some(media_type)
+}
+
+#[test]
+fn tests_parse_accept() {
+    let ranges = parse_accept("text/*;q=0.5, */*;q=0.1, Text/HTML, application/json;q=0.5, bad");
+    let order: Vec<(String, f32)> = ranges
+        .iter()
+        .map(|range| (format!("{}/{}", range.kind, range.subtype), range.quality))
+        .collect();
+    assert_eq!(
+        vec![
+            ("text/html".to_string(), 1.0),
+            ("application/json".to_string(), 0.5),
+            ("text/*".to_string(), 0.5),
+            ("*/*".to_string(), 0.1),
+        ],
+        order
+    );
+    assert!(parse_accept("*/html, ;q=1").is_empty());
+    assert_eq!(0.0, parse_accept("text/plain;q=x")[0].quality);
+}
+
+#[test]
+fn tests_negotiate() {
+    let available = ["application/json", "text/html; charset=utf-8"];
+    assert_eq!(Some("application/json"), negotiate(None, &available));
+    assert_eq!(
+        Some("text/html; charset=utf-8"),
+        negotiate(Some("text/html, application/json;q=0.9"), &available)
+    );
+    assert_eq!(
+        Some("application/json"),
+        negotiate(Some("text/*, application/*"), &available)
+    );
+    // the most specific range decides, even over a higher-ranked wildcard
+    assert_eq!(
+        Some("application/json"),
+        negotiate(Some("*/*, text/html;q=0.2"), &available)
+    );
+    assert_eq!(None, negotiate(Some("image/png"), &available));
+    assert_eq!(
+        None,
+        negotiate(
+            Some("*/*;q=0.5, application/json;q=0, text/*;q=0"),
+            &available
+        )
+    );
+
+    let mut request = crate::HttpRequest::default();
+    request
+        .headers
+        .append("Accept".to_string(), "image/*".to_string());
+    request
+        .headers
+        .append("Accept".to_string(), "text/html;q=0.1".to_string());
+    assert_eq!(
+        Ok("text/html; charset=utf-8"),
+        request.negotiate(&available).map_err(|_| ())
+    );
+    request.headers.remove("Accept");
+    request
+        .headers
+        .append("Accept".to_string(), "image/*".to_string());
+    let error = request.negotiate(&available).unwrap_err();
+    assert_eq!(crate::StatusCode::NotAcceptable, error.status());
+    assert_eq!(
+        "available types are application/json, text/html; charset=utf-8",
+        error.to_string()
+    );
+}
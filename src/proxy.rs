@@ -0,0 +1,679 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::BytesMut;
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+use crate::body::{self, BodyStream, Framing};
+use crate::config::ServerConfig;
+use crate::headers::HeaderMap;
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::{BoxFuture, has_path_prefix};
+use crate::status::StatusCode;
+
+/// How long the upstream may take to accept the connection and send its response head.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest accepted upstream response head.
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+
+/// How this server names itself in the Via headers it adds as a forward proxy.
+const VIA_NAME: &str = "codecrafters-http-server";
+
+/// Connection-specific headers, which are not forwarded in either direction.
+const HOP_BY_HOP: [&str; 8] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// A `[[proxies]]` table of the config file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Requests at or below this path prefix are forwarded.
+    pub path: String,
+    /// The upstream base URL, e.g. "http://127.0.0.1:3000".
+    pub upstream: String,
+    /// Whether `path` is removed before the request path is appended to the upstream URL.
+    pub strip_prefix: bool,
+}
+
+/// Forwards requests below a path prefix to an upstream HTTP server and streams its response
+/// back, adding X-Forwarded-For, X-Forwarded-Host and X-Forwarded-Proto.
+pub struct Proxy {
+    prefix: String,
+    /// The upstream's `host:port`.
+    authority: String,
+    /// The upstream URL's path, prepended to forwarded paths.
+    base_path: String,
+    strip_prefix: bool,
+}
+
+impl Proxy {
+    /// Forwards requests at or below `prefix`, e.g. "/api", to `upstream`, which must be an
+    /// `http://` URL.
+    pub fn new(prefix: impl Into<String>, upstream: &str) -> Result<Self> {
+        let rest = upstream
+            .strip_prefix("http://")
+            .with_context(|| format!("unsupported upstream {upstream:?}, expected http://"))?;
+        let (authority, base_path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if authority.is_empty() {
+            anyhow::bail!("upstream {upstream:?} has no host");
+        }
+        Ok(Proxy {
+            prefix: prefix.into().trim_end_matches('/').to_string(),
+            authority: with_default_port(authority),
+            base_path: base_path.trim_end_matches('/').to_string(),
+            strip_prefix: false,
+        })
+    }
+
+    /// Removes the prefix from forwarded paths, so "/api/users" reaches the upstream as "/users".
+    pub fn strip_prefix(mut self) -> Self {
+        self.strip_prefix = true;
+        self
+    }
+
+    /// The request target sent upstream.
+    fn target(&self, request: &HttpRequest) -> String {
+        let path = if request.raw_path.is_empty() {
+            &request.path
+        } else {
+            &request.raw_path
+        };
+        let path = match self.strip_prefix {
+            true => &path[self.prefix.len().min(path.len())..],
+            false => path,
+        };
+        let mut target = format!("{}{}", self.base_path, path);
+        if !target.starts_with('/') {
+            target.insert(0, '/');
+        }
+        if !request.raw_query.is_empty() {
+            target = format!("{target}?{}", request.raw_query);
+        }
+        target
+    }
+
+    /// The request head and buffered body sent upstream. A body that is still arriving follows
+    /// with the client's Content-Length, or chunked if the client sent none.
+    fn encode_request(
+        &self,
+        request: &HttpRequest,
+        config: &ServerConfig,
+        streamed: bool,
+    ) -> Vec<u8> {
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method, self.target(request));
+        let mut forwarded_for = request
+            .headers
+            .get_all("X-Forwarded-For")
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Some(peer) = request.peer_addr {
+            if !forwarded_for.is_empty() {
+                forwarded_for += ", ";
+            }
+            forwarded_for += &peer.ip().to_string();
+        }
+        for (name, value) in request.headers.iter() {
+            let skipped = ["Host", "Content-Length", "X-Forwarded-For"]
+                .iter()
+                .chain(&HOP_BY_HOP)
+                .any(|header| header.eq_ignore_ascii_case(name));
+            if !skipped {
+                head += &format!("{name}: {value}\r\n");
+            }
+        }
+        head += &format!("Host: {}\r\n", self.authority);
+        if !forwarded_for.is_empty() {
+            head += &format!("X-Forwarded-For: {forwarded_for}\r\n");
+        }
+        if let Some(host) = request.headers.get("Host") {
+            head += &format!("X-Forwarded-Host: {host}\r\n");
+        }
+        let proto = if config.tls_cert.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        head += &format!("X-Forwarded-Proto: {proto}\r\n");
+        finish_request(head, request, streamed)
+    }
+
+    async fn forward(
+        &self,
+        mut request: HttpRequest,
+        config: &ServerConfig,
+    ) -> Result<HttpResponse> {
+        let body = request.take_body_stream();
+        let head = self.encode_request(&request, config, body.is_some());
+        exchange(&self.authority, head, &request, body).await
+    }
+}
+
+/// `authority` with port 80 added if it names none, e.g. "[::1]:80" for "[::1]".
+fn with_default_port(authority: &str) -> String {
+    if authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.contains(']'))
+    {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    }
+}
+
+/// Ends the head of a request sent upstream and appends its buffered body. A body that is still
+/// arriving follows with the client's Content-Length, or chunked if the client sent none.
+fn finish_request(mut head: String, request: &HttpRequest, streamed: bool) -> Vec<u8> {
+    // one connection per request keeps the upstream's framing simple
+    head += "Connection: close\r\n";
+    if streamed {
+        match request.headers.get("Content-Length") {
+            Some(length) => head += &format!("Content-Length: {length}\r\n"),
+            None => head += "Transfer-Encoding: chunked\r\n",
+        }
+    } else if !request.body.is_empty() || !matches!(request.method.as_str(), "GET" | "HEAD") {
+        head += &format!("Content-Length: {}\r\n", request.body.len());
+    }
+    head += "\r\n";
+
+    let mut encoded = head.into_bytes();
+    encoded.extend_from_slice(&request.body);
+    encoded
+}
+
+/// Sends `head` to the server at `authority`, followed by the streamed `body` of `request` if
+/// it has one, and streams the response back.
+async fn exchange(
+    authority: &str,
+    head: Vec<u8>,
+    request: &HttpRequest,
+    body: Option<BodyStream>,
+) -> Result<HttpResponse> {
+    let deadline = Instant::now() + UPSTREAM_TIMEOUT;
+    let connected = tokio::time::timeout_at(deadline, TcpStream::connect(authority)).await;
+    let mut upstream = match connected {
+        Ok(Ok(upstream)) => upstream,
+        Ok(Err(e)) => {
+            tracing::warn!("Unable to connect to upstream {authority}: {e}");
+            return Ok(HttpResponse::new(StatusCode::BadGateway));
+        }
+        Err(_) => return Ok(HttpResponse::new(StatusCode::GatewayTimeout)),
+    };
+    let chunked = body.is_some() && !request.headers.contains_key("Content-Length");
+    upstream
+        .write_all(&head)
+        .await
+        .context("Unable to write to upstream")?;
+    if let Some(mut body) = body {
+        // each piece goes upstream as soon as the client sent it
+        while let Some(chunk) = body.chunk().await {
+            let chunk = chunk.context("Request body incomplete")?;
+            if chunked {
+                let size = format!("{:x}\r\n", chunk.len());
+                upstream.write_all(size.as_bytes()).await?;
+                upstream.write_all(&chunk).await?;
+                upstream.write_all(b"\r\n").await?;
+            } else {
+                upstream.write_all(&chunk).await?;
+            }
+        }
+        if chunked {
+            upstream.write_all(b"0\r\n\r\n").await?;
+        }
+    }
+
+    let mut input = BytesMut::with_capacity(4096);
+    let head_end = loop {
+        if let Some(end) = input.windows(4).position(|word| word == b"\r\n\r\n") {
+            break end;
+        }
+        if input.len() > MAX_RESPONSE_HEAD {
+            return Ok(HttpResponse::new(StatusCode::BadGateway));
+        }
+        match tokio::time::timeout_at(deadline, upstream.read_buf(&mut input)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => return Ok(HttpResponse::new(StatusCode::BadGateway)),
+            Ok(Ok(_)) => {}
+            Err(_) => return Ok(HttpResponse::new(StatusCode::GatewayTimeout)),
+        }
+    };
+    let Some((status, headers)) = parse_response_head(&input[..head_end]) else {
+        return Ok(HttpResponse::new(StatusCode::BadGateway));
+    };
+    let mut buffered = input.split_off(head_end + 4);
+
+    let mut resp = HttpResponse::new(status);
+    for (name, value) in headers.iter() {
+        if !HOP_BY_HOP
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case(name))
+        {
+            resp.headers.append(name.to_string(), value.to_string());
+        }
+    }
+    let bodyless = request.method == "HEAD"
+        || status.is_informational()
+        || status == StatusCode::NoContent
+        || status == StatusCode::NotModified;
+    if bodyless {
+        return Ok(resp);
+    }
+    let framing = if headers
+        .get("Transfer-Encoding")
+        .is_some_and(|coding| coding.to_ascii_lowercase().contains("chunked"))
+    {
+        // the response is re-chunked on the way out
+        resp.headers.remove("Content-Length");
+        Some(Framing::Chunked)
+    } else {
+        match headers.get("Content-Length").map(str::parse) {
+            Some(Ok(length)) => Some(Framing::Length(length)),
+            Some(Err(_)) => return Ok(HttpResponse::new(StatusCode::BadGateway)),
+            None => None,
+        }
+    };
+    match framing {
+        Some(Framing::Length(0)) => {}
+        Some(framing) => {
+            let (tx, body) = BodyStream::channel();
+            tokio::spawn(async move {
+                // the client reads the body at its own pace, so only the framing is enforced
+                let deadline = Instant::now() + Duration::from_secs(24 * 60 * 60);
+                if let Err(e) = body::forward(
+                    &mut upstream,
+                    &mut buffered,
+                    framing,
+                    tx,
+                    deadline,
+                    usize::MAX,
+                )
+                .await
+                {
+                    tracing::warn!("Upstream body error: {e:#}");
+                }
+            });
+            resp.set_stream(body);
+        }
+        // the body ends when the upstream closes the connection
+        None => resp.set_stream(std::io::Cursor::new(buffered).chain(upstream)),
+    }
+    Ok(resp)
+}
+
+fn parse_response_head(head: &[u8]) -> Option<(StatusCode, HeaderMap)> {
+    let head = std::str::from_utf8(head).ok()?;
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next()?;
+    let code: u16 = status_line.split_whitespace().nth(1)?.parse().ok()?;
+    let status = StatusCode::try_from(code).ok()?;
+    let mut headers = HeaderMap::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        headers.append(name.trim().to_string(), value.trim().to_string());
+    }
+    Some((status, headers))
+}
+
+impl Middleware<ServerConfig> for Proxy {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        config: Arc<ServerConfig>,
+        next: Next<'a, ServerConfig>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            if !has_path_prefix(&request.path, &self.prefix) {
+                return next.run(request, config).await;
+            }
+            self.forward(request, &config).await
+        })
+    }
+
+    fn accepts(&self, request: &HttpRequest, _config: &ServerConfig) -> bool {
+        has_path_prefix(&request.path, &self.prefix)
+    }
+
+    fn streams_body(&self, request: &HttpRequest, _config: &ServerConfig) -> bool {
+        has_path_prefix(&request.path, &self.prefix)
+    }
+}
+
+/// Forwards absolute-form requests like `GET http://example.com/ HTTP/1.1`, which clients send
+/// to the proxy they are configured with, to the origin server they name when the config's
+/// `proxy_mode` is set. Hop-by-hop headers, including those the client's Connection header
+/// lists, are dropped and a Via header is added in both directions. There is no TLS client, so
+/// `https` targets get a 501; clients tunnel those with CONNECT instead.
+#[derive(Default)]
+pub struct ForwardProxy;
+
+impl ForwardProxy {
+    pub fn new() -> Self {
+        ForwardProxy
+    }
+
+    /// The request head sent to the origin, which gets the path-only target and the Host the
+    /// client named.
+    fn encode_request(request: &HttpRequest, authority: &str, streamed: bool) -> Vec<u8> {
+        let mut target = request.raw_path.clone();
+        if !request.raw_query.is_empty() {
+            target = format!("{target}?{}", request.raw_query);
+        }
+        let mut head = format!("{} {target} HTTP/1.1\r\n", request.method);
+        let listed: Vec<&str> = request
+            .headers
+            .get_all("Connection")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        for (name, value) in request.headers.iter() {
+            let skipped = ["Host", "Content-Length", "Via"]
+                .iter()
+                .chain(&HOP_BY_HOP)
+                .chain(&listed)
+                .any(|header| header.eq_ignore_ascii_case(name));
+            if !skipped {
+                head += &format!("{name}: {value}\r\n");
+            }
+        }
+        head += &format!("Host: {authority}\r\n");
+        let version = request.version.as_str().trim_start_matches("HTTP/");
+        head += &format!("Via: {}\r\n", via(request.headers.get_all("Via"), version));
+        finish_request(head, request, streamed)
+    }
+}
+
+/// The Via header listing earlier `hops` and then this server, which received the message in
+/// HTTP `version`, e.g. "1.1".
+fn via<'a>(hops: impl Iterator<Item = &'a str>, version: &str) -> String {
+    let mut via: Vec<String> = hops.map(str::to_string).collect();
+    via.push(format!("{version} {VIA_NAME}"));
+    via.join(", ")
+}
+
+impl Middleware<ServerConfig> for ForwardProxy {
+    fn handle<'a>(
+        &'a self,
+        mut request: HttpRequest,
+        config: Arc<ServerConfig>,
+        next: Next<'a, ServerConfig>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            if !self.accepts(&request, &config) {
+                return next.run(request, config).await;
+            }
+            if request.target_scheme != Some("http") {
+                return Ok(HttpResponse::new(StatusCode::NotImplemented));
+            }
+            let authority = request.headers.get("Host").unwrap_or_default().to_string();
+            let body = request.take_body_stream();
+            let head = Self::encode_request(&request, &authority, body.is_some());
+            let mut resp = exchange(&with_default_port(&authority), head, &request, body).await?;
+            // the origin is always spoken to in HTTP/1.1
+            let via = via(resp.headers.get_all("Via"), "1.1");
+            resp.set_header("Via".to_string(), via);
+            Ok(resp)
+        })
+    }
+
+    fn accepts(&self, request: &HttpRequest, config: &ServerConfig) -> bool {
+        config.proxy_mode && request.target_scheme.is_some()
+    }
+
+    fn streams_body(&self, request: &HttpRequest, config: &ServerConfig) -> bool {
+        self.accepts(request, config)
+    }
+}
+
+#[test]
+fn tests_target() {
+    let request = |path: &str| HttpRequest {
+        method: "GET".to_string(),
+        path: path.to_string(),
+        raw_path: path.to_string(),
+        raw_query: "q=a%20b".to_string(),
+        ..Default::default()
+    };
+    let proxy = Proxy::new("/api/", "http://localhost:3000").unwrap();
+    assert_eq!("localhost:3000", proxy.authority);
+    assert_eq!("/api/users?q=a%20b", proxy.target(&request("/api/users")));
+
+    let proxy = Proxy::new("/api", "http://[::1]/v1/")
+        .unwrap()
+        .strip_prefix();
+    assert_eq!("[::1]:80", proxy.authority);
+    assert_eq!("/v1/users?q=a%20b", proxy.target(&request("/api/users")));
+    assert_eq!("/v1?q=a%20b", proxy.target(&request("/api")));
+
+    assert!(Proxy::new("/api", "https://example.com").is_err());
+}
+
+#[tokio::test]
+async fn tests_proxy() {
+    use crate::router::Router;
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = upstream.local_addr().unwrap();
+    let upstream = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut request = vec![];
+        while !request.ends_with(b"hello") {
+            let mut buf = [0; 1024];
+            let read = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+        }
+        stream
+            .write_all(
+                b"HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\nX-Upstream: yes\r\n\
+                  Connection: close\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        String::from_utf8(request).unwrap()
+    });
+
+    let router: Router<ServerConfig> = Router::new()
+        .get("/", |_, _| async { Ok(HttpResponse::ok()) })
+        .layer(Proxy::new("/api", &format!("http://{address}")).unwrap());
+    let mut request = HttpRequest {
+        method: "POST".to_string(),
+        path: "/api/items".to_string(),
+        raw_path: "/api/items".to_string(),
+        body: bytes::Bytes::from_static(b"hello"),
+        peer_addr: Some("10.0.0.7:5000".parse().unwrap()),
+        ..Default::default()
+    };
+    request
+        .headers
+        .insert("Host".to_string(), "gateway.local".to_string());
+    request
+        .headers
+        .insert("X-Forwarded-For".to_string(), "192.0.2.1".to_string());
+    request
+        .headers
+        .insert("Connection".to_string(), "keep-alive".to_string());
+    let resp = router
+        .handle(request, Arc::new(ServerConfig::default()))
+        .await
+        .unwrap();
+
+    assert_eq!(201, resp.status_code);
+    assert_eq!(Some("yes"), resp.headers.get("X-Upstream"));
+    assert_eq!(None, resp.headers.get("Transfer-Encoding"));
+    let mut body = String::new();
+    let crate::response::Body::Stream(mut stream) = resp.body else {
+        panic!("expected a streamed body");
+    };
+    stream.read_to_string(&mut body).await.unwrap();
+    assert_eq!("abcde", body);
+
+    let forwarded = upstream.await.unwrap();
+    assert!(forwarded.starts_with("POST /api/items HTTP/1.1\r\n"));
+    assert!(forwarded.contains(&format!("Host: {address}\r\n")));
+    assert!(forwarded.contains("X-Forwarded-For: 192.0.2.1, 10.0.0.7\r\n"));
+    assert!(forwarded.contains("X-Forwarded-Host: gateway.local\r\n"));
+    assert!(forwarded.contains("X-Forwarded-Proto: http\r\n"));
+    assert!(forwarded.contains("Connection: close\r\n"));
+    assert!(!forwarded.contains("keep-alive"));
+    assert!(forwarded.ends_with("Content-Length: 5\r\n\r\nhello"));
+
+    let unreachable: Router<ServerConfig> =
+        Router::new().layer(Proxy::new("/", "http://127.0.0.1:1").unwrap());
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        path: "/x".to_string(),
+        ..Default::default()
+    };
+    let resp = unreachable
+        .handle(request, Arc::new(ServerConfig::default()))
+        .await
+        .unwrap();
+    assert_eq!(502, resp.status_code);
+}
+
+#[tokio::test]
+async fn tests_proxy_streamed_body() {
+    use crate::body::Piece;
+    use crate::router::Router;
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = upstream.local_addr().unwrap();
+    let (first_read, first_forwarded) = tokio::sync::oneshot::channel();
+    let upstream = tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut request = vec![];
+        let mut first_read = Some(first_read);
+        while !request.ends_with(b"0\r\n\r\n") {
+            let mut buf = [0; 1024];
+            let read = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+            if request.ends_with(b"hello\r\n")
+                && let Some(first_read) = first_read.take()
+            {
+                first_read.send(()).unwrap();
+            }
+        }
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8(request).unwrap()
+    });
+
+    let router: Router<ServerConfig> =
+        Router::new().layer(Proxy::new("/api", &format!("http://{address}")).unwrap());
+    let (tx, body) = BodyStream::channel();
+    let request = HttpRequest {
+        method: "POST".to_string(),
+        path: "/api/upload".to_string(),
+        raw_path: "/api/upload".to_string(),
+        body_stream: Some(body),
+        ..Default::default()
+    };
+    let config = ServerConfig::default();
+    assert!(router.streams_body(&request, &config));
+    let (resp, _) = tokio::join!(router.handle(request, Arc::new(config)), async move {
+        tx.send(Ok(Piece::Data(bytes::Bytes::from_static(b"hello"))))
+            .await
+            .unwrap();
+        // the second piece only exists once the first reached the upstream
+        first_forwarded.await.unwrap();
+        tx.send(Ok(Piece::Data(bytes::Bytes::from_static(b" world"))))
+            .await
+            .unwrap();
+    });
+    assert_eq!(204, resp.unwrap().status_code);
+
+    let forwarded = upstream.await.unwrap();
+    assert!(forwarded.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(!forwarded.contains("Content-Length"));
+    assert!(forwarded.ends_with("\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"));
+}
+
+#[tokio::test]
+async fn tests_forward_proxy() {
+    use crate::router::Router;
+
+    let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = origin.local_addr().unwrap();
+    let origin = tokio::spawn(async move {
+        let (mut stream, _) = origin.accept().await.unwrap();
+        let mut request = vec![];
+        while !request.ends_with(b"\r\n\r\n") {
+            let mut buf = [0; 1024];
+            let read = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nVia: 1.0 cache\r\nContent-Length: 2\r\n\r\nok")
+            .await
+            .unwrap();
+        String::from_utf8(request).unwrap()
+    });
+
+    let router: Router<ServerConfig> = Router::new().layer(ForwardProxy::new());
+    let request = |target_scheme, via: &str| {
+        let mut request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/a b".to_string(),
+            raw_path: "/a%20b".to_string(),
+            raw_query: "x=1".to_string(),
+            target_scheme: Some(target_scheme),
+            ..Default::default()
+        };
+        for (name, value) in [
+            ("Host", address.to_string().as_str()),
+            ("Connection", "keep-alive, X-Hop"),
+            ("X-Hop", "dropped"),
+            ("Via", via),
+            ("Accept", "*/*"),
+        ] {
+            request.headers.append(name.to_string(), value.to_string());
+        }
+        request
+    };
+    let config = Arc::new(ServerConfig::default());
+    let resp = router
+        .handle(request("http", "1.0 client"), config)
+        .await
+        .unwrap();
+    assert_eq!(404, resp.status_code);
+
+    let config = Arc::new(ServerConfig {
+        proxy_mode: true,
+        ..Default::default()
+    });
+    let resp = router
+        .handle(request("http", "1.0 client"), config.clone())
+        .await
+        .unwrap();
+    assert_eq!(200, resp.status_code);
+    assert_eq!(
+        Some("1.0 cache, 1.1 codecrafters-http-server"),
+        resp.headers.get("Via")
+    );
+    let forwarded = origin.await.unwrap();
+    assert!(forwarded.starts_with("GET /a%20b?x=1 HTTP/1.1\r\n"));
+    assert!(forwarded.contains(&format!("Host: {address}\r\n")));
+    assert!(forwarded.contains("Accept: */*\r\n"));
+    assert!(forwarded.contains("Via: 1.0 client, 1.1 codecrafters-http-server\r\n"));
+    assert!(!forwarded.contains("X-Hop") && !forwarded.contains("keep-alive"));
+
+    let resp = router
+        .handle(request("https", "1.0 client"), config)
+        .await
+        .unwrap();
+    assert_eq!(501, resp.status_code);
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Result;
+
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::{BoxFuture, has_path_prefix};
+use crate::status::StatusCode;
+
+/// Buckets are pruned once this many clients are tracked.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Limits each client IP to `rate` requests per second with bursts of up to `burst` requests,
+/// answering the excess with 429 Too Many Requests.
+pub struct RateLimit {
+    rate: f64,
+    burst: f64,
+    paths: Vec<String>,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimit {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        assert!(rate > 0.0, "the rate limit must be positive");
+        RateLimit {
+            rate,
+            burst: f64::from(burst.max(1)),
+            paths: vec![],
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Limits only requests whose path is `prefix` or below it; without any prefix all requests
+    /// are limited.
+    pub fn limit_path(mut self, prefix: impl Into<String>) -> Self {
+        self.paths.push(prefix.into());
+        self
+    }
+
+    /// Takes a token for `client`, or returns how many seconds until one is available.
+    fn acquire(&self, client: IpAddr, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            // full buckets behave exactly like new ones, so they can be dropped
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst
+            });
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.rate).ceil() as u64)
+        }
+    }
+}
+
+impl<S: Send + Sync + 'static> Middleware<S> for RateLimit {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        state: Arc<S>,
+        next: Next<'a, S>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let limited = self.paths.is_empty()
+                || self
+                    .paths
+                    .iter()
+                    .any(|prefix| has_path_prefix(&request.path, prefix));
+            // requests without a peer address, e.g. in-process ones, are never limited
+            if let Some(client) = request.remote_addr().filter(|_| limited)
+                && let Err(retry_after) = self.acquire(client, Instant::now())
+            {
+                let mut resp = HttpResponse::new(StatusCode::TooManyRequests);
+                resp.set_header("Retry-After".to_string(), retry_after.to_string());
+                return Ok(resp);
+            }
+            next.run(request, state).await
+        })
+    }
+}
+
+#[test]
+fn tests_acquire() {
+    let limit = RateLimit::new(2.0, 3);
+    let client: IpAddr = "10.0.0.1".parse().unwrap();
+    let other: IpAddr = "10.0.0.2".parse().unwrap();
+    let start = Instant::now();
+
+    for _ in 0..3 {
+        assert_eq!(Ok(()), limit.acquire(client, start));
+    }
+    assert_eq!(Err(1), limit.acquire(client, start));
+    assert_eq!(Ok(()), limit.acquire(other, start));
+
+    // two tokens per second refill
+    let later = start + std::time::Duration::from_millis(500);
+    assert_eq!(Ok(()), limit.acquire(client, later));
+    assert_eq!(Err(1), limit.acquire(client, later));
+
+    let much_later = start + std::time::Duration::from_secs(60);
+    for _ in 0..3 {
+        assert_eq!(Ok(()), limit.acquire(client, much_later));
+    }
+    assert_eq!(Err(1), limit.acquire(client, much_later));
+}
+
+#[tokio::test]
+async fn tests_rate_limit() {
+    use crate::router::Router;
+
+    let router: Router<()> = Router::new()
+        .get("/api/:name", |_, _| async { Ok(HttpResponse::ok()) })
+        .get("/health", |_, _| async { Ok(HttpResponse::ok()) })
+        .layer(RateLimit::new(0.1, 1).limit_path("/api"));
+    let handle = async |path: &str| {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            peer_addr: Some("127.0.0.1:5000".parse().unwrap()),
+            ..Default::default()
+        };
+        router.handle(request, Arc::new(())).await.unwrap()
+    };
+
+    assert_eq!(200, handle("/api/a").await.status_code);
+    let resp = handle("/api/b").await;
+    assert_eq!(429, resp.status_code);
+    assert_eq!(Some("10"), resp.headers.get("Retry-After"));
+    assert_eq!(200, handle("/health").await.status_code);
+}
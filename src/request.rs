@@ -1,60 +1,1296 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
+use std::sync::Arc;
 
-use anyhow::{Context, Error};
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
 
+use crate::body::{BodyStream, Framing, MAX_CHUNK_LINE, parse_chunk_size, parse_trailer};
+use crate::cookie;
+use crate::error::HttpError;
+use crate::extensions::Extensions;
+use crate::forwarded::{self, Network};
+use crate::headers::HeaderMap;
+use crate::negotiate;
+use crate::status::StatusCode;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    #[default]
+    Http11,
+    Http2,
+}
+
+impl Version {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+            Version::Http2 => "HTTP/2",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct HttpRequest {
     pub method: String,
+    /// The percent-decoded request path, without the query string.
     pub path: String,
-    pub headers: HashMap<String, String>,
-    pub body: Vec<u8>,
+    /// The path exactly as it was sent by the client, without the query string.
+    pub raw_path: String,
+    /// The query string exactly as it was sent, without the `?`.
+    pub raw_query: String,
+    /// The scheme of an absolute-form target like "http://example.com/", which clients send to
+    /// proxies. `None` for the usual path-only targets.
+    pub target_scheme: Option<&'static str>,
+    pub version: Version,
+    pub headers: HeaderMap,
+    /// The buffered body, empty for routes that stream it, see [`HttpRequest::take_body_stream`].
+    pub body: Bytes,
+    /// Trailer fields sent after a chunked buffered body. Those of a streamed body are read
+    /// from [`BodyStream::trailers`].
+    pub trailers: HeaderMap,
+    pub(crate) body_stream: Option<BodyStream>,
+    /// Path parameters captured by the router, e.g. `name` for "/files/:name".
+    pub params: HashMap<String, String>,
+    /// Parameters from the query string, e.g. `upper` for "/echo/hi?upper=true".
+    pub query: HashMap<String, String>,
+    /// The address of the connected client, if the transport has one. Behind a proxy this is
+    /// the proxy, see [`HttpRequest::remote_addr`].
+    pub peer_addr: Option<SocketAddr>,
+    /// The client address the peer forwarded the request for, set by the server if the peer
+    /// is one of the config's `trusted_proxies`.
+    pub(crate) forwarded_for: Option<IpAddr>,
+    /// Values attached by the layers the request passed through.
+    pub extensions: Extensions,
 }
 
 impl HttpRequest {
-    pub fn from_bytes(bytes: BytesMut) -> Result<HttpRequest, Error> {
-        let header_end = bytes
-            .windows(4)
-            .position(|word| word == b"\r\n\r\n")
-            .context("Unable to find header/body seperator")?;
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|value| value.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(|value| value.as_str())
+    }
+
+    /// The address of the client the request came from: the peer's, or the one in its
+    /// Forwarded or X-Forwarded-For header if the peer is a trusted proxy. Used for logging and
+    /// rate limiting.
+    pub fn remote_addr(&self) -> Option<IpAddr> {
+        self.forwarded_for
+            .or_else(|| self.peer_addr.map(|peer| peer.ip()))
+    }
+
+    /// Takes the client address from the forwarding headers if the peer is one of `trusted`.
+    pub(crate) fn resolve_remote_addr(&mut self, trusted: &[Network]) {
+        if let Some(peer) = self.peer_addr
+            && !trusted.is_empty()
+        {
+            let client = forwarded::client_addr(peer.ip(), &self.headers, trusted);
+            self.forwarded_for = Some(client).filter(|client| *client != peer.ip());
+        }
+    }
+
+    /// Takes the body of a request to a route registered with
+    /// [`Router::route_streaming`](crate::Router::route_streaming), which is not buffered in
+    /// [`HttpRequest::body`]. Returns `None` for other routes and once taken.
+    pub fn take_body_stream(&mut self) -> Option<BodyStream> {
+        self.body_stream.take()
+    }
+
+    /// Picks the type in `available` the Accept header prefers, see
+    /// [`negotiate`](negotiate::negotiate). Fails with a 406 listing `available` if none is
+    /// acceptable.
+    pub fn negotiate<'a>(&self, available: &[&'a str]) -> Result<&'a str, HttpError> {
+        let accept = self.headers.get_all("Accept").collect::<Vec<_>>();
+        let accept = (!accept.is_empty()).then(|| accept.join(", "));
+        negotiate::negotiate(accept.as_deref(), available).ok_or_else(|| {
+            HttpError::new(
+                StatusCode::NotAcceptable,
+                format!("available types are {}", available.join(", ")),
+            )
+        })
+    }
+
+    /// How the body that follows the header block ends, `None` if there is none.
+    pub(crate) fn framing(&self) -> Option<Framing> {
+        if transfer_codings(&self.headers)
+            .last()
+            .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+        {
+            return Some(Framing::Chunked);
+        }
+        match content_length(&self.headers).ok()?? {
+            0 => None,
+            length => Some(Framing::Length(length)),
+        }
+    }
 
-        let header_data = &bytes[..header_end];
-        let body_data = &bytes[header_end + 4..];
+    /// Whether the client asked to keep the connection open, explicitly or by default for
+    /// HTTP/1.1.
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.headers.get("Connection").is_some_and(|connection| {
+                connection
+                    .split(',')
+                    .any(|item| item.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        match self.version {
+            Version::Http10 => has_token("keep-alive"),
+            Version::Http11 => !has_token("close"),
+            // connection-specific fields don't exist in HTTP/2
+            Version::Http2 => true,
+        }
+    }
+
+    /// Deserializes the JSON body, failing with a 415 if the Content-Type is not
+    /// `application/json` (or a `+json` type) and with a 400 if the body doesn't deserialize.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, HttpError> {
+        let content_type = self.headers.get("Content-Type").ok_or_else(|| {
+            HttpError::new(
+                StatusCode::UnsupportedMediaType,
+                "missing Content-Type, expected application/json",
+            )
+        })?;
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if mime != "application/json"
+            && !(mime.starts_with("application/") && mime.ends_with("+json"))
+        {
+            return Err(HttpError::new(
+                StatusCode::UnsupportedMediaType,
+                format!("unexpected Content-Type {content_type:?}, expected application/json"),
+            ));
+        }
+        serde_json::from_slice(&self.body)
+            .map_err(|e| HttpError::new(StatusCode::BadRequest, format!("invalid JSON body: {e}")))
+    }
+
+    /// The cookies sent in the Cookie header.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        self.headers
+            .get("Cookie")
+            .map(cookie::parse)
+            .unwrap_or_default()
+    }
 
-        let header_str = std::str::from_utf8(header_data).context("unable to parse header")?;
+    /// Parses an `application/x-www-form-urlencoded` body like a query string, with `+` as a
+    /// space. Fails with a 415 for other content types and a 400 for invalid percent-encoding.
+    pub fn form(&self) -> Result<HashMap<String, String>, HttpError> {
+        let expected = "expected application/x-www-form-urlencoded";
+        let content_type = self.headers.get("Content-Type").ok_or_else(|| {
+            HttpError::new(
+                StatusCode::UnsupportedMediaType,
+                format!("missing Content-Type, {expected}"),
+            )
+        })?;
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        if !mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return Err(HttpError::new(
+                StatusCode::UnsupportedMediaType,
+                format!("unexpected Content-Type {content_type:?}, {expected}"),
+            ));
+        }
+        let body = std::str::from_utf8(&self.body)
+            .map_err(|_| malformed("form body is not valid UTF-8"))?;
+        Ok(parse_query(body)?)
+    }
 
-        let mut lines = header_str.lines();
+    /// Returns the total length of the request once the full header block (and, for chunked
+    /// requests, the whole chunked body) is buffered.
+    pub fn expected_length(bytes: &[u8]) -> Result<Option<usize>, ParseError> {
+        let mut parser = RequestParser::new();
+        Ok(match parser.feed(bytes)? {
+            Parsed::Complete(_, length) => Some(length),
+            Parsed::NeedMoreData => parser.announced_length(),
+        })
+    }
+
+    /// Parses a request that was received in full, see [`RequestParser`] for one that arrives
+    /// piecemeal.
+    pub fn from_bytes(mut bytes: BytesMut) -> Result<HttpRequest, ParseError> {
+        match RequestParser::new().feed_buf(&mut bytes)? {
+            Parsed::Complete(request, _) => Ok(*request),
+            Parsed::NeedMoreData => Err(malformed("incomplete request")),
+        }
+    }
 
-        let request_line = lines.next().context("No request line")?;
-        let request_line_parts: Vec<&str> = request_line.split_whitespace().collect();
-        if request_line_parts.len() != 3 {
-            anyhow::bail!(
+    /// Parses the request line and header fields, leaving the body empty. The header fields
+    /// share a single copy of the head instead of each getting strings of their own.
+    /// Obsolete line folding is unfolded if `allow_obs_fold` is set and rejected otherwise.
+    pub(crate) fn from_head(
+        header_data: &[u8],
+        allow_obs_fold: bool,
+    ) -> Result<HttpRequest, ParseError> {
+        let header_str =
+            std::str::from_utf8(header_data).map_err(|_| malformed("unable to parse header"))?;
+        let head: Arc<str> = Arc::from(header_str);
+        let range = |part: &str| -> Range<usize> {
+            let start = part.as_ptr() as usize - head.as_ptr() as usize;
+            start..start + part.len()
+        };
+
+        // lines end in CRLF only, as a lone LF or CR would split lines differently for a server
+        // behind this one
+        if header_str
+            .split("\r\n")
+            .any(|line| line.contains(['\r', '\n']))
+        {
+            return Err(malformed("bare CR or LF in the request head"));
+        }
+        let mut lines = head.split("\r\n");
+
+        let request_line = lines.next().ok_or_else(|| malformed("No request line"))?;
+        // the parts are separated by exactly one space each
+        let mut request_line_parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) = (
+            request_line_parts.next(),
+            request_line_parts.next(),
+            request_line_parts.next(),
+            request_line_parts.next(),
+        ) else {
+            return Err(malformed(format!(
                 "invalid request line: expected 3 parts, got {}",
-                request_line_parts.len()
-            );
+                request_line.split(' ').count()
+            )));
+        };
+        if method.is_empty() || !method.bytes().all(is_token_char) {
+            return Err(malformed(format!("invalid method {method:?}")));
+        }
+        if target.is_empty() || target.bytes().any(|byte| byte.is_ascii_control()) {
+            return Err(malformed(format!("invalid request target {target:?}")));
         }
-        let mut request_headers = HashMap::new();
-        for header in lines {
-            if header.is_empty() {
+        let version = parse_version(version)?;
+        let mut request_headers = HeaderMap::new();
+        for line in lines {
+            if line.is_empty() {
                 break;
             }
-            let parts: Vec<&str> = header.split(": ").collect();
-            if parts.len() != 2 {
-                anyhow::bail!("invalid header: expected 2 parts, got {}", parts.len());
+            // a line starting with whitespace continues the previous field's value
+            if line.starts_with([' ', '\t']) {
+                if !allow_obs_fold {
+                    return Err(malformed("obsolete line folding is not allowed"));
+                }
+                let continuation = field_value(line)
+                    .ok_or_else(|| malformed("invalid header field continuation"))?;
+                if !request_headers.extend_last(continuation) {
+                    return Err(malformed("folded line without a header field"));
+                }
+                continue;
             }
-            request_headers.insert(parts[0].to_string(), parts[1].to_string());
+            let (name, value) = split_field(line).ok_or_else(|| {
+                let name = line.split(':').next().unwrap_or_default();
+                malformed(format!("invalid header field {name:?}"))
+            })?;
+            request_headers.append_shared(&head, range(name), range(value));
         }
-        let content_length: usize = request_headers
-            .get("Content-Length")
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(0);
+        check_framing(&request_headers)?;
 
-        let body = body_data[..content_length.min(body_data.len())].to_vec();
+        // proxies send the absolute form, whose authority replaces the Host header
+        let (target_scheme, target) = match absolute_form(target) {
+            Some((scheme, authority, path)) => {
+                if authority.is_empty() {
+                    return Err(malformed(format!("invalid request target {target:?}")));
+                }
+                request_headers.insert("Host".to_string(), authority.to_string());
+                (Some(scheme), path)
+            }
+            None => (None, target),
+        };
+
+        let (raw_path, raw_query, query) = match target.split_once('?') {
+            Some((path, query)) => (path, query, parse_query(query)?),
+            None => (target, "", HashMap::new()),
+        };
+        // an absolute-form target may leave out the path
+        let raw_path = if raw_path.is_empty() { "/" } else { raw_path };
+        let path = percent_decode(raw_path, false)
+            .map_err(|e| malformed(format!("invalid request path {raw_path:?}: {e}")))?;
 
         Ok(HttpRequest {
-            method: request_line_parts[0].to_string(),
-            path: request_line_parts[1].to_string(),
+            method: method.to_string(),
+            path,
+            raw_path: raw_path.to_string(),
+            raw_query: raw_query.to_string(),
+            version,
             headers: request_headers,
-            body,
+            query,
+            target_scheme,
+            ..Default::default()
+        })
+    }
+}
+
+/// Parses the version of a request line. Minor versions above 1.1 are answered as 1.1, the newest
+/// this server speaks, while other major versions are unsupported.
+fn parse_version(version: &str) -> Result<Version, ParseError> {
+    let digits = version.strip_prefix("HTTP/").map(str::as_bytes);
+    let Some(&[major, b'.', minor]) = digits else {
+        return Err(malformed(format!("invalid HTTP version {version:?}")));
+    };
+    if !major.is_ascii_digit() || !minor.is_ascii_digit() {
+        return Err(malformed(format!("invalid HTTP version {version:?}")));
+    }
+    match (major, minor) {
+        (b'1', b'0') => Ok(Version::Http10),
+        (b'1', _) => Ok(Version::Http11),
+        _ => Err(ParseError::UnsupportedVersion(version.to_string())),
+    }
+}
+
+/// Splits a header field line at its first colon into a name, which must be a token, and a value
+/// without the whitespace around it.
+pub(crate) fn split_field(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+    if name.is_empty() || !name.bytes().all(is_token_char) {
+        return None;
+    }
+    Some((name, field_value(value)?))
+}
+
+/// Trims a field value, rejecting control characters other than tabs.
+fn field_value(value: &str) -> Option<&str> {
+    let value = value.trim_matches([' ', '\t']);
+    let control = |byte: u8| byte.is_ascii_control() && byte != b'\t';
+    (!value.bytes().any(control)).then_some(value)
+}
+
+fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Splits an absolute-form request target like "http://example.com/a?b" into its lowercased
+/// scheme, its authority and the path and query that follow it.
+fn absolute_form(target: &str) -> Option<(&'static str, &str, &str)> {
+    let (scheme, rest) = target.split_once("://")?;
+    let scheme = if scheme.eq_ignore_ascii_case("http") {
+        "http"
+    } else if scheme.eq_ignore_ascii_case("https") {
+        "https"
+    } else {
+        return None;
+    };
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    Some((scheme, authority, path))
+}
+
+/// Decodes `%XX` escapes (and `+` as a space when `plus_as_space` is set), rejecting truncated
+/// or non-hex escapes and results that are not valid UTF-8.
+pub fn percent_decode(input: &str, plus_as_space: bool) -> Result<String, ParseError> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| malformed(format!("invalid percent-encoding at byte {i}")))?;
+                decoded.push(hex);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| malformed("percent-decoded value is not valid UTF-8"))
+}
+
+pub(crate) fn parse_query(query: &str) -> Result<HashMap<String, String>, ParseError> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(name, true)?, percent_decode(value, true)?))
+        })
+        .collect()
+}
+
+/// The codings of the Transfer-Encoding fields, in the order they were applied.
+fn transfer_codings(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+}
+
+/// The body length of the Content-Length fields, which may repeat it but must all agree.
+fn content_length(headers: &HeaderMap) -> Result<Option<usize>, ParseError> {
+    let mut length = None;
+    for value in headers
+        .get_all("Content-Length")
+        .flat_map(|value| value.split(','))
+    {
+        let value = value.trim();
+        let parsed = match value.bytes().all(|byte| byte.is_ascii_digit()) {
+            true => value.parse().ok(),
+            false => None,
+        };
+        let parsed =
+            parsed.ok_or_else(|| malformed(format!("invalid Content-Length {value:?}")))?;
+        if length.is_some_and(|length| length != parsed) {
+            return Err(malformed("conflicting Content-Length fields"));
+        }
+        length = Some(parsed);
+    }
+    Ok(length)
+}
+
+/// Rejects requests whose body could be delimited in more than one way. A proxy in front of
+/// the server might pick another than it does and take part of the body for a request of its
+/// own, or the other way round.
+fn check_framing(headers: &HeaderMap) -> Result<(), ParseError> {
+    let length = content_length(headers)?;
+    let mut codings = transfer_codings(headers).peekable();
+    if codings.peek().is_none() {
+        return Ok(());
+    }
+    if length.is_some() {
+        return Err(malformed("both Content-Length and Transfer-Encoding"));
+    }
+    let chunked = codings
+        .map(|coding| coding.eq_ignore_ascii_case("chunked"))
+        .collect::<Vec<_>>();
+    match chunked.iter().position(|&chunked| chunked) {
+        Some(position) if position == chunked.len() - 1 => Ok(()),
+        _ => Err(malformed(
+            "chunked must be the final transfer coding, applied once",
+        )),
+    }
+}
+
+/// Why a [`RequestParser`] gave up on a request.
+#[derive(Debug, Error)]
+pub enum ParseError {
+    /// The bytes received can never form a valid request.
+    #[error("{0}")]
+    Malformed(String),
+    /// The request line and header fields exceed [`RequestParser::max_head_size`].
+    #[error("request header fields too large")]
+    HeadTooLarge,
+    /// The body is announced or turns out to exceed [`RequestParser::max_body_size`].
+    #[error("request body too large")]
+    BodyTooLarge,
+    /// The request line names an HTTP version other than 1.x.
+    #[error("unsupported HTTP version {0:?}")]
+    UnsupportedVersion(String),
+}
+
+fn malformed(message: impl Into<String>) -> ParseError {
+    ParseError::Malformed(message.into())
+}
+
+/// What [`RequestParser::feed`] found in the bytes so far.
+#[derive(Debug)]
+pub enum Parsed {
+    /// The request is incomplete, feed the parser again once more bytes have arrived.
+    NeedMoreData,
+    /// A whole request, which took up this many bytes at the start of the buffer. Any bytes
+    /// after them belong to the next request.
+    Complete(Box<HttpRequest>, usize),
+}
+
+/// Parses a request from a buffer that grows as bytes arrive. Each [`RequestParser::feed`] gets
+/// everything received since the request began and carries on where the previous one stopped,
+/// so every byte is only examined once. After a complete request the parser starts over.
+#[derive(Debug)]
+pub struct RequestParser {
+    state: ParseState,
+    max_head_size: usize,
+    max_body_size: usize,
+    allow_obs_fold: bool,
+}
+
+#[derive(Debug)]
+enum ParseState {
+    /// Looking for the end of the header block, with this many bytes already searched.
+    Head(usize),
+    Body {
+        request: Box<HttpRequest>,
+        /// Where the body starts in the buffer.
+        start: usize,
+        body: BodyState,
+    },
+}
+
+#[derive(Debug)]
+enum BodyState {
+    Length(usize),
+    /// Decoding a chunked body, `pos` is where decoding left off.
+    Chunked {
+        pos: usize,
+        next: Chunk,
+        body: BytesMut,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Chunk {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailers,
+}
+
+impl Default for ParseState {
+    fn default() -> Self {
+        ParseState::Head(0)
+    }
+}
+
+impl Default for RequestParser {
+    fn default() -> Self {
+        RequestParser {
+            state: ParseState::default(),
+            max_head_size: usize::MAX,
+            max_body_size: usize::MAX,
+            allow_obs_fold: false,
+        }
+    }
+}
+
+impl RequestParser {
+    pub fn new() -> Self {
+        RequestParser::default()
+    }
+
+    /// Fails with [`ParseError::HeadTooLarge`] once the request line and header fields take up
+    /// more than `bytes`.
+    pub fn max_head_size(mut self, bytes: usize) -> Self {
+        self.max_head_size = bytes;
+        self
+    }
+
+    /// Fails with [`ParseError::BodyTooLarge`] as soon as the Content-Length or a chunk size
+    /// takes the body past `bytes`, before the body itself arrives.
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.max_body_size = bytes;
+        self
+    }
+
+    /// Unfolds header values continued on lines starting with whitespace, a form obsoleted by
+    /// RFC 7230, instead of rejecting them.
+    pub fn allow_obs_fold(mut self, allow: bool) -> Self {
+        self.allow_obs_fold = allow;
+        self
+    }
+
+    /// The request line and header fields once they are complete, with the offset where the
+    /// body starts.
+    pub fn head(&self) -> Option<(&HttpRequest, usize)> {
+        match &self.state {
+            ParseState::Body { request, start, .. } => Some((request, *start)),
+            _ => None,
+        }
+    }
+
+    /// Takes the parsed head, leaving the body to the caller, and starts over.
+    pub fn take_head(&mut self) -> Option<(HttpRequest, usize)> {
+        match std::mem::take(&mut self.state) {
+            ParseState::Body { request, start, .. } => Some((*request, start)),
+            state => {
+                self.state = state;
+                None
+            }
+        }
+    }
+
+    /// The total length of a request with a Content-Length once its head is parsed.
+    fn announced_length(&self) -> Option<usize> {
+        match &self.state {
+            ParseState::Body {
+                start,
+                body: BodyState::Length(length),
+                ..
+            } => Some(start + length),
+            _ => None,
+        }
+    }
+
+    /// Parses the request line and header fields from `buf`, for callers that decide how to
+    /// receive the body before it is parsed. Returns the head once it is complete.
+    pub fn parse_head(&mut self, buf: &[u8]) -> Result<Option<(&HttpRequest, usize)>, ParseError> {
+        if let ParseState::Head(scanned) = self.state {
+            // the terminator may straddle the bytes searched before
+            let from = scanned.saturating_sub(3);
+            let Some(end) = find(&buf[from..], b"\r\n\r\n").map(|end| from + end) else {
+                if buf.len() > self.max_head_size {
+                    return Err(ParseError::HeadTooLarge);
+                }
+                self.state = ParseState::Head(buf.len());
+                return Ok(None);
+            };
+            if end > self.max_head_size {
+                return Err(ParseError::HeadTooLarge);
+            }
+            let request = HttpRequest::from_head(&buf[..end], self.allow_obs_fold)?;
+            let start = end + 4;
+            let body = match request.framing() {
+                None => BodyState::Length(0),
+                Some(Framing::Length(length)) if length > self.max_body_size => {
+                    return Err(ParseError::BodyTooLarge);
+                }
+                Some(Framing::Length(length)) => BodyState::Length(length),
+                Some(Framing::Chunked) => BodyState::Chunked {
+                    pos: start,
+                    next: Chunk::Size,
+                    body: BytesMut::new(),
+                },
+            };
+            self.state = ParseState::Body {
+                request: Box::new(request),
+                start,
+                body,
+            };
+        }
+        Ok(self.head())
+    }
+
+    /// Parses as much of `buf`, all bytes received since the request began, as possible.
+    pub fn feed(&mut self, buf: &[u8]) -> Result<Parsed, ParseError> {
+        Ok(match self.complete(buf)? {
+            Some((mut request, body, end)) => {
+                if let Some(body) = body {
+                    request.body = Bytes::copy_from_slice(&buf[body]);
+                }
+                Parsed::Complete(request, end)
+            }
+            None => Parsed::NeedMoreData,
+        })
+    }
+
+    /// Like [`RequestParser::feed`], for the buffer the bytes are received into. A complete
+    /// request is split off the front of `buf`, and its body is a slice of those bytes rather
+    /// than a copy.
+    pub fn feed_buf(&mut self, buf: &mut BytesMut) -> Result<Parsed, ParseError> {
+        Ok(match self.complete(buf)? {
+            Some((mut request, body, end)) => {
+                let bytes = buf.split_to(end).freeze();
+                if let Some(body) = body {
+                    request.body = bytes.slice(body);
+                }
+                Parsed::Complete(request, end)
+            }
+            None => Parsed::NeedMoreData,
         })
     }
+
+    fn complete(&mut self, buf: &[u8]) -> Result<Option<Completed>, ParseError> {
+        let result = self.advance(buf);
+        if !matches!(result, Ok(None)) {
+            self.state = ParseState::default();
+        }
+        result
+    }
+
+    fn advance(&mut self, buf: &[u8]) -> Result<Option<Completed>, ParseError> {
+        if self.parse_head(buf)?.is_none() {
+            return Ok(None);
+        }
+        let ParseState::Body {
+            request,
+            start,
+            body,
+        } = &mut self.state
+        else {
+            unreachable!("the head was parsed above");
+        };
+        let (range, end) = match body {
+            BodyState::Length(length) => {
+                let end = *start + *length;
+                if buf.len() < end {
+                    return Ok(None);
+                }
+                (Some(*start..end), end)
+            }
+            BodyState::Chunked { pos, next, body } => {
+                let trailers = &mut request.trailers;
+                match decode_chunks(buf, pos, next, body, trailers, self.max_body_size)? {
+                    Some(end) => {
+                        request.body = std::mem::take(body).freeze();
+                        (None, end)
+                    }
+                    None => return Ok(None),
+                }
+            }
+        };
+        let ParseState::Body { request, .. } = std::mem::take(&mut self.state) else {
+            unreachable!("the state is only replaced here");
+        };
+        Ok(Some((request, range, end)))
+    }
+}
+
+/// A complete request, the range of its Content-Length body in the buffer, which is left for
+/// the caller to take, and where the request ends.
+type Completed = (Box<HttpRequest>, Option<Range<usize>>, usize);
+
+/// Finds `needle`, which starts with `\r`, jumping from one `\r` to the next rather than
+/// comparing at every offset.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let mut from = 0;
+    while let Some(offset) = haystack[from..].iter().position(|&byte| byte == b'\r') {
+        let at = from + offset;
+        if haystack[at..].starts_with(needle) {
+            return Some(at);
+        }
+        from = at + 1;
+    }
+    None
+}
+
+/// Decodes the chunks that are complete in `buf` from `pos` on into `body` and `trailers`,
+/// returning where the chunked body ends once its last chunk and trailers are in. A chunk that
+/// would take the body past `max_size` fails as soon as its size is read.
+fn decode_chunks(
+    buf: &[u8],
+    pos: &mut usize,
+    next: &mut Chunk,
+    body: &mut BytesMut,
+    trailers: &mut HeaderMap,
+    max_size: usize,
+) -> Result<Option<usize>, ParseError> {
+    let line = |pos: usize| -> Result<Option<&[u8]>, ParseError> {
+        match find(&buf[pos..], b"\r\n") {
+            Some(end) => Ok(Some(&buf[pos..pos + end])),
+            None if buf.len() - pos > MAX_CHUNK_LINE => Err(malformed("chunk line too long")),
+            None => Ok(None),
+        }
+    };
+    loop {
+        match *next {
+            Chunk::Size => {
+                let Some(size_line) = line(*pos)? else {
+                    return Ok(None);
+                };
+                let size = parse_chunk_size(size_line).ok_or_else(|| {
+                    let line = String::from_utf8_lossy(size_line);
+                    malformed(format!("invalid chunk size line {line:?}"))
+                })?;
+                if size > max_size.saturating_sub(body.len()) {
+                    return Err(ParseError::BodyTooLarge);
+                }
+                *pos += size_line.len() + 2;
+                *next = match size {
+                    0 => Chunk::Trailers,
+                    size => Chunk::Data(size),
+                };
+            }
+            Chunk::Data(size) => {
+                if buf.len() < *pos + size {
+                    return Ok(None);
+                }
+                body.extend_from_slice(&buf[*pos..*pos + size]);
+                *pos += size;
+                *next = Chunk::DataEnd;
+            }
+            Chunk::DataEnd => {
+                if buf.len() < *pos + 2 {
+                    return Ok(None);
+                }
+                if &buf[*pos..*pos + 2] != b"\r\n" {
+                    return Err(malformed("chunk is not terminated by CRLF"));
+                }
+                *pos += 2;
+                *next = Chunk::Size;
+            }
+            // trailer fields follow up to the terminating empty line
+            Chunk::Trailers => {
+                let Some(field) = line(*pos)? else {
+                    return Ok(None);
+                };
+                *pos += field.len() + 2;
+                if field.is_empty() {
+                    return Ok(Some(*pos));
+                }
+                let (name, value) =
+                    parse_trailer(field).ok_or_else(|| malformed("invalid trailer field"))?;
+                trailers.append(name, value);
+            }
+        }
+    }
+}
+
+#[test]
+fn tests_expected_length() {
+    let expected_length = |bytes: &[u8]| HttpRequest::expected_length(bytes).unwrap();
+    assert_eq!(None, expected_length(b"GET / HTTP/1.1\r\nHost: a"));
+    assert_eq!(Some(18), expected_length(b"GET / HTTP/1.1\r\n\r\n"));
+    assert_eq!(
+        Some(43),
+        expected_length(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab")
+    );
+    assert_eq!(
+        None,
+        expected_length(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n")
+    );
+    assert_eq!(
+        Some(60),
+        expected_length(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n"
+        )
+    );
+}
+
+#[test]
+fn tests_chunked_body() {
+    let request = HttpRequest::from_bytes(BytesMut::from(
+        &b"POST /files/a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+           4\r\nWiki\r\n6;ext=1\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n\
+           0\r\nChecksum: x\r\n\r\n"[..],
+    ))
+    .unwrap();
+    assert_eq!(b"Wikipedia in \r\n\r\nchunks.".to_vec(), request.body);
+    assert_eq!(Some("x"), request.trailers.get("checksum"));
+
+    assert!(
+        HttpRequest::from_bytes(BytesMut::from(
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nabc\r\n0\r\n\r\n"[..],
+        ))
+        .is_err()
+    );
+}
+
+#[test]
+fn tests_request_smuggling() {
+    // the framing of the body that would follow the head
+    let parse = |head: &str| {
+        let request = format!("POST / HTTP/1.1\r\nHost: a\r\n{head}\r\n\r\n");
+        let mut parser = RequestParser::new();
+        let parsed = parser.parse_head(request.as_bytes())?;
+        Ok::<_, ParseError>(parsed.unwrap().0.framing())
+    };
+    let framing = |head: &str| parse(head).unwrap();
+
+    assert_eq!(
+        Some(Framing::Length(3)),
+        framing("Content-Length: 3\r\nContent-Length: 3")
+    );
+    assert_eq!(Some(Framing::Length(3)), framing("Content-Length: 3, 3"));
+    assert_eq!(
+        Some(Framing::Chunked),
+        framing("Transfer-Encoding: gzip\r\nTransfer-Encoding: chunked")
+    );
+    for head in [
+        "Content-Length: 3\r\nTransfer-Encoding: chunked",
+        "Transfer-Encoding: chunked\r\nContent-Length: 0",
+        "Content-Length: 3\r\nContent-Length: 4",
+        "Content-Length: 3, 4",
+        "Content-Length: +3",
+        "Content-Length: 0x3",
+        "Content-Length: ",
+        "Content-Length: 99999999999999999999999",
+        "Transfer-Encoding: chunked, gzip",
+        "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked",
+        "Transfer-Encoding: xchunked",
+        // a bare LF would end the field for a lenient parser, smuggling the next line in
+        "X-Padding: a\nContent-Length: 3",
+        "Content-Length: 3\nX-Padding: a",
+        "X-Padding: a\rContent-Length: 3",
+    ] {
+        assert!(
+            matches!(parse(head), Err(ParseError::Malformed(_))),
+            "{head:?}"
+        );
+    }
+
+    assert_eq!(Some(0x1f), parse_chunk_size(b"1F"));
+    assert_eq!(Some(3), parse_chunk_size(b"3 ;name=value"));
+    for line in [
+        &b""[..],
+        b"+3",
+        b"-0",
+        b" 3",
+        b"3 x",
+        b"0x3",
+        b"3\n",
+        b"fffffffffffffffff",
+    ] {
+        assert_eq!(None, parse_chunk_size(line), "{line:?}");
+    }
+}
+
+#[test]
+fn tests_max_body_size() {
+    let parse = |input: &[u8]| RequestParser::new().max_body_size(4).feed(input);
+
+    assert!(matches!(
+        parse(b"PUT / HTTP/1.1\r\nContent-Length: 4\r\n\r\nabcd"),
+        Ok(Parsed::Complete(..))
+    ));
+    assert!(matches!(
+        parse(b"PUT / HTTP/1.1\r\nContent-Length: 5\r\n\r\n"),
+        Err(ParseError::BodyTooLarge)
+    ));
+    let chunked = b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+    assert!(matches!(
+        parse(&[&chunked[..], b"3\r\nabc\r\n1\r\nd\r\n0\r\n\r\n"].concat()),
+        Ok(Parsed::Complete(..))
+    ));
+    // the chunk that goes past the limit fails before its data is in
+    assert!(matches!(
+        parse(&[&chunked[..], b"3\r\nabc\r\n2\r\n"].concat()),
+        Err(ParseError::BodyTooLarge)
+    ));
+}
+
+#[test]
+fn tests_request_parser() {
+    // whatever the bytes are split into, the same request comes out
+    let input: &[u8] = b"POST /upload?x=1 HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+        4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nChecksum: abc\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+    let first_len = input.len() - b"GET / HTTP/1.1\r\n\r\n".len();
+    for split in 0..input.len() {
+        let mut parser = RequestParser::new();
+        let parsed = match parser.feed(&input[..split]).unwrap() {
+            Parsed::NeedMoreData => parser.feed(input).unwrap(),
+            parsed => parsed,
+        };
+        let Parsed::Complete(request, consumed) = parsed else {
+            panic!("incomplete after feeding everything, split at {split}");
+        };
+        assert_eq!(first_len, consumed);
+        assert_eq!("/upload", request.path);
+        assert_eq!(b"Wikipedia".to_vec(), request.body);
+        assert_eq!(Some("abc"), request.trailers.get("Checksum"));
+
+        // the parser starts over for the pipelined request
+        let Parsed::Complete(next, consumed) = parser.feed(&input[first_len..]).unwrap() else {
+            panic!("pipelined request incomplete");
+        };
+        assert_eq!("GET", next.method);
+        assert_eq!(input.len() - first_len, consumed);
+    }
+
+    let mut parser = RequestParser::new();
+    let mut buf = vec![];
+    for byte in b"PUT /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nab" {
+        buf.push(*byte);
+        assert!(matches!(parser.feed(&buf).unwrap(), Parsed::NeedMoreData));
+    }
+    assert_eq!(Some(38), parser.head().map(|(_, body_start)| body_start));
+    buf.extend_from_slice(b"cdef");
+    let Parsed::Complete(request, consumed) = parser.feed(&buf).unwrap() else {
+        panic!("incomplete request");
+    };
+    assert_eq!(b"abc".to_vec(), request.body);
+    assert_eq!(41, consumed);
+
+    let mut parser = RequestParser::new().max_head_size(16);
+    assert!(matches!(
+        parser.feed(b"GET /a-long-path HTTP/1.1\r\n"),
+        Err(ParseError::HeadTooLarge)
+    ));
+    assert!(matches!(
+        RequestParser::new().feed(b"GET /\r\n\r\n"),
+        Err(ParseError::Malformed(_))
+    ));
+    assert!(matches!(
+        RequestParser::new()
+            .feed(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcXY"),
+        Err(ParseError::Malformed(_))
+    ));
+
+    let mut parser = RequestParser::new();
+    let head = b"POST /files/a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel";
+    let (request, body_start) = parser.parse_head(head).unwrap().unwrap();
+    assert_eq!("/files/a", request.path);
+    assert_eq!(head.len() - 3, body_start);
+    let (request, _) = parser.take_head().unwrap();
+    assert!(request.body.is_empty());
+    assert!(parser.head().is_none());
+}
+
+#[test]
+fn tests_feed_buf() {
+    let mut buf =
+        BytesMut::from(&b"PUT /a HTTP/1.1\r\nX-Id: 7\r\nContent-Length: 3\r\n\r\nabcGET"[..]);
+    let received = buf.as_ptr();
+    let Parsed::Complete(request, consumed) = RequestParser::new().feed_buf(&mut buf).unwrap()
+    else {
+        panic!("incomplete request");
+    };
+    assert_eq!(&b"GET"[..], &buf[..]);
+    // the body is where it was received rather than a copy
+    assert_eq!(received.wrapping_add(consumed - 3), request.body.as_ptr());
+    assert_eq!(&b"abc"[..], request.body);
+    assert_eq!(Some("7"), request.headers.get("x-id"));
+}
+
+#[test]
+fn tests_header_case_insensitive() {
+    let request = HttpRequest::from_bytes(BytesMut::from(
+        &b"GET /user-agent HTTP/1.1\r\nuser-agent: curl\r\n\r\n"[..],
+    ))
+    .unwrap();
+    assert_eq!(Some("curl"), request.headers.get("User-Agent"));
+}
+
+#[test]
+fn tests_header_fields() {
+    let parse = |fields: &str, allow_obs_fold| {
+        let head = format!("GET / HTTP/1.1\r\n{fields}\r\n\r\n");
+        let mut parser = RequestParser::new().allow_obs_fold(allow_obs_fold);
+        match parser.feed(head.as_bytes()) {
+            Ok(Parsed::Complete(request, _)) => Ok(request.headers),
+            Ok(Parsed::NeedMoreData) => panic!("incomplete head"),
+            Err(ParseError::Malformed(reason)) => Err(reason),
+            Err(e) => panic!("{e}"),
+        }
+    };
+    let headers = parse(
+        "Host:example.com\r\nX-Time: \t12:30: noon \r\nX-Empty:",
+        false,
+    )
+    .unwrap();
+    assert_eq!(Some("example.com"), headers.get("Host"));
+    assert_eq!(Some("12:30: noon"), headers.get("X-Time"));
+    assert_eq!(Some(""), headers.get("X-Empty"));
+
+    assert_eq!(
+        Err("obsolete line folding is not allowed".to_string()),
+        parse("X-Long: one\r\n  two", false)
+    );
+    let headers = parse("X-Long: one\r\n  two\r\n\tthree\r\nHost: a", true).unwrap();
+    assert_eq!(Some("one two three"), headers.get("X-Long"));
+    assert_eq!(Some("a"), headers.get("Host"));
+
+    assert_eq!(
+        Err("invalid header field \"Bad Name\"".to_string()),
+        parse("Bad Name: x", false)
+    );
+    assert!(parse("Host : a", false).is_err());
+    assert!(parse(": a", false).is_err());
+    assert!(parse("no colon", false).is_err());
+    assert!(parse("X-Bell: a\x07b", false).is_err());
+    assert!(parse("X-Cr: a\rb", false).is_err());
+    assert!(parse("X-Fold: a\r\n b\x00", true).is_err());
+}
+
+#[test]
+fn tests_query_string() {
+    let request = HttpRequest::from_bytes(BytesMut::from(
+        &b"GET /echo/hi?upper=true&flag&&empty= HTTP/1.1\r\n\r\n"[..],
+    ))
+    .unwrap();
+    assert_eq!("/echo/hi", request.path);
+    assert_eq!(Some("true"), request.query("upper"));
+    assert_eq!(Some(""), request.query("flag"));
+    assert_eq!(Some(""), request.query("empty"));
+    assert_eq!(None, request.query("missing"));
+}
+
+#[test]
+fn tests_absolute_form() {
+    let parse = |target: &str| {
+        let head = format!("GET {target} HTTP/1.1\r\nHost: ignored.example\r\n\r\n");
+        HttpRequest::from_bytes(BytesMut::from(head.as_bytes()))
+    };
+    let request = parse("http://example.com:8080/echo/a%20b?x=1").unwrap();
+    assert_eq!(Some("http"), request.target_scheme);
+    assert_eq!("/echo/a b", request.path);
+    assert_eq!("/echo/a%20b", request.raw_path);
+    assert_eq!(Some("1"), request.query("x"));
+    assert_eq!(
+        vec!["example.com:8080"],
+        request.headers.get_all("Host").collect::<Vec<_>>()
+    );
+
+    let request = parse("HTTPS://example.com?x=1").unwrap();
+    assert_eq!(Some("https"), request.target_scheme);
+    assert_eq!("/", request.path);
+    assert_eq!("x=1", request.raw_query);
+    assert_eq!("/", parse("http://example.com").unwrap().path);
+    assert!(parse("http:///path").is_err());
+    // only http and https targets are absolute-form, anything else is a path
+    let request = parse("ftp://example.com/").unwrap();
+    assert_eq!("ftp://example.com/", request.path);
+    assert_eq!(None, request.target_scheme);
+}
+
+#[test]
+fn tests_percent_decode() {
+    assert_eq!(
+        "hello world",
+        percent_decode("hello%20world", false).unwrap()
+    );
+    assert_eq!("my+file", percent_decode("my%2Bfile", false).unwrap());
+    assert_eq!("a+b", percent_decode("a+b", false).unwrap());
+    assert_eq!("a b", percent_decode("a+b", true).unwrap());
+    assert_eq!("ü", percent_decode("%C3%BC", false).unwrap());
+    assert!(percent_decode("%zz", false).is_err());
+    assert!(percent_decode("abc%2", false).is_err());
+    assert!(percent_decode("%FF", false).is_err());
+
+    let request = HttpRequest::from_bytes(BytesMut::from(
+        &b"GET /echo/hello%20world?q=a%26b+c HTTP/1.1\r\n\r\n"[..],
+    ))
+    .unwrap();
+    assert_eq!("/echo/hello world", request.path);
+    assert_eq!("/echo/hello%20world", request.raw_path);
+    assert_eq!("q=a%26b+c", request.raw_query);
+    assert_eq!(Some("a&b c"), request.query("q"));
+
+    assert!(
+        HttpRequest::from_bytes(BytesMut::from(&b"GET /echo/%zz HTTP/1.1\r\n\r\n"[..])).is_err()
+    );
+}
+
+#[test]
+fn tests_json_body() {
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Upload {
+        name: String,
+    }
+    let request = |content_type: &str, body: &str| {
+        HttpRequest::from_bytes(BytesMut::from(
+            format!(
+                "POST / HTTP/1.1\r\nContent-Type: {content_type}\r\n\
+                 Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        ))
+        .unwrap()
+    };
+
+    let upload: Upload = request("application/json; charset=utf-8", r#"{"name":"a"}"#)
+        .json()
+        .unwrap();
+    assert_eq!("a", upload.name);
+    assert!(
+        request("application/merge-patch+json", r#"{"name":"a"}"#)
+            .json::<Upload>()
+            .is_ok()
+    );
+    assert!(
+        request("text/plain", r#"{"name":"a"}"#)
+            .json::<Upload>()
+            .is_err()
+    );
+    assert!(request("application/json", "{").json::<Upload>().is_err());
+    assert!(HttpRequest::default().json::<Upload>().is_err());
+}
+
+#[test]
+fn tests_version() {
+    let request = |input: &[u8]| HttpRequest::from_bytes(BytesMut::from(input)).unwrap();
+
+    let http10 = request(b"GET / HTTP/1.0\r\n\r\n");
+    assert_eq!(Version::Http10, http10.version);
+    assert!(!http10.keep_alive());
+    assert!(request(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").keep_alive());
+
+    let http11 = request(b"GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(Version::Http11, http11.version);
+    assert!(http11.keep_alive());
+    assert!(!request(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").keep_alive());
+}
+
+#[test]
+fn tests_request_line() {
+    let parse = |input: &[u8]| HttpRequest::from_bytes(BytesMut::from(input));
+
+    assert_eq!(
+        Version::Http11,
+        parse(b"GET / HTTP/1.2\r\n\r\n").unwrap().version
+    );
+    for line in [
+        "GET  / HTTP/1.1",
+        "GET\t/ HTTP/1.1",
+        "GET / HTTP/1.1 ",
+        "G(T / HTTP/1.1",
+        "GET /\x7f HTTP/1.1",
+        "GET / http/1.1",
+        "GET / HTTP/1",
+        "GET / HTTP/1.x",
+        "GET / HTTP/1.10",
+    ] {
+        let input = format!("{line}\r\n\r\n");
+        assert!(
+            matches!(parse(input.as_bytes()), Err(ParseError::Malformed(_))),
+            "{line:?}"
+        );
+    }
+    for version in ["HTTP/2.0", "HTTP/0.9", "HTTP/3.0"] {
+        let input = format!("GET / {version}\r\n\r\n");
+        assert!(matches!(
+            parse(input.as_bytes()),
+            Err(ParseError::UnsupportedVersion(v)) if v == version
+        ));
+    }
+}
+
+#[test]
+fn tests_form_body() {
+    let request = |content_type: &str, body: &str| HttpRequest {
+        headers: {
+            let mut headers = HeaderMap::new();
+            headers.insert("Content-Type".to_string(), content_type.to_string());
+            headers
+        },
+        body: Bytes::copy_from_slice(body.as_bytes()),
+        ..Default::default()
+    };
+
+    let form = request(
+        "application/x-www-form-urlencoded; charset=utf-8",
+        "name=Jane+Doe&note=50%25+off&empty=&flag",
+    )
+    .form()
+    .unwrap();
+    assert_eq!(Some("Jane Doe"), form.get("name").map(String::as_str));
+    assert_eq!(Some("50% off"), form.get("note").map(String::as_str));
+    assert_eq!(Some(""), form.get("empty").map(String::as_str));
+    assert_eq!(Some(""), form.get("flag").map(String::as_str));
+
+    assert!(
+        request("application/x-www-form-urlencoded", "a=%zz")
+            .form()
+            .is_err()
+    );
+    assert!(request("application/json", "a=b").form().is_err());
+}
+
+#[test]
+fn tests_repeated_headers() {
+    let request = HttpRequest::from_bytes(BytesMut::from(
+        &b"GET / HTTP/1.1\r\nAccept: text/html\r\nX-Forwarded-For: a\r\nx-forwarded-for: b\r\n\r\n"
+            [..],
+    ))
+    .unwrap();
+    assert_eq!(
+        vec!["a", "b"],
+        request
+            .headers
+            .get_all("X-Forwarded-For")
+            .collect::<Vec<_>>()
+    );
 }
@@ -1,59 +1,603 @@
-use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IoSlice};
+
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::cookie::Cookie;
+use crate::error::HttpError;
+use crate::headers::HeaderMap;
+use crate::request::Version;
+use crate::status::StatusCode;
+use crate::template::Template;
+use crate::upgrade::{OnUpgrade, Upgraded};
+
+pub enum Body {
+    Full(Vec<u8>),
+    /// A body read incrementally while writing the response, e.g. a large file.
+    Stream(Box<dyn AsyncRead + Send + Unpin>),
+}
+
+impl Body {
+    /// Returns the buffered body, or `None` for streamed bodies.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Full(bytes) => Some(bytes),
+            Body::Stream(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Full(bytes) => f.debug_tuple("Full").field(&bytes.len()).finish(),
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+type Trailers = Box<dyn FnOnce() -> HeaderMap + Send>;
+
+type OnComplete = Box<dyn FnOnce(Delivery) + Send>;
+
+/// The status recorded for responses the client disconnected from before they were written, as
+/// nginx logs them.
+pub const CLIENT_CLOSED_REQUEST: u16 = 499;
+
+/// How writing a response to the client ended, see [`HttpResponse::on_complete`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+    Complete,
+    /// The client disconnected before the whole response was written.
+    Aborted,
+    /// Writing failed otherwise, e.g. the streamed body could not be read.
+    Failed,
+}
 
-#[derive(Debug)]
 pub struct HttpResponse {
-    pub status_code: u16,
-    pub headers: HashMap<String, String>,
-    pub body: Vec<u8>,
+    pub status_code: StatusCode,
+    /// The version written in the status line, set by the server to match the request.
+    pub version: Version,
+    pub headers: HeaderMap,
+    pub body: Body,
+    pub(crate) on_upgrade: Option<OnUpgrade>,
+    /// Produces the trailer fields once the body has been written.
+    pub(crate) trailers: Option<Trailers>,
+    /// The pattern of the route that produced the response, set by the router.
+    pub(crate) route: Option<String>,
+    pub(crate) on_complete: Option<OnComplete>,
+}
+
+impl fmt::Debug for HttpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpResponse")
+            .field("status_code", &self.status_code)
+            .field("version", &self.version)
+            .field("headers", &self.headers)
+            .field("body", &self.body)
+            .field("upgrade", &self.on_upgrade.is_some())
+            .field("trailers", &self.trailers.is_some())
+            .field("route", &self.route)
+            .field("on_complete", &self.on_complete.is_some())
+            .finish()
+    }
 }
 impl HttpResponse {
-    pub fn new(status_code: u16) -> Self {
+    pub fn new(status_code: StatusCode) -> Self {
         HttpResponse {
             status_code,
-            headers: HashMap::new(),
-            body: vec![],
+            version: Version::Http11,
+            headers: HeaderMap::new(),
+            body: Body::Full(vec![]),
+            on_upgrade: None,
+            trailers: None,
+            route: None,
+            on_complete: None,
+        }
+    }
+
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder {
+            response: HttpResponse::ok(),
         }
     }
 
+    pub fn switching_protocols() -> Self {
+        HttpResponse::new(StatusCode::SwitchingProtocols)
+    }
+    pub fn partial_content() -> Self {
+        HttpResponse::new(StatusCode::PartialContent)
+    }
+    pub fn not_modified() -> Self {
+        HttpResponse::new(StatusCode::NotModified)
+    }
+    pub fn bad_request() -> Self {
+        HttpResponse::new(StatusCode::BadRequest)
+    }
+    pub fn forbidden() -> Self {
+        HttpResponse::new(StatusCode::Forbidden)
+    }
     pub fn not_found() -> Self {
-        HttpResponse::new(404)
+        HttpResponse::new(StatusCode::NotFound)
     }
     pub fn ok() -> Self {
-        HttpResponse::new(200)
+        HttpResponse::new(StatusCode::Ok)
     }
     pub fn created() -> Self {
-        HttpResponse::new(201)
+        HttpResponse::new(StatusCode::Created)
+    }
+    pub fn no_content() -> Self {
+        HttpResponse::new(StatusCode::NoContent)
+    }
+    pub fn method_not_allowed() -> Self {
+        HttpResponse::new(StatusCode::MethodNotAllowed)
+    }
+    pub fn request_timeout() -> Self {
+        HttpResponse::new(StatusCode::RequestTimeout)
+    }
+    pub fn payload_too_large() -> Self {
+        HttpResponse::new(StatusCode::ContentTooLarge)
+    }
+    pub fn range_not_satisfiable() -> Self {
+        HttpResponse::new(StatusCode::RangeNotSatisfiable)
+    }
+    pub fn precondition_failed() -> Self {
+        HttpResponse::new(StatusCode::PreconditionFailed)
+    }
+    pub fn expectation_failed() -> Self {
+        HttpResponse::new(StatusCode::ExpectationFailed)
+    }
+    pub fn request_header_fields_too_large() -> Self {
+        HttpResponse::new(StatusCode::RequestHeaderFieldsTooLarge)
     }
     pub fn internal_server_error() -> Self {
-        HttpResponse::new(500)
+        HttpResponse::new(StatusCode::InternalServerError)
+    }
+    pub fn service_unavailable() -> Self {
+        HttpResponse::new(StatusCode::ServiceUnavailable)
+    }
+
+    /// A 200 response with `value` serialized as the JSON body.
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Result<Self, HttpError> {
+        let body = serde_json::to_vec(value).map_err(|e| {
+            HttpError::new(
+                StatusCode::InternalServerError,
+                format!("Unable to serialize JSON body: {e}"),
+            )
+        })?;
+        let mut resp = HttpResponse::ok();
+        resp.set_header("Content-Type".to_string(), "application/json".to_string());
+        resp.set_header("Content-Length".to_string(), body.len().to_string());
+        resp.set_body(body);
+        Ok(resp)
+    }
+
+    /// A 200 response with `template` rendered with `context` as the HTML body.
+    pub fn html<T: Serialize + ?Sized>(
+        template: &Template,
+        context: &T,
+    ) -> Result<Self, HttpError> {
+        let body = template.render(context).map_err(|e| {
+            HttpError::new(
+                StatusCode::InternalServerError,
+                format!("Unable to render HTML body: {e}"),
+            )
+        })?;
+        let body = body.into_bytes();
+        let mut resp = HttpResponse::ok();
+        resp.set_header(
+            "Content-Type".to_string(),
+            "text/html; charset=utf-8".to_string(),
+        );
+        resp.set_header("Content-Length".to_string(), body.len().to_string());
+        resp.set_body(body);
+        Ok(resp)
     }
 
     pub fn set_header(&mut self, header: String, value: String) {
         self.headers.insert(header, value);
     }
 
+    /// Adds a Set-Cookie header, keeping those of previously added cookies.
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        self.headers
+            .append("Set-Cookie".to_string(), cookie.to_string());
+    }
+
     pub fn set_body(&mut self, body: Vec<u8>) {
-        self.body = body;
+        self.body = Body::Full(body);
+    }
+
+    /// Streams the body from `reader`. Without a Content-Length header the body is sent with
+    /// chunked transfer-encoding.
+    pub fn set_stream(&mut self, reader: impl AsyncRead + Send + Unpin + 'static) {
+        self.body = Body::Stream(Box::new(reader));
     }
 
-    fn reason(&self) -> String {
-        match self.status_code {
-            200 => "OK".to_string(),
-            201 => "Created".to_string(),
-            404 => "Not Found".to_string(),
-            500 => "Internal Server Error".to_string(),
-            _ => "Unknown".to_string(),
+    /// Sends the fields returned by `trailers` after the body, e.g. a checksum computed while it
+    /// was streamed, announcing their `names` in the Trailer header. Trailers need chunked
+    /// encoding, so the body is sent chunked even if it is buffered, and they are dropped for
+    /// HTTP/1.0 clients.
+    pub fn set_trailers<F>(&mut self, names: &[&str], trailers: F)
+    where
+        F: FnOnce() -> HeaderMap + Send + 'static,
+    {
+        self.headers.remove("Content-Length");
+        self.set_header("Trailer".to_string(), names.join(", "));
+        self.trailers = Some(Box::new(trailers));
+    }
+
+    /// Takes over the connection once this response, which must be a 101 Switching Protocols or
+    /// a 2xx answer to CONNECT, has been written. The handler gets the stream along with
+    /// whatever the client sent after the request, see [`Upgraded`]. Only HTTP/1.1 connections
+    /// can be upgraded.
+    pub fn on_upgrade<F, Fut>(&mut self, handler: F)
+    where
+        F: FnOnce(Upgraded) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.on_upgrade = Some(Box::new(move |connection| Box::pin(handler(connection))));
+    }
+
+    /// Calls `callback` once the server is done writing the response to the client, e.g. to log
+    /// whether it got all of it. Callbacks of several layers run innermost first. Responses
+    /// that are never written, like those of [`Server::handle`](crate::Server::handle), don't
+    /// call them.
+    pub fn on_complete(&mut self, callback: impl FnOnce(Delivery) + Send + 'static) {
+        self.on_complete = Some(match self.on_complete.take() {
+            Some(inner) => Box::new(move |delivery| {
+                inner(delivery);
+                callback(delivery);
+            }),
+            None => Box::new(callback),
+        });
+    }
+
+    /// Runs the [`on_complete`](HttpResponse::on_complete) callbacks of a response that was
+    /// taken apart for writing.
+    pub(crate) fn completed(on_complete: Option<OnComplete>, delivery: Delivery) {
+        if let Some(on_complete) = on_complete {
+            on_complete(delivery);
         }
     }
 
-    pub fn encode(&self) -> Vec<u8> {
-        let mut response =
-            format!("HTTP/1.1 {} {}\r\n", self.status_code, self.reason()).into_bytes();
-        for (header, value) in &self.headers {
+    fn is_chunked(&self) -> bool {
+        self.version == Version::Http11 && self.is_unsized()
+    }
+
+    fn is_unsized(&self) -> bool {
+        (matches!(self.body, Body::Stream(_)) || self.trailers.is_some())
+            && !self.headers.contains_key("Content-Length")
+    }
+
+    /// HTTP/1.0 clients don't understand chunked encoding, so a streamed body of unknown length
+    /// can only end by closing the connection.
+    pub(crate) fn is_close_delimited(&self) -> bool {
+        self.version == Version::Http10 && self.is_unsized()
+    }
+
+    pub fn encode_head(&self) -> Vec<u8> {
+        let mut response = format!("{} {}\r\n", self.version, self.status_code).into_bytes();
+        for (header, value) in self.headers.iter() {
             response.extend(format!("{}: {}\r\n", header, value).into_bytes());
         }
+        // persistent connections need every response body to be framed
+        if self.is_chunked() {
+            response.extend(b"Transfer-Encoding: chunked\r\n");
+        } else if let Body::Full(body) = &self.body
+            && !self.headers.contains_key("Content-Length")
+            && !self.status_code.is_informational()
+            && self.on_upgrade.is_none()
+            && self.status_code != StatusCode::NoContent
+            && self.status_code != StatusCode::NotModified
+        {
+            response.extend(format!("Content-Length: {}\r\n", body.len()).into_bytes());
+        }
         response.extend(b"\r\n");
-        response.extend(&self.body);
         response
     }
+
+    /// Writes only the status line and headers, as the response to a HEAD request.
+    pub async fn write_head_to<W: AsyncWrite + Unpin>(
+        self,
+        writer: &mut W,
+    ) -> Result<(), HttpError> {
+        writer.write_all(&self.encode_head()).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Writes the response, handing the head and a buffered body to the writer together so
+    /// they can leave in one write call rather than one after the other.
+    pub async fn write_to<W: AsyncWrite + Unpin>(self, writer: &mut W) -> Result<(), HttpError> {
+        let chunked = self.is_chunked();
+        let head = self.encode_head();
+        match self.body {
+            Body::Full(body) if chunked => {
+                let size = format!("{:x}\r\n", body.len());
+                let last = last_chunk(self.trailers);
+                if body.is_empty() {
+                    write_all_vectored(writer, &[&head, &last]).await?;
+                } else {
+                    write_all_vectored(writer, &[&head, size.as_bytes(), &body, b"\r\n", &last])
+                        .await?;
+                }
+            }
+            Body::Full(body) => write_all_vectored(writer, &[&head, &body]).await?,
+            Body::Stream(mut reader) if chunked => {
+                writer.write_all(&head).await?;
+                let mut buf = vec![0; 8192];
+                loop {
+                    let read = reader.read(&mut buf).await?;
+                    if read == 0 {
+                        break;
+                    }
+                    let size = format!("{:x}\r\n", read);
+                    write_all_vectored(writer, &[size.as_bytes(), &buf[..read], b"\r\n"]).await?;
+                    // streams like server-sent events must reach the client as they are produced
+                    writer.flush().await?;
+                }
+                writer.write_all(&last_chunk(self.trailers)).await?;
+            }
+            Body::Stream(mut reader) => {
+                writer.write_all(&head).await?;
+                tokio::io::copy(&mut reader, writer).await?;
+            }
+        }
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// The end of a chunked body, followed by the trailer fields if there are any.
+fn last_chunk(trailers: Option<Trailers>) -> Vec<u8> {
+    let mut last = b"0\r\n".to_vec();
+    if let Some(trailers) = trailers {
+        for (name, value) in trailers().iter() {
+            last.extend(format!("{name}: {value}\r\n").into_bytes());
+        }
+    }
+    last.extend(b"\r\n");
+    last
+}
+
+/// Writes all of `bufs` in order, passing them to the writer as one vectored write for as long
+/// as it takes several at once.
+async fn write_all_vectored<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    bufs: &[&[u8]],
+) -> io::Result<()> {
+    let mut slices: Vec<IoSlice> = bufs
+        .iter()
+        .filter(|buf| !buf.is_empty())
+        .map(|buf| IoSlice::new(buf))
+        .collect();
+    let mut remaining = &mut slices[..];
+    while !remaining.is_empty() {
+        let written = writer.write_vectored(remaining).await?;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        IoSlice::advance_slices(&mut remaining, written);
+    }
+    Ok(())
+}
+
+/// Builds an [`HttpResponse`] fluently, starting from a 200 with an empty body:
+///
+/// ```
+/// # use codecrafters_http_server::{HttpResponse, StatusCode};
+/// let resp = HttpResponse::builder()
+///     .status(StatusCode::Created)
+///     .header("Content-Type", "text/plain")
+///     .body("created")
+///     .build();
+/// assert_eq!(Some("7"), resp.headers.get("Content-Length"));
+/// ```
+pub struct ResponseBuilder {
+    response: HttpResponse,
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, status_code: StatusCode) -> Self {
+        self.response.status_code = status_code;
+        self
+    }
+
+    /// Sets a header, replacing a previous value for the same name.
+    pub fn header(mut self, header: impl Into<String>, value: impl Into<String>) -> Self {
+        self.response.headers.insert(header.into(), value.into());
+        self
+    }
+
+    pub fn cookie(mut self, cookie: Cookie) -> Self {
+        self.response.add_cookie(cookie);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.response.set_body(body.into());
+        self
+    }
+
+    /// Streams the body from `reader`, see [`HttpResponse::set_stream`].
+    pub fn stream(mut self, reader: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        self.response.set_stream(reader);
+        self
+    }
+
+    /// Sends trailer fields after the body, see [`HttpResponse::set_trailers`].
+    pub fn trailers<F>(mut self, names: &[&str], trailers: F) -> Self
+    where
+        F: FnOnce() -> HeaderMap + Send + 'static,
+    {
+        self.response.set_trailers(names, trailers);
+        self
+    }
+
+    /// Finishes the response, adding a Content-Length for buffered bodies unless one was set or
+    /// the body carries trailers.
+    pub fn build(mut self) -> HttpResponse {
+        if let Body::Full(body) = &self.response.body
+            && self.response.trailers.is_none()
+            && !self.response.headers.contains_key("Content-Length")
+        {
+            let length = body.len().to_string();
+            self.response
+                .set_header("Content-Length".to_string(), length);
+        }
+        self.response
+    }
+}
+
+#[tokio::test]
+async fn tests_write_chunked_stream() {
+    let mut resp = HttpResponse::ok();
+    resp.set_stream(&b"streamed body"[..]);
+    let mut output = vec![];
+    resp.write_to(&mut output).await.unwrap();
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nd\r\nstreamed body\r\n0\r\n\r\n",
+        String::from_utf8(output).unwrap()
+    );
+
+    let mut resp = HttpResponse::ok();
+    resp.set_header("Content-Length".to_string(), "13".to_string());
+    resp.set_stream(&b"streamed body"[..]);
+    let mut output = vec![];
+    resp.write_to(&mut output).await.unwrap();
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\nstreamed body",
+        String::from_utf8(output).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn tests_write_trailers() {
+    let resp = HttpResponse::builder()
+        .body("abc")
+        .trailers(&["Checksum"], || {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("Checksum".to_string(), "900150983cd24fb0".to_string());
+            trailers
+        })
+        .build();
+    let mut output = vec![];
+    resp.write_to(&mut output).await.unwrap();
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nTrailer: Checksum\r\nTransfer-Encoding: chunked\r\n\r\n\
+         3\r\nabc\r\n0\r\nChecksum: 900150983cd24fb0\r\n\r\n",
+        String::from_utf8(output).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn tests_write_vectored() {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Takes at most a few bytes per write, counting the calls.
+    #[derive(Default)]
+    struct Trickle {
+        written: Vec<u8>,
+        calls: usize,
+    }
+
+    impl AsyncWrite for Trickle {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.poll_write_vectored(cx, &[IoSlice::new(buf)])
+        }
+
+        fn poll_write_vectored(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            self.calls += 1;
+            let mut taken = 0;
+            for buf in bufs {
+                let take = buf.len().min(7 - taken);
+                self.written.extend_from_slice(&buf[..take]);
+                taken += take;
+            }
+            Poll::Ready(Ok(taken))
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            true
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    // every byte arrives although each write takes only part of head and body
+    let mut writer = Trickle::default();
+    let resp = HttpResponse::builder().body("hello world").build();
+    resp.write_to(&mut writer).await.unwrap();
+    let expected = "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world";
+    assert_eq!(expected, String::from_utf8(writer.written).unwrap());
+    assert_eq!(expected.len().div_ceil(7), writer.calls);
+}
+
+#[test]
+fn tests_json() {
+    let resp = HttpResponse::json(&serde_json::json!({"name": "a", "size": 1})).unwrap();
+    assert_eq!(200, resp.status_code);
+    assert_eq!(Some("application/json"), resp.headers.get("Content-Type"));
+    assert_eq!(Some("21"), resp.headers.get("Content-Length"));
+    assert_eq!(Some(&br#"{"name":"a","size":1}"#[..]), resp.body.as_bytes());
+}
+
+#[test]
+fn tests_builder() {
+    let resp = HttpResponse::builder()
+        .status(StatusCode::NotFound)
+        .header("X-Foo", "bar")
+        .header("x-foo", "baz")
+        .body(&b"hi"[..])
+        .build();
+    assert_eq!(404, resp.status_code);
+    assert_eq!(Some("baz"), resp.headers.get("X-Foo"));
+    assert_eq!(Some("2"), resp.headers.get("Content-Length"));
+    assert_eq!(Some(&b"hi"[..]), resp.body.as_bytes());
+
+    let resp = HttpResponse::builder().stream(&b"streamed"[..]).build();
+    assert_eq!(200, resp.status_code);
+    assert_eq!(None, resp.headers.get("Content-Length"));
+}
+
+#[tokio::test]
+async fn tests_write_http10_stream() {
+    let mut resp = HttpResponse::ok();
+    resp.version = Version::Http10;
+    resp.set_stream(&b"streamed body"[..]);
+    assert!(resp.is_close_delimited());
+    let mut output = vec![];
+    resp.write_to(&mut output).await.unwrap();
+    assert_eq!(
+        "HTTP/1.0 200 OK\r\n\r\nstreamed body",
+        String::from_utf8(output).unwrap()
+    );
+}
+
+#[test]
+fn tests_add_cookie() {
+    let resp = HttpResponse::builder()
+        .cookie(Cookie::new("a", "1"))
+        .cookie(Cookie::new("b", "2").http_only(true))
+        .build();
+    let head = String::from_utf8(resp.encode_head()).unwrap();
+    assert!(head.contains("Set-Cookie: a=1\r\nSet-Cookie: b=2; HttpOnly\r\n"));
 }
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer};
+
+use crate::config::ServerConfig;
+use crate::middleware::{Middleware, Next};
+use crate::request::{HttpRequest, percent_decode};
+use crate::response::HttpResponse;
+use crate::router::BoxFuture;
+use crate::status::StatusCode;
+
+/// A `[[redirects]]` table of the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedirectRule {
+    /// The path to redirect, a trailing `*` matches any rest of the path, e.g. "/old/*".
+    pub from: String,
+    /// The redirect target, a `*` in it is replaced by the rest matched in `from`.
+    pub to: String,
+    /// 301, 302, 307 or 308.
+    #[serde(deserialize_with = "redirect_status")]
+    pub status: StatusCode,
+}
+
+impl Default for RedirectRule {
+    fn default() -> Self {
+        RedirectRule {
+            from: String::new(),
+            to: String::new(),
+            status: StatusCode::MovedPermanently,
+        }
+    }
+}
+
+/// A `[[rewrites]]` table of the config file, serving `to` in place of `from` without the client
+/// seeing it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewriteRule {
+    /// Matched like [`RedirectRule::from`].
+    pub from: String,
+    /// Substituted like [`RedirectRule::to`].
+    pub to: String,
+}
+
+fn redirect_status<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StatusCode, D::Error> {
+    match u16::deserialize(deserializer)? {
+        301 => Ok(StatusCode::MovedPermanently),
+        302 => Ok(StatusCode::Found),
+        307 => Ok(StatusCode::TemporaryRedirect),
+        308 => Ok(StatusCode::PermanentRedirect),
+        status => Err(serde::de::Error::custom(format!(
+            "invalid redirect status {status}, expected 301, 302, 307 or 308"
+        ))),
+    }
+}
+
+/// Applies a rule's `from` pattern to `path`, returning `to` with the matched rest filled in.
+fn apply(from: &str, to: &str, path: &str) -> Option<String> {
+    let rest = match from.strip_suffix('*') {
+        Some(prefix) => path.strip_prefix(prefix)?,
+        None if path == from => "",
+        None => return None,
+    };
+    Some(to.replacen('*', rest, 1))
+}
+
+/// Evaluates the config's `redirects` and then its `rewrites` before the request is routed. The
+/// first matching rule of each list applies, and rewrites are not applied again to their result.
+#[derive(Default)]
+pub struct Rewrites;
+
+impl Rewrites {
+    pub fn new() -> Self {
+        Rewrites
+    }
+}
+
+impl Middleware<ServerConfig> for Rewrites {
+    fn handle<'a>(
+        &'a self,
+        mut request: HttpRequest,
+        config: Arc<ServerConfig>,
+        next: Next<'a, ServerConfig>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            // rules match the path as sent, so targets keep its escapes
+            let path = request.raw_path.clone();
+            if let Some((rule, mut location)) = config
+                .redirects
+                .iter()
+                .find_map(|rule| Some((rule, apply(&rule.from, &rule.to, &path)?)))
+            {
+                if !request.raw_query.is_empty() && !location.contains('?') {
+                    location = format!("{location}?{}", request.raw_query);
+                }
+                return Ok(HttpResponse::builder()
+                    .status(rule.status)
+                    .header("Location", location)
+                    .build());
+            }
+            if let Some(target) = config
+                .rewrites
+                .iter()
+                .find_map(|rule| apply(&rule.from, &rule.to, &path))
+            {
+                let Ok(decoded) = percent_decode(&target, false) else {
+                    return Ok(HttpResponse::bad_request());
+                };
+                request.path = decoded;
+                request.raw_path = target;
+            }
+            next.run(request, config).await
+        })
+    }
+
+    fn accepts(&self, request: &HttpRequest, config: &ServerConfig) -> bool {
+        // a rewritten path is not looked up again, so the target is assumed to exist
+        let path = &request.raw_path;
+        config
+            .redirects
+            .iter()
+            .any(|rule| apply(&rule.from, &rule.to, path).is_some())
+            || config
+                .rewrites
+                .iter()
+                .any(|rule| apply(&rule.from, &rule.to, path).is_some())
+    }
+}
+
+#[test]
+fn tests_apply() {
+    assert_eq!(
+        Some("/new/a/b".to_string()),
+        apply("/old/*", "/new/*", "/old/a/b")
+    );
+    assert_eq!(
+        Some("/new/".to_string()),
+        apply("/old/*", "/new/*", "/old/")
+    );
+    assert_eq!(None, apply("/old/*", "/new/*", "/older"));
+    assert_eq!(
+        Some("/files/index.html".to_string()),
+        apply("/", "/files/index.html", "/")
+    );
+    assert_eq!(None, apply("/", "/files/index.html", "/other"));
+    assert_eq!(
+        Some("/home".to_string()),
+        apply("/docs/*", "/home", "/docs/x")
+    );
+}
+
+#[tokio::test]
+async fn tests_rewrites() {
+    use crate::router::Router;
+
+    let router: Router<ServerConfig> = Router::new()
+        .get("/files/:name", |request: HttpRequest, _| async move {
+            let name = request.param("name").unwrap_or_default().to_string();
+            Ok(HttpResponse::builder().body(name).build())
+        })
+        .layer(Rewrites::new());
+    let config = Arc::new(ServerConfig {
+        redirects: vec![RedirectRule {
+            from: "/old/*".to_string(),
+            to: "/new/*".to_string(),
+            status: StatusCode::PermanentRedirect,
+        }],
+        rewrites: vec![RewriteRule {
+            from: "/".to_string(),
+            to: "/files/index.html".to_string(),
+        }],
+        ..Default::default()
+    });
+    let get = |target: &str| {
+        let (raw_path, raw_query) = target.split_once('?').unwrap_or((target, ""));
+        HttpRequest {
+            method: "GET".to_string(),
+            path: raw_path.to_string(),
+            raw_path: raw_path.to_string(),
+            raw_query: raw_query.to_string(),
+            ..Default::default()
+        }
+    };
+
+    let resp = router
+        .handle(get("/old/a%20b?x=1"), config.clone())
+        .await
+        .unwrap();
+    assert_eq!(308, resp.status_code);
+    assert_eq!(Some("/new/a%20b?x=1"), resp.headers.get("Location"));
+
+    let resp = router.handle(get("/"), config.clone()).await.unwrap();
+    assert_eq!(200, resp.status_code);
+    assert_eq!(Some(&b"index.html"[..]), resp.body.as_bytes());
+
+    let resp = router.handle(get("/files/a"), config).await.unwrap();
+    assert_eq!(Some(&b"a"[..]), resp.body.as_bytes());
+}
@@ -0,0 +1,809 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::error::HttpError;
+use crate::guard::Guard;
+use crate::method;
+use crate::middleware::{Middleware, Next};
+use crate::request::{HttpRequest, ParseError, percent_decode};
+use crate::response::HttpResponse;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Answers the requests of a route, given the request and the router's state. Async functions
+/// and closures taking `(HttpRequest, Arc<S>)` are handlers; types carrying their own data can
+/// implement it and be registered with [`Router::handler`].
+pub trait Handler<S>: Send + Sync + 'static {
+    fn call(&self, request: HttpRequest, state: Arc<S>) -> BoxFuture<'_, Result<HttpResponse>>;
+}
+
+impl<S, H, F> Handler<S> for H
+where
+    H: Fn(HttpRequest, Arc<S>) -> F + Send + Sync + 'static,
+    F: Future<Output = Result<HttpResponse>> + Send + 'static,
+{
+    fn call(&self, request: HttpRequest, state: Arc<S>) -> BoxFuture<'_, Result<HttpResponse>> {
+        Box::pin(self(request, state))
+    }
+}
+
+#[derive(Clone)]
+enum Segment {
+    Static(String),
+    Param(String),
+    /// A trailing `*name`, capturing the rest of the path.
+    Wildcard(String),
+}
+
+impl Segment {
+    fn matches(&self, actual: &str) -> bool {
+        match self {
+            Segment::Static(expected) => expected == actual,
+            Segment::Param(_) | Segment::Wildcard(_) => true,
+        }
+    }
+}
+
+struct Route<S> {
+    method: String,
+    pattern: String,
+    segments: Vec<Segment>,
+    /// Whether the handler reads the body as a stream instead of it being buffered.
+    stream_body: bool,
+    guards: Vec<Box<dyn Guard>>,
+    handler: Box<dyn Handler<S>>,
+}
+
+impl<S> Route<S> {
+    fn matches(&self, path: &[&str]) -> Option<HashMap<String, String>> {
+        let wildcard = matches!(self.segments.last(), Some(Segment::Wildcard(_)));
+        // a wildcard takes one or more segments
+        if self.segments.len() != path.len() && !(wildcard && path.len() > self.segments.len()) {
+            return None;
+        }
+        let mut params = HashMap::new();
+        for (i, (segment, actual)) in self.segments.iter().zip(path).enumerate() {
+            match segment {
+                Segment::Static(expected) if expected == actual => {}
+                Segment::Static(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), actual.to_string());
+                }
+                Segment::Wildcard(name) => {
+                    params.insert(name.clone(), path[i..].join("/"));
+                }
+            }
+        }
+        Some(params)
+    }
+
+    fn guards_pass(&self, request: &HttpRequest) -> bool {
+        self.guards.iter().all(|guard| guard.check(request))
+    }
+}
+
+impl<S: 'static> Route<S> {
+    async fn call(&self, request: HttpRequest, state: Arc<S>) -> Result<HttpResponse> {
+        let mut resp = self.handler.call(request, state).await?;
+        resp.route = Some(self.pattern.clone());
+        Ok(resp)
+    }
+}
+
+/// A router nested below a path prefix, see [`Router::nest`].
+struct Group<S> {
+    prefix: Vec<Segment>,
+    router: Router<S>,
+}
+
+impl<S> Group<S> {
+    fn contains(&self, path: &[&str]) -> bool {
+        path.len() >= self.prefix.len()
+            && self
+                .prefix
+                .iter()
+                .zip(path)
+                .all(|(segment, actual)| segment.matches(actual))
+    }
+}
+
+/// Dispatches requests to handlers registered for path patterns like "/files/:name", or
+/// "/files/*path" for any path below /files.
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+    groups: Vec<Group<S>>,
+    fallback: Option<Box<dyn Handler<S>>>,
+    middleware: Vec<Arc<dyn Middleware<S>>>,
+}
+
+impl<S: 'static> Router<S> {
+    pub fn new() -> Self {
+        Router {
+            routes: vec![],
+            groups: vec![],
+            fallback: None,
+            middleware: vec![],
+        }
+    }
+
+    pub fn get<H, F>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(HttpRequest, Arc<S>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<HttpResponse>> + Send + 'static,
+    {
+        self.route("GET", pattern, handler)
+    }
+
+    pub fn post<H, F>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(HttpRequest, Arc<S>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<HttpResponse>> + Send + 'static,
+    {
+        self.route("POST", pattern, handler)
+    }
+
+    pub fn delete<H, F>(self, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(HttpRequest, Arc<S>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<HttpResponse>> + Send + 'static,
+    {
+        self.route("DELETE", pattern, handler)
+    }
+
+    pub fn route<H, F>(self, method: &str, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(HttpRequest, Arc<S>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<HttpResponse>> + Send + 'static,
+    {
+        self.add_route(method, pattern, handler, false)
+    }
+
+    /// Registers `handler` like [`Router::route`], for handlers that aren't closures.
+    pub fn handler(self, method: &str, pattern: &str, handler: impl Handler<S>) -> Self {
+        self.add_route(method, pattern, handler, false)
+    }
+
+    /// Registers a route whose handler reads the request body while it arrives, through
+    /// [`HttpRequest::take_body_stream`], instead of the server buffering it first.
+    pub fn route_streaming<H, F>(self, method: &str, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(HttpRequest, Arc<S>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<HttpResponse>> + Send + 'static,
+    {
+        self.add_route(method, pattern, handler, true)
+    }
+
+    fn add_route(
+        mut self,
+        method: &str,
+        pattern: &str,
+        handler: impl Handler<S>,
+        stream_body: bool,
+    ) -> Self {
+        let segments = parse_pattern(pattern);
+        self.routes.push(Route {
+            method: method.to_string(),
+            pattern: pattern.to_string(),
+            segments,
+            stream_body,
+            guards: vec![],
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Answers requests no route matches with `handler` instead of 404, e.g. to serve a
+    /// single-page app's index for any path. Requests whose path only matches routes for other
+    /// methods are still answered with 405.
+    pub fn fallback<H, F>(mut self, handler: H) -> Self
+    where
+        H: Fn(HttpRequest, Arc<S>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<HttpResponse>> + Send + 'static,
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Adds a [`Guard`] to the route registered last, which then only handles requests the guard
+    /// passes. Others fall through to the next route matching them, or are answered with 404 if
+    /// there is none for their method.
+    ///
+    /// # Panics
+    ///
+    /// If no route was registered yet.
+    pub fn guard(mut self, guard: impl Guard) -> Self {
+        let route = self
+            .routes
+            .last_mut()
+            .expect("a route is registered before its guards");
+        route.guards.push(Box::new(guard));
+        self
+    }
+
+    /// Serves `router`'s routes below `prefix`, e.g. "/api/items" for its "/items" with the
+    /// prefix "/api". The layers added to `router` only run for requests below the prefix, all
+    /// of which it answers, with 404 if none of its routes match. The prefix may contain
+    /// parameters like "/users/:id", which its routes see.
+    ///
+    /// # Panics
+    ///
+    /// If the prefix contains a wildcard.
+    pub fn nest(mut self, prefix: &str, mut router: Router<S>) -> Self {
+        let prefix = parse_pattern(prefix);
+        assert!(
+            !prefix
+                .iter()
+                .any(|segment| matches!(segment, Segment::Wildcard(_))),
+            "a nested router's prefix has no wildcard"
+        );
+        router.prepend(&prefix);
+        self.groups.push(Group { prefix, router });
+        self
+    }
+
+    /// Moves the routes and groups below `prefix`.
+    fn prepend(&mut self, prefix: &[Segment]) {
+        let pattern: String = prefix
+            .iter()
+            .map(|segment| match segment {
+                Segment::Static(name) => format!("/{name}"),
+                Segment::Param(name) => format!("/:{name}"),
+                Segment::Wildcard(name) => format!("/*{name}"),
+            })
+            .collect();
+        for route in &mut self.routes {
+            route.segments.splice(0..0, prefix.iter().cloned());
+            route.pattern = match route.pattern.as_str() {
+                "/" | "" => pattern.clone(),
+                rest => format!("{pattern}{rest}"),
+            };
+        }
+        for group in &mut self.groups {
+            group.prefix.splice(0..0, prefix.iter().cloned());
+            group.router.prepend(prefix);
+        }
+    }
+
+    /// Wraps all routes and previously added layers in `middleware`, so the layer added last
+    /// sees the request first and the response last.
+    pub fn layer(mut self, middleware: impl Middleware<S>) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Turns the router into a layer serving its routes with `state`, for mounting routes that
+    /// need application state (database pools, counters) into a router with other state. Requests
+    /// none of its routes or layers accept fall through to the outer router.
+    pub fn with_state(self, state: impl Into<Arc<S>>) -> WithState<S> {
+        WithState {
+            router: self,
+            state: state.into(),
+        }
+    }
+
+    /// Runs the request through the middleware stack and the matching route.
+    pub async fn handle(&self, request: HttpRequest, state: Arc<S>) -> Result<HttpResponse> {
+        let next = Next {
+            router: self,
+            middleware: &self.middleware,
+        };
+        next.run(request, state).await
+    }
+
+    /// Whether a route or layer will handle `request`, rather than it being answered with 404
+    /// or 405.
+    pub(crate) fn accepts(&self, request: &HttpRequest, state: &S) -> bool {
+        if self
+            .middleware
+            .iter()
+            .any(|layer| layer.accepts(request, state))
+        {
+            return true;
+        }
+        let Ok(segments) = request_segments(request) else {
+            return false;
+        };
+        let path: Vec<&str> = segments.iter().map(String::as_str).collect();
+        if let Some(group) = self.groups.iter().find(|group| group.contains(&path)) {
+            return group.router.accepts(request, state);
+        }
+        let method_matches = |route: &Route<S>| {
+            route.method == request.method || route.method == "GET" && request.method == "HEAD"
+        };
+        let matching: Vec<&Route<S>> = self
+            .routes
+            .iter()
+            .filter(|route| route.matches(&path).is_some())
+            .collect();
+        if matching
+            .iter()
+            .any(|route| method_matches(route) && route.guards_pass(request))
+        {
+            return true;
+        }
+        // the fallback answers what dispatch would otherwise answer with 404
+        let guarded = matching
+            .iter()
+            .any(|route| method_matches(route) && !route.guards_pass(request));
+        self.fallback.is_some() && (guarded || matching.is_empty())
+    }
+
+    /// Runs the first route matching the request method and path, storing its path parameters on
+    /// the request, whose guards pass it. HEAD requests fall back to the GET route; the server
+    /// drops the body. Paths that only match for other methods are answered with 405, or with
+    /// 204 and their methods for OPTIONS.
+    pub(crate) async fn dispatch(
+        &self,
+        mut request: HttpRequest,
+        state: Arc<S>,
+    ) -> Result<HttpResponse> {
+        // "OPTIONS *" asks about the server as a whole rather than one resource
+        if request.method == "OPTIONS" && request.path == "*" {
+            let mut allowed: Vec<&str> = vec![];
+            self.collect_methods(&mut allowed);
+            return Ok(method::options(&allowed));
+        }
+        // a path that doesn't decode is the client's fault, answered with 400
+        let segments = request_segments(&request).map_err(HttpError::from)?;
+        let path: Vec<&str> = segments.iter().map(String::as_str).collect();
+        if let Some(group) = self.groups.iter().find(|group| group.contains(&path)) {
+            // boxed, as the group's dispatch is part of this future's type
+            return Box::pin(group.router.handle(request, state)).await;
+        }
+        let mut allowed: Vec<&str> = vec![];
+        let mut get_route = None;
+        // routes for the method that the guards turned away leave the request unanswered
+        let mut guarded = false;
+        for route in &self.routes {
+            let Some(params) = route.matches(&path) else {
+                continue;
+            };
+            let head = route.method == "GET" && request.method == "HEAD";
+            if !route.guards_pass(&request) {
+                guarded |= route.method == request.method || head;
+            } else if route.method == request.method {
+                request.params = params;
+                return route.call(request, state).await;
+            } else if head && get_route.is_none() {
+                get_route = Some((route, params));
+            }
+            for method in method::implied(&route.method) {
+                if !allowed.contains(&method) {
+                    allowed.push(method);
+                }
+            }
+        }
+
+        if let Some((route, params)) = get_route {
+            request.params = params;
+            return route.call(request, state).await;
+        }
+
+        if allowed.is_empty() || guarded {
+            return match &self.fallback {
+                Some(fallback) => fallback.call(request, state).await,
+                None => Ok(HttpResponse::not_found()),
+            };
+        }
+        if request.method == "OPTIONS" {
+            return Ok(method::options(&allowed));
+        }
+        let mut resp = HttpResponse::method_not_allowed();
+        resp.set_header("Allow".to_string(), method::allow(&allowed));
+        Ok(resp)
+    }
+
+    /// Adds the methods of all routes, nested ones included, to `allowed`.
+    fn collect_methods<'a>(&'a self, allowed: &mut Vec<&'a str>) {
+        for route in &self.routes {
+            for method in method::implied(&route.method) {
+                if !allowed.contains(&method) {
+                    allowed.push(method);
+                }
+            }
+        }
+        for group in &self.groups {
+            group.router.collect_methods(allowed);
+        }
+    }
+
+    /// Whether the layer or route that will handle `request` streams its body.
+    pub(crate) fn streams_body(&self, request: &HttpRequest, state: &S) -> bool {
+        if self
+            .middleware
+            .iter()
+            .any(|layer| layer.streams_body(request, state))
+        {
+            return true;
+        }
+        let Ok(segments) = request_segments(request) else {
+            return false;
+        };
+        let path: Vec<&str> = segments.iter().map(String::as_str).collect();
+        if let Some(group) = self.groups.iter().find(|group| group.contains(&path)) {
+            return group.router.streams_body(request, state);
+        }
+        self.routes
+            .iter()
+            .find(|route| {
+                route.method == request.method
+                    && route.matches(&path).is_some()
+                    && route.guards_pass(request)
+            })
+            .is_some_and(|route| route.stream_body)
+    }
+}
+
+/// A [`Router`] with its own state, see [`Router::with_state`].
+pub struct WithState<T> {
+    router: Router<T>,
+    state: Arc<T>,
+}
+
+impl<S, T> Middleware<S> for WithState<T>
+where
+    S: Send + Sync + 'static,
+    T: Send + Sync + 'static,
+{
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        state: Arc<S>,
+        next: Next<'a, S>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            if self.router.accepts(&request, &self.state) {
+                self.router.handle(request, self.state.clone()).await
+            } else {
+                next.run(request, state).await
+            }
+        })
+    }
+
+    fn accepts(&self, request: &HttpRequest, _state: &S) -> bool {
+        self.router.accepts(request, &self.state)
+    }
+
+    fn streams_body(&self, request: &HttpRequest, _state: &S) -> bool {
+        self.router.streams_body(request, &self.state)
+    }
+}
+
+/// The request's path segments, matched against the route patterns.
+fn request_segments(request: &HttpRequest) -> Result<Vec<String>, ParseError> {
+    // decoding each raw segment keeps an encoded "%2F" inside its parameter
+    if request.raw_path.is_empty() {
+        return Ok(split_path(&request.path)
+            .into_iter()
+            .map(str::to_string)
+            .collect());
+    }
+    split_path(&request.raw_path)
+        .into_iter()
+        .map(|segment| percent_decode(segment, false))
+        .collect()
+}
+
+impl<S: 'static> Default for Router<S> {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+/// Whether `path` is `prefix` or lies below it, e.g. "/files/a" for "/files".
+pub(crate) fn has_path_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Parses a route pattern into its segments.
+///
+/// # Panics
+///
+/// If a wildcard isn't the last segment.
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let segments: Vec<Segment> = split_path(pattern)
+        .into_iter()
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Static(segment.to_string())
+            }
+        })
+        .collect();
+    let wildcards = segments
+        .iter()
+        .position(|segment| matches!(segment, Segment::Wildcard(_)));
+    assert!(
+        wildcards.is_none_or(|position| position == segments.len() - 1),
+        "a wildcard is the last segment of the pattern {pattern:?}"
+    );
+    segments
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+#[tokio::test]
+async fn tests_router() {
+    async fn echo(request: HttpRequest, _: Arc<()>) -> Result<HttpResponse> {
+        let mut resp = HttpResponse::ok();
+        resp.set_body(request.param("msg").unwrap_or_default().as_bytes().into());
+        Ok(resp)
+    }
+    let router = Router::new()
+        .get("/", |_, _| async { Ok(HttpResponse::ok()) })
+        .get("/echo/:msg", echo)
+        .route("PUT", "/echo/:msg", echo);
+    let handle = async |method: &str, path: &str| {
+        let request = HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        router.handle(request, Arc::new(())).await.unwrap()
+    };
+
+    let actual = handle("GET", "/echo/hello").await;
+    assert_eq!(200, actual.status_code);
+    assert_eq!(Some(&b"hello"[..]), actual.body.as_bytes());
+
+    assert_eq!(200, handle("GET", "/").await.status_code);
+    assert_eq!(404, handle("GET", "/echo/hello/world").await.status_code);
+
+    let actual = handle("DELETE", "/echo/hello").await;
+    assert_eq!(405, actual.status_code);
+    assert_eq!(Some("GET, HEAD, PUT, OPTIONS"), actual.headers.get("Allow"));
+
+    let actual = handle("OPTIONS", "/echo/hello").await;
+    assert_eq!(204, actual.status_code);
+    assert_eq!(Some("GET, HEAD, PUT, OPTIONS"), actual.headers.get("Allow"));
+    assert_eq!(404, handle("OPTIONS", "/missing").await.status_code);
+    let actual = handle("OPTIONS", "*").await;
+    assert_eq!(Some("GET, HEAD, PUT, OPTIONS"), actual.headers.get("Allow"));
+
+    let actual = handle("HEAD", "/echo/hello").await;
+    assert_eq!(200, actual.status_code);
+    assert_eq!(Some(&b"hello"[..]), actual.body.as_bytes());
+
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        path: "/echo/a/b".to_string(),
+        raw_path: "/echo/a%2Fb".to_string(),
+        ..Default::default()
+    };
+    let actual = router.handle(request, Arc::new(())).await.unwrap();
+    assert_eq!(Some(&b"a/b"[..]), actual.body.as_bytes());
+}
+
+#[tokio::test]
+async fn tests_router_guards() {
+    use crate::guard;
+
+    fn reply(body: &'static str) -> impl Handler<()> {
+        move |_, _| async move { Ok(HttpResponse::builder().body(body).build()) }
+    }
+    let router = Router::new()
+        .handler("GET", "/items", reply("v2"))
+        .guard(guard::accept("application/vnd.items.v2+json"))
+        .handler("GET", "/items", reply("v1"))
+        .handler("POST", "/items", reply("json"))
+        .guard(guard::content_type("application/json"))
+        .guard(guard::header_present("X-Token"));
+    let handle = async |method: &str, headers: &[(&str, &str)]| {
+        let mut request = HttpRequest {
+            method: method.to_string(),
+            path: "/items".to_string(),
+            ..Default::default()
+        };
+        for (name, value) in headers {
+            request.headers.insert(name.to_string(), value.to_string());
+        }
+        router.handle(request, Arc::new(())).await.unwrap()
+    };
+
+    let actual = handle("GET", &[("Accept", "application/vnd.items.v2+json")]).await;
+    assert_eq!(Some(&b"v2"[..]), actual.body.as_bytes());
+    let actual = handle("GET", &[("Accept", "application/vnd.items.v1+json")]).await;
+    assert_eq!(Some(&b"v1"[..]), actual.body.as_bytes());
+    let actual = handle("HEAD", &[("Accept", "application/json")]).await;
+    assert_eq!(Some(&b"v1"[..]), actual.body.as_bytes());
+
+    let json = [("Content-Type", "application/json"), ("X-Token", "t")];
+    let actual = handle("POST", &json).await;
+    assert_eq!(Some(&b"json"[..]), actual.body.as_bytes());
+    assert_eq!(404, handle("POST", &json[..1]).await.status_code);
+    assert_eq!(405, handle("PUT", &json).await.status_code);
+}
+
+#[tokio::test]
+async fn tests_router_wildcard() {
+    async fn rest(request: HttpRequest, _: Arc<()>) -> Result<HttpResponse> {
+        let rest = request.param("path").unwrap_or_default().to_string();
+        Ok(HttpResponse::builder().body(rest).build())
+    }
+    let router = Router::new()
+        .get("/files/*path", rest)
+        .post("/files/upload", |_, _| async {
+            Ok(HttpResponse::created())
+        })
+        .fallback(|_, _| async { Ok(HttpResponse::builder().body("index").build()) });
+    let handle = async |method: &str, path: &str| {
+        let request = HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        router.handle(request, Arc::new(())).await.unwrap()
+    };
+
+    let actual = handle("GET", "/files/a/b/c.txt").await;
+    assert_eq!(Some(&b"a/b/c.txt"[..]), actual.body.as_bytes());
+    assert_eq!(Some("/files/*path"), actual.route.as_deref());
+    let actual = handle("GET", "/files/a").await;
+    assert_eq!(Some(&b"a"[..]), actual.body.as_bytes());
+
+    // the wildcard takes at least one segment
+    let actual = handle("GET", "/files").await;
+    assert_eq!(Some(&b"index"[..]), actual.body.as_bytes());
+    let actual = handle("GET", "/missing/page").await;
+    assert_eq!(Some(&b"index"[..]), actual.body.as_bytes());
+    assert_eq!(405, handle("DELETE", "/files/a").await.status_code);
+    assert_eq!(201, handle("POST", "/files/upload").await.status_code);
+}
+
+#[test]
+#[should_panic(expected = "a wildcard is the last segment")]
+fn tests_wildcard_in_the_middle() {
+    Router::<()>::new().get("/files/*path/raw", |_, _| async { Ok(HttpResponse::ok()) });
+}
+
+#[tokio::test]
+async fn tests_router_layers() {
+    fn tag(name: &'static str) -> impl Middleware<()> {
+        crate::middleware::from_fn(move |mut request: HttpRequest, state, next| {
+            Box::pin(async move {
+                request.headers.insert("X-Trace".to_string(), {
+                    let seen = request.headers.get("X-Trace").unwrap_or_default();
+                    format!("{seen}{name}>")
+                });
+                let mut resp = next.run(request, state).await?;
+                let body = resp.body.as_bytes().unwrap_or_default().to_vec();
+                resp.set_body([body, format!("<{name}").into_bytes()].concat());
+                Ok(resp)
+            })
+        })
+    }
+    let router = Router::new()
+        .get("/", |request: HttpRequest, _| async move {
+            let mut resp = HttpResponse::ok();
+            resp.set_body(request.headers.get("X-Trace").unwrap_or_default().into());
+            Ok(resp)
+        })
+        .layer(tag("inner"))
+        .layer(tag("outer"));
+
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        ..Default::default()
+    };
+    let actual = router.handle(request, Arc::new(())).await.unwrap();
+    assert_eq!(
+        Some(&b"outer>inner><inner<outer"[..]),
+        actual.body.as_bytes()
+    );
+}
+
+#[tokio::test]
+async fn tests_router_nest() {
+    let json_errors = crate::middleware::from_fn(|request, state, next| {
+        Box::pin(async move {
+            let mut resp = next.run(request, state).await?;
+            resp.set_header("Content-Type".to_string(), "application/json".to_string());
+            Ok(resp)
+        })
+    });
+    let items = Router::new().get("/:id", |request: HttpRequest, _| async move {
+        let body = format!(
+            "{}/{}",
+            request.param("user").unwrap_or_default(),
+            request.param("id").unwrap_or_default()
+        );
+        Ok(HttpResponse::builder().body(body).build())
+    });
+    let api = Router::new()
+        .get("/", |_, _| async { Ok(HttpResponse::ok()) })
+        .nest("/users/:user/items", items)
+        .layer(json_errors);
+    let router: Router<()> = Router::new()
+        .get("/files/:name", |_, _| async { Ok(HttpResponse::ok()) })
+        .nest("/api", api);
+    let get = async |path: &str| {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        router.handle(request, Arc::new(())).await.unwrap()
+    };
+
+    let actual = get("/api/users/ann/items/7").await;
+    assert_eq!(Some(&b"ann/7"[..]), actual.body.as_bytes());
+    assert_eq!(Some("/api/users/:user/items/:id"), actual.route.as_deref());
+    assert_eq!(Some("application/json"), actual.headers.get("Content-Type"));
+    assert_eq!(Some("/api"), get("/api").await.route.as_deref());
+
+    // the group answers everything below its prefix, through its layers
+    let actual = get("/api/missing").await;
+    assert_eq!(404, actual.status_code);
+    assert_eq!(Some("application/json"), actual.headers.get("Content-Type"));
+
+    let actual = get("/files/a.txt").await;
+    assert_eq!(200, actual.status_code);
+    assert_eq!(None, actual.headers.get("Content-Type"));
+    assert_eq!(404, get("/apis").await.status_code);
+}
+
+#[tokio::test]
+async fn tests_handler_state() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct AppState {
+        hits: AtomicUsize,
+    }
+    struct Greeting(&'static str);
+    impl Handler<AppState> for Greeting {
+        fn call(
+            &self,
+            _: HttpRequest,
+            state: Arc<AppState>,
+        ) -> BoxFuture<'_, Result<HttpResponse>> {
+            Box::pin(async move {
+                let hits = state.hits.fetch_add(1, Ordering::Relaxed) + 1;
+                Ok(HttpResponse::builder()
+                    .body(format!("{} #{hits}", self.0))
+                    .build())
+            })
+        }
+    }
+    let state = Arc::new(AppState::default());
+    let app = Router::new()
+        .handler("GET", "/hello", Greeting("hello"))
+        .get("/hits", |_, state: Arc<AppState>| async move {
+            let hits = state.hits.load(Ordering::Relaxed).to_string();
+            Ok(HttpResponse::builder().body(hits).build())
+        });
+    let router: Router<()> = Router::new()
+        .get("/", |_, _| async { Ok(HttpResponse::ok()) })
+        .layer(app.with_state(state.clone()));
+    let get = async |path: &str| {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        router.handle(request, Arc::new(())).await.unwrap()
+    };
+
+    assert_eq!(Some(&b"hello #1"[..]), get("/hello").await.body.as_bytes());
+    assert_eq!(Some(&b"hello #2"[..]), get("/hello").await.body.as_bytes());
+    assert_eq!(Some(&b"2"[..]), get("/hits").await.body.as_bytes());
+    assert_eq!(200, get("/").await.status_code);
+    assert_eq!(404, get("/missing").await.status_code);
+    assert_eq!(2, state.hits.load(Ordering::Relaxed));
+}
@@ -0,0 +1,1576 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result};
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+use crate::body::{self, BodyStream, Forwarded, Framing};
+use crate::config::{ConfigHandle, Overload, ServerConfig};
+use crate::error::HttpError;
+use crate::handlers::default_router;
+use crate::http2;
+use crate::listener::{Accepted, Listener};
+use crate::method;
+use crate::metrics::{CountingWriter, Metrics};
+use crate::middleware::Middleware;
+use crate::request::{HttpRequest, ParseError, Parsed, RequestParser, Version};
+use crate::response::{CLIENT_CLOSED_REQUEST, Delivery, HttpResponse};
+use crate::router::Router;
+use crate::status::StatusCode;
+use crate::tls;
+use crate::upgrade::Upgraded;
+
+/// An HTTP server serving a [`Router`] with the given [`ServerConfig`].
+pub struct Server {
+    config: ConfigHandle,
+    router: Arc<Router<ServerConfig>>,
+    metrics: Arc<Metrics>,
+    /// Whether connections are being accepted, reported by /readyz.
+    ready: Arc<AtomicBool>,
+}
+
+pub struct ServerBuilder {
+    config: ServerConfig,
+    router: Router<ServerConfig>,
+    metrics: Arc<Metrics>,
+}
+
+impl ServerBuilder {
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Replaces the built-in routes with `router`.
+    pub fn router(mut self, router: Router<ServerConfig>) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Registers an additional route next to the ones already configured.
+    pub fn route<H, F>(mut self, method: &str, pattern: &str, handler: H) -> Self
+    where
+        H: Fn(HttpRequest, Arc<ServerConfig>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<HttpResponse>> + Send + 'static,
+    {
+        self.router = self.router.route(method, pattern, handler);
+        self
+    }
+
+    /// Answers requests no route matches with `handler` instead of 404, see
+    /// [`Router::fallback`].
+    pub fn fallback<H, F>(mut self, handler: H) -> Self
+    where
+        H: Fn(HttpRequest, Arc<ServerConfig>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<HttpResponse>> + Send + 'static,
+    {
+        self.router = self.router.fallback(handler);
+        self
+    }
+
+    /// Serves `router`'s routes below `prefix`, see [`Router::nest`].
+    pub fn nest(mut self, prefix: &str, router: Router<ServerConfig>) -> Self {
+        self.router = self.router.nest(prefix, router);
+        self
+    }
+
+    /// Serves `router`'s routes ahead of the server's own, passing `state` to its handlers in
+    /// place of the config.
+    pub fn state_router<T: Send + Sync + 'static>(
+        self,
+        router: Router<T>,
+        state: impl Into<Arc<T>>,
+    ) -> Self {
+        self.layer(router.with_state(state))
+    }
+
+    /// Wraps the configured routes in `middleware`, see [`Router::layer`].
+    pub fn layer(mut self, middleware: impl Middleware<ServerConfig>) -> Self {
+        self.router = self.router.layer(middleware);
+        self
+    }
+
+    /// Serves the server's [`Metrics`] in the Prometheus text format at `path`. Replacing the
+    /// router afterwards removes the route again.
+    pub fn metrics_endpoint(self, path: &str) -> Self {
+        let metrics = self.metrics.clone();
+        self.route("GET", path, move |_, _| {
+            let metrics = metrics.clone();
+            async move {
+                Ok(HttpResponse::builder()
+                    .header("Content-Type", "text/plain; version=0.0.4")
+                    .body(metrics.render())
+                    .build())
+            }
+        })
+    }
+
+    /// Builds the server with the `/healthz` and `/readyz` probes appended, so routes registered
+    /// for the same paths take precedence.
+    pub fn build(self) -> Server {
+        let ready = Arc::new(AtomicBool::new(false));
+        let readiness = ready.clone();
+        let router = self
+            .router
+            .get("/healthz", |_, _| async { Ok(probe(StatusCode::Ok)) })
+            .get("/readyz", move |_, _| {
+                let ready = readiness.load(Ordering::SeqCst);
+                async move {
+                    Ok(probe(if ready {
+                        StatusCode::Ok
+                    } else {
+                        StatusCode::ServiceUnavailable
+                    }))
+                }
+            });
+        Server {
+            config: ConfigHandle::new(self.config),
+            router: Arc::new(router),
+            metrics: self.metrics,
+            ready,
+        }
+    }
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            config: ServerConfig::default(),
+            router: default_router(),
+            metrics: Arc::new(Metrics::new()),
+        }
+    }
+
+    pub fn config(&self) -> Arc<ServerConfig> {
+        self.config.current()
+    }
+
+    /// A handle for swapping in a new configuration while the server runs.
+    pub fn config_handle(&self) -> ConfigHandle {
+        self.config.clone()
+    }
+
+    /// The counters updated by every connection, whether or not they are served over HTTP.
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Answers `request` in-process, through the same layers and routes as a request received
+    /// on a connection, e.g. to test them without opening sockets. As on a connection, handler
+    /// errors become a 500, and streaming routes read the body from their [`BodyStream`]. The
+    /// body of a response to HEAD is left for the caller to ignore.
+    pub async fn handle(&self, mut request: HttpRequest) -> HttpResponse {
+        let config = self.config.current();
+        if self.router.streams_body(&request, &config) {
+            let body = std::mem::take(&mut request.body);
+            let trailers = std::mem::take(&mut request.trailers);
+            request.body_stream = Some(BodyStream::buffered(body, trailers));
+        }
+        let started = Instant::now();
+        let method = request.method.clone();
+        let version = request.version;
+        let mut resp = self
+            .router
+            .handle(request, config)
+            .await
+            .unwrap_or_else(error_response);
+        resp.version = version;
+        let route = resp.route.take();
+        self.metrics.record_request(
+            &method,
+            route.as_deref(),
+            resp.status_code.as_u16(),
+            started.elapsed(),
+        );
+        resp
+    }
+
+    /// Binds the configured addresses, or Unix socket if one is set, and serves until SIGINT or
+    /// SIGTERM.
+    pub async fn run(self) -> Result<()> {
+        let config = self.config.current();
+        let listeners = match &config.unix_socket {
+            #[cfg(unix)]
+            Some(path) => vec![Listener::bind_unix(path)?],
+            #[cfg(not(unix))]
+            Some(_) => anyhow::bail!("Unix domain sockets are not supported on this platform"),
+            None => {
+                let mut listeners = vec![];
+                for address in &config.addresses {
+                    let (host, port) = host_port(address, config.port)?;
+                    let listener = TcpListener::bind((host, port))
+                        .await
+                        .with_context(|| format!("Unable to bind {address}"))?;
+                    listeners.push(listener.into());
+                }
+                listeners
+            }
+        };
+        let shutdown = shutdown_signal()?;
+        let result = self.serve_all(listeners, shutdown).await;
+        if let Some(path) = &config.unix_socket {
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+
+    /// Serves connections from `listener` until `shutdown` completes, then waits up to the
+    /// configured grace period for open connections.
+    pub async fn serve(
+        self,
+        listener: impl Into<Listener>,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<()> {
+        self.serve_all(vec![listener.into()], shutdown).await
+    }
+
+    /// Serves connections from all `listeners` like [`Server::serve`], each accepted by its own
+    /// task.
+    pub async fn serve_all(
+        self,
+        listeners: Vec<Listener>,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<()> {
+        let handle = self.config;
+        let config = handle.current();
+        let router = self.router;
+        let metrics = self.metrics;
+        let ready = self.ready;
+        let acceptor = match (&config.tls_cert, &config.tls_key) {
+            (Some(cert), Some(key)) => Some(tls::acceptor(cert, key, config.http2)?),
+            (None, None) => None,
+            _ => anyhow::bail!("the TLS certificate and key must be configured together"),
+        };
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        let limiter = config
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+        tokio::pin!(shutdown);
+
+        ready.store(true, Ordering::SeqCst);
+        tracing::info!("Service ready with config: {:?}", config);
+        let (accepted_tx, mut accepted_rx) = mpsc::channel(listeners.len().max(1));
+        let mut accept_loops = JoinSet::new();
+        for listener in listeners {
+            tracing::info!("Listening on {listener}");
+            accept_loops.spawn(accept_loop(
+                listener,
+                limiter.clone(),
+                config.overload,
+                accepted_tx.clone(),
+            ));
+        }
+        drop(accepted_tx);
+        let mut next_id: u64 = 0;
+        loop {
+            let (accepted, permit) = tokio::select! {
+                Some(accepted) = accepted_rx.recv() => accepted?,
+                // reap finished connections so the set doesn't grow for the lifetime of the server
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+                _ = &mut shutdown => break,
+            };
+            let connection = Connection {
+                router: router.clone(),
+                config: handle.clone(),
+                metrics: metrics.clone(),
+                acceptor: acceptor.clone(),
+                shutdown: shutdown_rx.clone(),
+                saturated: limiter.is_some() && permit.is_none(),
+            };
+            next_id += 1;
+            let peer = match &accepted {
+                Accepted::Tcp(_, peer) => Some(tracing::field::display(*peer)),
+                #[cfg(unix)]
+                Accepted::Unix(_) => None,
+            };
+            let span = tracing::info_span!("connection", id = next_id, peer);
+            connections.spawn(
+                async move {
+                    // the permit is released once the connection is done
+                    let _permit = permit;
+                    let result = match accepted {
+                        Accepted::Tcp(stream, peer) => {
+                            // a response written in several parts must not wait for the client to
+                            // acknowledge the first one
+                            if let Err(e) = stream.set_nodelay(true) {
+                                tracing::warn!("Unable to set TCP_NODELAY: {e}");
+                            }
+                            connection.serve(stream, Some(peer)).await
+                        }
+                        #[cfg(unix)]
+                        Accepted::Unix(stream) => connection.serve(stream, None).await,
+                    };
+                    if let Err(e) = result {
+                        tracing::warn!("Connection error: {e:?}");
+                    }
+                }
+                .instrument(span),
+            );
+        }
+
+        // the grace period may have been reloaded since startup
+        let config = handle.current();
+        tracing::info!(
+            "Shutting down, waiting up to {:?} for {} connection(s)",
+            config.grace_period,
+            connections.len()
+        );
+        // stops accepting and closes the listeners
+        accept_loops.shutdown().await;
+        ready.store(false, Ordering::SeqCst);
+        let _ = shutdown_tx.send(true);
+        let drained = tokio::time::timeout(config.grace_period, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            tracing::warn!(
+                "Grace period elapsed, aborting {} connection(s)",
+                connections.len()
+            );
+            connections.shutdown().await;
+        }
+        Ok(())
+    }
+}
+
+/// A connection accepted by an [`accept_loop`] and its permit under `max_connections`.
+type AcceptResult = std::io::Result<(Accepted, Option<OwnedSemaphorePermit>)>;
+
+/// Accepts connections from `listener`, handing them to the serve loop until it stops listening
+/// or accepting fails.
+async fn accept_loop(
+    listener: Listener,
+    limiter: Option<Arc<Semaphore>>,
+    overload: Overload,
+    accepted: mpsc::Sender<AcceptResult>,
+) {
+    loop {
+        // when queueing, connections beyond the limit wait in the listen backlog
+        let queued = match (&limiter, overload) {
+            (Some(limiter), Overload::Queue) => match limiter.clone().acquire_owned().await {
+                Ok(permit) => Some(permit),
+                Err(_) => return,
+            },
+            _ => None,
+        };
+        let result = listener.accept().await.map(|stream| {
+            let permit = match &limiter {
+                Some(limiter) => queued.or_else(|| limiter.clone().try_acquire_owned().ok()),
+                None => None,
+            };
+            (stream, permit)
+        });
+        let failed = result.is_err();
+        if accepted.send(result).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Splits "host:port" or "[v6]:port" into the host and port, addresses without a port get
+/// `port`.
+fn host_port(address: &str, port: u16) -> Result<(&str, u16)> {
+    let invalid = || format!("Invalid address {address:?}");
+    if let Some(rest) = address.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']').with_context(invalid)?;
+        return match rest.strip_prefix(':') {
+            Some(port) => Ok((host, port.parse().with_context(invalid)?)),
+            None if rest.is_empty() => Ok((host, port)),
+            None => anyhow::bail!(invalid()),
+        };
+    }
+    match address.rsplit_once(':') {
+        // a bare IPv6 address
+        Some((host, _)) if host.contains(':') => Ok((address, port)),
+        Some((host, port)) => Ok((host, port.parse().with_context(invalid)?)),
+        None => Ok((address, port)),
+    }
+}
+
+/// What an accepted connection is served with.
+struct Connection {
+    router: Arc<Router<ServerConfig>>,
+    config: ConfigHandle,
+    metrics: Arc<Metrics>,
+    acceptor: Option<tokio_rustls::TlsAcceptor>,
+    shutdown: watch::Receiver<bool>,
+    /// Whether the connection limit is reached, so the connection is rejected.
+    saturated: bool,
+}
+
+impl Connection {
+    async fn serve<S>(self, stream: S, peer: Option<SocketAddr>) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let Connection {
+            router,
+            config,
+            metrics,
+            acceptor,
+            shutdown,
+            saturated,
+        } = self;
+        match acceptor {
+            Some(acceptor) => match acceptor.accept(stream).await {
+                Ok(stream) if saturated => reject_connection(stream).await,
+                Ok(stream) if stream.get_ref().1.alpn_protocol() == Some(b"h2") => {
+                    let _connection = metrics.connection();
+                    http2::serve(stream, peer, router, config, metrics.clone(), shutdown).await
+                }
+                Ok(stream) => {
+                    handle_connection(stream, peer, router, config, metrics, shutdown).await
+                }
+                Err(e) => Err(anyhow::Error::new(e).context("TLS handshake failed")),
+            },
+            None if saturated => reject_connection(stream).await,
+            None => handle_connection(stream, peer, router, config, metrics, shutdown).await,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn shutdown_signal() -> Result<impl Future<Output = ()>> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate()).context("Unable to listen for SIGTERM")?;
+    Ok(async move {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    })
+}
+
+#[cfg(not(unix))]
+fn shutdown_signal() -> Result<impl Future<Output = ()>> {
+    Ok(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    mut stream: S,
+    peer: Option<SocketAddr>,
+    router: Arc<Router<ServerConfig>>,
+    handle: ConfigHandle,
+    metrics: Arc<Metrics>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let _connection = metrics.connection();
+    let mut keep_alive = false;
+    let mut served = 0;
+    // bytes read past the end of a request, the start of the next pipelined one
+    let mut input = BytesMut::with_capacity(1024);
+    loop {
+        // each request runs with the config current when it started arriving
+        let config = handle.current();
+        // requests that are already being handled finish, idle connections close on shutdown
+        let read = tokio::select! {
+            read = read_request(&mut stream, &mut input, &config, keep_alive, &router) => read?,
+            Ok(_) = shutdown.wait_for(|shutting_down| *shutting_down) => break,
+        };
+        let mut streamed_body = None;
+        let parsed = match read {
+            ReadResult::Request(request) => Ok(*request),
+            ReadResult::Streaming(request, framing) => {
+                streamed_body = Some(framing);
+                Ok(*request)
+            }
+            ReadResult::Failed(e) => {
+                if let HttpError::Parse(ParseError::Malformed(message)) = &e {
+                    tracing::info!("Rejecting malformed request: {message}");
+                }
+                Err(HttpResponse::from(e))
+            }
+            ReadResult::Rejected(resp) => Err(resp),
+            ReadResult::Http2 => {
+                let stream = Upgraded::new(input.split().freeze(), stream);
+                return http2::serve(stream, peer, router, handle, metrics.clone(), shutdown).await;
+            }
+            // the client closed its side of the connection, no further requests will arrive
+            ReadResult::Closed => break,
+        };
+
+        let mut request = match parsed {
+            Ok(request) => request,
+            // the framing of anything that follows is unknown, so the connection is closed
+            Err(mut resp) => {
+                resp.set_header("Connection".to_string(), "close".to_string());
+                let mut writer = CountingWriter::new(&mut stream);
+                let written = resp.write_to(&mut writer).await;
+                metrics.record_bytes_sent(writer.written);
+                written.context("Unable to write")?;
+                break;
+            }
+        };
+        request.peer_addr = peer;
+        request.resolve_remote_addr(&config.trusted_proxies);
+        if config.http2 && streamed_body.is_none() && http2::wants_upgrade(&request) {
+            let mut resp = HttpResponse::switching_protocols();
+            resp.set_header("Connection".to_string(), "Upgrade".to_string());
+            resp.set_header("Upgrade".to_string(), "h2c".to_string());
+            let mut writer = CountingWriter::new(&mut stream);
+            let written = resp.write_to(&mut writer).await;
+            metrics.record_bytes_sent(writer.written);
+            written.context("Unable to write")?;
+            let deadline = Instant::now() + config.header_timeout;
+            let stream = http2::upgrade(stream, input, &request, deadline).await?;
+            return http2::serve(stream, peer, router, handle, metrics.clone(), shutdown).await;
+        }
+        let started = Instant::now();
+        let method = request.method.clone();
+        let span = request_span(&method, &request.raw_path);
+        let mut close = !request.keep_alive() || *shutdown.borrow();
+        let version = request.version;
+        let head = request.method == "HEAD";
+
+        let forwarding = streamed_body.map(|framing| {
+            let (tx, body) = BodyStream::channel();
+            request.body_stream = Some(body);
+            (framing, tx)
+        });
+        let handling = router
+            .handle(request, config.clone())
+            .instrument(span.clone());
+        // the handler reads the streamed body while it is forwarded from the connection
+        let (handled, forwarded) = match forwarding {
+            Some((framing, tx)) => {
+                let deadline = Instant::now() + config.body_timeout;
+                tokio::join!(
+                    handling,
+                    body::forward(
+                        &mut stream,
+                        &mut input,
+                        framing,
+                        tx,
+                        deadline,
+                        config.max_body_size
+                    )
+                )
+            }
+            None => (handling.await, Ok(Forwarded::Complete)),
+        };
+        let handled = handled.unwrap_or_else(error_response);
+        let mut result = match forwarded? {
+            Forwarded::Complete => handled,
+            Forwarded::Disconnected => break,
+            // the unread rest of the body would be taken for the next request
+            Forwarded::Abandoned => {
+                close = true;
+                handled
+            }
+            Forwarded::Rejected(mut resp) => {
+                close = true;
+                resp.on_complete = handled.on_complete;
+                resp
+            }
+        };
+        result.version = version;
+        served += 1;
+        let remaining = config
+            .max_keep_alive_requests
+            .map(|max| max.saturating_sub(served));
+        let close = close || result.is_close_delimited() || remaining == Some(0);
+
+        // a successful CONNECT turns the connection into a tunnel just like a 101
+        let switches = result.status_code == StatusCode::SwitchingProtocols
+            || method == "CONNECT" && result.status_code.is_success();
+        if switches && result.on_upgrade.is_some() {
+            // the handler keeps its own Connection: upgrade header and owns the stream from here,
+            // so the head goes out without a body length
+            let head = result.encode_head();
+            let on_upgrade = result
+                .on_upgrade
+                .take()
+                .context("upgrade handler was set")?;
+            let route = result.route.take();
+            let mut writer = CountingWriter::new(&mut stream);
+            let written = async {
+                writer.write_all(&head).await?;
+                writer.flush().await
+            }
+            .await;
+            let delivery = writer.delivery(&written);
+            metrics.record_bytes_sent(writer.written);
+            let status = match delivery {
+                Delivery::Aborted => CLIENT_CLOSED_REQUEST,
+                _ => result.status_code.as_u16(),
+            };
+            metrics.record_request(&method, route.as_deref(), status, started.elapsed());
+            span.record("status", status);
+            span.in_scope(|| HttpResponse::completed(result.on_complete.take(), delivery));
+            if delivery == Delivery::Aborted {
+                return Ok(());
+            }
+            written.context("Unable to write")?;
+            // bytes of the new protocol may have arrived along with the request
+            let buffered = input.split().freeze();
+            return on_upgrade(Upgraded::new(buffered, Box::new(stream))).await;
+        }
+
+        let connection = if close { "close" } else { "keep-alive" };
+        result.set_header("Connection".to_string(), connection.to_string());
+        if !close {
+            let limits = keep_alive_limits(config.keep_alive_timeout, remaining);
+            result.set_header("Keep-Alive".to_string(), limits);
+        }
+
+        let route = result.route.take();
+        let on_complete = result.on_complete.take();
+        let mut status = result.status_code.as_u16();
+        let mut writer = CountingWriter::new(&mut stream);
+        let written = if head {
+            result.write_head_to(&mut writer).await
+        } else {
+            result.write_to(&mut writer).await
+        };
+        let delivery = writer.delivery(&written);
+        metrics.record_bytes_sent(writer.written);
+        if delivery == Delivery::Aborted {
+            status = CLIENT_CLOSED_REQUEST;
+        }
+        metrics.record_request(&method, route.as_deref(), status, started.elapsed());
+        span.record("status", status);
+        span.in_scope(|| HttpResponse::completed(on_complete, delivery));
+        // the client is gone, which is nothing to report; the dropped body stops being read
+        if delivery == Delivery::Aborted {
+            break;
+        }
+        written.context("Unable to write")?;
+
+        if close {
+            break;
+        }
+        keep_alive = true;
+    }
+    Ok(())
+}
+
+/// The Keep-Alive header telling the client how long it may leave the connection idle and how
+/// many more requests it takes. The timeout is given in whole seconds, at least 1, as a client
+/// reads 0 as not keeping the connection at all.
+fn keep_alive_limits(timeout: std::time::Duration, remaining: Option<usize>) -> String {
+    let mut limits = format!("timeout={}", timeout.as_secs().max(1));
+    if let Some(remaining) = remaining {
+        limits.push_str(&format!(", max={remaining}"));
+    }
+    limits
+}
+
+/// The span of a request, within that of its connection. Its status is recorded once the
+/// response has been written.
+pub(crate) fn request_span(method: &str, path: &str) -> tracing::Span {
+    tracing::info_span!("request", method, path, status = tracing::field::Empty)
+}
+
+/// Whether a failed read or write means the client disconnected abruptly.
+pub(crate) fn is_disconnect(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            // e.g. a TLS client closing without close_notify
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+/// A plain text answer for the health probes.
+fn probe(status: StatusCode) -> HttpResponse {
+    HttpResponse::builder()
+        .status(status)
+        .header("Content-Type", "text/plain")
+        .header("Cache-Control", "no-store")
+        .body(format!("{}\n", status.reason()))
+        .build()
+}
+
+/// Answers a connection over the limit with a 503 without reading its request.
+async fn reject_connection<S: AsyncWrite + Unpin>(mut stream: S) -> Result<()> {
+    let mut resp = HttpResponse::service_unavailable();
+    resp.set_header("Retry-After".to_string(), "1".to_string());
+    resp.set_header("Connection".to_string(), "close".to_string());
+    resp.write_to(&mut stream).await.context("Unable to write")
+}
+
+enum ReadResult {
+    Request(Box<HttpRequest>),
+    /// The head of a request to a streaming route, whose body follows in the input buffer.
+    Streaming(Box<HttpRequest>, Framing),
+    /// The request is malformed or breaks a timeout or size limit.
+    Failed(HttpError),
+    /// The request is answered with this response without being handled.
+    Rejected(HttpResponse),
+    /// The client opened the connection with the HTTP/2 preface, which is in the input buffer.
+    Http2,
+    Closed,
+}
+
+/// Reads the next request into `input`, which holds any bytes left over from the previous one.
+/// A keep-alive connection may idle for `keep_alive_timeout` before the first byte, after which
+/// the header and body timeouts and size limits apply in turn.
+async fn read_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    input: &mut BytesMut,
+    config: &ServerConfig,
+    keep_alive: bool,
+    router: &Router<ServerConfig>,
+) -> Result<ReadResult> {
+    let idle = keep_alive && input.is_empty();
+    let mut deadline = Instant::now()
+        + if idle {
+            config.keep_alive_timeout
+        } else {
+            config.header_timeout
+        };
+    let mut parser = RequestParser::new()
+        .max_head_size(config.max_header_size)
+        .max_body_size(config.max_body_size)
+        .allow_obs_fold(config.allow_obs_fold);
+    let mut head_checked = false;
+    // a pipelined request may already be buffered in full
+    let mut read_more = input.is_empty();
+    loop {
+        if read_more {
+            let read = match tokio::time::timeout_at(deadline, stream.read_buf(input)).await {
+                // a client that went away without a word is not an error of the server's
+                Ok(Err(e)) if is_disconnect(&e) => return Ok(ReadResult::Closed),
+                Ok(read) => read.context("Failed to read")?,
+                // an idle keep-alive connection is closed without a response
+                Err(_) if idle && input.is_empty() => return Ok(ReadResult::Closed),
+                Err(_) => return Ok(ReadResult::Failed(HttpError::Timeout)),
+            };
+            // the client shut down its side, it may still read the answer to a truncated request
+            if read == 0 {
+                if input.is_empty() {
+                    return Ok(ReadResult::Closed);
+                }
+                return Ok(ReadResult::Failed(
+                    ParseError::Malformed(
+                        "connection closed before the full request was received".to_string(),
+                    )
+                    .into(),
+                ));
+            }
+            if idle && input.len() == read {
+                deadline = Instant::now() + config.header_timeout;
+            }
+        }
+        read_more = true;
+
+        // a client that knows the server speaks HTTP/2 starts with its preface right away
+        if !keep_alive && config.http2 {
+            let start = &input[..input.len().min(http2::PREFACE.len())];
+            if http2::PREFACE.starts_with(start) {
+                if input.len() >= http2::PREFACE.len() {
+                    return Ok(ReadResult::Http2);
+                }
+                continue;
+            }
+        }
+
+        let head = match parser.parse_head(input) {
+            Ok(head) => head,
+            Err(e) => return Ok(ReadResult::Failed(e.into())),
+        };
+        let Some((request, body_start)) = head else {
+            continue;
+        };
+
+        if !head_checked {
+            head_checked = true;
+            deadline = Instant::now() + config.body_timeout;
+            if let Err(e) = check_host(request) {
+                return Ok(ReadResult::Failed(e.into()));
+            }
+            // a method only a route or layer knows is still served
+            if !method::KNOWN.contains(&request.method.as_str()) && !router.accepts(request, config)
+            {
+                return Ok(ReadResult::Rejected(HttpResponse::new(
+                    StatusCode::NotImplemented,
+                )));
+            }
+            if unsupported_expectation(request) {
+                return Ok(ReadResult::Rejected(HttpResponse::expectation_failed()));
+            }
+            // the parser already refused a Content-Length above the limit
+            if let Some(framing) = request.framing() {
+                // HTTP/1.0 clients don't know interim responses, and a body that is already
+                // arriving needs no go-ahead
+                if request.headers.contains_key("Expect")
+                    && request.version == Version::Http11
+                    && input.len() == body_start
+                {
+                    if !router.accepts(request, config) {
+                        return Ok(ReadResult::Rejected(HttpResponse::expectation_failed()));
+                    }
+                    stream
+                        .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                        .await
+                        .context("Unable to write")?;
+                    stream.flush().await.context("Unable to write")?;
+                }
+                if router.streams_body(request, config) {
+                    let (request, body_start) = parser.take_head().context("head was parsed")?;
+                    // the body that follows is forwarded from the connection's buffer
+                    input.advance(body_start);
+                    return Ok(ReadResult::Streaming(Box::new(request), framing));
+                }
+            }
+        }
+        // the request is split off the buffer, with its body sharing the bytes read
+        match parser.feed_buf(input) {
+            Ok(Parsed::Complete(request, _)) => return Ok(ReadResult::Request(request)),
+            // the parser limits the decoded chunks; this bounds their framing and trailers too
+            Ok(Parsed::NeedMoreData) if input.len() - body_start > config.max_body_size => {
+                return Ok(ReadResult::Failed(HttpError::PayloadTooLarge));
+            }
+            Ok(Parsed::NeedMoreData) => {}
+            Err(e) => return Ok(ReadResult::Failed(e.into())),
+        }
+    }
+}
+
+/// HTTP/1.1 requests name the host they are for exactly once, HTTP/1.0 ones at most once.
+fn check_host(request: &HttpRequest) -> Result<(), ParseError> {
+    match request.headers.get_all("Host").count() {
+        0 if request.version == Version::Http11 => {
+            Err(ParseError::Malformed("missing Host header".to_string()))
+        }
+        0 | 1 => Ok(()),
+        _ => Err(ParseError::Malformed("multiple Host headers".to_string())),
+    }
+}
+
+/// Whether the request expects something other than `100-continue`, the only expectation
+/// this server knows.
+fn unsupported_expectation(request: &HttpRequest) -> bool {
+    request
+        .headers
+        .get("Expect")
+        .is_some_and(|expect| !expect.eq_ignore_ascii_case("100-continue"))
+}
+
+/// The response to a handler that failed: the status of an [`HttpError`] it returned, a 500
+/// for anything else.
+pub(crate) fn error_response(error: anyhow::Error) -> HttpResponse {
+    match error.downcast::<HttpError>() {
+        Ok(error) => {
+            if error.status().is_server_error() {
+                tracing::error!("Handler error: {error:?}");
+            }
+            HttpResponse::from(error)
+        }
+        Err(error) => {
+            tracing::error!("Handler error: {error:?}");
+            HttpResponse::internal_server_error()
+        }
+    }
+}
+
+/// Serves an in-memory connection with `router`, returning the client's end of it and the task
+/// serving it.
+#[cfg(test)]
+fn connect(
+    router: Arc<Router<ServerConfig>>,
+    config: ConfigHandle,
+    metrics: Arc<Metrics>,
+) -> (tokio::io::DuplexStream, tokio::task::JoinHandle<Result<()>>) {
+    let (client, server) = tokio::io::duplex(1024);
+    let connection = tokio::spawn(handle_connection(
+        server,
+        None,
+        router,
+        config,
+        metrics,
+        watch::channel(false).1,
+    ));
+    (client, connection)
+}
+
+/// Sends `request` over a new connection and reads what comes back until the server closes it.
+#[cfg(test)]
+async fn exchange(
+    router: Arc<Router<ServerConfig>>,
+    config: ConfigHandle,
+    request: &[u8],
+) -> String {
+    let (mut client, connection) = connect(router, config, Default::default());
+    client.write_all(request).await.unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    connection.await.unwrap().unwrap();
+    response
+}
+
+#[test]
+fn tests_host_port() {
+    assert_eq!(("0.0.0.0", 4221), host_port("0.0.0.0", 4221).unwrap());
+    assert_eq!(("0.0.0.0", 80), host_port("0.0.0.0:80", 4221).unwrap());
+    assert_eq!(
+        ("localhost", 8080),
+        host_port("localhost:8080", 4221).unwrap()
+    );
+    assert_eq!(("::", 8080), host_port("[::]:8080", 4221).unwrap());
+    assert_eq!(("::1", 4221), host_port("[::1]", 4221).unwrap());
+    assert_eq!(("::1", 4221), host_port("::1", 4221).unwrap());
+    assert!(host_port("0.0.0.0:http", 4221).is_err());
+    assert!(host_port("[::1", 4221).is_err());
+    assert!(host_port("[::1]8080", 4221).is_err());
+}
+
+#[tokio::test]
+async fn tests_handle_connection() {
+    let metrics = Arc::new(Metrics::new());
+    let (mut client, connection) = connect(
+        Arc::new(default_router()),
+        ConfigHandle::new(ServerConfig::default()),
+        metrics.clone(),
+    );
+
+    client
+        .write_all(b"GET /echo/abc HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    connection.await.unwrap().unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(response.ends_with("\r\n\r\nabc"));
+
+    let rendered = metrics.render();
+    assert!(
+        rendered.contains(
+            "http_requests_total{method=\"GET\",route=\"/echo/:msg\",status=\"200\"} 1\n"
+        )
+    );
+    assert!(rendered.contains(&format!("http_response_bytes_total {}\n", response.len())));
+    assert!(rendered.contains("http_connections_in_flight 0\n"));
+}
+
+#[tokio::test]
+async fn tests_handle_connection_bad_request() {
+    let response = exchange(
+        Arc::new(default_router()),
+        ConfigHandle::new(ServerConfig::default()),
+        b"GET /echo/%zz HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    )
+    .await;
+
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(response.ends_with(
+        "Bad Request: invalid request path \"/echo/%zz\": invalid percent-encoding at byte 6\n"
+    ));
+}
+
+#[tokio::test]
+async fn tests_handle_connection_smuggling() {
+    // read by Content-Length, the body would end right before the smuggled request
+    let response = exchange(
+        Arc::new(default_router()),
+        ConfigHandle::new(ServerConfig::default()),
+        b"POST /echo/a HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+          Transfer-Encoding: chunked\r\n\r\n0\r\n\r\n\
+          GET /echo/smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    )
+    .await;
+
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert_eq!(1, response.matches("HTTP/1.1").count());
+    assert!(!response.contains("smuggled"));
+}
+
+#[tokio::test]
+async fn tests_handle_connection_host() {
+    let send = async |request: &[u8]| {
+        let config = ConfigHandle::new(ServerConfig::default());
+        exchange(Arc::new(default_router()), config, request).await
+    };
+
+    let response = send(b"GET /echo/a HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(response.ends_with("Bad Request: missing Host header\n"));
+    let response = send(b"GET /echo/a HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n").await;
+    assert!(response.ends_with("Bad Request: multiple Host headers\n"));
+    let response = send(b"GET /echo/a HTTP/1.0\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+    let response = send(
+        b"GET http://example.com/echo/proxied HTTP/1.1\r\nHost: example.com\r\n\
+          Connection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nproxied"));
+
+    let response = send(b"BREW /echo/a HTTP/1.1\r\nHost: a\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    let response = send(b"GET /echo/a HTTP/3.0\r\nHost: a\r\n\r\n").await;
+    assert!(response.contains(" 505 HTTP Version Not Supported\r\n"));
+}
+
+#[tokio::test]
+async fn tests_handle_connection_head() {
+    let response = exchange(
+        Arc::new(default_router()),
+        ConfigHandle::new(ServerConfig::default()),
+        b"HEAD /echo/abc HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain\r\n"));
+    assert!(response.contains("Content-Length: 3\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+}
+
+#[tokio::test]
+async fn tests_handle_connection_client_abort() {
+    use tokio::io::AsyncReadExt as _;
+
+    let (delivered, mut delivery) = tokio::sync::mpsc::unbounded_channel();
+    let router = Router::new().get("/large", move |_, _| {
+        let delivered = delivered.clone();
+        async move {
+            let mut resp = HttpResponse::ok();
+            resp.set_stream(tokio::io::repeat(b'x').take(1 << 20));
+            resp.on_complete(move |outcome| delivered.send(outcome).unwrap());
+            Ok(resp)
+        }
+    });
+    let metrics = Arc::new(Metrics::new());
+    let (mut client, connection) = connect(
+        Arc::new(router),
+        ConfigHandle::new(ServerConfig::default()),
+        metrics.clone(),
+    );
+
+    client
+        .write_all(b"GET /large HTTP/1.1\r\nHost: x\r\n\r\n")
+        .await
+        .unwrap();
+    let mut start = [0; 512];
+    client.read_exact(&mut start).await.unwrap();
+    drop(client);
+
+    // the connection ends without an error
+    connection.await.unwrap().unwrap();
+    assert_eq!(Some(Delivery::Aborted), delivery.recv().await);
+    assert!(metrics.render().contains("status=\"499\"} 1"));
+}
+
+#[tokio::test]
+async fn tests_handle_connection_timeouts() {
+    let root_dir = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-timeouts-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root_dir).unwrap();
+    let config = ConfigHandle::new(ServerConfig {
+        static_directory: Some(root_dir.to_string_lossy().to_string()),
+        header_timeout: std::time::Duration::from_millis(20),
+        body_timeout: std::time::Duration::from_millis(20),
+        keep_alive_timeout: std::time::Duration::from_millis(20),
+        ..Default::default()
+    });
+    let router = Arc::new(default_router());
+
+    // nothing sent at all
+    let response = exchange(router.clone(), config.clone(), b"").await;
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+
+    // the body never arrives
+    let response = exchange(
+        router.clone(),
+        config.clone(),
+        b"POST /files/a HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nab",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    // the partial upload is removed again
+    assert!(!root_dir.join("a").exists());
+
+    // an idle keep-alive connection is closed without another response
+    let response = exchange(router, config, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(!response.contains("408"));
+}
+
+#[tokio::test]
+async fn tests_handle_connection_size_limits() {
+    let config = ConfigHandle::new(ServerConfig {
+        max_header_size: 80,
+        max_body_size: 4,
+        ..Default::default()
+    });
+    let send =
+        async |request: &[u8]| exchange(Arc::new(default_router()), config.clone(), request).await;
+
+    let long_header = format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Long: {}\r\n\r\n",
+        "a".repeat(64)
+    );
+    let response = send(long_header.as_bytes()).await;
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+
+    let response =
+        send(b"POST /files/a HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+
+    let response = send(
+        b"POST /files/a HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+          5\r\nabcde\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+    // a chunk too large is refused as soon as its size arrives, without waiting for its data
+    let response = send(
+        b"POST /echo/a HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+          2\r\nab\r\nffffff\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+
+    let response = send(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[tokio::test]
+async fn tests_handle_connection_streaming_upload() {
+    let root_dir = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-uploads-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root_dir).unwrap();
+    let (mut client, connection) = connect(
+        Arc::new(default_router()),
+        ConfigHandle::new(ServerConfig {
+            static_directory: Some(root_dir.to_string_lossy().to_string()),
+            ..Default::default()
+        }),
+        Default::default(),
+    );
+
+    // the body is larger than the duplex buffer, so it can only arrive while being written out
+    let body = "0123456789".repeat(1000);
+    client
+        .write_all(
+            b"POST /files/streamed.txt HTTP/1.1\r\nHost: localhost\r\n\
+              Transfer-Encoding: chunked\r\n\
+              Connection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    for chunk in body.as_bytes().chunks(3000) {
+        client
+            .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+            .await
+            .unwrap();
+        client.write_all(chunk).await.unwrap();
+        client.write_all(b"\r\n").await.unwrap();
+    }
+    client.write_all(b"0\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    connection.await.unwrap().unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+    assert_eq!(
+        body,
+        std::fs::read_to_string(root_dir.join("streamed.txt")).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn tests_handle_connection_http10() {
+    // without Connection: keep-alive an HTTP/1.0 connection closes after the response
+    let response = exchange(
+        Arc::new(default_router()),
+        ConfigHandle::new(ServerConfig::default()),
+        b"GET /echo/abc HTTP/1.0\r\n\r\n",
+    )
+    .await;
+
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(response.ends_with("\r\n\r\nabc"));
+}
+
+#[tokio::test]
+async fn tests_serve_connection_limit() {
+    use tokio::io::AsyncWriteExt;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = Server::builder()
+        .config(ServerConfig {
+            max_connections: Some(1),
+            overload: Overload::Reject,
+            ..Default::default()
+        })
+        .build();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let serving = tokio::spawn(server.serve(listener, async {
+        let _ = stopped.await;
+    }));
+
+    let mut first = tokio::net::TcpStream::connect(address).await.unwrap();
+    first
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = [0; 17];
+    first.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"HTTP/1.1 200 OK\r\n", &buf);
+
+    // the first connection is kept alive and holds the only permit
+    let mut second = tokio::net::TcpStream::connect(address).await.unwrap();
+    let mut response = String::new();
+    second.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    assert!(response.contains("Retry-After: 1\r\n"));
+
+    drop(first);
+    stop.send(()).unwrap();
+    serving.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn tests_probes() {
+    let server = Server::builder().build();
+    let probe = async |path: &str| {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        server
+            .router
+            .handle(request, server.config())
+            .await
+            .unwrap()
+            .status_code
+    };
+
+    assert_eq!(200, probe("/healthz").await);
+    // not ready until serving and again once shutdown begins
+    assert_eq!(503, probe("/readyz").await);
+    server.ready.store(true, Ordering::SeqCst);
+    assert_eq!(200, probe("/readyz").await);
+}
+
+#[tokio::test]
+async fn tests_server_handle() {
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-handle-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root).unwrap();
+    let server = Server::builder()
+        .config(ServerConfig {
+            static_directory: Some(root.to_str().unwrap().to_string()),
+            ..Default::default()
+        })
+        .route("GET", "/fail", |_, _| async { anyhow::bail!("failed") })
+        .build();
+    let request = |method: &str, path: &str, body: &'static [u8]| HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        version: Version::Http10,
+        body: bytes::Bytes::from_static(body),
+        ..Default::default()
+    };
+
+    // the body reaches the streaming upload route without a connection
+    let resp = server
+        .handle(request("POST", "/files/in-process.txt", b"uploaded"))
+        .await;
+    assert_eq!(201, resp.status_code);
+    assert_eq!(Version::Http10, resp.version);
+    assert_eq!(
+        "uploaded",
+        std::fs::read_to_string(root.join("in-process.txt")).unwrap()
+    );
+    let _ = std::fs::remove_file(root.join("put.txt"));
+    let resp = server.handle(request("PUT", "/files/put.txt", b"v1")).await;
+    assert_eq!(201, resp.status_code);
+    assert!(resp.headers.get("ETag").is_some());
+    let resp = server.handle(request("PUT", "/files/put.txt", b"v2")).await;
+    assert_eq!(204, resp.status_code);
+    assert_eq!("v2", std::fs::read_to_string(root.join("put.txt")).unwrap());
+    // the temporary files were renamed into place
+    let temp_files = std::fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".tmp"))
+        .count();
+    assert_eq!(0, temp_files);
+
+    let resp = server.handle(request("GET", "/fail", b"")).await;
+    assert_eq!(500, resp.status_code);
+    assert_eq!(
+        200,
+        server
+            .handle(request("GET", "/healthz", b""))
+            .await
+            .status_code
+    );
+    assert!(server.metrics().render().contains("status=\"500\""));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn tests_handler_errors() {
+    let server = Server::builder()
+        .route("POST", "/json", |request: HttpRequest, _| async move {
+            let value: serde_json::Value = request.json()?;
+            Ok(HttpResponse::json(&value)?)
+        })
+        .route("PUT", "/taken", |_, _| async {
+            Err(HttpError::new(StatusCode::Conflict, "taken").into())
+        })
+        .route("GET", "/broken", |_, _| async { anyhow::bail!("broken") })
+        .build();
+    let request = |method: &str, raw_path: &str, body: &'static [u8]| {
+        let mut request = HttpRequest {
+            method: method.to_string(),
+            path: raw_path.to_string(),
+            raw_path: raw_path.to_string(),
+            body: bytes::Bytes::from_static(body),
+            ..Default::default()
+        };
+        request
+            .headers
+            .insert("Content-Type".to_string(), "application/json".to_string());
+        request
+    };
+
+    // the status of an HttpError is kept, anything else is the server's fault
+    let resp = server.handle(request("POST", "/json", b"{")).await;
+    assert_eq!(400, resp.status_code);
+    assert!(
+        resp.body
+            .as_bytes()
+            .unwrap()
+            .starts_with(b"Bad Request: invalid JSON body")
+    );
+    assert_eq!(
+        200,
+        server
+            .handle(request("POST", "/json", b"[1]"))
+            .await
+            .status_code
+    );
+    assert_eq!(
+        409,
+        server
+            .handle(request("PUT", "/taken", b""))
+            .await
+            .status_code
+    );
+    assert_eq!(
+        500,
+        server
+            .handle(request("GET", "/broken", b""))
+            .await
+            .status_code
+    );
+    assert_eq!(
+        400,
+        server
+            .handle(request("GET", "/echo/%zz", b""))
+            .await
+            .status_code
+    );
+}
+
+#[tokio::test]
+async fn tests_expect_continue() {
+    let router = Arc::new(
+        Router::new().post("/upload", |request: HttpRequest, _| async move {
+            Ok(HttpResponse::builder().body(request.body).build())
+        }),
+    );
+    let config = ConfigHandle::new(ServerConfig::default());
+
+    let (mut client, _) = connect(router.clone(), config.clone(), Default::default());
+    client
+        .write_all(
+            b"POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\n\
+              Content-Length: 5\r\n\
+              Connection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut interim = [0; 25];
+    client.read_exact(&mut interim).await.unwrap();
+    assert_eq!(b"HTTP/1.1 100 Continue\r\n\r\n", &interim);
+    client.write_all(b"hello").await.unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nhello"));
+
+    for head in [
+        "POST /missing HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\n\
+         Content-Length: 5\r\n\r\n",
+        "POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: magic\r\nContent-Length: 5\r\n\r\n",
+    ] {
+        let response = exchange(router.clone(), config.clone(), head.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+    }
+}
+
+#[tokio::test]
+async fn tests_handle_connection_pipelined() {
+    let router = Router::new()
+        .get("/echo/:msg", |request: HttpRequest, _| async move {
+            let msg = request.param("msg").unwrap_or_default().to_string();
+            Ok(HttpResponse::builder().body(msg).build())
+        })
+        .route_streaming(
+            "POST",
+            "/upload",
+            |mut request: HttpRequest, _| async move {
+                let mut body = vec![];
+                if let Some(mut stream) = request.take_body_stream() {
+                    stream.read_to_end(&mut body).await?;
+                }
+                Ok(HttpResponse::builder().body(body).build())
+            },
+        );
+    let response = exchange(
+        Arc::new(router),
+        ConfigHandle::new(ServerConfig::default()),
+        b"GET /echo/a HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\nbcd\
+              POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+              1\r\ne\r\n0\r\n\r\n\
+              GET /echo/f HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    let bodies: Vec<&str> = response
+        .split("HTTP/1.1 200 OK\r\n")
+        .skip(1)
+        .filter_map(|resp| resp.split_once("\r\n\r\n").map(|(_, body)| body))
+        .collect();
+    assert_eq!(vec!["a", "bcd", "e", "f"], bodies);
+}
+
+#[test]
+fn tests_keep_alive_limits() {
+    assert_eq!(
+        "timeout=5",
+        keep_alive_limits(std::time::Duration::from_secs(5), None)
+    );
+    assert_eq!(
+        "timeout=1, max=3",
+        keep_alive_limits(std::time::Duration::from_millis(500), Some(3))
+    );
+}
+
+#[tokio::test]
+async fn tests_handle_connection_max_keep_alive_requests() {
+    let config = ConfigHandle::new(ServerConfig {
+        max_keep_alive_requests: Some(2),
+        ..Default::default()
+    });
+
+    // the third request is never read
+    let response = exchange(
+        Arc::new(default_router()),
+        config,
+        b"GET /echo/a HTTP/1.1\r\nHost: localhost\r\n\r\n\
+          GET /echo/b HTTP/1.1\r\nHost: localhost\r\n\r\n\
+          GET /echo/c HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    )
+    .await;
+
+    let responses: Vec<&str> = response.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+    assert_eq!(2, responses.len());
+    assert!(responses[0].contains("Connection: keep-alive\r\nKeep-Alive: timeout=5, max=1\r\n"));
+    assert!(responses[1].contains("Connection: close\r\n"));
+    assert!(!responses[1].contains("Keep-Alive"));
+}
+
+#[tokio::test]
+async fn tests_handle_connection_half_close() {
+    let router = Arc::new(default_router().route_streaming(
+        "POST",
+        "/upload",
+        |mut request: HttpRequest, _| async move {
+            let mut body = vec![];
+            if let Some(mut stream) = request.take_body_stream() {
+                stream.read_to_end(&mut body).await?;
+            }
+            Ok(HttpResponse::ok())
+        },
+    ));
+    let exchange = async |request: &[u8]| {
+        let config = ConfigHandle::new(ServerConfig::default());
+        let (mut client, connection) = connect(router.clone(), config, Default::default());
+        client.write_all(request).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        // none of these are errors of the connection
+        connection.await.unwrap().unwrap();
+        response
+    };
+
+    // a keep-alive request is answered before the connection closes
+    let response = exchange(b"GET /echo/abc HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nabc"));
+    assert_eq!("", exchange(b"").await);
+
+    let response = exchange(b"GET /echo/abc HTTP/1.1\r\nHost").await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    let response =
+        exchange(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc")
+            .await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+    // a reset mid-request leaves nothing to answer
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let connection = tokio::spawn(handle_connection(
+        server,
+        None,
+        router.clone(),
+        ConfigHandle::new(ServerConfig::default()),
+        Default::default(),
+        watch::channel(false).1,
+    ));
+    client
+        .write_all(b"GET /echo/abc HTTP/1.1\r\nHost: localhost\r\n")
+        .await
+        .unwrap();
+    client.set_linger(Some(std::time::Duration::ZERO)).unwrap();
+    drop(client);
+    connection.await.unwrap().unwrap();
+}
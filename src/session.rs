@@ -0,0 +1,304 @@
+//! Server-side sessions identified by a signed cookie. The cookie only carries a random session
+//! ID and its HMAC-SHA256 signature; the values live in memory until the session has been idle
+//! for its time to live.
+//!
+//! ```
+//! # use codecrafters_http_server::{HttpResponse, Router};
+//! # use codecrafters_http_server::session::{Session, Sessions};
+//! let router: Router<()> = Router::new()
+//!     .get("/visits", |request, _| async move {
+//!         let session = request.extensions.get::<Session>().expect("installed by Sessions");
+//!         let visits = session.get::<u64>("visits").unwrap_or(0) + 1;
+//!         session.set("visits", visits)?;
+//!         Ok(HttpResponse::builder().body(visits.to_string()).build())
+//!     })
+//!     .layer(Sessions::new(b"a secret of at least 32 random bytes"));
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::cookie::{Cookie, SameSite};
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::BoxFuture;
+
+#[derive(Debug, Default)]
+struct State {
+    values: HashMap<String, Value>,
+    changed: bool,
+    destroyed: bool,
+}
+
+/// The session of a request, added to its extensions by the [`Sessions`] layer. Clones share
+/// the same values, which are saved once the handler's response passes back through the layer.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+impl Session {
+    fn with_values(values: HashMap<String, Value>) -> Self {
+        let state = State {
+            values,
+            ..Default::default()
+        };
+        Session {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// The value stored under `key`, `None` if there is none or it isn't a `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let state = self.state.lock().unwrap();
+        serde_json::from_value(state.values.get(key)?.clone()).ok()
+    }
+
+    /// Stores `value` under `key`, starting the session if it is new.
+    pub fn set(&self, key: impl Into<String>, value: impl Serialize) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.state.lock().unwrap();
+        state.values.insert(key.into(), value);
+        state.changed = true;
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.changed |= state.values.remove(key).is_some();
+    }
+
+    /// Ends the session, e.g. on logout: its values are dropped and the client is told to delete
+    /// the cookie.
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.values.clear();
+        state.destroyed = true;
+    }
+}
+
+struct Stored {
+    values: HashMap<String, Value>,
+    expires: Instant,
+}
+
+/// Loads the session named by a signed cookie before the handler runs and saves it afterwards.
+/// Sessions only get a cookie once a value is set, and expire after being idle for the TTL.
+pub struct Sessions {
+    key: hmac::Key,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    random: SystemRandom,
+    store: Mutex<HashMap<String, Stored>>,
+}
+
+impl Sessions {
+    /// Signs session cookies with `secret`, which should be at least 32 random bytes.
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Sessions {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_ref()),
+            cookie_name: "session".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: false,
+            random: SystemRandom::new(),
+            store: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The name of the session cookie, "session" by default.
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Self {
+        self.cookie_name = name.into();
+        self
+    }
+
+    /// How long an idle session is kept, a day by default.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Marks the cookie Secure, for servers only reachable over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// The cookie value for session `id`: the ID and its signature.
+    fn sign(&self, id: &str) -> String {
+        let tag = hmac::sign(&self.key, id.as_bytes());
+        format!("{id}.{}", BASE64.encode(tag.as_ref()))
+    }
+
+    /// The session ID of a cookie value, if its signature is valid.
+    fn verify<'c>(&self, cookie: &'c str) -> Option<&'c str> {
+        let (id, tag) = cookie.split_once('.')?;
+        let tag = BASE64.decode(tag).ok()?;
+        hmac::verify(&self.key, id.as_bytes(), &tag).ok()?;
+        Some(id)
+    }
+
+    fn new_id(&self) -> Result<String> {
+        let mut id = [0; 32];
+        self.random
+            .fill(&mut id)
+            .map_err(|_| anyhow::anyhow!("Unable to generate a session ID"))?;
+        Ok(BASE64.encode(id))
+    }
+
+    /// The values of session `id`, dropping expired sessions. Concurrent requests of a session
+    /// each get a copy, and the last one to finish wins.
+    fn load(&self, id: &str) -> Option<HashMap<String, Value>> {
+        let mut store = self.store.lock().unwrap();
+        let now = Instant::now();
+        store.retain(|_, stored| stored.expires > now);
+        store.get(id).map(|stored| stored.values.clone())
+    }
+
+    fn cookie(&self, value: String) -> Cookie {
+        Cookie::new(&self.cookie_name, value)
+            .path("/")
+            .max_age(self.ttl)
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+    }
+}
+
+impl<S: Send + Sync + 'static> Middleware<S> for Sessions {
+    fn handle<'a>(
+        &'a self,
+        mut request: HttpRequest,
+        state: Arc<S>,
+        next: Next<'a, S>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let id = request
+                .cookies()
+                .get(&self.cookie_name)
+                .and_then(|cookie| self.verify(cookie).map(str::to_string));
+            let loaded = id.as_deref().and_then(|id| self.load(id));
+            let id = id.filter(|_| loaded.is_some());
+            let session = Session::with_values(loaded.unwrap_or_default());
+            request.extensions.insert(session.clone());
+
+            let result = next.run(request, state).await;
+            let session = std::mem::take(&mut *session.state.lock().unwrap());
+            if session.destroyed || session.values.is_empty() {
+                // a session ended by the handler, or one that was never started
+                let mut resp = result?;
+                if let Some(id) = id {
+                    self.store.lock().unwrap().remove(&id);
+                    resp.add_cookie(Cookie::removal(&self.cookie_name).path("/"));
+                }
+                return Ok(resp);
+            }
+            let (id, is_new) = match id {
+                Some(id) => (id, false),
+                None => (self.new_id()?, true),
+            };
+            let stored = Stored {
+                values: session.values,
+                expires: Instant::now() + self.ttl,
+            };
+            self.store.lock().unwrap().insert(id.clone(), stored);
+            let mut resp = result?;
+            // the cookie's Max-Age is only renewed when something changed
+            if is_new || session.changed {
+                resp.add_cookie(self.cookie(self.sign(&id)));
+            }
+            Ok(resp)
+        })
+    }
+}
+
+#[test]
+fn tests_sign() {
+    let sessions = Sessions::new(b"secret");
+    let cookie = sessions.sign("abc");
+    assert_eq!(Some("abc"), sessions.verify(&cookie));
+    assert_eq!(None, sessions.verify(&cookie.replacen("abc", "abd", 1)));
+    assert_eq!(None, sessions.verify("abc"));
+    assert_eq!(None, Sessions::new(b"other").verify(&cookie));
+}
+
+#[tokio::test]
+async fn tests_sessions() {
+    use crate::router::Router;
+
+    let router: Router<()> = Router::new()
+        .get("/count", |request, _| async move {
+            let session = request.extensions.get::<Session>().unwrap();
+            let count = session.get::<u32>("count").unwrap_or(0) + 1;
+            session.set("count", count)?;
+            Ok(HttpResponse::builder().body(count.to_string()).build())
+        })
+        .get("/peek", |request, _| async move {
+            let count = request
+                .extensions
+                .get::<Session>()
+                .unwrap()
+                .get::<u32>("count");
+            Ok(HttpResponse::builder().body(format!("{count:?}")).build())
+        })
+        .get("/logout", |request, _| async move {
+            request.extensions.get::<Session>().unwrap().destroy();
+            Ok(HttpResponse::ok())
+        })
+        .layer(Sessions::new(b"secret").ttl(Duration::from_secs(60)));
+    let get = async |path: &str, cookie: Option<&str>| {
+        let mut request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        if let Some(cookie) = cookie {
+            request
+                .headers
+                .insert("Cookie".to_string(), cookie.to_string());
+        }
+        router.handle(request, Arc::new(())).await.unwrap()
+    };
+
+    let resp = get("/peek", None).await;
+    assert_eq!(Some(&b"None"[..]), resp.body.as_bytes());
+    assert_eq!(None, resp.headers.get("Set-Cookie"));
+
+    let resp = get("/count", None).await;
+    let set_cookie = resp.headers.get("Set-Cookie").unwrap().to_string();
+    assert!(set_cookie.contains("; Max-Age=60; HttpOnly; SameSite=Lax"));
+    let cookie = set_cookie.split(';').next().unwrap();
+    assert_eq!(
+        Some(&b"2"[..]),
+        get("/count", Some(cookie)).await.body.as_bytes()
+    );
+    let resp = get("/peek", Some(cookie)).await;
+    assert_eq!(Some(&b"Some(2)"[..]), resp.body.as_bytes());
+    assert_eq!(None, resp.headers.get("Set-Cookie"));
+
+    // a forged cookie starts over
+    let forged = format!("{}x", cookie);
+    let resp = get("/peek", Some(&forged)).await;
+    assert_eq!(Some(&b"None"[..]), resp.body.as_bytes());
+
+    let resp = get("/logout", Some(cookie)).await;
+    assert!(
+        resp.headers
+            .get("Set-Cookie")
+            .unwrap()
+            .contains("Max-Age=0")
+    );
+    let resp = get("/peek", Some(cookie)).await;
+    assert_eq!(Some(&b"None"[..]), resp.body.as_bytes());
+}
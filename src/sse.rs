@@ -0,0 +1,188 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc;
+
+use crate::response::HttpResponse;
+
+/// A single server-sent event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+    data: String,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn data(data: impl Into<String>) -> Self {
+        Event {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the event type, which clients subscribe to with `addEventListener`.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Tells the client how long to wait before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn encode(&self) -> String {
+        let mut encoded = String::new();
+        // line breaks would end the field, so each line gets its own
+        let single_line = |value: &str| value.replace(['\r', '\n'], "");
+        if let Some(event) = &self.event {
+            encoded += &format!("event: {}\n", single_line(event));
+        }
+        if let Some(id) = &self.id {
+            encoded += &format!("id: {}\n", single_line(id));
+        }
+        if let Some(retry) = self.retry {
+            encoded += &format!("retry: {}\n", retry.as_millis());
+        }
+        for line in self.data.split('\n') {
+            encoded += &format!("data: {}\n", line.strip_suffix('\r').unwrap_or(line));
+        }
+        encoded.push('\n');
+        encoded
+    }
+}
+
+/// Sends events to a client connected through [`stream`].
+#[derive(Clone)]
+pub struct EventSender {
+    tx: mpsc::Sender<Vec<u8>>,
+}
+
+impl EventSender {
+    /// Queues `event` for the client, failing once the client has disconnected.
+    pub async fn send(&self, event: Event) -> Result<()> {
+        self.send_raw(event.encode().into_bytes()).await
+    }
+
+    /// Sends a comment line, which clients ignore, e.g. to keep idle proxies from timing out.
+    pub async fn comment(&self, comment: &str) -> Result<()> {
+        self.send_raw(format!(": {}\n\n", comment.replace(['\r', '\n'], "")).into_bytes())
+            .await
+    }
+
+    async fn send_raw(&self, bytes: Vec<u8>) -> Result<()> {
+        self.tx
+            .send(bytes)
+            .await
+            .map_err(|_| anyhow::anyhow!("event stream client disconnected"))
+    }
+}
+
+/// Creates a `text/event-stream` response whose events are written as they are sent through the
+/// returned [`EventSender`]. The response ends once every sender is dropped.
+///
+/// ```
+/// # use codecrafters_http_server::sse::{self, Event};
+/// # async fn handler() -> anyhow::Result<codecrafters_http_server::HttpResponse> {
+/// let (events, resp) = sse::stream();
+/// tokio::spawn(async move {
+///     for i in 0.. {
+///         if events.send(Event::data(format!("tick {i}"))).await.is_err() {
+///             break;
+///         }
+///         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+///     }
+/// });
+/// Ok(resp)
+/// # }
+/// ```
+pub fn stream() -> (EventSender, HttpResponse) {
+    let (tx, rx) = mpsc::channel(16);
+    let mut resp = HttpResponse::ok();
+    resp.set_header("Content-Type".to_string(), "text/event-stream".to_string());
+    resp.set_header("Cache-Control".to_string(), "no-cache".to_string());
+    resp.set_stream(ChannelReader {
+        rx,
+        pending: vec![],
+        pos: 0,
+    });
+    (EventSender { tx }, resp)
+}
+
+/// Adapts the event channel to the response's streamed body.
+struct ChannelReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for ChannelReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.pos == self.pending.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(bytes)) => {
+                    self.pending = bytes;
+                    self.pos = 0;
+                }
+                // all senders are gone, which ends the body
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let len = buf.remaining().min(self.pending.len() - self.pos);
+        let pos = self.pos;
+        buf.put_slice(&self.pending[pos..pos + len]);
+        self.pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn tests_event_encode() {
+    assert_eq!("data: hello\n\n", Event::data("hello").encode());
+    assert_eq!(
+        "event: update\nid: 7\nretry: 1500\ndata: line one\ndata: line two\n\n",
+        Event::data("line one\r\nline two")
+            .event("update")
+            .id("7")
+            .retry(Duration::from_millis(1500))
+            .encode()
+    );
+}
+
+#[tokio::test]
+async fn tests_stream() {
+    let (events, resp) = stream();
+    assert_eq!(Some("text/event-stream"), resp.headers.get("Content-Type"));
+
+    let writer = tokio::spawn(async move {
+        let mut output = vec![];
+        resp.write_to(&mut output).await.unwrap();
+        output
+    });
+    events.send(Event::data("one")).await.unwrap();
+    events.comment("ping").await.unwrap();
+    drop(events);
+
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+         Transfer-Encoding: chunked\r\n\r\nb\r\ndata: one\n\n\r\n8\r\n: ping\n\n\r\n0\r\n\r\n",
+        String::from_utf8(writer.await.unwrap()).unwrap()
+    );
+}
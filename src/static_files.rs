@@ -0,0 +1,1699 @@
+use std::io::{Cursor, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::compression::{self, Encoding};
+use crate::config::ServerConfig;
+use crate::date;
+use crate::digest::{self, Verifier};
+use crate::error::HttpError;
+use crate::file_cache;
+use crate::mime;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::status::StatusCode;
+use crate::template::Template;
+
+/// A `[[cache_control]]` table of the config file, setting the Cache-Control header of the
+/// static files it matches. The first matching rule applies.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheControlRule {
+    /// A file inside the static directory, a trailing `*` matches any rest of its path, e.g.
+    /// "assets/*". Any file matches if empty.
+    pub path: String,
+    /// File extensions matched (without the dot, in any case), any if empty.
+    pub extensions: Vec<String>,
+    /// The header value, e.g. "public, max-age=31536000, immutable" or "no-cache".
+    pub value: String,
+}
+
+impl CacheControlRule {
+    fn matches(&self, name: &str) -> bool {
+        let path_matches = match self.path.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => self.path.is_empty() || self.path == name,
+        };
+        path_matches && (self.extensions.is_empty() || has_extension(name, &self.extensions))
+    }
+}
+
+/// Whether the extension of `name` is one of `extensions`, given without the dot in any case.
+fn has_extension(name: &str, extensions: &[String]) -> bool {
+    let Some(extension) = Path::new(name).extension().and_then(|ext| ext.to_str()) else {
+        return false;
+    };
+    extensions.iter().any(|candidate| {
+        candidate
+            .trim_start_matches('.')
+            .eq_ignore_ascii_case(extension)
+    })
+}
+
+/// Whether the config lets the /files routes serve or write `name`, a path below the static
+/// directory. Dotfiles are refused anywhere in the path, the extension rules apply to files.
+fn is_permitted(config: &ServerConfig, name: &str, directory: bool) -> bool {
+    let dotfile = Path::new(name)
+        .components()
+        .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+    if dotfile && !config.allow_dotfiles {
+        return false;
+    }
+    let allowed =
+        config.allowed_extensions.is_empty() || has_extension(name, &config.allowed_extensions);
+    directory || allowed && !has_extension(name, &config.denied_extensions)
+}
+
+/// The Cache-Control value the config's rules give the file `name`.
+fn cache_control<'a>(rules: &'a [CacheControlRule], name: &str) -> Option<&'a str> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(name))
+        .map(|rule| rule.value.as_str())
+}
+
+/// Which symlinks inside the static directory are followed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// None, paths through a symlink are refused.
+    Never,
+    /// Those whose target is inside the static directory as well.
+    #[default]
+    SameDir,
+    /// All of them, wherever they point. Only for directories whose links are trusted.
+    Always,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "never" => Ok(SymlinkPolicy::Never),
+            "same-dir" => Ok(SymlinkPolicy::SameDir),
+            "always" => Ok(SymlinkPolicy::Always),
+            _ => Err(format!(
+                "unknown symlink policy {policy:?}, expected never, same-dir or always"
+            )),
+        }
+    }
+}
+
+/// Resolves `name` inside `root`, refusing anything that would end up outside of it, or
+/// passes through a symlink `symlinks` doesn't follow.
+pub async fn resolve(root: &Path, name: &str, symlinks: SymlinkPolicy) -> Option<PathBuf> {
+    let relative = Path::new(name);
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let root = tokio::fs::canonicalize(root).await.ok()?;
+    let candidate = root.join(relative);
+    if symlinks == SymlinkPolicy::Never {
+        // the path stays inside the root as long as none of its existing parts is a symlink
+        let mut path = root;
+        for component in relative.components() {
+            path.push(component);
+            match tokio::fs::symlink_metadata(&path).await {
+                Ok(metadata) if metadata.file_type().is_symlink() => return None,
+                Ok(_) => {}
+                // the rest doesn't exist yet, e.g. a file about to be uploaded
+                Err(_) => break,
+            }
+        }
+        return Some(candidate);
+    }
+    // symlinks may point anywhere, so check where the path actually ends up. The rest of the
+    // path may not exist yet, e.g. the directories of a file about to be uploaded, so resolve
+    // its deepest existing part
+    let mut existing = candidate.as_path();
+    let mut rest = vec![];
+    let resolved = loop {
+        match tokio::fs::canonicalize(existing).await {
+            Ok(resolved) => break resolved,
+            Err(_) => {
+                // a dangling symlink would be followed by whatever creates the file
+                let dangling = tokio::fs::symlink_metadata(existing)
+                    .await
+                    .is_ok_and(|metadata| metadata.file_type().is_symlink());
+                if dangling && symlinks != SymlinkPolicy::Always {
+                    return None;
+                }
+                rest.push(existing.file_name()?);
+                existing = existing.parent()?;
+            }
+        }
+    };
+    let resolved = rest
+        .into_iter()
+        .rev()
+        .fold(resolved, |path, name| path.join(name));
+    (symlinks == SymlinkPolicy::Always || resolved.starts_with(&root)).then_some(resolved)
+}
+
+async fn file_path(request: &HttpRequest, config: &ServerConfig) -> Result<PathBuf, HttpResponse> {
+    // a route without a name, like "/files", refers to the directory itself
+    let file_name = request.param("name").unwrap_or_default();
+    let Some(root_dir) = &config.static_directory else {
+        return Err(HttpResponse::not_found());
+    };
+    let file_path = resolve(Path::new(root_dir), file_name, config.follow_symlinks)
+        .await
+        .ok_or_else(HttpResponse::forbidden)?;
+    let directory = tokio::fs::metadata(&file_path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir());
+    if !is_permitted(config, file_name, directory) {
+        return Err(HttpResponse::forbidden());
+    }
+    Ok(file_path)
+}
+
+pub async fn get_file(request: HttpRequest, config: Arc<ServerConfig>) -> Result<HttpResponse> {
+    let file_path = match file_path(&request, &config).await {
+        Ok(file_path) => file_path,
+        Err(resp) => return Ok(resp),
+    };
+
+    let Ok(metadata) = tokio::fs::metadata(&file_path).await else {
+        return Ok(HttpResponse::not_found());
+    };
+    if metadata.is_dir() && config.dir_listing {
+        let name = request.param("name").unwrap_or_default();
+        return list_directory(&request, &config, &file_path, name).await;
+    }
+    if !metadata.is_file() {
+        return Ok(HttpResponse::not_found());
+    }
+    let name = request.param("name").unwrap_or_default();
+    let variants = match config.precompressed {
+        true => precompressed_variants(&config, name).await,
+        false => vec![],
+    };
+    let available: Vec<Encoding> = variants.iter().map(|(encoding, ..)| *encoding).collect();
+    let encoding = request
+        .headers
+        .get("Accept-Encoding")
+        .and_then(|accept_encoding| compression::negotiate_among(accept_encoding, &available));
+    // the variant's own length and modification time give it an ETag of its own
+    let (served_path, metadata) = match variants
+        .into_iter()
+        .find(|(variant, ..)| Some(*variant) == encoding)
+    {
+        Some((_, path, metadata)) => (path, metadata),
+        None => (file_path.clone(), metadata),
+    };
+    let file_length = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = entity_tag(file_length, modified);
+    let cache_control = cache_control(&config.cache_control, name);
+
+    if is_not_modified(&request, &etag, modified) {
+        let mut resp = HttpResponse::not_modified();
+        resp.set_header("ETag".to_string(), etag);
+        if let Some(cache_control) = cache_control {
+            resp.set_header("Cache-Control".to_string(), cache_control.to_string());
+        }
+        if let Some(modified) = modified {
+            resp.set_header("Last-Modified".to_string(), date::http_date(modified));
+        }
+        if !available.is_empty() {
+            resp.headers
+                .append("Vary".to_string(), "Accept-Encoding".to_string());
+        }
+        return Ok(resp);
+    }
+
+    let ranges = match request.headers.get("Range") {
+        Some(range) => match parse_range(range, file_length) {
+            RangeRequest::Satisfiable(ranges) => ranges,
+            RangeRequest::Unsatisfiable => {
+                let mut resp = HttpResponse::range_not_satisfiable();
+                resp.set_header(
+                    "Content-Range".to_string(),
+                    format!("bytes */{}", file_length),
+                );
+                return Ok(resp);
+            }
+            RangeRequest::Ignored => vec![],
+        },
+        None => vec![],
+    };
+
+    let content_type = mime::content_type(&file_path, &config.mime_types);
+    let cached = file_cache::read(&served_path, modified, file_length, config.file_cache_size)
+        .await
+        .context("Failed to read file")?;
+    let open = async || {
+        tokio::fs::File::open(&served_path)
+            .await
+            .context("Failed to open file")
+    };
+    // the digest is of the whole file, ranges included
+    let digest = match (&cached, config.file_digests) {
+        (_, false) => None,
+        (Some(body), true) => Some(digest::sha256(body)),
+        (None, true) => Some(
+            digest::sha256_file(&served_path)
+                .await
+                .context("Failed to read file")?,
+        ),
+    };
+
+    let mut resp = match ranges.as_slice() {
+        [] => {
+            let mut resp = HttpResponse::ok();
+            resp.set_header("Content-Length".to_string(), file_length.to_string());
+            match &cached {
+                Some(body) => resp.set_stream(Cursor::new(body.clone())),
+                None => resp.set_stream(open().await?),
+            }
+            resp.set_header("Content-Type".to_string(), content_type);
+            resp
+        }
+        [(start, end)] => {
+            let mut resp = HttpResponse::partial_content();
+            resp.set_header(
+                "Content-Range".to_string(),
+                format!("bytes {}-{}/{}", start, end, file_length),
+            );
+            resp.set_header("Content-Length".to_string(), (end - start + 1).to_string());
+            resp.set_stream(read_range(cached.as_ref(), &served_path, *start, *end).await?);
+            resp.set_header("Content-Type".to_string(), content_type);
+            resp
+        }
+        ranges => {
+            let boundary = multipart_boundary();
+            let mut parts = vec![];
+            for &(start, end) in ranges {
+                let part = read_range(cached.as_ref(), &served_path, start, end).await?;
+                parts.push(((start, end), part));
+            }
+            let (body, length) = byteranges(&boundary, &content_type, file_length, parts);
+            let mut resp = HttpResponse::partial_content();
+            resp.set_header("Content-Length".to_string(), length.to_string());
+            resp.set_stream(body);
+            resp.set_header(
+                "Content-Type".to_string(),
+                format!("multipart/byteranges; boundary={boundary}"),
+            );
+            resp
+        }
+    };
+    resp.set_header("Accept-Ranges".to_string(), "bytes".to_string());
+    resp.set_header("ETag".to_string(), etag);
+    if let Some(digest) = digest {
+        resp.set_header("Digest".to_string(), digest);
+    }
+    if let Some(cache_control) = cache_control {
+        resp.set_header("Cache-Control".to_string(), cache_control.to_string());
+    }
+    if let Some(modified) = modified {
+        resp.set_header("Last-Modified".to_string(), date::http_date(modified));
+    }
+    if let Some(encoding) = encoding {
+        resp.set_header(
+            "Content-Encoding".to_string(),
+            encoding.as_str().to_string(),
+        );
+    }
+    if !available.is_empty() {
+        resp.headers
+            .append("Vary".to_string(), "Accept-Encoding".to_string());
+    }
+    Ok(resp)
+}
+
+/// The inclusive byte range of the file, from the cached contents if there are any.
+async fn read_range(
+    cached: Option<&bytes::Bytes>,
+    file_path: &Path,
+    start: u64,
+    end: u64,
+) -> Result<Box<dyn AsyncRead + Send + Unpin>> {
+    if let Some(body) = cached {
+        let range = body.slice(start as usize..=end as usize);
+        return Ok(Box::new(Cursor::new(range)));
+    }
+    let mut file = tokio::fs::File::open(file_path)
+        .await
+        .context("Failed to open file")?;
+    file.seek(SeekFrom::Start(start))
+        .await
+        .context("Failed to seek file")?;
+    Ok(Box::new(file.take(end - start + 1)))
+}
+
+/// A random boundary for a multipart body, which can't turn up in the file's bytes by accident.
+fn multipart_boundary() -> String {
+    let mut bytes = [0u8; 16];
+    // an unavailable system RNG leaves the zero bytes, which still make a valid boundary
+    let _ = SystemRandom::new().fill(&mut bytes);
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The multipart/byteranges body of several ranges of a file and its length, each part headed
+/// by the file's Content-Type and the range's Content-Range.
+fn byteranges(
+    boundary: &str,
+    content_type: &str,
+    file_length: u64,
+    parts: Vec<((u64, u64), Box<dyn AsyncRead + Send + Unpin>)>,
+) -> (Box<dyn AsyncRead + Send + Unpin>, u64) {
+    let mut body: Box<dyn AsyncRead + Send + Unpin> = Box::new(tokio::io::empty());
+    let mut length = 0;
+    let mut separator = "";
+    for ((start, end), part) in parts {
+        let head = format!(
+            "{separator}--{boundary}\r\nContent-Type: {content_type}\r\n\
+             Content-Range: bytes {start}-{end}/{file_length}\r\n\r\n"
+        );
+        length += head.len() as u64 + (end - start + 1);
+        body = Box::new(body.chain(Cursor::new(head)).chain(part));
+        separator = "\r\n";
+    }
+    let tail = format!("\r\n--{boundary}--\r\n");
+    length += tail.len() as u64;
+    (Box::new(body.chain(Cursor::new(tail))), length)
+}
+
+/// The precompressed siblings of the file `name`, like app.js.br for app.js, with their
+/// metadata. They are resolved like the file itself, so the symlink policy applies to them too.
+async fn precompressed_variants(
+    config: &ServerConfig,
+    name: &str,
+) -> Vec<(Encoding, PathBuf, std::fs::Metadata)> {
+    let Some(root_dir) = &config.static_directory else {
+        return vec![];
+    };
+    let mut variants = vec![];
+    for encoding in Encoding::ALL {
+        let variant = format!("{name}.{}", encoding.extension());
+        let Some(path) = resolve(Path::new(root_dir), &variant, config.follow_symlinks).await
+        else {
+            continue;
+        };
+        if let Ok(metadata) = tokio::fs::metadata(&path).await
+            && metadata.is_file()
+        {
+            variants.push((encoding, path, metadata));
+        }
+    }
+    variants
+}
+
+/// Writes the request body to the file, streaming it to disk as it arrives when the route was
+/// registered with [`Router::route_streaming`](crate::Router::route_streaming). Writes whose
+/// If-Match or If-Unmodified-Since doesn't hold for the current file are answered with 412,
+/// and bodies that don't match the Digest or Content-MD5 sent along with 400.
+pub async fn post_file(
+    mut request: HttpRequest,
+    config: Arc<ServerConfig>,
+) -> Result<HttpResponse> {
+    let file_path = match file_path(&request, &config).await {
+        Ok(file_path) => file_path,
+        Err(resp) => return Ok(resp),
+    };
+    if !precondition_holds(&request, &file_path).await {
+        return Ok(HttpResponse::precondition_failed());
+    }
+
+    if let Err(resp) = upload(&mut request, &file_path).await {
+        return Ok(resp);
+    }
+    Ok(with_etag(HttpResponse::created(), &file_path).await)
+}
+
+/// Creates or replaces the file with the request body like [`post_file`], answering with 201
+/// if the file is new and 204 if it replaced one. With a Content-Range the body is written at
+/// its offset instead, see [`put_range`].
+pub async fn put_file(mut request: HttpRequest, config: Arc<ServerConfig>) -> Result<HttpResponse> {
+    let file_path = match file_path(&request, &config).await {
+        Ok(file_path) => file_path,
+        Err(resp) => return Ok(resp),
+    };
+    if !precondition_holds(&request, &file_path).await {
+        return Ok(HttpResponse::precondition_failed());
+    }
+    if let Some(content_range) = request.headers.get("Content-Range") {
+        let Some(range) = parse_content_range(content_range) else {
+            let message = format!("invalid Content-Range {content_range:?}");
+            return Ok(HttpError::new(StatusCode::BadRequest, message).into());
+        };
+        let max_copy = config.max_body_size as u64;
+        return put_range(&mut request, &file_path, range, max_copy).await;
+    }
+
+    let existed = tokio::fs::metadata(&file_path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file());
+    if let Err(resp) = upload(&mut request, &file_path).await {
+        return Ok(resp);
+    }
+    let resp = if existed {
+        HttpResponse::no_content()
+    } else {
+        HttpResponse::created()
+    };
+    Ok(with_etag(resp, &file_path).await)
+}
+
+/// Writes the request body to `file_path`, failing with the response to send if it can't.
+async fn upload(request: &mut HttpRequest, file_path: &Path) -> Result<(), HttpResponse> {
+    let verifier = Verifier::from_headers(&request.headers)
+        .map_err(|message| HttpError::new(StatusCode::BadRequest, message))?;
+    match write_body(request, file_path, verifier).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            Err(HttpError::new(StatusCode::BadRequest, "the body doesn't match its digest").into())
+        }
+        Err(err) => {
+            tracing::error!("Error writing file: {:?}", err);
+            Err(HttpResponse::internal_server_error())
+        }
+    }
+}
+
+/// Writes the request body to a temporary file next to `file_path` and renames it into place
+/// once complete, so readers see either the old file or all of the new one. A body not
+/// matching the `verifier` is dropped, leaving the file as it was, and gives `false`.
+async fn write_body(
+    request: &mut HttpRequest,
+    file_path: &Path,
+    mut verifier: Option<Verifier>,
+) -> std::io::Result<bool> {
+    let temp_path = temp_path(file_path);
+    let written = async {
+        create_parent(file_path).await?;
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        copy_body(request, &mut file, &mut verifier).await?;
+        if verifier.is_some_and(|verifier| !verifier.matches()) {
+            return Ok(false);
+        }
+        tokio::fs::rename(&temp_path, file_path).await?;
+        Ok(true)
+    }
+    .await;
+    if !matches!(written, Ok(true)) {
+        // don't leave a truncated or corrupted upload behind
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    written
+}
+
+/// A path next to `file_path` for an upload to be written to before it's renamed into place,
+/// unique per upload.
+fn temp_path(file_path: &Path) -> PathBuf {
+    static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+    let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+    let upload = UPLOADS.fetch_add(1, Ordering::Relaxed);
+    file_path.with_file_name(format!(".{file_name}.{}-{upload}.tmp", std::process::id()))
+}
+
+/// Creates the directories an upload to `file_path` goes into, e.g. for "a/b/c.txt".
+async fn create_parent(file_path: &Path) -> std::io::Result<()> {
+    match file_path.parent() {
+        Some(parent) => tokio::fs::create_dir_all(parent).await,
+        None => Ok(()),
+    }
+}
+
+/// Writes the request body to `file`, passing it through `verifier` as well, and returns its
+/// length.
+async fn copy_body(
+    request: &mut HttpRequest,
+    file: &mut tokio::fs::File,
+    verifier: &mut Option<Verifier>,
+) -> std::io::Result<u64> {
+    let mut written = 0;
+    match request.take_body_stream() {
+        Some(mut body) => {
+            while let Some(chunk) = body.chunk().await {
+                let chunk = chunk?;
+                if let Some(verifier) = verifier {
+                    verifier.update(&chunk);
+                }
+                written += chunk.len() as u64;
+                file.write_all(&chunk).await?;
+            }
+        }
+        None => {
+            if let Some(verifier) = verifier {
+                verifier.update(&request.body);
+            }
+            written += request.body.len() as u64;
+            file.write_all(&request.body).await?;
+        }
+    }
+    file.flush().await?;
+    Ok(written)
+}
+
+/// The `bytes first-last/length` of an upload's Content-Range, where the length of the whole
+/// file may be `*` if it isn't known yet.
+#[derive(Debug, PartialEq)]
+struct UploadRange {
+    first: u64,
+    last: u64,
+    length: Option<u64>,
+}
+
+fn parse_content_range(header: &str) -> Option<UploadRange> {
+    let (range, length) = header.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let number = |value: &str| {
+        let digits = !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit());
+        digits.then(|| value.parse().ok()).flatten()
+    };
+    let (first, last) = (number(first)?, number(last)?);
+    let length = match length {
+        "*" => None,
+        length => Some(number(length)?),
+    };
+    let valid = first <= last && length.is_none_or(|length| last < length);
+    valid.then_some(UploadRange {
+        first,
+        last,
+        length,
+    })
+}
+
+/// Writes the body of a PUT with a Content-Range into the file at the range's offset, which
+/// lets an interrupted upload resume where the file's length, as HEAD reports it, left off.
+/// Ranges starting past the end of the file are answered with 416. Once the range ends the
+/// whole file's length, anything after it is dropped.
+///
+/// A range appending to the file is written in place, and a body that doesn't fill it or match
+/// its digest is cut off again, so the upload resumes before it. A range rewriting existing
+/// bytes is written to a copy of the file renamed into place once complete, like [`write_body`],
+/// leaving the file as it was if the body falls short. As that copies the whole file, files
+/// larger than `max_copy` only take appends, rewrites of them are answered with 413.
+async fn put_range(
+    request: &mut HttpRequest,
+    file_path: &Path,
+    range: UploadRange,
+    max_copy: u64,
+) -> Result<HttpResponse> {
+    let current = match tokio::fs::metadata(file_path).await {
+        Ok(metadata) if metadata.is_file() => Some(metadata.len()),
+        Ok(_) => {
+            let message = "the path isn't a file";
+            return Ok(HttpError::new(StatusCode::Conflict, message).into());
+        }
+        Err(_) => None,
+    };
+    let length = current.unwrap_or(0);
+    if range.first > length {
+        let mut resp = HttpResponse::range_not_satisfiable();
+        resp.set_header("Content-Range".to_string(), format!("bytes */{length}"));
+        return Ok(resp);
+    }
+    let append = range.first == length;
+    if !append && length > max_copy {
+        let message = "the file is too large to rewrite, only appends are taken";
+        return Ok(HttpError::new(StatusCode::ContentTooLarge, message).into());
+    }
+    let mut verifier = match Verifier::from_headers(&request.headers) {
+        Ok(verifier) => verifier,
+        Err(message) => return Ok(HttpError::new(StatusCode::BadRequest, message).into()),
+    };
+
+    create_parent(file_path)
+        .await
+        .context("Failed to create directory")?;
+    let target = match append {
+        true => file_path.to_path_buf(),
+        false => {
+            let temp_path = temp_path(file_path);
+            tokio::fs::copy(file_path, &temp_path)
+                .await
+                .context("Failed to copy file")?;
+            temp_path
+        }
+    };
+    let written = async {
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&target)
+            .await?;
+        file.seek(SeekFrom::Start(range.first)).await?;
+        let written = copy_body(request, &mut file, &mut verifier).await?;
+        let failure = if written != range.last - range.first + 1 {
+            Some("the body doesn't fill its Content-Range")
+        } else if verifier.is_some_and(|verifier| !verifier.matches()) {
+            Some("the body doesn't match its digest")
+        } else {
+            None
+        };
+        if failure.is_some() {
+            // never below the bytes the file had before
+            if append {
+                file.set_len(length).await?;
+            }
+            return Ok(failure);
+        }
+        if let Some(total) = range.length
+            && range.last + 1 == total
+        {
+            file.set_len(total).await?;
+        }
+        if !append {
+            tokio::fs::rename(&target, file_path).await?;
+        }
+        Ok::<_, std::io::Error>(None)
+    }
+    .await;
+    // an interrupted append keeps what arrived of it, for the client to resume after
+    if !append && !matches!(written, Ok(None)) {
+        let _ = tokio::fs::remove_file(&target).await;
+    }
+    if let Some(message) = written.context("Failed to write file")? {
+        return Ok(HttpError::new(StatusCode::BadRequest, message).into());
+    }
+    let resp = match current {
+        Some(_) => HttpResponse::no_content(),
+        None => HttpResponse::created(),
+    };
+    Ok(with_etag(resp, file_path).await)
+}
+
+/// Adds the written file's ETag, for clients to make their next write conditional.
+async fn with_etag(mut resp: HttpResponse, file_path: &Path) -> HttpResponse {
+    if let Ok(metadata) = tokio::fs::metadata(file_path).await {
+        let etag = entity_tag(metadata.len(), metadata.modified().ok());
+        resp.set_header("ETag".to_string(), etag);
+    }
+    resp
+}
+
+static DIRECTORY_LISTING: LazyLock<Template> = LazyLock::new(|| {
+    Template::parse(
+        "<!DOCTYPE html>\n<html>\n\
+         <head><meta charset=\"utf-8\"><title>Index of {{title}}</title></head>\n\
+         <body>\n<h1>Index of {{title}}</h1>\n<table>\n\
+         <tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n\
+         {{#each entries}}<tr><td><a href=\"{{href}}\">{{name}}{{#if directory}}/{{/if}}</a></td>\
+         <td>{{#if directory}}-{{else}}{{size}}{{/if}}</td>\
+         <td>{{#if modified}}{{modified}}{{else}}-{{/if}}</td></tr>\n{{/each}}\
+         </table>\n</body>\n</html>\n",
+    )
+    .expect("directory listing template is valid")
+});
+
+#[derive(Serialize)]
+struct DirEntry {
+    name: String,
+    directory: bool,
+    size: u64,
+    /// The modification time as an HTTP-date.
+    modified: Option<String>,
+}
+
+/// Lists the directory at `dir`, whose path below the static directory is `name`, as HTML or,
+/// for clients accepting JSON but not HTML, as JSON. Entries the config refuses are left out.
+async fn list_directory(
+    request: &HttpRequest,
+    config: &ServerConfig,
+    dir: &Path,
+    name: &str,
+) -> Result<HttpResponse> {
+    let mut entries = vec![];
+    let mut read_dir = tokio::fs::read_dir(dir)
+        .await
+        .context("Failed to read directory")?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let entry_name = entry.file_name().to_string_lossy().to_string();
+        // symlinks are listed as what they point to, as long as they would be followed
+        let target = match entry.file_type().await {
+            Ok(file_type) if file_type.is_symlink() => {
+                let root = config.static_directory.as_deref().unwrap_or_default();
+                let path = entry_path(name, &entry_name);
+                resolve(Path::new(root), &path, config.follow_symlinks).await
+            }
+            Ok(_) => Some(entry.path()),
+            Err(_) => None,
+        };
+        let Some(target) = target else {
+            continue;
+        };
+        let Ok(metadata) = tokio::fs::metadata(target).await else {
+            continue;
+        };
+        if !is_permitted(config, &entry_name, metadata.is_dir()) {
+            continue;
+        }
+        entries.push(DirEntry {
+            name: entry_name,
+            directory: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(date::http_date),
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let media_type = match request.negotiate(&["text/html", "application/json"]) {
+        Ok(media_type) => media_type,
+        Err(error) => return Ok(error.into()),
+    };
+    if media_type == "application/json" {
+        let mut resp = HttpResponse::json(&entries)?;
+        resp.headers
+            .append("Vary".to_string(), "Accept".to_string());
+        return Ok(resp);
+    }
+
+    let rows: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            // nested entries are addressed through a single encoded path parameter
+            let path = entry_path(name, &entry.name);
+            serde_json::json!({
+                "href": format!("/files/{}", percent_encode(&path)),
+                "name": entry.name,
+                "directory": entry.directory,
+                "size": entry.size,
+                "modified": entry.modified,
+            })
+        })
+        .collect();
+    let context = serde_json::json!({ "title": format!("/{name}"), "entries": rows });
+    let mut resp = HttpResponse::html(&DIRECTORY_LISTING, &context)?;
+    resp.headers
+        .append("Vary".to_string(), "Accept".to_string());
+    Ok(resp)
+}
+
+/// The path below the static directory of `entry` in the directory at `dir`.
+fn entry_path(dir: &str, entry: &str) -> String {
+    match dir.is_empty() {
+        true => entry.to_string(),
+        false => format!("{}/{entry}", dir.trim_end_matches('/')),
+    }
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+pub(crate) fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A strong validator derived from the file size and modification time.
+fn entity_tag(length: u64, modified: Option<SystemTime>) -> String {
+    let mtime = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", length, mtime.as_nanos())
+}
+
+/// Evaluates If-Match, or If-Unmodified-Since when no entity tags were sent, against the file
+/// at `file_path` before it is written or deleted.
+async fn precondition_holds(request: &HttpRequest, file_path: &Path) -> bool {
+    let metadata = tokio::fs::metadata(file_path)
+        .await
+        .ok()
+        .filter(|metadata| metadata.is_file());
+    let current = metadata.map(|metadata| {
+        let modified = metadata.modified().ok();
+        (entity_tag(metadata.len(), modified), modified)
+    });
+    is_unchanged(
+        request,
+        current
+            .as_ref()
+            .map(|(etag, modified)| (etag.as_str(), *modified)),
+    )
+}
+
+/// `current` is the file's entity tag and modification time, `None` if there is no file.
+fn is_unchanged(request: &HttpRequest, current: Option<(&str, Option<SystemTime>)>) -> bool {
+    if let Some(if_match) = request.headers.get("If-Match") {
+        let Some((etag, _)) = current else {
+            return false;
+        };
+        // If-Match uses the strong comparison, so weak tags never match
+        return if_match
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || candidate == etag);
+    }
+    let Some(since) = request
+        .headers
+        .get("If-Unmodified-Since")
+        .and_then(date::parse_http_date)
+    else {
+        return true;
+    };
+    // the header is ignored for files without a modification time
+    match current {
+        Some((_, Some(modified))) => unix_secs(modified) <= unix_secs(since),
+        _ => true,
+    }
+}
+
+/// HTTP-dates have a resolution of one second.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Evaluates If-None-Match, or If-Modified-Since when no entity tags were sent.
+fn is_not_modified(request: &HttpRequest, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = request.headers.get("If-None-Match") {
+        // If-None-Match uses the weak comparison, so W/ prefixes are ignored
+        return if_none_match.split(',').map(str::trim).any(|candidate| {
+            candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+        });
+    }
+    let (Some(since), Some(modified)) = (
+        request
+            .headers
+            .get("If-Modified-Since")
+            .and_then(date::parse_http_date),
+        modified,
+    ) else {
+        return false;
+    };
+    unix_secs(modified) <= unix_secs(since)
+}
+
+pub async fn delete_file(request: HttpRequest, config: Arc<ServerConfig>) -> Result<HttpResponse> {
+    let file_path = match file_path(&request, &config).await {
+        Ok(file_path) => file_path,
+        Err(resp) => return Ok(resp),
+    };
+
+    // a symlink is removed itself rather than the file it points to, so only its directory is
+    // resolved
+    let name = Path::new(request.param("name").unwrap_or_default());
+    let (Some(parent), Some(file_name), Some(root_dir)) = (
+        name.parent().and_then(Path::to_str),
+        name.file_name(),
+        &config.static_directory,
+    ) else {
+        return Ok(HttpResponse::not_found());
+    };
+    let Some(link_path) = resolve(Path::new(root_dir), parent, config.follow_symlinks)
+        .await
+        .map(|directory| directory.join(file_name))
+    else {
+        return Ok(HttpResponse::forbidden());
+    };
+
+    // only regular files and links to them are removed, directories are answered as if missing
+    let is_file = tokio::fs::metadata(&file_path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file());
+    match tokio::fs::symlink_metadata(&link_path).await {
+        Ok(metadata) if metadata.is_file() || (metadata.is_symlink() && is_file) => {}
+        _ => return Ok(HttpResponse::not_found()),
+    }
+    if !precondition_holds(&request, &file_path).await {
+        return Ok(HttpResponse::precondition_failed());
+    }
+    match tokio::fs::remove_file(&link_path).await {
+        Ok(()) => Ok(HttpResponse::no_content()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HttpResponse::not_found()),
+        Err(e) => {
+            tracing::error!("Error deleting file: {:?}", e);
+            Ok(HttpResponse::internal_server_error())
+        }
+    }
+}
+
+/// More ranges than this in one request are answered with the full file, as a client asking
+/// for that many pieces of it is better off with all of it.
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, PartialEq)]
+enum RangeRequest {
+    /// Inclusive byte ranges within the file, in ascending order and with overlapping or
+    /// adjacent ones merged.
+    Satisfiable(Vec<(u64, u64)>),
+    Unsatisfiable,
+    /// Malformed headers or too many ranges, which are answered with the full file.
+    Ignored,
+}
+
+fn parse_range(header: &str, file_length: u64) -> RangeRequest {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Ignored;
+    };
+    let specs: Vec<&str> = specs.split(',').map(str::trim).collect();
+    if specs.len() > MAX_RANGES {
+        return RangeRequest::Ignored;
+    }
+    let mut ranges = vec![];
+    for spec in specs {
+        match parse_range_spec(spec, file_length) {
+            Some(Some(range)) => ranges.push(range),
+            // ranges starting past the end are left out, the others are still served
+            Some(None) => {}
+            None => return RangeRequest::Ignored,
+        }
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = vec![];
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= *last_end + 1 => *last_end = end.max(*last_end),
+            _ => merged.push((start, end)),
+        }
+    }
+    RangeRequest::Satisfiable(merged)
+}
+
+/// Parses one `first-last` or `-suffix` of a Range header into an inclusive range, `None` if
+/// it's malformed and `Some(None)` if it lies outside the file.
+fn parse_range_spec(spec: &str, file_length: u64) -> Option<Option<(u64, u64)>> {
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return None,
+        ("", suffix) => {
+            let suffix = suffix.parse::<u64>().ok()?;
+            if suffix == 0 {
+                return Some(None);
+            }
+            (
+                file_length.saturating_sub(suffix),
+                file_length.saturating_sub(1),
+            )
+        }
+        (start, end) => {
+            let start = start.parse::<u64>().ok()?;
+            let end = match end {
+                "" => file_length.saturating_sub(1),
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end.min(file_length.saturating_sub(1)),
+                    _ => return None,
+                },
+            };
+            (start, end)
+        }
+    };
+    Some((start < file_length).then_some((start, end)))
+}
+
+#[test]
+fn tests_parse_range() {
+    assert_eq!(
+        RangeRequest::Satisfiable(vec![(0, 4)]),
+        parse_range("bytes=0-4", 10)
+    );
+    assert_eq!(
+        RangeRequest::Satisfiable(vec![(5, 9)]),
+        parse_range("bytes=5-", 10)
+    );
+    assert_eq!(
+        RangeRequest::Satisfiable(vec![(7, 9)]),
+        parse_range("bytes=-3", 10)
+    );
+    assert_eq!(
+        RangeRequest::Satisfiable(vec![(0, 9)]),
+        parse_range("bytes=-30", 10)
+    );
+    assert_eq!(
+        RangeRequest::Satisfiable(vec![(8, 9)]),
+        parse_range("bytes=8-20", 10)
+    );
+    assert_eq!(RangeRequest::Unsatisfiable, parse_range("bytes=10-", 10));
+    assert_eq!(RangeRequest::Unsatisfiable, parse_range("bytes=-0", 10));
+    assert_eq!(RangeRequest::Ignored, parse_range("bytes=4-2", 10));
+    assert_eq!(RangeRequest::Ignored, parse_range("items=0-4", 10));
+    assert_eq!(
+        RangeRequest::Satisfiable(vec![(0, 1), (4, 5)]),
+        parse_range("bytes=4-5, 0-1", 10)
+    );
+    assert_eq!(
+        RangeRequest::Satisfiable(vec![(0, 6)]),
+        parse_range("bytes=0-3,2-5,6-6", 10)
+    );
+    assert_eq!(
+        RangeRequest::Satisfiable(vec![(0, 1)]),
+        parse_range("bytes=0-1,20-30", 10)
+    );
+    assert_eq!(RangeRequest::Ignored, parse_range("bytes=0-1,x", 10));
+    assert_eq!(
+        RangeRequest::Ignored,
+        parse_range(&format!("bytes={}", ["0-0"; 17].join(",")), 10)
+    );
+}
+
+#[tokio::test]
+async fn tests_resolve() {
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-resolve-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(root.join("nested")).unwrap();
+    std::fs::write(root.join("inside.txt"), b"inside").unwrap();
+    let canonical_root = root.canonicalize().unwrap();
+
+    assert_eq!(
+        Some(canonical_root.join("inside.txt")),
+        resolve(&root, "inside.txt", SymlinkPolicy::SameDir).await
+    );
+    assert_eq!(
+        Some(canonical_root.join("new.txt")),
+        resolve(&root, "new.txt", SymlinkPolicy::SameDir).await
+    );
+    assert_eq!(None, resolve(&root, "..", SymlinkPolicy::SameDir).await);
+    assert_eq!(
+        None,
+        resolve(&root, "../../etc/passwd", SymlinkPolicy::SameDir).await
+    );
+    assert_eq!(
+        None,
+        resolve(&root, "/etc/passwd", SymlinkPolicy::SameDir).await
+    );
+    assert_eq!(
+        None,
+        resolve(&root, "nested/../../inside.txt", SymlinkPolicy::SameDir).await
+    );
+
+    #[cfg(unix)]
+    {
+        let link = root.join("escape");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink("/etc", &link).unwrap();
+        assert_eq!(None, resolve(&root, "escape", SymlinkPolicy::SameDir).await);
+        assert_eq!(
+            Some(PathBuf::from("/etc")),
+            resolve(&root, "escape", SymlinkPolicy::Always).await
+        );
+
+        let link = root.join("inner");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(root.join("nested"), &link).unwrap();
+        assert_eq!(
+            Some(canonical_root.join("nested").join("new.txt")),
+            resolve(&root, "inner/new.txt", SymlinkPolicy::SameDir).await
+        );
+        assert_eq!(
+            None,
+            resolve(&root, "inner/new.txt", SymlinkPolicy::Never).await
+        );
+        assert_eq!(
+            Some(canonical_root.join("nested").join("new.txt")),
+            resolve(&root, "nested/new.txt", SymlinkPolicy::Never).await
+        );
+    }
+    assert_eq!(Ok(SymlinkPolicy::SameDir), "same-dir".parse());
+    assert!("sometimes".parse::<SymlinkPolicy>().is_err());
+}
+
+#[test]
+fn tests_is_not_modified() {
+    let modified = Some(UNIX_EPOCH + std::time::Duration::from_secs(784_111_777));
+    let etag = entity_tag(10, modified);
+    let request = |header: &str, value: &str| {
+        let mut request = HttpRequest::default();
+        request
+            .headers
+            .insert(header.to_string(), value.to_string());
+        request
+    };
+
+    assert!(is_not_modified(
+        &request("If-None-Match", &etag),
+        &etag,
+        modified
+    ));
+    assert!(is_not_modified(
+        &request("If-None-Match", &format!("\"other\", W/{etag}")),
+        &etag,
+        modified
+    ));
+    assert!(is_not_modified(
+        &request("If-None-Match", "*"),
+        &etag,
+        modified
+    ));
+    assert!(!is_not_modified(
+        &request("If-None-Match", "\"other\""),
+        &etag,
+        modified
+    ));
+
+    let since = |value| request("If-Modified-Since", value);
+    assert!(is_not_modified(
+        &since("Sun, 06 Nov 1994 08:49:37 GMT"),
+        &etag,
+        modified
+    ));
+    assert!(is_not_modified(
+        &since("Mon, 07 Nov 1994 00:00:00 GMT"),
+        &etag,
+        modified
+    ));
+    assert!(!is_not_modified(
+        &since("Sat, 05 Nov 1994 08:49:37 GMT"),
+        &etag,
+        modified
+    ));
+    assert!(!is_not_modified(&since("not a date"), &etag, modified));
+    assert!(!is_not_modified(&HttpRequest::default(), &etag, modified));
+}
+
+#[test]
+fn tests_cache_control() {
+    let rule = |path: &str, extensions: &[&str], value: &str| CacheControlRule {
+        path: path.to_string(),
+        extensions: extensions.iter().map(|e| e.to_string()).collect(),
+        value: value.to_string(),
+    };
+    let rules = [
+        rule(
+            "assets/*",
+            &["js", ".CSS"],
+            "public, max-age=31536000, immutable",
+        ),
+        rule("", &["html"], "no-cache"),
+        rule("robots.txt", &[], "max-age=86400"),
+    ];
+    assert_eq!(
+        Some("public, max-age=31536000, immutable"),
+        cache_control(&rules, "assets/app.3f2a.css")
+    );
+    assert_eq!(None, cache_control(&rules, "assets/logo.png"));
+    assert_eq!(None, cache_control(&rules, "app.js"));
+    assert_eq!(Some("no-cache"), cache_control(&rules, "docs/index.HTML"));
+    assert_eq!(Some("max-age=86400"), cache_control(&rules, "robots.txt"));
+    assert_eq!(None, cache_control(&rules, "sub/robots.txt"));
+    assert_eq!(None, cache_control(&[], "index.html"));
+}
+
+#[test]
+fn tests_is_unchanged() {
+    let modified = Some(UNIX_EPOCH + std::time::Duration::from_secs(784_111_777));
+    let etag = entity_tag(10, modified);
+    let current = Some((etag.as_str(), modified));
+    let request = |header: &str, value: &str| {
+        let mut request = HttpRequest::default();
+        request
+            .headers
+            .insert(header.to_string(), value.to_string());
+        request
+    };
+
+    assert!(is_unchanged(&HttpRequest::default(), current));
+    assert!(is_unchanged(&HttpRequest::default(), None));
+    assert!(is_unchanged(
+        &request("If-Match", &format!("\"a\", {etag}")),
+        current
+    ));
+    assert!(is_unchanged(&request("If-Match", "*"), current));
+    assert!(!is_unchanged(&request("If-Match", "*"), None));
+    assert!(!is_unchanged(
+        &request("If-Match", &format!("W/{etag}")),
+        current
+    ));
+    assert!(!is_unchanged(&request("If-Match", "\"other\""), current));
+
+    let since = |value| request("If-Unmodified-Since", value);
+    assert!(is_unchanged(
+        &since("Sun, 06 Nov 1994 08:49:37 GMT"),
+        current
+    ));
+    assert!(!is_unchanged(
+        &since("Sat, 05 Nov 1994 08:49:37 GMT"),
+        current
+    ));
+    assert!(is_unchanged(&since("Sat, 05 Nov 1994 08:49:37 GMT"), None));
+    assert!(is_unchanged(&since("not a date"), current));
+}
+
+#[tokio::test]
+async fn tests_list_directory() {
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-listing-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(root.join("sub dir")).unwrap();
+    std::fs::write(root.join("a<b>.txt"), b"abc").unwrap();
+    std::fs::write(root.join("sub dir").join("nested.txt"), b"nested").unwrap();
+    let config = Arc::new(ServerConfig {
+        static_directory: Some(root.to_string_lossy().to_string()),
+        dir_listing: true,
+        ..Default::default()
+    });
+    let get = |name: Option<&str>, accept: &str| {
+        let mut request = HttpRequest::default();
+        if let Some(name) = name {
+            request.params.insert("name".to_string(), name.to_string());
+        }
+        request
+            .headers
+            .insert("Accept".to_string(), accept.to_string());
+        get_file(request, config.clone())
+    };
+
+    let resp = get(None, "text/html").await.unwrap();
+    assert_eq!(
+        Some("text/html; charset=utf-8"),
+        resp.headers.get("Content-Type")
+    );
+    let html = String::from_utf8(resp.body.as_bytes().unwrap().to_vec()).unwrap();
+    assert!(html.contains("<a href=\"/files/a%3Cb%3E.txt\">a&lt;b&gt;.txt</a></td><td>3</td>"));
+    assert!(html.contains("<a href=\"/files/sub%20dir\">sub dir/</a></td><td>-</td>"));
+
+    let resp = get(Some("sub dir"), "application/json").await.unwrap();
+    assert_eq!(Some("application/json"), resp.headers.get("Content-Type"));
+    let json: serde_json::Value = serde_json::from_slice(resp.body.as_bytes().unwrap()).unwrap();
+    assert_eq!("nested.txt", json[0]["name"]);
+    assert_eq!(6, json[0]["size"]);
+    assert_eq!(false, json[0]["directory"]);
+
+    // q-values and wildcards count
+    let resp = get(None, "text/html;q=0.5, application/json")
+        .await
+        .unwrap();
+    assert_eq!(Some("application/json"), resp.headers.get("Content-Type"));
+    let resp = get(None, "*/*").await.unwrap();
+    assert_eq!(
+        Some("text/html; charset=utf-8"),
+        resp.headers.get("Content-Type")
+    );
+    assert_eq!(Some("Accept"), resp.headers.get("Vary"));
+    assert_eq!(406, get(None, "image/png").await.unwrap().status_code);
+
+    let config = Arc::new(ServerConfig {
+        dir_listing: false,
+        ..(*config).clone()
+    });
+    assert_eq!(
+        404,
+        get_file(HttpRequest::default(), config)
+            .await
+            .unwrap()
+            .status_code
+    );
+}
+
+#[tokio::test]
+async fn tests_file_rules() {
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-file-rules-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(root.join("sub")).unwrap();
+    for name in [".env", "a.txt", "b.bak", "page.HTML", "sub/c.txt"] {
+        std::fs::write(root.join(name), b"x").unwrap();
+    }
+    let config = Arc::new(ServerConfig {
+        static_directory: Some(root.to_string_lossy().to_string()),
+        dir_listing: true,
+        allowed_extensions: vec!["txt".to_string(), ".html".to_string()],
+        denied_extensions: vec!["html".to_string()],
+        ..Default::default()
+    });
+    let request = |name: &str| {
+        let mut request = HttpRequest::default();
+        request.params.insert("name".to_string(), name.to_string());
+        request
+    };
+    let get = async |config: &Arc<ServerConfig>, name: &str| {
+        let resp = get_file(request(name), config.clone()).await.unwrap();
+        resp.status_code.as_u16()
+    };
+
+    assert_eq!(200, get(&config, "a.txt").await);
+    assert_eq!(200, get(&config, "sub").await);
+    assert_eq!(200, get(&config, "sub/c.txt").await);
+    assert_eq!(403, get(&config, ".env").await);
+    assert_eq!(403, get(&config, "b.bak").await);
+    assert_eq!(403, get(&config, "page.HTML").await);
+    assert_eq!(403, get(&config, ".git/config").await);
+
+    let mut upload = request(".env");
+    upload.body = bytes::Bytes::from_static(b"SECRET=1");
+    let resp = put_file(upload, config.clone()).await.unwrap();
+    assert_eq!(403, resp.status_code);
+    assert_eq!(b"x", &std::fs::read(root.join(".env")).unwrap()[..]);
+
+    let mut listing = HttpRequest::default();
+    listing
+        .headers
+        .insert("Accept".to_string(), "application/json".to_string());
+    let resp = get_file(listing, config.clone()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(resp.body.as_bytes().unwrap()).unwrap();
+    let names: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(vec!["a.txt", "sub"], names);
+
+    let permissive = Arc::new(ServerConfig {
+        static_directory: config.static_directory.clone(),
+        allow_dotfiles: true,
+        ..Default::default()
+    });
+    assert_eq!(200, get(&permissive, ".env").await);
+    assert_eq!(200, get(&permissive, "b.bak").await);
+}
+
+#[tokio::test]
+async fn tests_file_digests() {
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-digests-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("a.txt"), b"old").unwrap();
+    let config = Arc::new(ServerConfig {
+        static_directory: Some(root.to_string_lossy().to_string()),
+        file_digests: true,
+        ..Default::default()
+    });
+    let put = async |header: &str, value: &str| {
+        let mut request = HttpRequest::default();
+        request
+            .params
+            .insert("name".to_string(), "a.txt".to_string());
+        request
+            .headers
+            .insert(header.to_string(), value.to_string());
+        request.body = bytes::Bytes::from_static(b"abc");
+        let resp = put_file(request, config.clone()).await.unwrap();
+        resp.status_code.as_u16()
+    };
+
+    // a corrupted upload leaves the file alone
+    assert_eq!(400, put("Content-MD5", "1B2M2Y8AsgTpgAmY7PhCfg==").await);
+    assert_eq!(b"old", &std::fs::read(root.join("a.txt")).unwrap()[..]);
+    assert_eq!(400, put("Content-MD5", "not base64").await);
+    assert_eq!(204, put("Content-MD5", "kAFQmDzST7DWlj99KOF/cg==").await);
+    assert_eq!(
+        204,
+        put(
+            "Digest",
+            "sha-256=ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+        )
+        .await
+    );
+    assert_eq!(b"abc", &std::fs::read(root.join("a.txt")).unwrap()[..]);
+
+    let mut request = HttpRequest::default();
+    request
+        .params
+        .insert("name".to_string(), "a.txt".to_string());
+    let resp = get_file(request, config.clone()).await.unwrap();
+    assert_eq!(
+        Some("sha-256=ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="),
+        resp.headers.get("Digest")
+    );
+}
+
+#[tokio::test]
+async fn tests_put_range() {
+    assert_eq!(
+        Some(UploadRange {
+            first: 0,
+            last: 4,
+            length: Some(10)
+        }),
+        parse_content_range("bytes 0-4/10")
+    );
+    assert_eq!(
+        Some(UploadRange {
+            first: 5,
+            last: 9,
+            length: None
+        }),
+        parse_content_range("bytes 5-9/*")
+    );
+    assert_eq!(None, parse_content_range("bytes 5-4/10"));
+    assert_eq!(None, parse_content_range("bytes 0-10/10"));
+    assert_eq!(None, parse_content_range("bytes */10"));
+    assert_eq!(None, parse_content_range("bytes +0-4/10"));
+
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-put-range-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root).unwrap();
+    let _ = std::fs::remove_file(root.join("a.txt"));
+    let config = Arc::new(ServerConfig {
+        static_directory: Some(root.to_string_lossy().to_string()),
+        ..Default::default()
+    });
+    let put = async |content_range: &str, body: &'static [u8]| {
+        let mut request = HttpRequest::default();
+        request
+            .params
+            .insert("name".to_string(), "a.txt".to_string());
+        request
+            .headers
+            .insert("Content-Range".to_string(), content_range.to_string());
+        request.body = bytes::Bytes::from_static(body);
+        let resp = put_file(request, config.clone()).await.unwrap();
+        resp.status_code.as_u16()
+    };
+    let read = || std::fs::read(root.join("a.txt")).unwrap();
+
+    assert_eq!(201, put("bytes 0-4/10", b"hello").await);
+    assert_eq!(b"hello", &read()[..]);
+    // a range past the end of what arrived so far can't be written
+    assert_eq!(416, put("bytes 6-9/10", b"orld").await);
+    // a short body is cut off again
+    assert_eq!(400, put("bytes 5-9/10", b" wo").await);
+    assert_eq!(b"hello", &read()[..]);
+    assert_eq!(204, put("bytes 5-9/10", b" worl").await);
+    assert_eq!(b"hello worl", &read()[..]);
+    // a short rewrite of existing bytes leaves the file as it was
+    assert_eq!(400, put("bytes 0-4/*", b"HE").await);
+    assert_eq!(b"hello worl", &read()[..]);
+    assert_eq!(204, put("bytes 0-4/*", b"HELLO").await);
+    assert_eq!(b"HELLO worl", &read()[..]);
+    // as does one failing partway through the file, the bytes around it included
+    assert_eq!(400, put("bytes 3-6/*", b"LOW").await);
+    assert_eq!(b"HELLO worl", &read()[..]);
+    let temp_files = std::fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".tmp"))
+        .count();
+    assert_eq!(0, temp_files);
+    // the last range drops anything after the whole file's length
+    assert_eq!(204, put("bytes 0-1/2", b"hi").await);
+    assert_eq!(b"hi", &read()[..]);
+    assert_eq!(400, put("bytes zero-1/2", b"hi").await);
+
+    // files larger than a body only take appends, as rewriting them copies the whole file
+    let small = Arc::new(ServerConfig {
+        static_directory: config.static_directory.clone(),
+        max_body_size: 1,
+        ..Default::default()
+    });
+    let put_small = async |content_range: &str, body: &'static [u8]| {
+        let mut request = HttpRequest::default();
+        request
+            .params
+            .insert("name".to_string(), "a.txt".to_string());
+        request
+            .headers
+            .insert("Content-Range".to_string(), content_range.to_string());
+        request.body = bytes::Bytes::from_static(body);
+        let resp = put_file(request, small.clone()).await.unwrap();
+        resp.status_code.as_u16()
+    };
+    assert_eq!(413, put_small("bytes 0-0/*", b"H").await);
+    assert_eq!(b"hi", &read()[..]);
+    assert_eq!(204, put_small("bytes 2-2/*", b"!").await);
+    assert_eq!(b"hi!", &read()[..]);
+
+    // a dangling symlink isn't followed out of the directory
+    let outside = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-put-range-outside-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&outside);
+    let link = root.join("link.txt");
+    let _ = std::fs::remove_file(&link);
+    std::os::unix::fs::symlink(&outside, &link).unwrap();
+    let mut request = HttpRequest::default();
+    request
+        .params
+        .insert("name".to_string(), "link.txt".to_string());
+    request
+        .headers
+        .insert("Content-Range".to_string(), "bytes 0-1/2".to_string());
+    request.body = bytes::Bytes::from_static(b"hi");
+    let resp = put_file(request, config.clone()).await.unwrap();
+    assert_eq!(403, resp.status_code.as_u16());
+    assert!(!outside.exists());
+    std::fs::remove_file(&link).unwrap();
+}
+
+#[tokio::test]
+async fn tests_delete_file() {
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-delete-file-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("nested")).unwrap();
+    std::fs::write(root.join("target.txt"), "kept").unwrap();
+    std::fs::write(root.join("nested/gone.txt"), "gone").unwrap();
+    std::os::unix::fs::symlink(root.join("target.txt"), root.join("link.txt")).unwrap();
+    let config = Arc::new(ServerConfig {
+        static_directory: Some(root.to_string_lossy().to_string()),
+        ..Default::default()
+    });
+    let delete = async |name: &str| {
+        let mut request = HttpRequest::default();
+        request.params.insert("name".to_string(), name.to_string());
+        let resp = delete_file(request, config.clone()).await.unwrap();
+        resp.status_code.as_u16()
+    };
+
+    // the link goes, the sibling file it points to stays
+    assert_eq!(204, delete("link.txt").await);
+    assert!(std::fs::symlink_metadata(root.join("link.txt")).is_err());
+    assert_eq!(
+        "kept",
+        std::fs::read_to_string(root.join("target.txt")).unwrap()
+    );
+    assert_eq!(204, delete("nested/gone.txt").await);
+    assert!(!root.join("nested/gone.txt").exists());
+    assert_eq!(404, delete("nested").await);
+    assert_eq!(404, delete("missing.txt").await);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn tests_precompressed() {
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-precompressed-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("app.js"), b"plain").unwrap();
+    std::fs::write(root.join("app.js.gz"), b"gzipped").unwrap();
+    std::fs::write(root.join("app.js.br"), b"brotli").unwrap();
+    std::fs::write(root.join("other.js"), b"plain").unwrap();
+    let config = Arc::new(ServerConfig {
+        static_directory: Some(root.to_string_lossy().to_string()),
+        precompressed: true,
+        ..Default::default()
+    });
+    let get = async |name: &str, accept_encoding: Option<&str>| {
+        let mut request = HttpRequest::default();
+        request.params.insert("name".to_string(), name.to_string());
+        if let Some(accept_encoding) = accept_encoding {
+            request
+                .headers
+                .insert("Accept-Encoding".to_string(), accept_encoding.to_string());
+        }
+        let mut resp = get_file(request, config.clone()).await.unwrap();
+        let mut body = vec![];
+        if let crate::response::Body::Stream(reader) = &mut resp.body {
+            reader.read_to_end(&mut body).await.unwrap();
+        }
+        (resp, body)
+    };
+
+    let (resp, body) = get("app.js", Some("gzip, br")).await;
+    assert_eq!(b"brotli", &body[..]);
+    assert_eq!(Some("br"), resp.headers.get("Content-Encoding"));
+    assert_eq!(
+        Some("text/javascript; charset=utf-8"),
+        resp.headers.get("Content-Type")
+    );
+    assert_eq!(Some("Accept-Encoding"), resp.headers.get("Vary"));
+    let brotli_etag = resp.headers.get("ETag").unwrap().to_string();
+
+    let (resp, body) = get("app.js", Some("gzip, zstd")).await;
+    assert_eq!(b"gzipped", &body[..]);
+    assert_eq!(Some("gzip"), resp.headers.get("Content-Encoding"));
+    assert_ne!(Some(brotli_etag.as_str()), resp.headers.get("ETag"));
+
+    let (resp, body) = get("app.js", None).await;
+    assert_eq!(b"plain", &body[..]);
+    assert_eq!(None, resp.headers.get("Content-Encoding"));
+    assert_eq!(Some("Accept-Encoding"), resp.headers.get("Vary"));
+
+    let (resp, _) = get("other.js", Some("gzip")).await;
+    assert_eq!(None, resp.headers.get("Content-Encoding"));
+    assert_eq!(None, resp.headers.get("Vary"));
+}
+
+#[tokio::test]
+async fn tests_byteranges() {
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-byteranges-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("a.txt"), b"0123456789").unwrap();
+    let config = Arc::new(ServerConfig {
+        static_directory: Some(root.to_string_lossy().to_string()),
+        ..Default::default()
+    });
+    let mut request = HttpRequest::default();
+    request
+        .params
+        .insert("name".to_string(), "a.txt".to_string());
+    request
+        .headers
+        .insert("Range".to_string(), "bytes=7-8, 0-1".to_string());
+    let mut resp = get_file(request, config).await.unwrap();
+    assert_eq!(206, resp.status_code.as_u16());
+    let content_type = resp.headers.get("Content-Type").unwrap().to_string();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap();
+    let mut body = vec![];
+    if let crate::response::Body::Stream(reader) = &mut resp.body {
+        reader.read_to_end(&mut body).await.unwrap();
+    }
+    assert_eq!(
+        format!(
+            "--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Range: bytes 0-1/10\r\n\r\n01\r\n\
+             --{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Range: bytes 7-8/10\r\n\r\n78\r\n\
+             --{boundary}--\r\n"
+        ),
+        String::from_utf8(body.clone()).unwrap()
+    );
+    assert_eq!(
+        Some(body.len().to_string().as_str()),
+        resp.headers.get("Content-Length")
+    );
+}
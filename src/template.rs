@@ -0,0 +1,327 @@
+//! A small Handlebars-like template language for HTML bodies:
+//!
+//! ```
+//! # use codecrafters_http_server::template::Template;
+//! let template = Template::parse(
+//!     "<h1>{{title}}</h1>{{#each items}}<p>{{name}}{{#if new}} (new){{/if}}</p>{{/each}}",
+//! )
+//! .unwrap();
+//! let context = serde_json::json!({
+//!     "title": "Fish & chips",
+//!     "items": [{"name": "cod", "new": true}, {"name": "<haddock>"}],
+//! });
+//! assert_eq!(
+//!     "<h1>Fish &amp; chips</h1><p>cod (new)</p><p>&lt;haddock&gt;</p>",
+//!     template.render(&context).unwrap()
+//! );
+//! ```
+//!
+//! `{{name}}` inserts a value HTML-escaped and `{{{name}}}` inserts it as is. Names may reach into
+//! nested objects (`{{user.name}}`) and are looked up in the enclosing `{{#each}}` items from the
+//! innermost outwards, where `{{this}}` is the item itself. `{{#if name}}` takes its branch unless
+//! the value is missing, null, false, 0, empty or an empty list, with an optional `{{else}}`.
+//! `{{! comments }}` are dropped.
+
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::static_files::html_escape;
+
+/// Why a template could not be parsed or rendered.
+#[derive(Debug, Error, PartialEq)]
+pub enum TemplateError {
+    #[error("template syntax error at byte {offset}: {message}")]
+    Syntax { offset: usize, message: String },
+    #[error("unable to serialize template context: {0}")]
+    Context(String),
+}
+
+enum Node {
+    Text(String),
+    Value {
+        path: Vec<String>,
+        raw: bool,
+    },
+    Each {
+        path: Vec<String>,
+        body: Vec<Node>,
+    },
+    If {
+        path: Vec<String>,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A block being parsed, closed by `{{/kind}}`.
+struct Open {
+    kind: &'static str,
+    path: Vec<String>,
+    offset: usize,
+    /// The nodes of the `{{#if}}` branch once `{{else}}` was seen.
+    then: Option<Vec<Node>>,
+    outer: Vec<Node>,
+}
+
+/// A parsed template, rendered with any serializable context.
+pub struct Template {
+    nodes: Vec<Node>,
+    escape: bool,
+}
+
+impl Template {
+    /// Parses `source`, failing on unclosed tags and blocks.
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        let syntax = |offset, message: &str| TemplateError::Syntax {
+            offset,
+            message: message.to_string(),
+        };
+        let mut nodes = vec![];
+        let mut open: Vec<Open> = vec![];
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                nodes.push(Node::Text(rest[..start].to_string()));
+            }
+            let offset = source.len() - rest.len() + start;
+            let (raw, close) = match rest[start..].starts_with("{{{") {
+                true => (true, "}}}"),
+                false => (false, "}}"),
+            };
+            let tag_start = start + close.len();
+            let tag_len = rest[tag_start..]
+                .find(close)
+                .ok_or_else(|| syntax(offset, "unclosed tag"))?;
+            let tag = rest[tag_start..tag_start + tag_len].trim();
+            rest = &rest[tag_start + tag_len + close.len()..];
+
+            if raw {
+                nodes.push(Node::Value {
+                    path: parse_path(tag),
+                    raw,
+                });
+            } else if tag.starts_with('!') {
+                continue;
+            } else if let Some(block) = tag.strip_prefix('#') {
+                let (kind, name) = block.split_once(char::is_whitespace).unwrap_or((block, ""));
+                let kind = match kind {
+                    "each" => "each",
+                    "if" => "if",
+                    _ => return Err(syntax(offset, &format!("unknown block {kind:?}"))),
+                };
+                open.push(Open {
+                    kind,
+                    path: parse_path(name.trim()),
+                    offset,
+                    then: None,
+                    outer: std::mem::take(&mut nodes),
+                });
+            } else if tag == "else" {
+                match open.last_mut() {
+                    Some(block) if block.kind == "if" && block.then.is_none() => {
+                        block.then = Some(std::mem::take(&mut nodes));
+                    }
+                    _ => return Err(syntax(offset, "{{else}} outside of {{#if}}")),
+                }
+            } else if let Some(kind) = tag.strip_prefix('/') {
+                let block = open
+                    .pop()
+                    .filter(|block| block.kind == kind.trim())
+                    .ok_or_else(|| syntax(offset, &format!("unexpected {{{{/{kind}}}}}")))?;
+                let body = std::mem::replace(&mut nodes, block.outer);
+                nodes.push(match block.then {
+                    Some(then) => Node::If {
+                        path: block.path,
+                        then,
+                        otherwise: body,
+                    },
+                    None if block.kind == "if" => Node::If {
+                        path: block.path,
+                        then: body,
+                        otherwise: vec![],
+                    },
+                    None => Node::Each {
+                        path: block.path,
+                        body,
+                    },
+                });
+            } else {
+                nodes.push(Node::Value {
+                    path: parse_path(tag),
+                    raw,
+                });
+            }
+        }
+        if let Some(block) = open.last() {
+            return Err(syntax(
+                block.offset,
+                &format!("{{{{#{}}}}} is never closed", block.kind),
+            ));
+        }
+        if !rest.is_empty() {
+            nodes.push(Node::Text(rest.to_string()));
+        }
+        Ok(Template {
+            nodes,
+            escape: true,
+        })
+    }
+
+    /// Inserts `{{name}}` values as they are rather than HTML-escaped, for plain text.
+    pub fn without_escaping(mut self) -> Self {
+        self.escape = false;
+        self
+    }
+
+    /// Renders the template with the fields of `context`, usually a struct or a JSON object.
+    pub fn render<T: Serialize + ?Sized>(&self, context: &T) -> Result<String, TemplateError> {
+        let context =
+            serde_json::to_value(context).map_err(|e| TemplateError::Context(e.to_string()))?;
+        let mut output = String::new();
+        self.render_nodes(&self.nodes, &mut vec![&context], &mut output);
+        Ok(output)
+    }
+
+    fn render_nodes<'a>(&self, nodes: &'a [Node], scopes: &mut Vec<&'a Value>, out: &mut String) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Value { path, raw } => {
+                    let value = display(lookup(scopes, path));
+                    match self.escape && !raw {
+                        true => out.push_str(&html_escape(&value)),
+                        false => out.push_str(&value),
+                    }
+                }
+                Node::Each { path, body } => {
+                    if let Value::Array(items) = lookup(scopes, path) {
+                        for item in items {
+                            scopes.push(item);
+                            self.render_nodes(body, scopes, out);
+                            scopes.pop();
+                        }
+                    }
+                }
+                Node::If {
+                    path,
+                    then,
+                    otherwise,
+                } => match is_truthy(lookup(scopes, path)) {
+                    true => self.render_nodes(then, scopes, out),
+                    false => self.render_nodes(otherwise, scopes, out),
+                },
+            }
+        }
+    }
+}
+
+/// Splits `a.b` into its field names, `this` (or `.`) stands for the current item.
+fn parse_path(name: &str) -> Vec<String> {
+    let name = match name.strip_prefix("this") {
+        Some(rest) if rest.is_empty() || rest.starts_with('.') => rest,
+        _ => name,
+    };
+    name.split('.')
+        .filter(|field| !field.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The value at `path` in the innermost scope whose object has the path's first field.
+fn lookup<'a>(scopes: &[&'a Value], path: &[String]) -> &'a Value {
+    let Some((first, rest)) = path.split_first() else {
+        return scopes.last().copied().unwrap_or(&Value::Null);
+    };
+    let Some(mut value) = scopes.iter().rev().find_map(|scope| scope.get(first)) else {
+        return &Value::Null;
+    };
+    for field in rest {
+        value = value.get(field).unwrap_or(&Value::Null);
+    }
+    value
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+#[test]
+fn tests_render() {
+    let render = |source: &str, context: Value| Template::parse(source)?.render(&context);
+    let context = serde_json::json!({
+        "name": "<b>",
+        "count": 0,
+        "user": {"name": "ann", "tags": ["a", "b"]},
+        "rows": [{"name": "row"}, {"other": 1}],
+    });
+
+    assert_eq!(
+        Ok("&lt;b&gt; <b>".to_string()),
+        render("{{name}} {{{ name }}}", context.clone())
+    );
+    assert_eq!(
+        Ok("ann: a,b,".to_string()),
+        render(
+            "{{this.user.name}}: {{#each user.tags}}{{this}},{{/each}}",
+            context.clone()
+        )
+    );
+    // items fall back to outer scopes for fields they don't have
+    assert_eq!(
+        Ok("row|&lt;b&gt;|".to_string()),
+        render("{{#each rows}}{{name}}|{{/each}}", context.clone())
+    );
+    assert_eq!(
+        Ok("no, yes".to_string()),
+        render(
+            "{{#if count}}yes{{else}}no{{/if}}, {{#if user}}yes{{/if}}",
+            context.clone()
+        )
+    );
+    assert_eq!(
+        Ok("[]".to_string()),
+        render("[{{missing.field}}{{! dropped }}]", context.clone())
+    );
+
+    let plain = Template::parse("{{name}}").unwrap().without_escaping();
+    assert_eq!(Ok("<b>".to_string()), plain.render(&context));
+
+    for source in [
+        "{{name",
+        "{{#if a}}",
+        "{{#each a}}{{/if}}",
+        "{{else}}",
+        "{{#with a}}{{/with}}",
+    ] {
+        assert!(
+            matches!(
+                render(source, Value::Null),
+                Err(TemplateError::Syntax { .. })
+            ),
+            "{source}"
+        );
+    }
+    assert_eq!(
+        Some(TemplateError::Syntax {
+            offset: 2,
+            message: "{{#if}} is never closed".to_string()
+        }),
+        Template::parse("ab{{#if x}}").err()
+    );
+}
@@ -0,0 +1,146 @@
+//! Bounds on how long handlers may take to produce a response, e.g.
+//!
+//! ```toml
+//! handler_timeout = 30
+//!
+//! [[handler_timeouts]]
+//! path = "/upload"
+//! timeout = 300
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::config::{ServerConfig, seconds};
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::{BoxFuture, has_path_prefix};
+use crate::status::StatusCode;
+
+/// A `[[handler_timeouts]]` table of the config file, overriding `handler_timeout` for a path
+/// prefix.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutRule {
+    /// Requests at or below this path prefix get `timeout`, the longest matching prefix wins.
+    pub path: String,
+    #[serde(deserialize_with = "seconds")]
+    pub timeout: Duration,
+}
+
+/// How long the handler of `request` may take: that of the longest path prefix matching it,
+/// otherwise the config's `handler_timeout`.
+fn limit(request: &HttpRequest, config: &ServerConfig) -> Option<Duration> {
+    config
+        .handler_timeouts
+        .iter()
+        .filter(|rule| has_path_prefix(&request.path, &rule.path))
+        .max_by_key(|rule| rule.path.trim_end_matches('/').len())
+        .map(|rule| rule.timeout)
+        .or(config.handler_timeout)
+}
+
+/// Whether `request` is forwarded to another server, which a timeout makes a 504 rather than
+/// a 503.
+fn is_forwarded(request: &HttpRequest, config: &ServerConfig) -> bool {
+    config.proxy_mode && request.target_scheme.is_some()
+        || config
+            .proxies
+            .iter()
+            .any(|proxy| has_path_prefix(&request.path, &proxy.path))
+}
+
+/// Cancels handlers that take longer than the config's `handler_timeout` or `handler_timeouts`
+/// to produce a response, answering 503 instead, or 504 for requests forwarded to an
+/// upstream. Only producing the response is bounded: streamed bodies are limited by the
+/// client reading them.
+#[derive(Default)]
+pub struct Timeouts;
+
+impl Timeouts {
+    pub fn new() -> Self {
+        Timeouts
+    }
+}
+
+impl Middleware<ServerConfig> for Timeouts {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        config: Arc<ServerConfig>,
+        next: Next<'a, ServerConfig>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let Some(limit) = limit(&request, &config) else {
+                return next.run(request, config).await;
+            };
+            let status = match is_forwarded(&request, &config) {
+                true => StatusCode::GatewayTimeout,
+                false => StatusCode::ServiceUnavailable,
+            };
+            // dropping the handler's future on timeout cancels it
+            match tokio::time::timeout(limit, next.run(request, config)).await {
+                Ok(resp) => resp,
+                Err(_) => {
+                    tracing::warn!("Handler timed out after {limit:?}");
+                    Ok(HttpResponse::new(status))
+                }
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn tests_timeouts() {
+    use crate::proxy::ProxyConfig;
+    use crate::router::Router;
+
+    let sleep = || async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        Ok(HttpResponse::ok())
+    };
+    let router: Router<ServerConfig> = Router::new()
+        .get("/slow", move |_, _| sleep())
+        .get("/upload/slow", move |_, _| sleep())
+        .get("/api/slow", move |_, _| sleep())
+        .layer(Timeouts::new());
+    let config = Arc::new(ServerConfig {
+        handler_timeout: Some(Duration::from_millis(50)),
+        handler_timeouts: vec![TimeoutRule {
+            path: "/upload/".to_string(),
+            timeout: Duration::from_secs(5),
+        }],
+        proxies: vec![ProxyConfig {
+            path: "/api".to_string(),
+            upstream: "http://127.0.0.1:1".to_string(),
+            strip_prefix: false,
+        }],
+        ..Default::default()
+    });
+    let get = async |path: &str| {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        let resp = router.handle(request, config.clone()).await.unwrap();
+        resp.status_code.as_u16()
+    };
+
+    assert_eq!(503, get("/slow").await);
+    assert_eq!(200, get("/upload/slow").await);
+    assert_eq!(504, get("/api/slow").await);
+
+    let unlimited = Arc::new(ServerConfig::default());
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        path: "/slow".to_string(),
+        ..Default::default()
+    };
+    let resp = router.handle(request, unlimited).await.unwrap();
+    assert_eq!(200, resp.status_code);
+}
@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig as TlsConfig;
+
+/// Builds a TLS acceptor from a PEM certificate chain and private key, offering HTTP/2 through
+/// ALPN if `http2` is set.
+pub fn acceptor(cert_path: &str, key_path: &str, http2: bool) -> Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .with_context(|| format!("Unable to read certificate {cert_path}"))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Unable to parse certificate {cert_path}"))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Unable to read private key {key_path}"))?;
+
+    let mut config = TlsConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid certificate or private key")?;
+    if http2 {
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    }
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
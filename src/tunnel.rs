@@ -0,0 +1,140 @@
+//! CONNECT tunnels, which let clients reach TLS servers through this one acting as a forward
+//! proxy. Targets have to be allowed by the config's `connect_targets`, e.g.
+//!
+//! ```toml
+//! connect_targets = ["example.com:443", "*.example.org:443", "localhost:*"]
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::net::TcpStream;
+
+use crate::config::ServerConfig;
+use crate::middleware::{Middleware, Next};
+use crate::request::{HttpRequest, Version};
+use crate::response::HttpResponse;
+use crate::router::BoxFuture;
+use crate::status::StatusCode;
+
+/// How long the target may take to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The host and port of a CONNECT request's authority-form target, e.g. "example.com:443",
+/// which the parser leaves in the path. IPv6 hosts lose their brackets.
+fn target(request: &HttpRequest) -> Option<(&str, u16)> {
+    let (host, port) = request.raw_path.rsplit_once(':')?;
+    let host = match host.strip_prefix('[') {
+        Some(host) => host.strip_suffix(']')?,
+        None => host,
+    };
+    if host.is_empty() || host.contains(['/', '[', ']']) {
+        return None;
+    }
+    Some((host, port.parse().ok()?))
+}
+
+/// Whether `host:port` matches one of the `host:port` patterns, where the host may start with
+/// `*.` for any subdomain and the port may be `*`.
+fn is_allowed(patterns: &[String], host: &str, port: u16) -> bool {
+    patterns.iter().any(|pattern| {
+        let Some((host_pattern, port_pattern)) = pattern.rsplit_once(':') else {
+            return false;
+        };
+        let host_matches = match host_pattern.strip_prefix("*.") {
+            Some(domain) => host.len().checked_sub(domain.len() + 1).is_some_and(|dot| {
+                host.as_bytes()[dot] == b'.' && host[dot + 1..].eq_ignore_ascii_case(domain)
+            }),
+            None => host_pattern.eq_ignore_ascii_case(host),
+        };
+        host_matches && (port_pattern == "*" || port_pattern.parse() == Ok(port))
+    })
+}
+
+/// Answers CONNECT requests by opening a TCP connection to the target, answering 200 and then
+/// copying bytes in both directions until either side closes. Targets not allowed by the
+/// config's `connect_targets` get a 403, and with none configured CONNECT is left to the router.
+#[derive(Default)]
+pub struct Tunnel;
+
+impl Tunnel {
+    pub fn new() -> Self {
+        Tunnel
+    }
+}
+
+impl Middleware<ServerConfig> for Tunnel {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        config: Arc<ServerConfig>,
+        next: Next<'a, ServerConfig>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            if !self.accepts(&request, &config) {
+                return next.run(request, config).await;
+            }
+            // HTTP/2 streams can't be taken over like a connection
+            if request.version == Version::Http2 {
+                return Ok(HttpResponse::new(StatusCode::NotImplemented));
+            }
+            let Some((host, port)) = target(&request) else {
+                return Ok(HttpResponse::bad_request());
+            };
+            if !is_allowed(&config.connect_targets, host, port) {
+                return Ok(HttpResponse::forbidden());
+            }
+            let connected =
+                tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port))).await;
+            let mut upstream = match connected {
+                Ok(Ok(upstream)) => upstream,
+                Ok(Err(e)) => {
+                    tracing::warn!("Unable to connect to {host}:{port}: {e}");
+                    return Ok(HttpResponse::new(StatusCode::BadGateway));
+                }
+                Err(_) => return Ok(HttpResponse::new(StatusCode::GatewayTimeout)),
+            };
+            let mut resp = HttpResponse::ok();
+            resp.on_upgrade(move |mut connection| async move {
+                tokio::io::copy_bidirectional(&mut connection, &mut upstream).await?;
+                Ok(())
+            });
+            Ok(resp)
+        })
+    }
+
+    fn accepts(&self, request: &HttpRequest, config: &ServerConfig) -> bool {
+        request.method == "CONNECT" && !config.connect_targets.is_empty()
+    }
+}
+
+#[test]
+fn tests_target() {
+    let request = |target: &str| HttpRequest {
+        method: "CONNECT".to_string(),
+        raw_path: target.to_string(),
+        ..Default::default()
+    };
+    assert_eq!(
+        Some(("example.com", 443)),
+        target(&request("example.com:443"))
+    );
+    assert_eq!(Some(("::1", 8443)), target(&request("[::1]:8443")));
+    assert_eq!(None, target(&request("example.com")));
+    assert_eq!(None, target(&request("/path:443")));
+    assert_eq!(None, target(&request(":443")));
+    assert_eq!(None, target(&request("example.com:https")));
+}
+
+#[test]
+fn tests_is_allowed() {
+    let patterns = ["example.com:443", "*.example.org:443", "localhost:*"].map(String::from);
+    assert!(is_allowed(&patterns, "Example.com", 443));
+    assert!(!is_allowed(&patterns, "example.com", 80));
+    assert!(is_allowed(&patterns, "api.example.org", 443));
+    assert!(!is_allowed(&patterns, "example.org", 443));
+    assert!(!is_allowed(&patterns, "badexample.org", 443));
+    assert!(is_allowed(&patterns, "localhost", 8080));
+    assert!(!is_allowed(&patterns, "127.0.0.1", 8080));
+}
@@ -0,0 +1,105 @@
+//! Handing a connection over to another protocol once a 101 Switching Protocols response has
+//! been written, see [`HttpResponse::on_upgrade`](crate::HttpResponse::on_upgrade).
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::router::BoxFuture;
+
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+pub(crate) type OnUpgrade =
+    Box<dyn FnOnce(Upgraded) -> BoxFuture<'static, anyhow::Result<()>> + Send>;
+
+/// A connection taken over from the HTTP/1 server. The client may already have sent bytes of
+/// the new protocol along with the request; the server had read those into its buffer, and
+/// they are read back first.
+pub struct Upgraded<S = Box<dyn Connection>> {
+    buffered: Bytes,
+    io: S,
+}
+
+impl<S> Upgraded<S> {
+    pub(crate) fn new(buffered: Bytes, io: S) -> Self {
+        Upgraded { buffered, io }
+    }
+
+    /// The bytes received after the request that have not been read yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffered
+    }
+
+    /// The underlying stream and the bytes already read from it that came after the request,
+    /// for protocols that do their own buffering.
+    pub fn into_parts(self) -> (S, Bytes) {
+        (self.io, self.buffered)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Upgraded<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.buffered.is_empty() {
+            return Pin::new(&mut self.io).poll_read(cx, buf);
+        }
+        let len = buf.remaining().min(self.buffered.len());
+        buf.put_slice(&self.buffered[..len]);
+        self.buffered.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Upgraded<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[tokio::test]
+async fn tests_upgraded() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut client, server) = tokio::io::duplex(64);
+    let mut upgraded = Upgraded::new(Bytes::from_static(b"early "), server);
+    assert_eq!(b"early ", upgraded.buffered());
+    client.write_all(b"later").await.unwrap();
+    drop(client);
+    let mut read = String::new();
+    upgraded.read_to_string(&mut read).await.unwrap();
+    assert_eq!("early later", read);
+    let (_, buffered) = upgraded.into_parts();
+    assert!(buffered.is_empty());
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::config::ServerConfig;
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::{BoxFuture, Router};
+
+/// Settings for the requests to some host names, configured in `[[vhosts]]` tables.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VirtualHostConfig {
+    /// Host names like "example.com" or "*.example.com", the latter matching any subdomain.
+    pub hosts: Vec<String>,
+    /// Replaces the server's static directory for these hosts.
+    #[serde(alias = "directory")]
+    pub static_directory: Option<String>,
+    pub dir_listing: Option<bool>,
+}
+
+/// Routes requests by their Host header. Requests to a host listed in the config's `vhosts` see
+/// its static directory, and requests to a host with its own [`Router`] are dispatched there;
+/// all others fall through to the server's routes.
+#[derive(Default)]
+pub struct VirtualHosts {
+    routers: Vec<(String, Router<ServerConfig>)>,
+}
+
+impl VirtualHosts {
+    pub fn new() -> Self {
+        VirtualHosts::default()
+    }
+
+    /// Serves requests to `host`, e.g. "api.example.com" or "*.example.com", from `router`
+    /// instead of the server's routes.
+    pub fn router(mut self, host: impl Into<String>, router: Router<ServerConfig>) -> Self {
+        self.routers.push((host.into(), router));
+        self
+    }
+}
+
+impl Middleware<ServerConfig> for VirtualHosts {
+    fn handle<'a>(
+        &'a self,
+        request: HttpRequest,
+        mut config: Arc<ServerConfig>,
+        next: Next<'a, ServerConfig>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let Some(host) = request_host(&request) else {
+                return next.run(request, config).await;
+            };
+            if let Some(vhost) = config.vhosts.iter().find(|vhost| {
+                vhost
+                    .hosts
+                    .iter()
+                    .any(|pattern| host_matches(pattern, &host))
+            }) {
+                let mut host_config = (*config).clone();
+                if vhost.static_directory.is_some() {
+                    host_config.static_directory = vhost.static_directory.clone();
+                }
+                if let Some(dir_listing) = vhost.dir_listing {
+                    host_config.dir_listing = dir_listing;
+                }
+                config = Arc::new(host_config);
+            }
+            match self
+                .routers
+                .iter()
+                .find(|(pattern, _)| host_matches(pattern, &host))
+            {
+                Some((_, router)) => router.handle(request, config).await,
+                None => next.run(request, config).await,
+            }
+        })
+    }
+
+    fn accepts(&self, request: &HttpRequest, config: &ServerConfig) -> bool {
+        let Some(host) = request_host(request) else {
+            return false;
+        };
+        self.routers
+            .iter()
+            .find(|(pattern, _)| host_matches(pattern, &host))
+            .is_some_and(|(_, router)| router.accepts(request, config))
+    }
+
+    fn streams_body(&self, request: &HttpRequest, config: &ServerConfig) -> bool {
+        let Some(host) = request_host(request) else {
+            return false;
+        };
+        self.routers
+            .iter()
+            .find(|(pattern, _)| host_matches(pattern, &host))
+            .is_some_and(|(_, router)| router.streams_body(request, config))
+    }
+}
+
+/// The lowercased Host header without its port.
+fn request_host(request: &HttpRequest) -> Option<String> {
+    let host = request.headers.get("Host")?.trim();
+    let name = match host.strip_prefix('[') {
+        // an IPv6 literal, whose colons are not a port separator
+        Some(rest) => &host[..rest.find(']')? + 2],
+        None => host.split(':').next()?,
+    };
+    Some(name.trim_end_matches('.').to_ascii_lowercase())
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain.to_ascii_lowercase().as_str())
+            .and_then(|subdomain| subdomain.strip_suffix('.'))
+            .is_some_and(|subdomain| !subdomain.is_empty()),
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+#[test]
+fn tests_host_matches() {
+    let host = |value: &str| {
+        let mut request = HttpRequest::default();
+        request
+            .headers
+            .insert("Host".to_string(), value.to_string());
+        request_host(&request)
+    };
+    assert_eq!(Some("example.com".to_string()), host("Example.COM:8080"));
+    assert_eq!(Some("example.com".to_string()), host("example.com."));
+    assert_eq!(Some("[::1]".to_string()), host("[::1]:4221"));
+    assert_eq!(None, request_host(&HttpRequest::default()));
+
+    assert!(host_matches("example.com", "example.com"));
+    assert!(host_matches("*.example.com", "www.example.com"));
+    assert!(host_matches("*.example.com", "a.b.example.com"));
+    assert!(!host_matches("*.example.com", "example.com"));
+    assert!(!host_matches("*.example.com", "badexample.com"));
+}
+
+#[tokio::test]
+async fn tests_virtual_hosts() {
+    let api = Router::new().get("/", |_, _| async {
+        Ok(HttpResponse::builder().body("api").build())
+    });
+    let router = Router::new()
+        .get("/", |_, config: Arc<ServerConfig>| async move {
+            let directory = config.static_directory.clone().unwrap_or_default();
+            Ok(HttpResponse::builder().body(directory).build())
+        })
+        .layer(VirtualHosts::new().router("api.example.com", api));
+    let config = Arc::new(ServerConfig {
+        static_directory: Some("/srv/default".to_string()),
+        vhosts: vec![VirtualHostConfig {
+            hosts: vec!["*.blog.example".to_string()],
+            static_directory: Some("/srv/blog".to_string()),
+            dir_listing: None,
+        }],
+        ..Default::default()
+    });
+    let get = async |host: &str| {
+        let mut request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            ..Default::default()
+        };
+        request.headers.insert("Host".to_string(), host.to_string());
+        let resp = router.handle(request, config.clone()).await.unwrap();
+        String::from_utf8(resp.body.as_bytes().unwrap().to_vec()).unwrap()
+    };
+
+    assert_eq!("api", get("API.example.com").await);
+    assert_eq!("/srv/blog", get("alice.blog.example:4221").await);
+    assert_eq!("/srv/default", get("other.example").await);
+}
@@ -0,0 +1,366 @@
+use std::future::Future;
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::status::StatusCode;
+use crate::upgrade::Upgraded;
+
+/// Appended to Sec-WebSocket-Key before hashing, see RFC 6455 section 1.3.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Messages larger than this, summed over all fragments, fail the connection.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// A close frame with its optional status code and reason.
+    Close(Option<(u16, String)>),
+}
+
+/// Answers a WebSocket handshake request with 101 Switching Protocols and runs `handler` on the
+/// upgraded connection. Requests that are not valid handshakes get a 400, or a 426 for
+/// unsupported protocol versions.
+pub fn upgrade<F, Fut>(request: &HttpRequest, handler: F) -> HttpResponse
+where
+    F: FnOnce(WebSocket) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let has_token = |header: &str, token: &str| {
+        request.headers.get(header).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    if request.method != "GET"
+        || !has_token("Upgrade", "websocket")
+        || !has_token("Connection", "upgrade")
+    {
+        return HttpResponse::bad_request();
+    }
+    if request.headers.get("Sec-WebSocket-Version") != Some("13") {
+        let mut resp = HttpResponse::new(StatusCode::UpgradeRequired);
+        resp.set_header("Sec-WebSocket-Version".to_string(), "13".to_string());
+        return resp;
+    }
+    let Some(key) = request.headers.get("Sec-WebSocket-Key").filter(|key| {
+        BASE64
+            .decode(key.trim())
+            .is_ok_and(|nonce| nonce.len() == 16)
+    }) else {
+        return HttpResponse::bad_request();
+    };
+
+    let mut resp = HttpResponse::switching_protocols();
+    resp.set_header("Upgrade".to_string(), "websocket".to_string());
+    resp.set_header("Connection".to_string(), "Upgrade".to_string());
+    resp.set_header("Sec-WebSocket-Accept".to_string(), accept_key(key.trim()));
+    resp.on_upgrade(move |connection| handler(WebSocket::new(connection)));
+    resp
+}
+
+/// Computes the Sec-WebSocket-Accept value for a client's Sec-WebSocket-Key.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+/// The server side of a WebSocket connection.
+pub struct WebSocket {
+    connection: Upgraded,
+    closed: bool,
+}
+
+impl WebSocket {
+    pub fn new(connection: Upgraded) -> Self {
+        WebSocket {
+            connection,
+            closed: false,
+        }
+    }
+
+    /// Receives the next message, reassembling fragmented ones. Pings are answered
+    /// automatically and a close frame is echoed back; `None` means the connection is closed.
+    pub async fn recv(&mut self) -> Result<Option<Message>> {
+        let mut fragments: Option<(u8, Vec<u8>)> = None;
+        loop {
+            if self.closed {
+                return Ok(None);
+            }
+            let Some(frame) = read_frame(&mut self.connection).await? else {
+                return Ok(None);
+            };
+            if !frame.masked {
+                anyhow::bail!("client frames must be masked");
+            }
+
+            let (opcode, payload) = match frame.opcode {
+                OP_PING => {
+                    self.write_frame(OP_PONG, &frame.payload).await?;
+                    return Ok(Some(Message::Ping(frame.payload)));
+                }
+                OP_PONG => return Ok(Some(Message::Pong(frame.payload))),
+                OP_CLOSE => {
+                    let close = parse_close(&frame.payload)?;
+                    let code = close.as_ref().map(|(code, _)| code.to_be_bytes());
+                    self.write_frame(OP_CLOSE, code.as_ref().map_or(&[][..], |code| code))
+                        .await?;
+                    self.closed = true;
+                    return Ok(Some(Message::Close(close)));
+                }
+                OP_TEXT | OP_BINARY if fragments.is_none() => (frame.opcode, frame.payload),
+                OP_CONTINUATION => {
+                    let (opcode, mut payload) = fragments
+                        .take()
+                        .context("continuation frame without a message")?;
+                    payload.extend_from_slice(&frame.payload);
+                    (opcode, payload)
+                }
+                OP_TEXT | OP_BINARY => anyhow::bail!("new message before the previous one ended"),
+                opcode => anyhow::bail!("unknown opcode {opcode:#x}"),
+            };
+            if payload.len() > MAX_MESSAGE_SIZE {
+                anyhow::bail!("message exceeds {MAX_MESSAGE_SIZE} bytes");
+            }
+            if !frame.fin {
+                fragments = Some((opcode, payload));
+                continue;
+            }
+            return Ok(Some(match opcode {
+                OP_TEXT => Message::Text(
+                    String::from_utf8(payload).context("text message is not valid UTF-8")?,
+                ),
+                _ => Message::Binary(payload),
+            }));
+        }
+    }
+
+    /// Sends `message` as a single frame. Sending a close message closes the connection.
+    pub async fn send(&mut self, message: Message) -> Result<()> {
+        match message {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(OP_BINARY, &data).await,
+            Message::Ping(data) => self.write_frame(OP_PING, &data).await,
+            Message::Pong(data) => self.write_frame(OP_PONG, &data).await,
+            Message::Close(close) => {
+                let payload = close
+                    .map(|(code, reason)| [&code.to_be_bytes()[..], reason.as_bytes()].concat())
+                    .unwrap_or_default();
+                self.write_frame(OP_CLOSE, &payload).await?;
+                self.closed = true;
+                Ok(())
+            }
+        }
+    }
+
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        self.connection
+            .write_all(&encode_frame(opcode, payload, None))
+            .await?;
+        self.connection.flush().await?;
+        Ok(())
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    masked: bool,
+    payload: Vec<u8>,
+}
+
+/// Reads one frame and unmasks its payload, returning `None` on a clean end of stream.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Frame>> {
+    let mut head = [0; 2];
+    match reader.read_exact(&mut head).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let fin = head[0] & 0x80 != 0;
+    if head[0] & 0x70 != 0 {
+        anyhow::bail!("reserved bits set without a negotiated extension");
+    }
+    let opcode = head[0] & 0x0f;
+    let masked = head[1] & 0x80 != 0;
+    let length = match head[1] & 0x7f {
+        126 => u64::from(reader.read_u16().await?),
+        127 => reader.read_u64().await?,
+        length => u64::from(length),
+    };
+    if opcode >= OP_CLOSE && (!fin || length > 125) {
+        anyhow::bail!("control frames must not be fragmented or exceed 125 bytes");
+    }
+    let length = usize::try_from(length)
+        .ok()
+        .filter(|length| *length <= MAX_MESSAGE_SIZE)
+        .with_context(|| format!("frame exceeds {MAX_MESSAGE_SIZE} bytes"))?;
+
+    let mut mask = [0; 4];
+    if masked {
+        reader.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload).await?;
+    if masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Ok(Some(Frame {
+        fin,
+        opcode,
+        masked,
+        payload,
+    }))
+}
+
+/// Encodes a single final frame, masked with `mask` if given (as clients must).
+fn encode_frame(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        length @ 0..=125 => frame.push(mask_bit | length as u8),
+        length @ 126..=0xffff => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(
+                payload
+                    .iter()
+                    .enumerate()
+                    .map(|(i, byte)| byte ^ mask[i % 4]),
+            );
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<(u16, String)>> {
+    match payload {
+        [] => Ok(None),
+        [_] => anyhow::bail!("close frame with a truncated status code"),
+        [high, low, reason @ ..] => {
+            let reason = String::from_utf8(reason.to_vec()).context("close reason is not UTF-8")?;
+            Ok(Some((u16::from_be_bytes([*high, *low]), reason)))
+        }
+    }
+}
+
+#[test]
+fn tests_accept_key() {
+    // the example from RFC 6455 section 1.3
+    assert_eq!(
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+        accept_key("dGhlIHNhbXBsZSBub25jZQ==")
+    );
+}
+
+#[test]
+fn tests_upgrade() {
+    let request = |version: &str| {
+        let mut request = HttpRequest {
+            method: "GET".to_string(),
+            ..Default::default()
+        };
+        for (header, value) in [
+            ("Upgrade", "websocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+            ("Sec-WebSocket-Version", version),
+        ] {
+            request
+                .headers
+                .insert(header.to_string(), value.to_string());
+        }
+        request
+    };
+    let upgrade = |request: &HttpRequest| upgrade(request, |_| async { Ok(()) });
+
+    let resp = upgrade(&request("13"));
+    assert_eq!(101, resp.status_code);
+    assert_eq!(
+        Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+        resp.headers.get("Sec-WebSocket-Accept")
+    );
+    assert!(resp.on_upgrade.is_some());
+
+    let resp = upgrade(&request("8"));
+    assert_eq!(426, resp.status_code);
+    assert_eq!(Some("13"), resp.headers.get("Sec-WebSocket-Version"));
+
+    let mut bad_key = request("13");
+    bad_key
+        .headers
+        .insert("Sec-WebSocket-Key".to_string(), "short".to_string());
+    assert_eq!(400, upgrade(&bad_key).status_code);
+    assert_eq!(400, upgrade(&HttpRequest::default()).status_code);
+}
+
+#[tokio::test]
+async fn tests_websocket_messages() {
+    let (mut client, server) = tokio::io::duplex(1024);
+    let mut socket = WebSocket::new(Upgraded::new(Default::default(), Box::new(server)));
+    let mask = Some([1, 2, 3, 4]);
+
+    let mut input = encode_frame(OP_TEXT, b"hel", mask);
+    input[0] &= !0x80;
+    input.extend(encode_frame(OP_CONTINUATION, b"lo", mask));
+    input.extend(encode_frame(OP_PING, b"p", mask));
+    input.extend(encode_frame(OP_BINARY, &[0; 300], mask));
+    input.extend(encode_frame(OP_CLOSE, &[0x03, 0xe8], mask));
+    client.write_all(&input).await.unwrap();
+
+    assert_eq!(
+        Some(Message::Text("hello".to_string())),
+        socket.recv().await.unwrap()
+    );
+    assert_eq!(
+        Some(Message::Ping(b"p".to_vec())),
+        socket.recv().await.unwrap()
+    );
+    assert_eq!(
+        Some(Message::Binary(vec![0; 300])),
+        socket.recv().await.unwrap()
+    );
+    assert_eq!(
+        Some(Message::Close(Some((1000, String::new())))),
+        socket.recv().await.unwrap()
+    );
+    assert_eq!(None, socket.recv().await.unwrap());
+    drop(socket);
+
+    let mut output = vec![];
+    client.read_to_end(&mut output).await.unwrap();
+    let mut expected = encode_frame(OP_PONG, b"p", None);
+    expected.extend(encode_frame(OP_CLOSE, &[0x03, 0xe8], None));
+    assert_eq!(expected, output);
+}
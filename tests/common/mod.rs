@@ -0,0 +1,269 @@
+//! Starts servers on ephemeral ports and talks to them over real sockets.
+
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::{Buf, BytesMut};
+use codecrafters_http_server::{Server, ServerBuilder, ServerConfig};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+
+/// A server running in the background until it is dropped.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl TestServer {
+    /// Serves the built-in routes with `config`.
+    pub async fn start(config: ServerConfig) -> TestServer {
+        TestServer::from_builder(Server::builder().config(config)).await
+    }
+
+    pub async fn from_builder(builder: ServerBuilder) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let shutdown = async {
+                let _ = stopped.await;
+            };
+            if let Err(e) = builder.build().serve(listener, shutdown).await {
+                eprintln!("test server failed: {e:#}");
+            }
+        });
+        TestServer {
+            addr,
+            _shutdown: shutdown,
+        }
+    }
+
+    pub async fn client(&self) -> TestClient {
+        TestClient::connect(self.addr).await
+    }
+}
+
+/// One connection to a [`TestServer`], which may carry several requests.
+pub struct TestClient {
+    stream: TcpStream,
+    /// Bytes read past the end of the last response.
+    buf: BytesMut,
+}
+
+impl TestClient {
+    pub async fn connect(addr: SocketAddr) -> TestClient {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        TestClient {
+            stream,
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// Writes `bytes` exactly as given, for requests the builders can't express.
+    pub async fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).await.unwrap();
+    }
+
+    /// Writes `bytes` in pieces of `size`, pausing in between so each arrives on its own.
+    pub async fn send_in_pieces(&mut self, bytes: &[u8], size: usize) {
+        for piece in bytes.chunks(size) {
+            self.send_raw(piece).await;
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    /// Sends `request` and reads the response to it.
+    pub async fn send(&mut self, request: TestRequest) -> TestResponse {
+        let head_only = request.method == "HEAD";
+        self.send_raw(&request.encode()).await;
+        self.read_response(head_only).await.unwrap()
+    }
+
+    pub async fn get(&mut self, path: &str) -> TestResponse {
+        self.send(TestRequest::new("GET", path)).await
+    }
+
+    /// Reads the next response, which has no body if `head_only`, like the response to HEAD.
+    pub async fn read_response(&mut self, head_only: bool) -> Result<TestResponse> {
+        let end = loop {
+            if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                break end;
+            }
+            self.fill().await?;
+        };
+        let head = String::from_utf8(self.buf[..end].to_vec()).context("invalid head")?;
+        self.buf.advance(end + 4);
+        let mut lines = head.split("\r\n");
+        let status_line = lines.next().unwrap_or_default();
+        let status = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .with_context(|| format!("invalid status line {status_line:?}"))?;
+        let headers = lines
+            .filter_map(|line| line.split_once(": "))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let mut resp = TestResponse {
+            status,
+            headers,
+            body: vec![],
+        };
+        if head_only || status < 200 || status == 204 || status == 304 {
+            return Ok(resp);
+        }
+        if resp.header("Transfer-Encoding") == Some("chunked") {
+            resp.body = self.read_chunked().await?;
+        } else if let Some(length) = resp.header("Content-Length") {
+            let length: usize = length.parse().context("invalid Content-Length")?;
+            while self.buf.len() < length {
+                self.fill().await?;
+            }
+            resp.body = self.buf.split_to(length).to_vec();
+        } else {
+            // delimited by the server closing the connection
+            while self.fill().await.is_ok() {}
+            resp.body = self.buf.split().to_vec();
+        }
+        Ok(resp)
+    }
+
+    /// Reads the next `len` bytes, for connections that stopped speaking HTTP.
+    pub async fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        while self.buf.len() < len {
+            self.fill().await?;
+        }
+        Ok(self.buf.split_to(len).to_vec())
+    }
+
+    /// Whether the server closed the connection, without data arriving first.
+    pub async fn is_closed(&mut self) -> bool {
+        self.buf.is_empty() && matches!(self.stream.read_buf(&mut self.buf).await, Ok(0))
+    }
+
+    async fn read_chunked(&mut self) -> Result<Vec<u8>> {
+        let mut body = vec![];
+        loop {
+            let line_end = loop {
+                if let Some(end) = find(&self.buf, b"\r\n") {
+                    break end;
+                }
+                self.fill().await?;
+            };
+            let line = std::str::from_utf8(&self.buf[..line_end])?;
+            let size = usize::from_str_radix(line.split(';').next().unwrap_or_default(), 16)
+                .with_context(|| format!("invalid chunk size {line:?}"))?;
+            self.buf.advance(line_end + 2);
+            if size == 0 {
+                // skip trailer fields up to the empty line
+                loop {
+                    while find(&self.buf, b"\r\n").is_none() {
+                        self.fill().await?;
+                    }
+                    let end = find(&self.buf, b"\r\n").unwrap();
+                    self.buf.advance(end + 2);
+                    if end == 0 {
+                        return Ok(body);
+                    }
+                }
+            }
+            while self.buf.len() < size + 2 {
+                self.fill().await?;
+            }
+            body.extend_from_slice(&self.buf[..size]);
+            self.buf.advance(size + 2);
+        }
+    }
+
+    async fn fill(&mut self) -> Result<()> {
+        let read =
+            tokio::time::timeout(Duration::from_secs(5), self.stream.read_buf(&mut self.buf))
+                .await
+                .context("timed out waiting for the server")??;
+        if read == 0 {
+            anyhow::bail!("connection closed");
+        }
+        Ok(())
+    }
+}
+
+/// A request put together field by field, with a Content-Length added for a body.
+pub struct TestRequest {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl TestRequest {
+    pub fn new(method: &str, target: &str) -> TestRequest {
+        TestRequest {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: vec![("Host".to_string(), "localhost".to_string())],
+            body: vec![],
+        }
+    }
+
+    pub fn version(mut self, version: &str) -> TestRequest {
+        self.version = version.to_string();
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> TestRequest {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> TestRequest {
+        self.body = body.into();
+        self
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut head = format!("{} {} {}\r\n", self.method, self.target, self.version);
+        for (name, value) in &self.headers {
+            head += &format!("{name}: {value}\r\n");
+        }
+        if !self.body.is_empty() {
+            head += &format!("Content-Length: {}\r\n", self.body.len());
+        }
+        head += "\r\n";
+        let mut encoded = head.into_bytes();
+        encoded.extend_from_slice(&self.body);
+        encoded
+    }
+}
+
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn text(&self) -> &str {
+        std::str::from_utf8(&self.body).unwrap()
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -0,0 +1,428 @@
+mod common;
+
+use codecrafters_http_server::tunnel::Tunnel;
+use codecrafters_http_server::{HttpResponse, Server, ServerConfig};
+use common::{TestClient, TestRequest, TestServer};
+
+#[tokio::test]
+async fn tests_routes() {
+    let server = TestServer::start(ServerConfig::default()).await;
+    let mut client = server.client().await;
+
+    let resp = client.get("/").await;
+    assert_eq!(200, resp.status);
+    assert_eq!(Some("0"), resp.header("Content-Length"));
+
+    let resp = client.get("/echo/hello").await;
+    assert_eq!(200, resp.status);
+    assert_eq!(Some("text/plain"), resp.header("Content-Type"));
+    assert_eq!("hello", resp.text());
+
+    let resp = client
+        .send(TestRequest::new("GET", "/user-agent").header("User-Agent", "test-client/1.0"))
+        .await;
+    assert_eq!("test-client/1.0", resp.text());
+
+    assert_eq!(404, client.get("/missing").await.status);
+}
+
+#[tokio::test]
+async fn tests_custom_routes() {
+    let builder = Server::builder().config(ServerConfig::default()).route(
+        "PUT",
+        "/items/:id",
+        |request, _| async move {
+            let id = request.param("id").unwrap_or_default().to_string();
+            let body = String::from_utf8_lossy(&request.body).to_string();
+            Ok(HttpResponse::builder().body(format!("{id}={body}")).build())
+        },
+    );
+    let server = TestServer::from_builder(builder).await;
+    let mut client = server.client().await;
+
+    let resp = client
+        .send(TestRequest::new("PUT", "/items/7").body("seven"))
+        .await;
+    assert_eq!(200, resp.status);
+    assert_eq!("7=seven", resp.text());
+}
+
+#[tokio::test]
+async fn tests_keep_alive() {
+    let server = TestServer::start(ServerConfig::default()).await;
+    let mut client = server.client().await;
+    for message in ["a", "b", "c"] {
+        assert_eq!(
+            message,
+            client.get(&format!("/echo/{message}")).await.text()
+        );
+    }
+
+    // pipelined requests are answered in order
+    client
+        .send_raw(
+            b"GET /echo/one HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET /echo/two HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+    assert_eq!("one", client.read_response(false).await.unwrap().text());
+    assert_eq!("two", client.read_response(false).await.unwrap().text());
+
+    let mut client = server.client().await;
+    let resp = client
+        .send(TestRequest::new("GET", "/echo/bye").header("Connection", "close"))
+        .await;
+    assert_eq!(Some("close"), resp.header("Connection"));
+    assert!(client.is_closed().await);
+
+    let mut client = server.client().await;
+    let resp = client
+        .send(TestRequest::new("GET", "/echo/old").version("HTTP/1.0"))
+        .await;
+    assert_eq!("old", resp.text());
+    assert!(client.is_closed().await);
+}
+
+#[tokio::test]
+async fn tests_partial_writes() {
+    let server = TestServer::start(ServerConfig::default()).await;
+    let mut client = server.client().await;
+
+    client
+        .send_in_pieces(b"GET /echo/slowly HTTP/1.1\r\nHost: localhost\r\n\r\n", 1)
+        .await;
+    assert_eq!("slowly", client.read_response(false).await.unwrap().text());
+
+    // the end of the header block and the body arrive in separate pieces
+    let request = b"GET /echo/split HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody";
+    client.send_in_pieces(request, 7).await;
+    assert_eq!("split", client.read_response(false).await.unwrap().text());
+}
+
+#[tokio::test]
+async fn tests_malformed_requests() {
+    let server = TestServer::start(ServerConfig {
+        max_header_size: 256,
+        max_body_size: 16,
+        ..Default::default()
+    })
+    .await;
+
+    let mut client = server.client().await;
+    client.send_raw(b"GET /\r\n\r\n").await;
+    assert_eq!(400, client.read_response(false).await.unwrap().status);
+    assert!(client.is_closed().await);
+
+    let mut client = server.client().await;
+    let long_header = format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Long: {}\r\n\r\n",
+        "a".repeat(300)
+    );
+    client.send_raw(long_header.as_bytes()).await;
+    assert_eq!(431, client.read_response(false).await.unwrap().status);
+
+    let mut client = server.client().await;
+    let resp = client
+        .send(TestRequest::new("POST", "/files/big").body(vec![b'x'; 17]))
+        .await;
+    assert_eq!(413, resp.status);
+
+    let mut client = server.client().await;
+    client
+        .send_raw(
+            b"POST /echo/a HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+              zz\r\n",
+        )
+        .await;
+    assert_eq!(400, client.read_response(false).await.unwrap().status);
+}
+
+#[tokio::test]
+async fn tests_files() {
+    let root = std::env::temp_dir().join(format!(
+        "codecrafters-http-server-integration-files-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&root).unwrap();
+    let server = TestServer::start(ServerConfig {
+        static_directory: Some(root.to_str().unwrap().to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = TestClient::connect(server.addr).await;
+
+    let resp = client
+        .send(TestRequest::new("POST", "/files/notes.txt").body("some notes"))
+        .await;
+    assert_eq!(201, resp.status);
+
+    let resp = client.get("/files/notes.txt").await;
+    assert_eq!(200, resp.status);
+    assert_eq!("some notes", resp.text());
+
+    let resp = client
+        .send(TestRequest::new("HEAD", "/files/notes.txt"))
+        .await;
+    assert_eq!(Some("10"), resp.header("Content-Length"));
+    assert!(resp.body.is_empty());
+
+    // a chunked upload streams to the file
+    client
+        .send_raw(
+            b"POST /files/chunked.txt HTTP/1.1\r\nHost: localhost\r\n\
+              Transfer-Encoding: chunked\r\n\r\n\
+              3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+        )
+        .await;
+    assert_eq!(201, client.read_response(false).await.unwrap().status);
+    assert_eq!("abcde", client.get("/files/chunked.txt").await.text());
+
+    let resp = client
+        .send(TestRequest::new("DELETE", "/files/notes.txt"))
+        .await;
+    assert!(resp.status == 200 || resp.status == 204);
+    assert_eq!(404, client.get("/files/notes.txt").await.status);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn tests_unix_socket() {
+    use codecrafters_http_server::listener::Listener;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("http-test-{}.sock", std::process::id()));
+    // a socket left behind by an earlier run is replaced
+    drop(Listener::bind_unix(&path).unwrap());
+    let listener = Listener::bind_unix(&path).unwrap();
+    let (shutdown, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        Server::builder().build().serve(listener, shutdown).await
+    });
+
+    let mut stream = UnixStream::connect(&path).await.unwrap();
+    stream
+        .write_all(b"GET /echo/unix HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{resp}");
+    assert!(resp.ends_with("\r\n\r\nunix"), "{resp}");
+
+    drop(shutdown);
+    server.await.unwrap().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn tests_multiple_listeners() {
+    use tokio::net::TcpListener;
+
+    let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addrs = [first.local_addr().unwrap(), second.local_addr().unwrap()];
+    let (shutdown, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        let listeners = vec![first.into(), second.into()];
+        Server::builder()
+            .build()
+            .serve_all(listeners, shutdown)
+            .await
+    });
+
+    for addr in addrs {
+        let mut client = TestClient::connect(addr).await;
+        assert_eq!("both", client.get("/echo/both").await.text());
+    }
+
+    drop(shutdown);
+    server.await.unwrap().unwrap();
+    for addr in addrs {
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn tests_http2() {
+    use bytes::Bytes;
+
+    let builder = Server::builder().route("POST", "/upload", |request, _| async move {
+        let size = request.body.len().to_string();
+        Ok(HttpResponse::builder().body(size).build())
+    });
+    let server = TestServer::from_builder(builder).await;
+    let stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let (client, connection) = h2::client::handshake(stream).await.unwrap();
+    tokio::spawn(connection);
+
+    let get = |path: &str| {
+        let client = client.clone();
+        let uri = format!("http://{}{path}", server.addr);
+        async move {
+            let request = http::Request::get(uri).body(()).unwrap();
+            let mut client = client.ready().await.unwrap();
+            let (response, _) = client.send_request(request, true).unwrap();
+            let (parts, mut body) = response.await.unwrap().into_parts();
+            let mut text = vec![];
+            while let Some(data) = body.data().await {
+                text.extend_from_slice(&data.unwrap());
+            }
+            (parts.status.as_u16(), String::from_utf8(text).unwrap())
+        }
+    };
+    // the streams are answered concurrently on one connection
+    let (a, b, missing) = tokio::join!(get("/echo/a"), get("/echo/b"), get("/missing"));
+    assert_eq!((200, "a".to_string()), a);
+    assert_eq!((200, "b".to_string()), b);
+    assert_eq!(404, missing.0);
+
+    let request = http::Request::post(format!("http://{}/upload", server.addr))
+        .body(())
+        .unwrap();
+    let mut client = client.ready().await.unwrap();
+    let (response, mut body) = client.send_request(request, false).unwrap();
+    body.send_data(Bytes::from(vec![b'x'; 100_000]), true)
+        .unwrap();
+    let (parts, mut body) = response.await.unwrap().into_parts();
+    assert_eq!(200, parts.status.as_u16());
+    assert_eq!(&b"100000"[..], &body.data().await.unwrap().unwrap()[..]);
+}
+
+#[tokio::test]
+async fn tests_h2c_upgrade() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = TestServer::start(ServerConfig::default()).await;
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET /echo/upgraded HTTP/1.1\r\nHost: localhost\r\n\
+            Connection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut head = vec![];
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(
+        head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{head}"
+    );
+
+    // the preface and an empty SETTINGS frame, after which the response arrives on stream 1
+    stream
+        .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+        .await
+        .unwrap();
+    let mut body = vec![];
+    loop {
+        let mut header = [0; 9];
+        stream.read_exact(&mut header).await.unwrap();
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut payload = vec![0; length];
+        stream.read_exact(&mut payload).await.unwrap();
+        let (kind, flags, stream_id) = (header[3], header[4], header[5..] == [0, 0, 0, 1]);
+        // DATA on stream 1, until END_STREAM
+        if kind == 0 && stream_id {
+            body.extend(payload);
+            if flags & 1 != 0 {
+                break;
+            }
+        }
+    }
+    assert_eq!(b"upgraded".to_vec(), body);
+}
+
+#[tokio::test]
+async fn tests_upgrade() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let builder =
+        Server::builder()
+            .config(ServerConfig::default())
+            .route("GET", "/upper", |_, _| async {
+                let mut resp = HttpResponse::switching_protocols();
+                resp.set_header("Upgrade".to_string(), "upper".to_string());
+                resp.set_header("Connection".to_string(), "Upgrade".to_string());
+                resp.on_upgrade(|mut connection| async move {
+                    let mut buf = [0; 64];
+                    loop {
+                        let read = connection.read(&mut buf).await?;
+                        if read == 0 {
+                            return Ok(());
+                        }
+                        connection
+                            .write_all(&buf[..read].to_ascii_uppercase())
+                            .await?;
+                    }
+                });
+                Ok(resp)
+            });
+    let server = TestServer::from_builder(builder).await;
+    let mut client = server.client().await;
+    // the first bytes of the new protocol arrive along with the request
+    client
+        .send_raw(
+            b"GET /upper HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+            Upgrade: upper\r\n\r\nearly ",
+        )
+        .await;
+    let resp = client.read_response(false).await.unwrap();
+    assert_eq!(101, resp.status);
+    client.send_raw(b"late").await;
+    assert_eq!(b"EARLY LATE".to_vec(), client.read_bytes(10).await.unwrap());
+}
+
+#[tokio::test]
+async fn tests_connect_tunnel() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = upstream.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut buf = [0; 64];
+        let read = stream.read(&mut buf).await.unwrap();
+        stream.write_all(&buf[..read]).await.unwrap();
+    });
+    let config = ServerConfig {
+        connect_targets: vec!["127.0.0.1:*".to_string()],
+        ..Default::default()
+    };
+    let builder = Server::builder().config(config).layer(Tunnel::new());
+    let server = TestServer::from_builder(builder).await;
+
+    let mut client = server.client().await;
+    assert_eq!(
+        403,
+        client
+            .send(TestRequest::new("CONNECT", "localhost:1"))
+            .await
+            .status
+    );
+
+    let mut client = server.client().await;
+    let target = format!("127.0.0.1:{port}");
+    client
+        .send_raw(&TestRequest::new("CONNECT", &target).encode())
+        .await;
+    let resp = client.read_response(true).await.unwrap();
+    assert_eq!(200, resp.status);
+    assert_eq!(None, resp.header("Content-Length"));
+    client.send_raw(b"through the tunnel").await;
+    assert_eq!(
+        b"through the tunnel".to_vec(),
+        client.read_bytes(18).await.unwrap()
+    );
+}